@@ -1,4 +1,4 @@
-from . import simplisma, transformers
+from . import mcr, simplisma, transformers
 from .pyroimcr import *
 
-__all__ = ["transformers", "simplisma", pyroimcr.__all__]
+__all__ = ["mcr", "transformers", "simplisma", pyroimcr.__all__]
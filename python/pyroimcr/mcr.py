@@ -0,0 +1,22 @@
+"""
+Module exposing the native MCR-ALS implementation (Tauler 1995) and the alternative resolution methods.
+Multivariate curve resolution decomposes a data matrix in concentration profiles and spectra.
+
+Functions
+---------
+mcr_als
+    Resolve a data matrix by alternating least squares
+mcr_als_augmented
+    Resolve a row-wise augmented matrix of several runs with per-run constraints
+mcr_als_weighted
+    Resolve a data matrix by weighted alternating least squares
+get_roi_weights
+    Build the weights of the cells of MSroi from a heteroscedastic noise model
+nmf
+    Resolve a non-negative data matrix by non-negative matrix factorisation
+parafac2
+    Resolve a row-wise augmented matrix of several runs with PARAFAC2-ALS
+"""
+from .pyroimcr import get_roi_weights, mcr_als, mcr_als_augmented, mcr_als_weighted, nmf, parafac2
+
+__all__ = ["mcr_als", "mcr_als_augmented", "mcr_als_weighted", "get_roi_weights", "nmf", "parafac2"]
@@ -1,17 +1,17 @@
-from typing import Dict, List, Tuple
+from typing import Any, Dict, List, Optional, Tuple
 
 from numpy.typing import NDArray
 
 def import_data(path: str, format: str) -> Tuple[List[NDArray], NDArray]:
     """
-    Get peaks and relatives times from centroided LC-MS data in mzxml or netcdf format.
+    Get peaks and relatives times from centroided LC-MS data in mzxml, mzml or netcdf format.
 
     Parameters
     ----------
     path : str
         the path to the LC-MS run file
     format : str
-        file format of the LC-MS run file, only mzxml' and 'netcdf' files are currently supported
+        file format of the LC-MS run file, 'mzxml', 'mzml' and 'netcdf' files are currently supported
 
     Returns
     -------
@@ -30,8 +30,10 @@ def get_roi(
     tol: float = 0.5,
     tol_units: str = "da",
     update_method: str = "mean",
-    min_occ: int = 1
-) -> Tuple[NDArray, NDArray]:
+    min_occ: int = 1,
+    n_threads: int = 1,
+    sparse: Optional[str] = None
+) -> Tuple[NDArray, Any]:
     """Given the peaks lists of one or more LC-MS runs return their ROIs
 
     Args:
@@ -43,11 +45,556 @@ def get_roi(
         tol_units (str, optional): Unit of `tol`; allowed values are `da` for Daltons and `ppm`. Defaults to "da".
         update_method (str, optional): Which metrics to use when computing the mz value of a ROI. Defaults to "mean".
         min_occ (int, optional): Minimum number of peaks to be present in a ROI for it to be considered. Defaults to 1.
+        n_threads (int, optional): Number of threads used to build the ROIs, 0 uses all the available cores. Other than 1, the m/z axis is split in partitions overlapping by 10 times the tolerance, and the ROIs equal the single threaded ones unless a ROI drifts further than that past a partition bound. Defaults to 1.
+        sparse (str, optional): Return msroi as a sparse matrix; allowed values are `csr` and `csc`. Defaults to None, a dense matrix.
 
     Returns:
     -------
     mzroi : ndarray
         list of mz value representative of each ROI
-    msroi : ndarray
-        matrix with dimensions (n_times x n_rois), element msroi[i,j] represent the intensity for ROI j at time i
+    msroi : ndarray or tuple
+        matrix with dimensions (n_times x n_rois), element msroi[i,j] represent the intensity for ROI j at time i.
+        With `sparse`, the tuple `((data, indices, indptr), shape)` taken by `scipy.sparse.csr_matrix` or `csc_matrix`,
+        e.g. `scipy.sparse.csr_matrix(*msroi)`
+    """
+
+def get_peak_table(peaks_list: List[List[NDArray]], times_list: List[NDArray]) -> Any:
+    """Long format table of the peaks of every scan, shared with pyarrow (14 or later) without copying.
+
+    Args:
+        peaks_list (List[List[ndarray]]): List of of the peaks lists of all the runs to consider
+        times_list (List[ndarray]): List of the scan times for all the considered runs
+
+    Returns:
+    -------
+    peaks : pyarrow.Table
+        one row per peak with the columns `run`, `scan` (position in the run), `rt`, `mz` and `intensity`
+    """
+
+class ArrowBatch:
+    """Record batch of the tables, exported through the Arrow PyCapsule interface without copying."""
+
+    def __arrow_c_schema__(self) -> Any: ...
+    def __arrow_c_array__(self, requested_schema: Optional[Any] = None) -> Tuple[Any, Any]: ...
+
+def get_roi_tables(
+    peaks_list: List[List[NDArray]],
+    times_list: List[NDArray],
+    *,
+    threshold: float = 1000.0,
+    t_factor: float = 1.0,
+    tol: float = 0.5,
+    tol_units: str = "da",
+    update_method: str = "mean",
+    min_occ: int = 1,
+    n_threads: int = 1
+) -> Tuple[Any, Any]:
+    """Build the ROIs as `get_roi` and return them as tables, shared with pyarrow (14 or later) without copying.
+
+    Args:
+        peaks_list (List[List[ndarray]]): List of of the peaks lists of all the runs to consider
+        times_list (List[ndarray]): List of the scan times for all the considered runs
+        threshold (float, optional): Min intensity for a peak to be considered. Defaults to 1000.0.
+        t_factor (float, optional): Minimum Signal to Noise ration for a ROI to be considered. Defaults to 1.0.
+        tol (float, optional): Maximum difference allowed between to peaks mz value to consider them alike. Defaults to 0.5.
+        tol_units (str, optional): Unit of `tol`; allowed values are `da` for Daltons and `ppm`. Defaults to "da".
+        update_method (str, optional): Which metrics to use when computing the mz value of a ROI. Defaults to "mean".
+        min_occ (int, optional): Minimum number of peaks to be present in a ROI for it to be considered. Defaults to 1.
+        n_threads (int, optional): Number of threads used to build the ROIs, 0 uses all the available cores. Other than 1, the m/z axis is split in partitions overlapping by 10 times the tolerance, and the ROIs equal the single threaded ones unless a ROI drifts further than that past a partition bound. Defaults to 1.
+
+    Returns:
+    -------
+    rois : pyarrow.Table
+        one row per peak assigned to a ROI with the columns `roi_id` (column of msroi), `run`, `scan`, `rt`, `mz` and `intensity`
+    summary : pyarrow.Table
+        one row per ROI with the columns `roi_id`, `mz`, `n_scans`, `n_runs`, `apex_time`, `apex_run`, `max_intensity`
+        and `total_intensity`
+    """
+
+def smooth_roi(
+    msroi: Any,
+    *,
+    method: str = "savgol",
+    window: int = 11,
+    order: int = 3,
+    sigma: float = 2.0
+) -> Any:
+    """Smooth each ROI chromatogram (column) of a MSroi matrix.
+
+    Scans with zero intensity are treated as missing: they are excluded from the local fits
+    and only gaps enclosed by measured scans are filled, so peaks are not shifted.
+
+    Args:
+        msroi (ndarray or scipy.sparse matrix): MSroi matrix with dimensions (n_times x n_rois), dense or CSR/CSC sparse
+        method (str, optional): Smoothing method; allowed values are `savgol` for Savitzky-Golay, `mean` for moving average and `gaussian`. Defaults to "savgol".
+        window (int, optional): Window width in scans for `savgol` and `mean`, must be odd for `savgol`. Defaults to 11.
+        order (int, optional): Polynomial order for `savgol`. Defaults to 3.
+        sigma (float, optional): Standard deviation in scans for `gaussian`. Defaults to 2.0.
+
+    Returns:
+    -------
+    msroi : ndarray or tuple
+        smoothed MSroi matrix with the same dimensions as the input; for a sparse input, the tuple
+        `((data, indices, indptr), shape)` of a sparse matrix with the same format
+    """
+
+def correct_baseline(
+    msroi: NDArray,
+    *,
+    method: str = "als",
+    lam: float = 1e5,
+    p: float = 0.01,
+    window: int = 51,
+    order: int = 3
+) -> Tuple[NDArray, NDArray]:
+    """Estimate and subtract the baseline of each ROI chromatogram (column) of a MSroi matrix.
+
+    Args:
+        msroi (ndarray): MSroi matrix with dimensions (n_times x n_rois)
+        method (str, optional): Baseline method; allowed values are `als` for asymmetric least squares, `rolling_min` for rolling minimum and `poly` for iterative polynomial fitting. Defaults to "als".
+        lam (float, optional): Smoothness of the `als` baseline. Defaults to 1e5.
+        p (float, optional): Asymmetry of the `als` baseline, weight of the points above it. Defaults to 0.01.
+        window (int, optional): Window width in scans for `rolling_min`. Defaults to 51.
+        order (int, optional): Polynomial order for `poly`. Defaults to 3.
+
+    Returns:
+    -------
+    corrected : ndarray
+        MSroi matrix with the baseline subtracted and clipped at zero; cells without a peak
+        are left out of the fit and kept at zero
+    baseline : ndarray
+        estimated baseline with the same dimensions as the input
+    """
+
+def correct_tic_baseline(
+    tic: NDArray,
+    *,
+    method: str = "als",
+    lam: float = 1e5,
+    p: float = 0.01,
+    window: int = 51,
+    order: int = 3
+) -> Tuple[NDArray, NDArray]:
+    """Estimate and subtract the baseline of a single chromatogram such as the TIC.
+
+    Takes the same arguments as `correct_baseline`.
+
+    Returns:
+    -------
+    corrected : ndarray
+        chromatogram with the baseline subtracted
+    baseline : ndarray
+        estimated baseline
+    """
+
+def detect_roi_peaks(
+    msroi: NDArray,
+    times: NDArray,
+    *,
+    method: str = "prominence",
+    min_prominence: float = 0.0,
+    min_scale: float = 1.0,
+    max_scale: float = 16.0,
+    min_snr: float = 3.0
+) -> Dict[str, NDArray]:
+    """Detect the chromatographic peaks in each ROI chromatogram (column) of a MSroi matrix.
+
+    Args:
+        msroi (ndarray): MSroi matrix with dimensions (n_times x n_rois)
+        times (ndarray): retention time of each row of `msroi`
+        method (str, optional): Detection method; allowed values are `prominence` for local maxima filtered by prominence and `cwt` for the continuous wavelet transform. Defaults to "prominence".
+        min_prominence (float, optional): Minimum prominence of a peak for `prominence`. Defaults to 0.0.
+        min_scale (float, optional): Smallest wavelet scale in scans for `cwt`. Defaults to 1.0.
+        max_scale (float, optional): Largest wavelet scale in scans for `cwt`. Defaults to 16.0.
+        min_snr (float, optional): Minimum signal to noise ratio of a peak. Defaults to 3.0.
+
+    Returns:
+    -------
+    features : dict[str, ndarray]
+        one entry per detected peak: `roi` (column of `msroi`), `apex`, `start` and `end` (row indices),
+        `apex_time`, `height`, `area` and `snr`
+    """
+
+def mcr_als(
+    d: Any,
+    init: NDArray,
+    *,
+    init_type: str = "spectra",
+    max_iter: int = 50,
+    tol: float = 1e-3,
+    non_negativity: Optional[str] = None,
+    nnls: bool = False,
+    unimodality: Optional[float] = None,
+    closure: Optional[float] = None,
+    normalisation: Optional[str] = None,
+    c_mask: Optional[NDArray] = None,
+    st_mask: Optional[NDArray] = None,
+    mask_upper_bound: bool = False,
+    bands: bool = False
+) -> Dict[str, Any]:
+    """Resolve a data matrix (e.g. MSroi) by multivariate curve resolution alternating least squares.
+
+    Args:
+        d (ndarray or scipy.sparse matrix): data matrix with dimensions (n_times x n_rois), dense or CSR/CSC sparse.
+            A sparse matrix is never made dense and `residuals` is None in the result.
+        init (ndarray): initial estimate, spectra (n_components x n_rois) or concentration profiles (n_times x n_components)
+        init_type (str, optional): Kind of initial estimate; allowed values are `spectra` and `concentrations`. Defaults to "spectra".
+        max_iter (int, optional): Maximum number of iterations. Defaults to 50.
+        tol (float, optional): Convergence threshold on the relative change of the lack of fit between iterations. Defaults to 1e-3.
+        non_negativity (str, optional): Profiles constrained to be non-negative; allowed values are `c`, `st` and `both`. Defaults to None.
+        nnls (bool, optional): Impose non-negativity by non-negative least squares instead of clipping. Defaults to False.
+        unimodality (float, optional): Tolerance of the unimodality constraint on `c`, 1.0 for strict unimodality. Defaults to None.
+        closure (float, optional): Total to which each row of `c` is closed. Defaults to None.
+        normalisation (str, optional): Normalisation of the spectra; allowed values are `max`, `length` and `area`. Defaults to None.
+        c_mask (ndarray, optional): Equality mask with the shape of `c`, NaN cells are free. Defaults to None.
+        st_mask (ndarray, optional): Equality mask with the shape of `st`, NaN cells are free. Defaults to None.
+        mask_upper_bound (bool, optional): Use the mask values as upper bounds instead of exact values. Defaults to False.
+        bands (bool, optional): Compute the feasible bands of the profiles under the same constraints with MCR-BANDS, only for a dense `d`. Defaults to False.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        `c` (n_times x n_components) and `st` (n_components x n_rois) of the best fit, `residuals`,
+        `lof` (%), `r2` (%), `r2_components` with the variance explained by each component alone (%),
+        `contributions` with the share of each component in the reconstruction (%),
+        `lof_history` with the lack of fit of each iteration, `n_iter` and `converged`.
+        With `bands`, also the maximum and minimum signal contribution functions `scf_max` and `scf_min`
+        and the profiles at the band boundaries `c_max`, `c_min`, `st_max` and `st_min`
+    """
+
+def mcr_als_augmented(
+    d: NDArray,
+    init: NDArray,
+    run_sizes: List[int],
+    *,
+    init_type: str = "spectra",
+    max_iter: int = 50,
+    tol: float = 1e-3,
+    non_negativity: Optional[str] = None,
+    nnls: bool = False,
+    unimodality: Optional[float] = None,
+    closure: Optional[float] = None,
+    normalisation: Optional[str] = None,
+    c_mask: Optional[NDArray] = None,
+    st_mask: Optional[NDArray] = None,
+    mask_upper_bound: bool = False,
+    presence: Optional[NDArray] = None,
+    trilinearity: bool = False
+) -> Dict[str, Any]:
+    """MCR-ALS of a row-wise augmented matrix (e.g. the MSroi of several runs) with per-run constraints.
+
+    Takes the same arguments as `mcr_als`; the constraints on `c` are applied separately to each run block.
+
+    Args:
+        run_sizes (List[int]): number of rows (scans) of each run, in the order the runs are stacked in `d`
+        presence (ndarray, optional): boolean mask (n_runs x n_components), False where a component is absent from a run. Defaults to None.
+        trilinearity (bool, optional): Force each concentration profile to have the same shape in every run, runs must have the same number of scans. Defaults to False.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        the same entries as `mcr_als` plus `areas`, the (n_components x n_runs) table of the areas of each concentration profile in each run
+    """
+
+def mcr_als_weighted(
+    d: NDArray,
+    init: NDArray,
+    *,
+    weights: Optional[NDArray] = None,
+    uncertainty: Optional[NDArray] = None,
+    noise: Optional[float] = None,
+    proportional: float = 0.1,
+    missing: float = 0.1,
+    init_type: str = "spectra",
+    max_iter: int = 50,
+    tol: float = 1e-3,
+    non_negativity: Optional[str] = None,
+    nnls: bool = False,
+    unimodality: Optional[float] = None,
+    closure: Optional[float] = None,
+    normalisation: Optional[str] = None,
+    c_mask: Optional[NDArray] = None,
+    st_mask: Optional[NDArray] = None,
+    mask_upper_bound: bool = False
+) -> Dict[str, Any]:
+    """Resolve a data matrix (e.g. MSroi) by weighted MCR-ALS, minimising the sum of weighted squared residuals.
+
+    Takes the same arguments as `mcr_als`. Without `weights` or `uncertainty` the weights are built from `d` as in `get_roi_weights`.
+
+    Args:
+        weights (ndarray, optional): weight of every cell of `d`, e.g. its inverse variance. Defaults to None.
+        uncertainty (ndarray, optional): standard deviation of every cell of `d`. Defaults to None.
+        noise (float, optional): noise level of the automatic weights. Defaults to None.
+        proportional (float, optional): noise proportional to the intensity of the automatic weights. Defaults to 0.1.
+        missing (float, optional): relative weight of the empty cells of the automatic weights. Defaults to 0.1.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        the same entries as `mcr_als`, with weighted `lof` and `r2`, plus the `weights` used
+    """
+
+def get_roi_weights(
+    msroi: NDArray, *, noise: Optional[float] = None, proportional: float = 0.1, missing: float = 0.1
+) -> NDArray:
+    """Weights of the cells of MSroi under a heteroscedastic noise model.
+
+    The standard deviation of a cell is `sqrt(noise**2 + (proportional * intensity)**2)` and its weight the inverse variance.
+    Empty cells, where no peak was detected, get `missing` times the weight of a cell at the noise level.
+
+    Args:
+        msroi (ndarray): MSroi matrix with dimensions (n_times x n_rois)
+        noise (float, optional): noise level, the smallest intensity in `msroi` if None. Defaults to None.
+        proportional (float, optional): noise proportional to the intensity. Defaults to 0.1.
+        missing (float, optional): relative weight of the empty cells, 0.0 to ignore them. Defaults to 0.1.
+
+    Returns:
+    -------
+    weights : ndarray
+        (n_times x n_rois) weight of every cell
+    """
+
+def nmf(
+    d: NDArray,
+    init: NDArray,
+    *,
+    init_type: str = "spectra",
+    method: str = "hals",
+    max_iter: int = 50,
+    tol: float = 1e-3
+) -> Dict[str, Any]:
+    """Resolve a non-negative data matrix (e.g. MSroi) by non-negative matrix factorisation.
+
+    Args:
+        d (ndarray): data matrix with dimensions (n_times x n_rois)
+        init (ndarray): initial estimate, spectra (n_components x n_rois) or concentration profiles (n_times x n_components)
+        init_type (str, optional): Kind of initial estimate; allowed values are `spectra` and `concentrations`. Defaults to "spectra".
+        method (str, optional): Update rule; allowed values are `mu` (multiplicative updates) and `hals`. Defaults to "hals".
+        max_iter (int, optional): Maximum number of iterations. Defaults to 50.
+        tol (float, optional): Convergence threshold on the relative change of the lack of fit between iterations. Defaults to 1e-3.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        the same entries as `mcr_als`
+    """
+
+def parafac2(
+    d: NDArray,
+    init: NDArray,
+    run_sizes: List[int],
+    *,
+    init_type: str = "spectra",
+    max_iter: int = 50,
+    tol: float = 1e-3,
+    non_negativity: bool = False
+) -> Dict[str, Any]:
+    """Resolve a row-wise augmented matrix (e.g. the MSroi of several runs) with PARAFAC2-ALS.
+
+    The spectra are shared by all runs, while the concentration profiles may change shape between runs,
+    e.g. with retention time shifts. Runs may have different numbers of scans.
+
+    Args:
+        d (ndarray): data matrix with dimensions (n_times x n_rois)
+        init (ndarray): initial estimate, spectra (n_components x n_rois) or concentration profiles (n_times x n_components)
+        run_sizes (List[int]): number of rows (scans) of each run, in the order the runs are stacked in `d`
+        init_type (str, optional): Kind of initial estimate; allowed values are `spectra` and `concentrations`. Defaults to "spectra".
+        max_iter (int, optional): Maximum number of iterations. Defaults to 50.
+        tol (float, optional): Convergence threshold on the relative change of the lack of fit between iterations. Defaults to 1e-3.
+        non_negativity (bool, optional): Fit the spectra and the run scales by non-negative least squares. Defaults to False.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        the same entries as `mcr_als`, `c` holding the concentration profiles of all runs stacked as in `d`
+    """
+
+def get_simplisma(
+    data: NDArray, n_components: int, *, noise: float = 5.0
+) -> Tuple[NDArray, NDArray, NDArray]:
+    """Find the purest variables (columns) of a data matrix with SIMPLISMA.
+
+    Applied to MSroi the purest ROIs give initial concentration profiles, applied to its transpose
+    the purest scans give initial spectra.
+
+    Args:
+        data (ndarray): data matrix with dimensions (n_rows x n_variables)
+        n_components (int): number of pure variables to find
+        noise (float, optional): offset added to the variable means, in percent of the largest mean. Defaults to 5.0.
+
+    Returns:
+    -------
+    purest : ndarray
+        indices of the purest variables, in order of selection
+    purity : ndarray
+        (n_components x n_variables) purity spectra used to select each variable
+    profiles : ndarray
+        (n_rows x n_components) columns of `data` of the purest variables
+    """
+
+def get_efa(data: NDArray, n_components: int) -> Dict[str, Any]:
+    """Forward and backward evolving factor analysis of the rows (scans) of a data matrix such as MSroi.
+
+    Args:
+        data (ndarray): data matrix with dimensions (n_times x n_rois)
+        n_components (int): number of components
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        `forward` and `backward` (n_times x n_components + 1) singular value evolution curves, `noise` level,
+        `appearance` and `disappearance` row of each component (None if it never rises above the noise) and
+        `c` (n_times x n_components) initial concentration profiles
+    """
+
+def get_moving_window_efa(
+    data: NDArray, window: int, *, n_values: int = 5, threshold: Optional[float] = None
+) -> Dict[str, Any]:
+    """Fixed size moving window evolving factor analysis of the rows (scans) of a data matrix.
+
+    Args:
+        data (ndarray): data matrix with dimensions (n_times x n_rois)
+        window (int): number of consecutive rows in each window
+        n_values (int, optional): number of singular values returned for each window. Defaults to 5.
+        threshold (float, optional): noise level of the singular values, estimated from the data if None. Defaults to None.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        `singular_values` (n_times - window + 1 x n_values), row `i` covering rows `i` to `i + window - 1`,
+        the `threshold` used and the `local_rank` of each window
+    """
+
+def estimate_rank(data: NDArray, *, max_rank: int = 10, n_splits: int = 7) -> Dict[str, Any]:
+    """Diagnostics for the number of MCR components of a data matrix such as MSroi, which is not centered.
+
+    Entry `k` of each curve refers to the model with `k + 1` components.
+
+    Args:
+        data (ndarray): data matrix with dimensions (n_times x n_rois)
+        max_rank (int, optional): largest number of components considered. Defaults to 10.
+        n_splits (int, optional): number of interleaved groups of rows left out for the cross-validated PRESS. Defaults to 7.
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        `singular_values`, `explained_variance` and `cumulative_variance` (%), the `ind` function,
+        the `broken_stick` expected variance (%), the cross-validated `press`, the rank given by
+        each criterion (`ind_rank`, `broken_stick_rank`, `press_rank`) and the `suggested` rank, their median
+    """
+
+def get_component_report(
+    c: NDArray,
+    st: NDArray,
+    mzroi: NDArray,
+    times_list: List[NDArray],
+    *,
+    n_top: int = 10,
+    path: Optional[str] = None
+) -> Dict[str, NDArray]:
+    """Map the components of an MCR model of MSroi back to its ROIs and m/z values.
+
+    Args:
+        c (ndarray): concentration profiles with dimensions (n_times x n_components)
+        st (ndarray): spectra with dimensions (n_components x n_rois)
+        mzroi (ndarray): m/z value of each ROI
+        times_list (List[ndarray]): scan times of each run, in the order the runs are stacked in `c`
+        n_top (int, optional): number of ROIs listed for each component. Defaults to 10.
+        path (str, optional): file the table is also written to, tab separated for `.tsv` files and comma separated otherwise. Defaults to None.
+
+    Returns:
+    -------
+    table : dict[str, ndarray]
+        one entry per component and ROI: `component`, `rank` of the ROI, `roi` (column of MSroi), `mz`,
+        `loading` relative to the largest one of the component, `selectivity` (share of the component in the
+        signal of the ROI) and `apex_time_run0`, `apex_time_run1`, ... the apex time of the component in each run
+    """
+
+def export_roi(
+    msroi: NDArray,
+    mzroi: NDArray,
+    times: NDArray,
+    path: str,
+    *,
+    runs: Optional[List[int]] = None,
+    metadata_path: Optional[str] = None,
+    delimiter: str = ",",
+    precision: Optional[int] = None,
+    transpose: bool = False
+) -> None:
+    """Write MSroi as delimited text with the m/z values of the ROIs as column headers and the time and run of each scan as row labels.
+
+    Args:
+        msroi (ndarray): ROI intensities with dimensions (n_times x n_rois)
+        mzroi (ndarray): m/z value of each ROI
+        times (ndarray): retention time of each row of MSroi
+        path (str): file MSroi is written to
+        runs (List[int], optional): run index of each row of MSroi. Defaults to None, a single run.
+        metadata_path (str, optional): file the table of ROI metadata is written to, with one line per ROI: `roi`, `mz`,
+            `n_scans` and `n_runs` in which it is found, `apex_time`, `apex_run`, `max_intensity` and `total_intensity`. Defaults to None.
+        delimiter (str, optional): single character separating the fields. Defaults to ",".
+        precision (int, optional): number of decimals of the values, all significant digits if None. Defaults to None.
+        transpose (bool, optional): write the tables transposed, one ROI per row. Defaults to False.
+    """
+
+def export_mat(
+    msroi: NDArray,
+    mzroi: NDArray,
+    times: NDArray,
+    path: str,
+    *,
+    run_sizes: Optional[List[int]] = None,
+    compress: bool = True
+) -> None:
+    """Write the ROIs to a MATLAB .mat file (Level 5) with the variable names of the MATLAB ROIMCR scripts: `MSroi`, `mzroi`
+    (row vector), `time` (column vector) and `nrows`, the number of rows of each run in `MSroi`.
+
+    Args:
+        msroi (ndarray): ROI intensities with dimensions (n_times x n_rois)
+        mzroi (ndarray): m/z value of each ROI
+        times (ndarray): retention time of each row of MSroi
+        path (str): file the ROIs are written to
+        run_sizes (List[int], optional): number of rows of each run in MSroi, adding up to its number of rows. Defaults to None, a single run.
+        compress (bool, optional): compress the variables with zlib, as MATLAB 7 does. Defaults to True.
+    """
+
+def import_mat(path: str) -> Dict[str, Any]:
+    """Read the ROIs from a MATLAB .mat file (Level 5, compressed or not), as written by `export_mat` or saved from MATLAB.
+
+    Only `MSroi` is required: `mzroi` defaults to the column indices, `time` to the row indices and `nrows` to a single run.
+
+    Args:
+        path (str): MAT-file to read
+
+    Returns:
+        Dict[str, Any]: `msroi`, `mzroi`, `times` and `run_sizes`
+    """
+
+def load_config(path: str) -> Dict[str, Any]:
+    """Read a processing configuration from a TOML or JSON file, rejecting unknown keys.
+
+    Args:
+        path (str): configuration file, `.toml` or `.json`; relative input files are taken from its directory
+
+    Returns:
+    -------
+    config : dict[str, Any]
+        the configuration with all the defaults filled in: `input`, `roi`, and the optional `threads`,
+        `filling` and `mcr` sections
+    """
+
+def run_config(path: str) -> Dict[str, Any]:
+    """Run the processing described by a TOML or JSON configuration file.
+
+    The input runs are loaded and augmented, their ROIs built, the missing scans of the ROI chromatograms
+    filled when the configuration has a `filling` section and MSroi resolved by MCR-ALS when it has an `mcr` section.
+
+    Args:
+        path (str): configuration file, `.toml` or `.json`
+
+    Returns:
+    -------
+    result : dict[str, Any]
+        `mzroi`, `msroi`, the retention `times` of the rows of MSroi, the number of scans of each run
+        (`run_sizes`) and the MCR-ALS result (`mcr`, see `mcr_als`) or None
     """
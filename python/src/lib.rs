@@ -1,13 +1,48 @@
-use std::path;
-
 use numpy::ndarray::{Array1, Array2};
 use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2, ToPyArray};
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
 use rayon::prelude::*;
+use std::path::Path;
+
+use roimcr::baseline::{correct_chromatogram, correct_msroi};
+use roimcr::config::{read_config, run_config as run_processing};
+use roimcr::efa::{efa, moving_window_efa};
+use roimcr::enums::{
+    BaselineMethod, ConstraintTarget, InitialEstimate, MzErrorType, MzRoiUpdater, NmfMethod,
+    NormalisationType, PeakDetectionMethod, SmoothingMethod, SparseLayout,
+};
+use roimcr::error::RoiError;
+use roimcr::export::{write_msroi, write_roi_metadata};
+use roimcr::mat::{read_roi_mat, write_roi_mat};
+use roimcr::mcr::bands::mcr_bands;
+use roimcr::mcr::constraints::{Equality, McrConstraints};
+use roimcr::mcr::weighted::{roi_weights, uncertainty_weights};
+use roimcr::mcr::{figures_of_merit, figures_of_merit_sparse, mcr_als_sparse};
+use roimcr::peaks::detect_peaks;
+use roimcr::rank;
+use roimcr::report::{component_report, write_component_report};
+use roimcr::simplisma::simplisma;
+use roimcr::smoothing::{smooth_msroi, smooth_msroi_sparse};
+use roimcr::structs::data::{
+    ComponentRoi, ComponentSummary, McrFiguresOfMerit, McrResult, RoiMatrices, SparseMatrix,
+};
+use roimcr::structs::options::{
+    AugmentedParams, ConstraintParams, ExportParams, McrParams, PeakParams, RoiParams, WeightParams,
+};
+use roimcr::{compute_roi, compute_roi_parallel, compute_roi_sparse, load_data};
 
-use roimcr::enums::{MzErrorType, MzRoiUpdater};
-use roimcr::structs::options::RoiParams;
-use roimcr::{compute_roi, load_data};
+#[cfg(feature = "arrow")]
+use arrow_array::ffi::{to_ffi, FFI_ArrowSchema};
+#[cfg(feature = "arrow")]
+use arrow_array::{Array, RecordBatch, StructArray};
+#[cfg(feature = "arrow")]
+use pyo3::types::PyCapsule;
+#[cfg(feature = "arrow")]
+use roimcr::export::roi_statistics;
+#[cfg(feature = "arrow")]
+use roimcr::tables::{check_runs, peak_table, roi_summary_table, roi_table};
 
 #[pyfunction]
 /// Load a file .
@@ -25,19 +60,11 @@ fn import_data<'py>(
 
     let peaks_list = peaks_list
         .iter()
-        .cloned()
-        .map(|peaks| {
-            peaks
-                .iter()
-                .cloned()
-                .map(|scan| scan.to_pyarray(py))
-                .collect()
-        })
+        .map(|peaks| peaks.iter().map(|scan| scan.to_pyarray(py)).collect())
         .collect();
 
     let times_list = times_list
         .iter()
-        .cloned()
         .map(|times| times.to_pyarray(py))
         .collect();
     (peaks_list, times_list)
@@ -50,10 +77,13 @@ fn import_data<'py>(
     tol = "0.5",
     tol_units = "\"da\"",
     update_method = "\"mean\"",
-    min_occ = "1"
+    min_occ = "1",
+    n_threads = "1",
+    sparse = "None"
 )]
-fn get_roi<'py>(
-    py: Python<'py>,
+#[allow(clippy::too_many_arguments)]
+fn get_roi(
+    py: Python<'_>,
     peaks_list: Vec<Vec<PyReadonlyArray2<'_, f64>>>,
     times_list: Vec<PyReadonlyArray1<'_, f64>>,
     threshold: f64,
@@ -62,7 +92,39 @@ fn get_roi<'py>(
     tol_units: &str,
     update_method: &str,
     min_occ: u32,
-) -> (&'py PyArray1<f64>, &'py PyArray2<f64>) {
+    n_threads: usize,
+    sparse: Option<&str>,
+) -> PyResult<PyObject> {
+    let settings = roi_params(threshold, t_factor, tol, tol_units, update_method, min_occ);
+    let (peaks_list, times_list) = runs_from_py(&peaks_list, &times_list);
+
+    if let Some(layout) = sparse {
+        let layout = SparseLayout::from_name(layout).ok_or_else(|| {
+            PyValueError::new_err("Unknown sparse layout! Allowed layouts are: 'csr' and 'csc'")
+        })?;
+        let (mzroi, msroi, _) =
+            compute_roi_sparse(&peaks_list, &times_list, settings, layout, n_threads);
+        return Ok((mzroi.into_pyarray(py), sparse_to_py(py, msroi)).into_py(py));
+    }
+
+    let (mzroi, msroi, _) = if n_threads == 1 {
+        compute_roi(&peaks_list, &times_list, settings)
+    } else {
+        compute_roi_parallel(&peaks_list, &times_list, settings, n_threads)
+    };
+
+    Ok((mzroi.into_pyarray(py), msroi.into_pyarray(py)).into_py(py))
+}
+
+/// ROI settings from the keyword arguments of `get_roi`.
+fn roi_params(
+    threshold: f64,
+    t_factor: f64,
+    tol: f64,
+    tol_units: &str,
+    update_method: &str,
+    min_occ: u32,
+) -> RoiParams {
     let mzroi_updater = match update_method.to_lowercase().as_str() {
         "mean" => MzRoiUpdater::Mean,
         "max" => MzRoiUpdater::Max,
@@ -81,15 +143,22 @@ fn get_roi<'py>(
         }
     };
 
-    let settings = RoiParams {
+    RoiParams {
         threshold,
         t_factor,
         mz_error,
         mzroi_updater,
         min_occ,
-    };
+    }
+}
 
-    let peaks_list: Vec<Vec<Array2<f64>>> = peaks_list
+/// Owned copies of the peaks and times of every run.
+#[allow(clippy::type_complexity)]
+fn runs_from_py(
+    peaks_list: &[Vec<PyReadonlyArray2<'_, f64>>],
+    times_list: &[PyReadonlyArray1<'_, f64>],
+) -> (Vec<Vec<Array2<f64>>>, Vec<Array1<f64>>) {
+    let peaks_list = peaks_list
         .iter()
         .map(|run| {
             run.iter()
@@ -105,7 +174,7 @@ fn get_roi<'py>(
         })
         .collect();
 
-    let times_list: Vec<Array1<f64>> = times_list
+    let times_list = times_list
         .iter()
         .map(|times| {
             times
@@ -116,10 +185,1103 @@ fn get_roi<'py>(
                 .to_owned()
         })
         .collect();
+    (peaks_list, times_list)
+}
+
+#[cfg(feature = "arrow")]
+#[pyfunction]
+/// Table of the peaks of every scan, as a `pyarrow.Table`.
+fn get_peak_table(
+    py: Python<'_>,
+    peaks_list: Vec<Vec<PyReadonlyArray2<'_, f64>>>,
+    times_list: Vec<PyReadonlyArray1<'_, f64>>,
+) -> PyResult<PyObject> {
+    let (peaks_list, times_list) = runs_from_py(&peaks_list, &times_list);
+    table_to_py(
+        py,
+        peak_table(&peaks_list, &times_list).map_err(value_error)?,
+    )
+}
+
+#[cfg(feature = "arrow")]
+#[pyfunction(
+    py_args = "*",
+    threshold = "1000.0",
+    t_factor = "1.0",
+    tol = "0.5",
+    tol_units = "\"da\"",
+    update_method = "\"mean\"",
+    min_occ = "1",
+    n_threads = "1"
+)]
+#[allow(clippy::too_many_arguments)]
+/// Long format table of the ROI peaks and table of ROI summaries, as `pyarrow.Table`.
+fn get_roi_tables(
+    py: Python<'_>,
+    peaks_list: Vec<Vec<PyReadonlyArray2<'_, f64>>>,
+    times_list: Vec<PyReadonlyArray1<'_, f64>>,
+    threshold: f64,
+    t_factor: f64,
+    tol: f64,
+    tol_units: &str,
+    update_method: &str,
+    min_occ: u32,
+    n_threads: usize,
+) -> PyResult<(PyObject, PyObject)> {
+    let settings = roi_params(threshold, t_factor, tol, tol_units, update_method, min_occ);
+    let (peaks_list, times_list) = runs_from_py(&peaks_list, &times_list);
+    check_runs(&peaks_list, &times_list).map_err(value_error)?;
+
+    let (mzroi, msroi, roicell) = if n_threads == 1 {
+        compute_roi(&peaks_list, &times_list, settings)
+    } else {
+        compute_roi_parallel(&peaks_list, &times_list, settings, n_threads)
+    };
+
+    let times = Array1::from_iter(times_list.iter().flatten().cloned());
+    let runs = Array1::from_iter(
+        times_list
+            .iter()
+            .enumerate()
+            .flat_map(|(run, times)| std::iter::repeat_n(run, times.len())),
+    );
+    let statistics = roi_statistics(&msroi, &mzroi, &times, &runs).map_err(value_error)?;
+
+    Ok((
+        table_to_py(py, roi_table(&roicell, &times_list).map_err(value_error)?)?,
+        table_to_py(py, roi_summary_table(&statistics).map_err(value_error)?)?,
+    ))
+}
+
+/// Capsule names of the Arrow PyCapsule interface.
+#[cfg(feature = "arrow")]
+const SCHEMA_CAPSULE: &std::ffi::CStr = c"arrow_schema";
+#[cfg(feature = "arrow")]
+const ARRAY_CAPSULE: &std::ffi::CStr = c"arrow_array";
+
+/// Record batch exported through the Arrow PyCapsule interface, which pyarrow and other
+/// Arrow libraries import without copying its buffers.
+#[cfg(feature = "arrow")]
+#[pyclass(module = "pyroimcr")]
+struct ArrowBatch {
+    batch: RecordBatch,
+}
+
+#[cfg(feature = "arrow")]
+#[pymethods]
+impl ArrowBatch {
+    fn __arrow_c_schema__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let schema = FFI_ArrowSchema::try_from(self.batch.schema().as_ref())
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyCapsule::new(py, schema, SCHEMA_CAPSULE)?.into_py(py))
+    }
+
+    /// The batch is exported with its own schema whatever the `requested_schema`, as the
+    /// interface allows.
+    #[allow(unused_variables)]
+    #[args(requested_schema = "None")]
+    fn __arrow_c_array__(
+        &self,
+        py: Python<'_>,
+        requested_schema: Option<PyObject>,
+    ) -> PyResult<(PyObject, PyObject)> {
+        let data = StructArray::from(self.batch.clone()).into_data();
+        let (array, schema) = to_ffi(&data).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok((
+            PyCapsule::new(py, schema, SCHEMA_CAPSULE)?.into_py(py),
+            PyCapsule::new(py, array, ARRAY_CAPSULE)?.into_py(py),
+        ))
+    }
+}
+
+/// `pyarrow.Table` sharing the buffers of a record batch. Needs pyarrow 14 or later.
+#[cfg(feature = "arrow")]
+fn table_to_py(py: Python<'_>, batch: RecordBatch) -> PyResult<PyObject> {
+    let pyarrow = py.import("pyarrow")?;
+    let batch = pyarrow.call_method1("record_batch", (ArrowBatch { batch },))?;
+    let table = pyarrow
+        .getattr("Table")?
+        .call_method1("from_batches", (vec![batch],))?;
+    Ok(table.into_py(py))
+}
+
+/// Compressed arrays and shape of a sparse matrix, as taken by the `scipy.sparse`
+/// constructors: `csr_matrix(*triplet)` or `csc_matrix(*triplet)`.
+fn sparse_to_py(py: Python<'_>, matrix: SparseMatrix) -> PyObject {
+    let to_index = |values: Vec<usize>| {
+        values
+            .into_iter()
+            .map(|value| value as i64)
+            .collect::<Vec<_>>()
+    };
+    (
+        (
+            matrix.data.into_pyarray(py),
+            to_index(matrix.indices).into_pyarray(py),
+            to_index(matrix.indptr).into_pyarray(py),
+        ),
+        matrix.shape,
+    )
+        .into_py(py)
+}
+
+/// Whether `matrix` looks like a `scipy.sparse` matrix.
+fn is_sparse(matrix: &PyAny) -> bool {
+    matrix.hasattr("indptr").unwrap_or(false)
+}
+
+/// A `scipy.sparse` CSR or CSC matrix, read through its `format`, `shape`, `data`,
+/// `indices` and `indptr` attributes.
+fn sparse_from_py(matrix: &PyAny) -> PyResult<SparseMatrix> {
+    let format: String = matrix.getattr("format")?.extract()?;
+    let layout = SparseLayout::from_name(&format).ok_or_else(|| {
+        PyValueError::new_err("Only CSR and CSC sparse matrices are supported, use .tocsr()")
+    })?;
+    let array = |name: &str, dtype: &str| matrix.getattr(name)?.call_method1("astype", (dtype,));
+    let indices = |name: &str| -> PyResult<Vec<usize>> {
+        array(name, "int64")?
+            .extract::<PyReadonlyArray1<i64>>()?
+            .as_array()
+            .iter()
+            .map(|&index| {
+                usize::try_from(index).map_err(|_| {
+                    PyValueError::new_err(format!(
+                        "Negative value {} in the {} of the sparse matrix",
+                        index, name
+                    ))
+                })
+            })
+            .collect()
+    };
+
+    SparseMatrix::new(
+        matrix.getattr("shape")?.extract()?,
+        layout,
+        indices("indptr")?,
+        indices("indices")?,
+        array("data", "float64")?
+            .extract::<PyReadonlyArray1<f64>>()?
+            .to_vec()?,
+    )
+    .map_err(value_error)
+}
+
+#[pyfunction(
+    py_args = "*",
+    method = "\"savgol\"",
+    window = "11",
+    order = "3",
+    sigma = "2.0"
+)]
+fn smooth_roi(
+    py: Python<'_>,
+    msroi: &PyAny,
+    method: &str,
+    window: usize,
+    order: usize,
+    sigma: f64,
+) -> PyResult<PyObject> {
+    let method =
+        match method.to_lowercase().as_str() {
+            "savgol" => SmoothingMethod::SavitzkyGolay(window, order),
+            "mean" => SmoothingMethod::MovingAverage(window),
+            "gaussian" => SmoothingMethod::Gaussian(sigma),
+            _ => return Err(PyValueError::new_err(
+                "Unknown smoothing method! Allowed methods are: 'savgol', 'mean' and 'gaussian'",
+            )),
+        };
+
+    if is_sparse(msroi) {
+        let msroi = sparse_from_py(msroi)?;
+        let smoothed = smooth_msroi_sparse(&msroi, &method).map_err(value_error)?;
+        return Ok(sparse_to_py(py, smoothed));
+    }
+    let msroi: PyReadonlyArray2<f64> = msroi.extract()?;
+    Ok(smooth_msroi(&msroi.as_array().to_owned(), &method)
+        .map_err(value_error)?
+        .into_pyarray(py)
+        .into_py(py))
+}
+
+fn parse_baseline_method(
+    method: &str,
+    lam: f64,
+    p: f64,
+    window: usize,
+    order: usize,
+) -> PyResult<BaselineMethod> {
+    match method.to_lowercase().as_str() {
+        "als" => Ok(BaselineMethod::AsymmetricLeastSquares(lam, p)),
+        "rolling_min" => Ok(BaselineMethod::RollingMinimum(window)),
+        "poly" => Ok(BaselineMethod::Polynomial(order)),
+        _ => Err(PyValueError::new_err(
+            "Unknown baseline method! Allowed methods are: 'als', 'rolling_min' and 'poly'",
+        )),
+    }
+}
+
+#[pyfunction(
+    py_args = "*",
+    method = "\"als\"",
+    lam = "1e5",
+    p = "0.01",
+    window = "51",
+    order = "3"
+)]
+fn correct_baseline<'py>(
+    py: Python<'py>,
+    msroi: PyReadonlyArray2<'_, f64>,
+    method: &str,
+    lam: f64,
+    p: f64,
+    window: usize,
+    order: usize,
+) -> PyResult<(&'py PyArray2<f64>, &'py PyArray2<f64>)> {
+    let method = parse_baseline_method(method, lam, p, window, order)?;
+    let (corrected, baseline) =
+        correct_msroi(&msroi.as_array().to_owned(), &method).map_err(value_error)?;
+
+    Ok((corrected.into_pyarray(py), baseline.into_pyarray(py)))
+}
 
-    let (mzroi, msroi, _) = compute_roi(&peaks_list, &times_list, settings);
+#[pyfunction(
+    py_args = "*",
+    method = "\"als\"",
+    lam = "1e5",
+    p = "0.01",
+    window = "51",
+    order = "3"
+)]
+fn correct_tic_baseline<'py>(
+    py: Python<'py>,
+    tic: PyReadonlyArray1<'_, f64>,
+    method: &str,
+    lam: f64,
+    p: f64,
+    window: usize,
+    order: usize,
+) -> PyResult<(&'py PyArray1<f64>, &'py PyArray1<f64>)> {
+    let method = parse_baseline_method(method, lam, p, window, order)?;
+    let (corrected, baseline) =
+        correct_chromatogram(tic.as_array(), &method).map_err(value_error)?;
+
+    Ok((corrected.into_pyarray(py), baseline.into_pyarray(py)))
+}
+
+#[pyfunction(
+    py_args = "*",
+    method = "\"prominence\"",
+    min_prominence = "0.0",
+    min_scale = "1.0",
+    max_scale = "16.0",
+    min_snr = "3.0"
+)]
+#[allow(clippy::too_many_arguments)]
+fn detect_roi_peaks<'py>(
+    py: Python<'py>,
+    msroi: PyReadonlyArray2<'_, f64>,
+    times: PyReadonlyArray1<'_, f64>,
+    method: &str,
+    min_prominence: f64,
+    min_scale: f64,
+    max_scale: f64,
+    min_snr: f64,
+) -> PyResult<&'py PyDict> {
+    let method = match method.to_lowercase().as_str() {
+        "prominence" => PeakDetectionMethod::LocalMaxima(min_prominence),
+        "cwt" => PeakDetectionMethod::ContinuousWavelet(min_scale, max_scale),
+        _ => {
+            return Err(PyValueError::new_err(
+                "Unknown peak detection method! Allowed methods are: 'prominence' and 'cwt'",
+            ))
+        }
+    };
+    let params = PeakParams { method, min_snr };
+
+    let peaks = detect_peaks(
+        &msroi.as_array().to_owned(),
+        &times.as_array().to_owned(),
+        &params,
+    )
+    .map_err(value_error)?;
+
+    let features = PyDict::new(py);
+    features.set_item(
+        "roi",
+        peaks
+            .iter()
+            .map(|p| p.roi)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "apex",
+        peaks
+            .iter()
+            .map(|p| p.apex)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "start",
+        peaks
+            .iter()
+            .map(|p| p.start)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "end",
+        peaks
+            .iter()
+            .map(|p| p.end)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "apex_time",
+        peaks
+            .iter()
+            .map(|p| p.apex_time)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "height",
+        peaks
+            .iter()
+            .map(|p| p.height)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "area",
+        peaks
+            .iter()
+            .map(|p| p.area)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    features.set_item(
+        "snr",
+        peaks
+            .iter()
+            .map(|p| p.snr)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    Ok(features)
+}
+
+#[pyfunction(
+    py_args = "*",
+    init_type = "\"spectra\"",
+    max_iter = "50",
+    tol = "1e-3",
+    non_negativity = "None",
+    nnls = "false",
+    unimodality = "None",
+    closure = "None",
+    normalisation = "None",
+    c_mask = "None",
+    st_mask = "None",
+    mask_upper_bound = "false",
+    bands = "false"
+)]
+#[allow(clippy::too_many_arguments)]
+fn mcr_als<'py>(
+    py: Python<'py>,
+    d: &PyAny,
+    init: PyReadonlyArray2<'_, f64>,
+    init_type: &str,
+    max_iter: usize,
+    tol: f64,
+    non_negativity: Option<&str>,
+    nnls: bool,
+    unimodality: Option<f64>,
+    closure: Option<f64>,
+    normalisation: Option<&str>,
+    c_mask: Option<PyReadonlyArray2<'_, f64>>,
+    st_mask: Option<PyReadonlyArray2<'_, f64>>,
+    mask_upper_bound: bool,
+    bands: bool,
+) -> PyResult<&'py PyDict> {
+    let initial = parse_initial_estimate(init, init_type)?;
+    let params = McrParams { max_iter, tol };
+    let constraints = build_constraints(
+        non_negativity,
+        nnls,
+        unimodality,
+        closure,
+        normalisation,
+        c_mask.map(|mask| mask.as_array().to_owned()),
+        st_mask.map(|mask| mask.as_array().t().to_owned()),
+        mask_upper_bound,
+    )?;
+
+    if is_sparse(d) {
+        if bands {
+            return Err(PyValueError::new_err("MCR-BANDS needs a dense data matrix"));
+        }
+        let d = sparse_from_py(d)?;
+        let result = mcr_als_sparse(&d, &initial, &params, &constraints).map_err(value_error)?;
+        let merit = figures_of_merit_sparse(&d, &result).map_err(value_error)?;
+        let output = mcr_merit_to_dict(py, merit, result)?;
+        output.set_item("residuals", py.None())?;
+        return Ok(output);
+    }
+
+    let d: PyReadonlyArray2<f64> = d.extract()?;
+    let d = d.as_array().to_owned();
+    let result = roimcr::mcr::mcr_als(&d, &initial, &params, &constraints).map_err(value_error)?;
+    let feasible = bands
+        .then(|| mcr_bands(&result, &constraints))
+        .transpose()
+        .map_err(value_error)?;
+
+    let output = mcr_result_to_dict(py, &d, result)?;
+    if let Some(feasible) = feasible {
+        output.set_item("scf_max", feasible.scf_max.into_pyarray(py))?;
+        output.set_item("scf_min", feasible.scf_min.into_pyarray(py))?;
+        output.set_item("c_max", feasible.c_max.into_pyarray(py))?;
+        output.set_item("c_min", feasible.c_min.into_pyarray(py))?;
+        output.set_item("st_max", feasible.st_max.into_pyarray(py))?;
+        output.set_item("st_min", feasible.st_min.into_pyarray(py))?;
+    }
+    Ok(output)
+}
+
+#[pyfunction(
+    py_args = "*",
+    init_type = "\"spectra\"",
+    max_iter = "50",
+    tol = "1e-3",
+    non_negativity = "None",
+    nnls = "false",
+    unimodality = "None",
+    closure = "None",
+    normalisation = "None",
+    c_mask = "None",
+    st_mask = "None",
+    mask_upper_bound = "false",
+    presence = "None",
+    trilinearity = "false"
+)]
+#[allow(clippy::too_many_arguments)]
+fn mcr_als_augmented<'py>(
+    py: Python<'py>,
+    d: PyReadonlyArray2<'_, f64>,
+    init: PyReadonlyArray2<'_, f64>,
+    run_sizes: Vec<usize>,
+    init_type: &str,
+    max_iter: usize,
+    tol: f64,
+    non_negativity: Option<&str>,
+    nnls: bool,
+    unimodality: Option<f64>,
+    closure: Option<f64>,
+    normalisation: Option<&str>,
+    c_mask: Option<PyReadonlyArray2<'_, f64>>,
+    st_mask: Option<PyReadonlyArray2<'_, f64>>,
+    mask_upper_bound: bool,
+    presence: Option<PyReadonlyArray2<'_, bool>>,
+    trilinearity: bool,
+) -> PyResult<&'py PyDict> {
+    let initial = parse_initial_estimate(init, init_type)?;
+    let params = McrParams { max_iter, tol };
+    let constraints = build_constraints(
+        non_negativity,
+        nnls,
+        unimodality,
+        closure,
+        normalisation,
+        c_mask.map(|mask| mask.as_array().to_owned()),
+        st_mask.map(|mask| mask.as_array().t().to_owned()),
+        mask_upper_bound,
+    )?;
+    let options = AugmentedParams {
+        presence: presence.map(|presence| presence.as_array().to_owned()),
+        trilinearity,
+    };
+
+    let d = d.as_array().to_owned();
+    let result = roimcr::mcr::augmented::mcr_als_augmented(
+        &d,
+        &run_sizes,
+        &initial,
+        &params,
+        constraints,
+        &options,
+    )
+    .map_err(value_error)?;
+
+    let output = mcr_result_to_dict(py, &d, result.mcr)?;
+    output.set_item("areas", result.areas.into_pyarray(py))?;
+    Ok(output)
+}
+
+#[pyfunction(
+    py_args = "*",
+    weights = "None",
+    uncertainty = "None",
+    noise = "None",
+    proportional = "0.1",
+    missing = "0.1",
+    init_type = "\"spectra\"",
+    max_iter = "50",
+    tol = "1e-3",
+    non_negativity = "None",
+    nnls = "false",
+    unimodality = "None",
+    closure = "None",
+    normalisation = "None",
+    c_mask = "None",
+    st_mask = "None",
+    mask_upper_bound = "false"
+)]
+#[allow(clippy::too_many_arguments)]
+fn mcr_als_weighted<'py>(
+    py: Python<'py>,
+    d: PyReadonlyArray2<'_, f64>,
+    init: PyReadonlyArray2<'_, f64>,
+    weights: Option<PyReadonlyArray2<'_, f64>>,
+    uncertainty: Option<PyReadonlyArray2<'_, f64>>,
+    noise: Option<f64>,
+    proportional: f64,
+    missing: f64,
+    init_type: &str,
+    max_iter: usize,
+    tol: f64,
+    non_negativity: Option<&str>,
+    nnls: bool,
+    unimodality: Option<f64>,
+    closure: Option<f64>,
+    normalisation: Option<&str>,
+    c_mask: Option<PyReadonlyArray2<'_, f64>>,
+    st_mask: Option<PyReadonlyArray2<'_, f64>>,
+    mask_upper_bound: bool,
+) -> PyResult<&'py PyDict> {
+    let d = d.as_array().to_owned();
+    let weights = match (weights, uncertainty) {
+        (Some(weights), None) => weights.as_array().to_owned(),
+        (None, Some(uncertainty)) => {
+            uncertainty_weights(&uncertainty.as_array().to_owned()).map_err(value_error)?
+        }
+        (None, None) => roi_weights(&d, &weight_params(&d, noise, proportional, missing))
+            .map_err(value_error)?,
+        (Some(_), Some(_)) => {
+            return Err(PyValueError::new_err(
+                "Pass either weights or uncertainty, not both",
+            ))
+        }
+    };
+    let initial = parse_initial_estimate(init, init_type)?;
+    let params = McrParams { max_iter, tol };
+    let constraints = build_constraints(
+        non_negativity,
+        nnls,
+        unimodality,
+        closure,
+        normalisation,
+        c_mask.map(|mask| mask.as_array().to_owned()),
+        st_mask.map(|mask| mask.as_array().t().to_owned()),
+        mask_upper_bound,
+    )?;
+
+    let result =
+        roimcr::mcr::weighted::mcr_als_weighted(&d, &weights, &initial, &params, &constraints)
+            .map_err(value_error)?;
+
+    let output = mcr_result_to_dict(py, &d, result)?;
+    output.set_item("weights", weights.into_pyarray(py))?;
+    Ok(output)
+}
+
+#[pyfunction(py_args = "*", noise = "None", proportional = "0.1", missing = "0.1")]
+fn get_roi_weights<'py>(
+    py: Python<'py>,
+    msroi: PyReadonlyArray2<'_, f64>,
+    noise: Option<f64>,
+    proportional: f64,
+    missing: f64,
+) -> PyResult<&'py PyArray2<f64>> {
+    let msroi = msroi.as_array().to_owned();
+    let params = weight_params(&msroi, noise, proportional, missing);
+    Ok(roi_weights(&msroi, &params)
+        .map_err(value_error)?
+        .into_pyarray(py))
+}
+
+/// Noise model of `roi_weights`, the noise level defaulting to the smallest intensity
+/// recorded in `msroi`, close to the ROI threshold.
+fn weight_params(
+    msroi: &Array2<f64>,
+    noise: Option<f64>,
+    proportional: f64,
+    missing: f64,
+) -> WeightParams {
+    let noise = noise.unwrap_or_else(|| {
+        msroi
+            .iter()
+            .cloned()
+            .filter(|&x| x > 0.0)
+            .fold(f64::INFINITY, f64::min)
+    });
+    WeightParams {
+        noise: if noise.is_finite() { noise } else { 1.0 },
+        proportional,
+        missing,
+    }
+}
+
+#[pyfunction(
+    py_args = "*",
+    init_type = "\"spectra\"",
+    method = "\"hals\"",
+    max_iter = "50",
+    tol = "1e-3"
+)]
+fn nmf<'py>(
+    py: Python<'py>,
+    d: PyReadonlyArray2<'_, f64>,
+    init: PyReadonlyArray2<'_, f64>,
+    init_type: &str,
+    method: &str,
+    max_iter: usize,
+    tol: f64,
+) -> PyResult<&'py PyDict> {
+    let initial = parse_initial_estimate(init, init_type)?;
+    let method = match method.to_lowercase().as_str() {
+        "mu" => NmfMethod::MultiplicativeUpdate,
+        "hals" => NmfMethod::Hals,
+        _ => {
+            return Err(PyValueError::new_err(
+                "Unknown NMF method! Allowed methods are: 'mu' and 'hals'",
+            ))
+        }
+    };
+    let params = McrParams { max_iter, tol };
+
+    let d = d.as_array().to_owned();
+    let result = roimcr::nmf::nmf(&d, &initial, method, &params).map_err(value_error)?;
+
+    mcr_result_to_dict(py, &d, result)
+}
+
+#[pyfunction(
+    py_args = "*",
+    init_type = "\"spectra\"",
+    max_iter = "50",
+    tol = "1e-3",
+    non_negativity = "false"
+)]
+#[allow(clippy::too_many_arguments)]
+fn parafac2<'py>(
+    py: Python<'py>,
+    d: PyReadonlyArray2<'_, f64>,
+    init: PyReadonlyArray2<'_, f64>,
+    run_sizes: Vec<usize>,
+    init_type: &str,
+    max_iter: usize,
+    tol: f64,
+    non_negativity: bool,
+) -> PyResult<&'py PyDict> {
+    let initial = parse_initial_estimate(init, init_type)?;
+    let params = McrParams { max_iter, tol };
+
+    let d = d.as_array().to_owned();
+    let result = roimcr::parafac2::parafac2(&d, &run_sizes, &initial, &params, non_negativity)
+        .map_err(value_error)?;
+
+    mcr_result_to_dict(py, &d, result)
+}
+
+fn parse_initial_estimate(
+    init: PyReadonlyArray2<'_, f64>,
+    init_type: &str,
+) -> PyResult<InitialEstimate> {
+    match init_type.to_lowercase().as_str() {
+        "spectra" => Ok(InitialEstimate::Spectra(init.as_array().to_owned())),
+        "concentrations" => Ok(InitialEstimate::Concentrations(init.as_array().to_owned())),
+        _ => Err(PyValueError::new_err(
+            "Unknown initial estimate type! Allowed types are: 'spectra' and 'concentrations'",
+        )),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_constraints(
+    non_negativity: Option<&str>,
+    nnls: bool,
+    unimodality: Option<f64>,
+    closure: Option<f64>,
+    normalisation: Option<&str>,
+    c_mask: Option<Array2<f64>>,
+    st_mask: Option<Array2<f64>>,
+    mask_upper_bound: bool,
+) -> PyResult<McrConstraints> {
+    let non_negativity = non_negativity
+        .map(|target| match target.to_lowercase().as_str() {
+            "c" => Ok(ConstraintTarget::C),
+            "st" => Ok(ConstraintTarget::St),
+            "both" => Ok(ConstraintTarget::Both),
+            _ => Err(PyValueError::new_err(
+                "Unknown non-negativity target! Allowed targets are: 'c', 'st' and 'both'",
+            )),
+        })
+        .transpose()?;
+    let normalisation = normalisation
+        .map(|kind| match kind.to_lowercase().as_str() {
+            "max" => Ok(NormalisationType::Max),
+            "length" => Ok(NormalisationType::Length),
+            "area" => Ok(NormalisationType::Area),
+            _ => Err(PyValueError::new_err(
+                "Unknown normalisation! Allowed normalisations are: 'max', 'length' and 'area'",
+            )),
+        })
+        .transpose()?;
+    let mut constraints = McrConstraints::from_params(&ConstraintParams {
+        non_negativity,
+        nnls,
+        unimodality,
+        closure,
+        normalisation,
+    });
+
+    // Equality masks come before the other constraints
+    if let Some(mask) = c_mask {
+        constraints.c.insert(
+            0,
+            Box::new(Equality {
+                mask,
+                upper_bound: mask_upper_bound,
+            }),
+        );
+    }
+    if let Some(mask) = st_mask {
+        constraints.st.insert(
+            0,
+            Box::new(Equality {
+                mask,
+                upper_bound: mask_upper_bound,
+            }),
+        );
+    }
+    Ok(constraints)
+}
+
+fn mcr_result_to_dict<'py>(
+    py: Python<'py>,
+    d: &Array2<f64>,
+    result: McrResult,
+) -> PyResult<&'py PyDict> {
+    let merit = figures_of_merit(d, &result).map_err(value_error)?;
+    mcr_merit_to_dict(py, merit, result)
+}
+
+fn mcr_merit_to_dict(
+    py: Python<'_>,
+    merit: McrFiguresOfMerit,
+    result: McrResult,
+) -> PyResult<&PyDict> {
+    let output = PyDict::new(py);
+    output.set_item("c", result.c.into_pyarray(py))?;
+    output.set_item("st", result.st.into_pyarray(py))?;
+    output.set_item("residuals", result.residuals.into_pyarray(py))?;
+    output.set_item("lof", result.lof)?;
+    output.set_item("r2", result.r2)?;
+    output.set_item("r2_components", merit.r2_components.into_pyarray(py))?;
+    output.set_item("contributions", merit.contributions.into_pyarray(py))?;
+    output.set_item("lof_history", result.lof_history.into_pyarray(py))?;
+    output.set_item("n_iter", result.n_iter)?;
+    output.set_item("converged", result.converged)?;
+    Ok(output)
+}
+
+#[pyfunction(py_args = "*", noise = "5.0")]
+fn get_simplisma<'py>(
+    py: Python<'py>,
+    data: PyReadonlyArray2<'_, f64>,
+    n_components: usize,
+    noise: f64,
+) -> PyResult<(&'py PyArray1<usize>, &'py PyArray2<f64>, &'py PyArray2<f64>)> {
+    let result =
+        simplisma(&data.as_array().to_owned(), n_components, noise).map_err(value_error)?;
+
+    Ok((
+        result.purest.into_pyarray(py),
+        result.purity.into_pyarray(py),
+        result.profiles.into_pyarray(py),
+    ))
+}
+
+#[pyfunction]
+fn get_efa<'py>(
+    py: Python<'py>,
+    data: PyReadonlyArray2<'_, f64>,
+    n_components: usize,
+) -> PyResult<&'py PyDict> {
+    let result = efa(&data.as_array().to_owned(), n_components).map_err(value_error)?;
+
+    let output = PyDict::new(py);
+    output.set_item("forward", result.forward.into_pyarray(py))?;
+    output.set_item("backward", result.backward.into_pyarray(py))?;
+    output.set_item("noise", result.noise)?;
+    output.set_item("appearance", result.appearance)?;
+    output.set_item("disappearance", result.disappearance)?;
+    output.set_item("c", result.c.into_pyarray(py))?;
+    Ok(output)
+}
+
+#[pyfunction(py_args = "*", n_values = "5", threshold = "None")]
+fn get_moving_window_efa<'py>(
+    py: Python<'py>,
+    data: PyReadonlyArray2<'_, f64>,
+    window: usize,
+    n_values: usize,
+    threshold: Option<f64>,
+) -> PyResult<&'py PyDict> {
+    let result = moving_window_efa(&data.as_array().to_owned(), window, n_values, threshold)
+        .map_err(value_error)?;
+
+    let output = PyDict::new(py);
+    output.set_item("singular_values", result.singular_values.into_pyarray(py))?;
+    output.set_item("threshold", result.threshold)?;
+    output.set_item("local_rank", result.local_rank.into_pyarray(py))?;
+    Ok(output)
+}
+
+#[pyfunction(py_args = "*", max_rank = "10", n_splits = "7")]
+fn estimate_rank<'py>(
+    py: Python<'py>,
+    data: PyReadonlyArray2<'_, f64>,
+    max_rank: usize,
+    n_splits: usize,
+) -> PyResult<&'py PyDict> {
+    let result = rank::estimate_rank(&data.as_array().to_owned(), max_rank, n_splits)
+        .map_err(value_error)?;
+
+    let output = PyDict::new(py);
+    output.set_item("singular_values", result.singular_values.into_pyarray(py))?;
+    output.set_item(
+        "explained_variance",
+        result.explained_variance.into_pyarray(py),
+    )?;
+    output.set_item(
+        "cumulative_variance",
+        result.cumulative_variance.into_pyarray(py),
+    )?;
+    output.set_item("ind", result.ind.into_pyarray(py))?;
+    output.set_item("broken_stick", result.broken_stick.into_pyarray(py))?;
+    output.set_item("press", result.press.into_pyarray(py))?;
+    output.set_item("ind_rank", result.ind_rank)?;
+    output.set_item("broken_stick_rank", result.broken_stick_rank)?;
+    output.set_item("press_rank", result.press_rank)?;
+    output.set_item("suggested", result.suggested)?;
+    Ok(output)
+}
+
+#[pyfunction(py_args = "*", n_top = "10", path = "None")]
+fn get_component_report<'py>(
+    py: Python<'py>,
+    c: PyReadonlyArray2<'_, f64>,
+    st: PyReadonlyArray2<'_, f64>,
+    mzroi: PyReadonlyArray1<'_, f64>,
+    times_list: Vec<PyReadonlyArray1<'_, f64>>,
+    n_top: usize,
+    path: Option<&str>,
+) -> PyResult<&'py PyDict> {
+    let times_list: Vec<Array1<f64>> = times_list
+        .iter()
+        .map(|times| times.as_array().to_owned())
+        .collect();
+    let report = component_report(
+        &c.as_array().to_owned(),
+        &st.as_array().to_owned(),
+        &mzroi.as_array().to_owned(),
+        &times_list,
+        n_top,
+    )
+    .map_err(value_error)?;
+    if let Some(path) = path {
+        write_component_report(&report, Path::new(path)).map_err(value_error)?;
+    }
+
+    let rows: Vec<(&ComponentSummary, usize, &ComponentRoi)> = report
+        .iter()
+        .flat_map(|summary| {
+            summary
+                .rois
+                .iter()
+                .enumerate()
+                .map(move |(rank, roi)| (summary, rank, roi))
+        })
+        .collect();
+
+    let table = PyDict::new(py);
+    table.set_item(
+        "component",
+        rows.iter()
+            .map(|(summary, _, _)| summary.component)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    table.set_item(
+        "rank",
+        rows.iter()
+            .map(|(_, rank, _)| *rank)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    table.set_item(
+        "roi",
+        rows.iter()
+            .map(|(_, _, roi)| roi.roi)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    table.set_item(
+        "mz",
+        rows.iter()
+            .map(|(_, _, roi)| roi.mz)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    table.set_item(
+        "loading",
+        rows.iter()
+            .map(|(_, _, roi)| roi.loading)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    table.set_item(
+        "selectivity",
+        rows.iter()
+            .map(|(_, _, roi)| roi.selectivity)
+            .collect::<Vec<_>>()
+            .into_pyarray(py),
+    )?;
+    for run in 0..times_list.len() {
+        table.set_item(
+            format!("apex_time_run{}", run),
+            rows.iter()
+                .map(|(summary, _, _)| summary.apex_times[run])
+                .collect::<Vec<_>>()
+                .into_pyarray(py),
+        )?;
+    }
+    Ok(table)
+}
+
+#[pyfunction(
+    py_args = "*",
+    runs = "None",
+    metadata_path = "None",
+    delimiter = "','",
+    transpose = "false"
+)]
+#[allow(clippy::too_many_arguments)]
+fn export_roi(
+    msroi: PyReadonlyArray2<'_, f64>,
+    mzroi: PyReadonlyArray1<'_, f64>,
+    times: PyReadonlyArray1<'_, f64>,
+    path: &str,
+    runs: Option<Vec<usize>>,
+    metadata_path: Option<&str>,
+    delimiter: char,
+    precision: Option<usize>,
+    transpose: bool,
+) -> PyResult<()> {
+    let msroi = msroi.as_array().to_owned();
+    let mzroi = mzroi.as_array().to_owned();
+    let times = times.as_array().to_owned();
+    let runs = match runs {
+        Some(runs) => Array1::from(runs),
+        None => Array1::zeros(msroi.nrows()),
+    };
+    let params = ExportParams {
+        delimiter,
+        precision,
+        transpose,
+    };
+
+    write_msroi(&msroi, &mzroi, &times, &runs, Path::new(path), &params).map_err(value_error)?;
+    if let Some(metadata_path) = metadata_path {
+        write_roi_metadata(
+            &msroi,
+            &mzroi,
+            &times,
+            &runs,
+            Path::new(metadata_path),
+            &params,
+        )
+        .map_err(value_error)?;
+    }
+    Ok(())
+}
+
+#[pyfunction(py_args = "*", run_sizes = "None", compress = "true")]
+fn export_mat(
+    msroi: PyReadonlyArray2<'_, f64>,
+    mzroi: PyReadonlyArray1<'_, f64>,
+    times: PyReadonlyArray1<'_, f64>,
+    path: &str,
+    run_sizes: Option<Vec<usize>>,
+    compress: bool,
+) -> PyResult<()> {
+    let msroi = msroi.as_array().to_owned();
+    let rois = RoiMatrices {
+        mzroi: mzroi.as_array().to_owned(),
+        times: times.as_array().to_owned(),
+        run_sizes: run_sizes.unwrap_or_else(|| vec![msroi.nrows()]),
+        msroi,
+    };
+    write_roi_mat(Path::new(path), &rois, compress).map_err(value_error)
+}
+
+#[pyfunction]
+fn import_mat<'py>(py: Python<'py>, path: &str) -> PyResult<&'py PyDict> {
+    let rois = read_roi_mat(Path::new(path)).map_err(value_error)?;
+
+    let output = PyDict::new(py);
+    output.set_item("mzroi", rois.mzroi.into_pyarray(py))?;
+    output.set_item("msroi", rois.msroi.into_pyarray(py))?;
+    output.set_item("times", rois.times.into_pyarray(py))?;
+    output.set_item("run_sizes", rois.run_sizes)?;
+    Ok(output)
+}
+
+#[pyfunction]
+fn load_config(py: Python<'_>, path: &str) -> PyResult<PyObject> {
+    let config = read_config(Path::new(path)).map_err(value_error)?;
+    let text =
+        serde_json::to_string(&config).map_err(|error| PyValueError::new_err(error.to_string()))?;
+    Ok(py
+        .import("json")?
+        .call_method1("loads", (text,))?
+        .to_object(py))
+}
+
+#[pyfunction]
+fn run_config<'py>(py: Python<'py>, path: &str) -> PyResult<&'py PyDict> {
+    let config = read_config(Path::new(path)).map_err(value_error)?;
+    let result = run_processing(&config).map_err(value_error)?;
+
+    let output = PyDict::new(py);
+    output.set_item("mzroi", result.mzroi.into_pyarray(py))?;
+    output.set_item("times", result.times.into_pyarray(py))?;
+    output.set_item("run_sizes", result.run_sizes)?;
+    match result.mcr {
+        Some(mcr) => output.set_item("mcr", mcr_result_to_dict(py, &result.msroi, mcr)?)?,
+        None => output.set_item("mcr", py.None())?,
+    }
+    output.set_item("msroi", result.msroi.into_pyarray(py))?;
+    Ok(output)
+}
 
-    (mzroi.into_pyarray(py), msroi.into_pyarray(py))
+/// Errors of the processing functions, raised as `ValueError`.
+fn value_error(error: RoiError) -> PyErr {
+    PyValueError::new_err(error.to_string())
 }
 
 /// A Python module implemented in Rust.
@@ -127,5 +1289,31 @@ fn get_roi<'py>(
 fn pyroimcr(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(import_data, m)?)?;
     m.add_function(wrap_pyfunction!(get_roi, m)?)?;
+    #[cfg(feature = "arrow")]
+    m.add_function(wrap_pyfunction!(get_peak_table, m)?)?;
+    #[cfg(feature = "arrow")]
+    m.add_function(wrap_pyfunction!(get_roi_tables, m)?)?;
+    #[cfg(feature = "arrow")]
+    m.add_class::<ArrowBatch>()?;
+    m.add_function(wrap_pyfunction!(smooth_roi, m)?)?;
+    m.add_function(wrap_pyfunction!(correct_baseline, m)?)?;
+    m.add_function(wrap_pyfunction!(correct_tic_baseline, m)?)?;
+    m.add_function(wrap_pyfunction!(detect_roi_peaks, m)?)?;
+    m.add_function(wrap_pyfunction!(mcr_als, m)?)?;
+    m.add_function(wrap_pyfunction!(mcr_als_augmented, m)?)?;
+    m.add_function(wrap_pyfunction!(mcr_als_weighted, m)?)?;
+    m.add_function(wrap_pyfunction!(get_roi_weights, m)?)?;
+    m.add_function(wrap_pyfunction!(nmf, m)?)?;
+    m.add_function(wrap_pyfunction!(parafac2, m)?)?;
+    m.add_function(wrap_pyfunction!(get_simplisma, m)?)?;
+    m.add_function(wrap_pyfunction!(get_component_report, m)?)?;
+    m.add_function(wrap_pyfunction!(get_efa, m)?)?;
+    m.add_function(wrap_pyfunction!(get_moving_window_efa, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate_rank, m)?)?;
+    m.add_function(wrap_pyfunction!(export_roi, m)?)?;
+    m.add_function(wrap_pyfunction!(export_mat, m)?)?;
+    m.add_function(wrap_pyfunction!(import_mat, m)?)?;
+    m.add_function(wrap_pyfunction!(load_config, m)?)?;
+    m.add_function(wrap_pyfunction!(run_config, m)?)?;
     Ok(())
 }
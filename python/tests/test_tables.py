@@ -0,0 +1,54 @@
+import numpy as np
+import pytest
+
+pa = pytest.importorskip("pyarrow", minversion="14")
+
+import pyroimcr
+
+
+def runs():
+    """Two runs of three scans with two peaks at m/z 100 and 200, the last scan empty."""
+    peaks_list = []
+    times_list = []
+    for run in range(2):
+        peaks = [
+            np.array([[100.0 + 0.01 * scan, 5000.0 * (scan + 1)], [200.0, 2000.0 + run]])
+            for scan in range(2)
+        ]
+        peaks.append(np.zeros((0, 2)))
+        peaks_list.append(peaks)
+        times_list.append(np.array([1.0, 2.0, 3.0]) + run)
+    return peaks_list, times_list
+
+
+def test_peak_table_is_shared_without_copying():
+    peaks_list, times_list = runs()
+    allocated = pa.total_allocated_bytes()
+    table = pyroimcr.get_peak_table(peaks_list, times_list)
+    # The buffers are those of the Rust batch: pyarrow allocated none of its own
+    assert pa.total_allocated_bytes() == allocated
+
+    assert table.column_names == ["run", "scan", "rt", "mz", "intensity"]
+    assert table.num_rows == 8
+    mzs = np.concatenate([scan[:, 0] for peaks in peaks_list for scan in peaks])
+    np.testing.assert_array_equal(table.column("mz").to_numpy(), mzs)
+    np.testing.assert_array_equal(table.column("run").to_numpy(), [0, 0, 0, 0, 1, 1, 1, 1])
+    np.testing.assert_array_equal(table.column("rt").to_numpy(), [1, 1, 2, 2, 2, 2, 3, 3])
+
+
+def test_roi_tables_are_shared_without_copying():
+    peaks_list, times_list = runs()
+    allocated = pa.total_allocated_bytes()
+    rois, summary = pyroimcr.get_roi_tables(peaks_list, times_list, threshold=100.0, tol=0.1)
+    assert pa.total_allocated_bytes() == allocated
+
+    assert rois.num_rows == 8
+    assert summary.num_rows == 2
+    np.testing.assert_array_equal(summary.column("n_scans").to_numpy(), [4, 4])
+    np.testing.assert_array_equal(summary.column("n_runs").to_numpy(), [2, 2])
+
+
+def test_mismatched_runs_are_rejected():
+    peaks_list, times_list = runs()
+    with pytest.raises(ValueError):
+        pyroimcr.get_peak_table(peaks_list, times_list[:1])
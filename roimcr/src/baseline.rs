@@ -0,0 +1,419 @@
+use ndarray::prelude::*;
+use ndarray::Zip;
+
+use crate::enums::BaselineMethod;
+use crate::error::RoiError;
+use crate::utils::solve_linear_system;
+
+/// Reweighting steps of the asymmetric least squares baseline.
+const ALS_ITERATIONS: usize = 10;
+/// Maximum number of fits of the iterative polynomial baseline.
+const POLYNOMIAL_ITERATIONS: usize = 100;
+
+/// Estimate the baseline of every ROI chromatogram (column) of `msroi` and subtract it.
+/// Returns the corrected matrix and the estimated baseline matrix.
+///
+/// Cells without a peak (zero intensity) are not measurements, as in `smooth_msroi`: the
+/// baseline is fitted to the other cells only and interpolated over them, and they stay
+/// at zero in the corrected matrix. Corrected intensities are clipped at zero.
+pub fn correct_msroi(
+    msroi: &Array2<f64>,
+    method: &BaselineMethod,
+) -> Result<(Array2<f64>, Array2<f64>), RoiError> {
+    check_method(method)?;
+    let mut corrected: Array2<f64> = Array2::zeros(msroi.raw_dim());
+    let mut baseline: Array2<f64> = Array2::zeros(msroi.raw_dim());
+
+    for (i, chromatogram) in msroi.axis_iter(Axis(1)).enumerate() {
+        let observed: Vec<bool> = chromatogram.iter().map(|&v| v != 0.0).collect();
+        let estimate = fit_baseline(chromatogram, &observed, method);
+        corrected
+            .column_mut(i)
+            .assign(
+                &Zip::from(&chromatogram)
+                    .and(&estimate)
+                    .map_collect(|&value, &base| {
+                        if value == 0.0 {
+                            0.0
+                        } else {
+                            (value - base).max(0.0)
+                        }
+                    }),
+            );
+        baseline.column_mut(i).assign(&estimate);
+    }
+    Ok((corrected, baseline))
+}
+
+/// Estimate the baseline of a single chromatogram, e.g. the TIC returned by
+/// `MsRun::get_tic`, and subtract it. Returns the corrected chromatogram and the baseline.
+/// Every point is a measurement, zeros included.
+pub fn correct_chromatogram(
+    values: ArrayView1<f64>,
+    method: &BaselineMethod,
+) -> Result<(Array1<f64>, Array1<f64>), RoiError> {
+    let baseline = estimate_baseline(values, method)?;
+    Ok((&values - &baseline, baseline))
+}
+
+/// Baseline of a chromatogram whose points are all measurements.
+pub fn estimate_baseline(
+    values: ArrayView1<f64>,
+    method: &BaselineMethod,
+) -> Result<Array1<f64>, RoiError> {
+    check_method(method)?;
+    Ok(fit_baseline(values, &vec![true; values.len()], method))
+}
+
+fn check_method(method: &BaselineMethod) -> Result<(), RoiError> {
+    match *method {
+        BaselineMethod::AsymmetricLeastSquares(lambda, p) => {
+            if !(lambda > 0.0 && lambda.is_finite() && p > 0.0 && p < 1.0) {
+                return Err(RoiError::InvalidArgument(
+                    "ALS baseline needs a positive smoothness and an asymmetry between 0 and 1"
+                        .to_string(),
+                ));
+            }
+        }
+        BaselineMethod::RollingMinimum(window) => {
+            if window == 0 {
+                return Err(RoiError::InvalidArgument(
+                    "Rolling minimum window must be at least one scan".to_string(),
+                ));
+            }
+        }
+        BaselineMethod::Polynomial(_) => {}
+    }
+    Ok(())
+}
+
+/// Baseline fitted to the `observed` points of `values` with a valid `method`.
+fn fit_baseline(
+    values: ArrayView1<f64>,
+    observed: &[bool],
+    method: &BaselineMethod,
+) -> Array1<f64> {
+    match *method {
+        BaselineMethod::AsymmetricLeastSquares(lambda, p) => {
+            als_baseline(values, observed, lambda, p)
+        }
+        BaselineMethod::RollingMinimum(window) => {
+            rolling_minimum_baseline(values, observed, window)
+        }
+        BaselineMethod::Polynomial(order) => polynomial_baseline(values, observed, order),
+    }
+}
+
+/// Asymmetric least squares (Eilers & Boelens, 2005): a smooth curve penalised on its
+/// second differences, fitted with small weights `p` on the points above it. Points
+/// that aren't `observed` get no weight, so the curve interpolates over them.
+fn als_baseline(values: ArrayView1<f64>, observed: &[bool], lambda: f64, p: f64) -> Array1<f64> {
+    let n = values.len();
+    // The penalty leaves straight lines free, which two points pin down
+    if n < 3 || observed.iter().filter(|&&o| o).count() < 2 {
+        return constant_baseline(values, observed);
+    }
+
+    // Bands of lambda * D'D, D being the second difference operator
+    let mut penalty = [vec![0.0; n], vec![0.0; n], vec![0.0; n]];
+    let stencil = [1.0, -2.0, 1.0];
+    for k in 0..(n - 2) {
+        for a in 0..3 {
+            for b in a..3 {
+                penalty[b - a][k + a] += lambda * stencil[a] * stencil[b];
+            }
+        }
+    }
+
+    let mut weights: Vec<f64> = observed
+        .iter()
+        .map(|&o| if o { 1.0 } else { 0.0 })
+        .collect();
+    let mut baseline = values.to_owned();
+
+    for _ in 0..ALS_ITERATIONS {
+        let mut bands = penalty.clone();
+        for i in 0..n {
+            bands[0][i] += weights[i];
+        }
+        let rhs: Vec<f64> = values
+            .iter()
+            .zip(weights.iter())
+            .map(|(y, w)| y * w)
+            .collect();
+        baseline = Array1::from_vec(solve_pentadiagonal(&bands, &rhs));
+
+        for i in (0..n).filter(|&i| observed[i]) {
+            weights[i] = if values[i] > baseline[i] { p } else { 1.0 - p };
+        }
+    }
+    baseline
+}
+
+/// Solve a symmetric positive definite pentadiagonal system by banded Cholesky
+/// factorisation. `bands` holds the main diagonal and the first two super-diagonals.
+fn solve_pentadiagonal(bands: &[Vec<f64>; 3], rhs: &[f64]) -> Vec<f64> {
+    let n = rhs.len();
+    let mut l0 = vec![0.0; n];
+    let mut l1 = vec![0.0; n];
+    let mut l2 = vec![0.0; n];
+
+    for i in 0..n {
+        if i >= 2 {
+            l2[i] = bands[2][i - 2] / l0[i - 2];
+        }
+        if i >= 1 {
+            l1[i] = (bands[1][i - 1] - l2[i] * l1[i - 1]) / l0[i - 1];
+        }
+        l0[i] = (bands[0][i] - l1[i] * l1[i] - l2[i] * l2[i]).sqrt();
+    }
+
+    let mut y = vec![0.0; n];
+    for i in 0..n {
+        let mut value = rhs[i];
+        if i >= 1 {
+            value -= l1[i] * y[i - 1];
+        }
+        if i >= 2 {
+            value -= l2[i] * y[i - 2];
+        }
+        y[i] = value / l0[i];
+    }
+
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let mut value = y[i];
+        if i + 1 < n {
+            value -= l1[i + 1] * x[i + 1];
+        }
+        if i + 2 < n {
+            value -= l2[i + 2] * x[i + 2];
+        }
+        x[i] = value / l0[i];
+    }
+    x
+}
+
+/// Minimum over a centred window of `window` scans, smoothed with a moving average of the
+/// same width. Only `observed` points are used; scans whose window holds none are
+/// interpolated linearly.
+fn rolling_minimum_baseline(
+    values: ArrayView1<f64>,
+    observed: &[bool],
+    window: usize,
+) -> Array1<f64> {
+    let n = values.len();
+    let half = window / 2;
+    let bounds = |i: usize| (i.saturating_sub(half), (i + half + 1).min(n));
+
+    let minima: Vec<Option<f64>> = (0..n)
+        .map(|i| {
+            let (start, end) = bounds(i);
+            (start..end)
+                .filter(|&j| observed[j])
+                .map(|j| values[j])
+                .reduce(f64::min)
+        })
+        .collect();
+
+    let smoothed: Vec<Option<f64>> = (0..n)
+        .map(|i| {
+            let (start, end) = bounds(i);
+            let window: Vec<f64> = minima[start..end].iter().flatten().cloned().collect();
+            if window.is_empty() {
+                None
+            } else {
+                Some(window.iter().sum::<f64>() / window.len() as f64)
+            }
+        })
+        .collect();
+    interpolate_gaps(&smoothed)
+}
+
+/// Fill the missing values by linear interpolation between their neighbours, or with the
+/// nearest value at the ends; zeros if no value is known.
+fn interpolate_gaps(values: &[Option<f64>]) -> Array1<f64> {
+    let known: Vec<usize> = (0..values.len()).filter(|&i| values[i].is_some()).collect();
+    let (first, last) = match (known.first(), known.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Array1::zeros(values.len()),
+    };
+    let value = |i: usize| values[i].unwrap_or_default();
+
+    let mut filled = Array1::zeros(values.len());
+    for i in 0..values.len() {
+        filled[i] = if i <= first {
+            value(first)
+        } else if i >= last {
+            value(last)
+        } else if values[i].is_some() {
+            value(i)
+        } else {
+            let right = known.partition_point(|&k| k < i);
+            let (a, b) = (known[right - 1], known[right]);
+            let t = (i - a) as f64 / (b - a) as f64;
+            value(a) + t * (value(b) - value(a))
+        };
+    }
+    filled
+}
+
+/// Iterative polynomial fitting (Lieber & Mahadevan-Jansen, 2003): the points above the
+/// fitted polynomial are clipped to it until the fit stops changing. Only `observed`
+/// points are fitted, and the order is lowered when there are too few of them.
+fn polynomial_baseline(values: ArrayView1<f64>, observed: &[bool], order: usize) -> Array1<f64> {
+    let n = values.len();
+    let points: Vec<usize> = (0..n).filter(|&i| observed[i]).collect();
+    if points.len() < 2 {
+        return constant_baseline(values, observed);
+    }
+    let order = order.min(points.len() - 1);
+
+    // Scan positions scaled to [-1, 1] to keep the normal equations well conditioned
+    let position = |i: usize| 2.0 * i as f64 / (n - 1) as f64 - 1.0;
+    let x: Vec<f64> = points.iter().map(|&i| position(i)).collect();
+    let mut target: Array1<f64> = points.iter().map(|&i| values[i]).collect();
+    let mut baseline = target.clone();
+    let mut coefficients = None;
+
+    for _ in 0..POLYNOMIAL_ITERATIONS {
+        let mut ata = vec![vec![0.0; order + 1]; order + 1];
+        let mut atb = vec![0.0; order + 1];
+        for (&xi, &yi) in x.iter().zip(target.iter()) {
+            let powers: Vec<f64> = (0..=2 * order).map(|p| xi.powi(p as i32)).collect();
+            for p in 0..=order {
+                for q in 0..=order {
+                    ata[p][q] += powers[p + q];
+                }
+                atb[p] += powers[p] * yi;
+            }
+        }
+        let fit = match solve_linear_system(ata, atb) {
+            Some(fit) => fit,
+            None => break,
+        };
+
+        let fitted: Array1<f64> = x.iter().map(|&xi| evaluate(&fit, xi)).collect();
+        coefficients = Some(fit);
+
+        let change = (&fitted - &baseline).mapv(f64::abs).sum();
+        let scale = fitted.mapv(f64::abs).sum().max(f64::EPSILON);
+        baseline = fitted;
+        target.zip_mut_with(&baseline, |y, &b| *y = y.min(b));
+
+        if change / scale < 1e-6 {
+            break;
+        }
+    }
+    match coefficients {
+        Some(coefficients) => (0..n)
+            .map(|i| evaluate(&coefficients, position(i)))
+            .collect(),
+        None => constant_baseline(values, observed),
+    }
+}
+
+/// Polynomial with the given coefficients, constant term first, evaluated at `x`.
+fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0.0, |acc, &coefficient| acc * x + coefficient)
+}
+
+/// Baseline at the smallest observed value, for chromatograms too short to fit.
+fn constant_baseline(values: ArrayView1<f64>, observed: &[bool]) -> Array1<f64> {
+    let lowest = (0..values.len())
+        .filter(|&i| observed[i])
+        .map(|i| values[i])
+        .reduce(f64::min)
+        .unwrap_or(0.0);
+    Array1::from_elem(values.len(), lowest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::gaussian;
+
+    const METHODS: [BaselineMethod; 3] = [
+        BaselineMethod::AsymmetricLeastSquares(1e4, 0.01),
+        BaselineMethod::RollingMinimum(31),
+        BaselineMethod::Polynomial(2),
+    ];
+
+    /// Gaussian peak of height 1000 on a sloping baseline of 100 to 150.
+    fn drifting_peak(n: usize) -> Array1<f64> {
+        Array1::from_shape_fn(n, |i| {
+            100.0 + 50.0 * i as f64 / n as f64 + 1e3 * gaussian(i as f64, n as f64 / 2.0, 4.0)
+        })
+    }
+
+    #[test]
+    fn baseline_stays_below_the_peak() {
+        let values = drifting_peak(200);
+        for method in METHODS.iter() {
+            let (corrected, baseline) = correct_chromatogram(values.view(), method).unwrap();
+            assert!((corrected[100] - 1e3).abs() < 100.0, "{:?}", method);
+            assert!(corrected[10].abs() < 100.0, "{:?}", method);
+            assert!(baseline[100] < 200.0, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn zero_cells_are_left_out_of_the_fit() {
+        // The peak is only detected in every other scan; counting the empty scans as
+        // measurements would drag the baseline down to zero
+        let mut msroi = Array2::zeros((200, 2));
+        msroi.column_mut(0).assign(&drifting_peak(200));
+        msroi.column_mut(1).assign(&drifting_peak(200));
+        for i in (0..200).step_by(2) {
+            msroi[[i, 1]] = 0.0;
+        }
+
+        for method in METHODS.iter() {
+            let (corrected, baseline) = correct_msroi(&msroi, method).unwrap();
+            for i in 0..200 {
+                assert!(
+                    (baseline[[i, 1]] - baseline[[i, 0]]).abs() < 20.0,
+                    "{:?}",
+                    method
+                );
+                if i % 2 == 0 {
+                    assert_eq!(corrected[[i, 1]], 0.0);
+                } else {
+                    assert!((corrected[[i, 1]] - corrected[[i, 0]]).abs() < 20.0);
+                }
+            }
+            assert!(corrected.iter().all(|&value| value >= 0.0));
+        }
+    }
+
+    #[test]
+    fn sparse_chromatograms_get_a_constant_baseline() {
+        let mut msroi = Array2::zeros((50, 2));
+        msroi[[20, 0]] = 500.0;
+        for method in METHODS.iter() {
+            let (corrected, baseline) = correct_msroi(&msroi, method).unwrap();
+            assert!(corrected.iter().all(|&value| value == 0.0));
+            assert!(baseline.column(1).iter().all(|&value| value == 0.0));
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let values = drifting_peak(20);
+        for method in [
+            BaselineMethod::AsymmetricLeastSquares(0.0, 0.01),
+            BaselineMethod::AsymmetricLeastSquares(1e4, 1.0),
+            BaselineMethod::AsymmetricLeastSquares(f64::NAN, 0.01),
+            BaselineMethod::RollingMinimum(0),
+        ] {
+            assert!(matches!(
+                estimate_baseline(values.view(), &method),
+                Err(RoiError::InvalidArgument(_))
+            ));
+            assert!(correct_msroi(&Array2::zeros((20, 1)), &method).is_err());
+        }
+    }
+}
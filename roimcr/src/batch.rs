@@ -0,0 +1,281 @@
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use crate::enums::InputFormat;
+use crate::error::RoiError;
+use crate::read_run;
+use crate::structs::data::{BatchEntry, BatchReport};
+use crate::structs::io::MsRun;
+
+/// Files of a batch: the files of a directory whose format is known from their extension,
+/// or the files matching a glob pattern such as `plate1/*.mzXML`, sorted by path.
+pub fn collect_files(input: &str) -> Result<Vec<PathBuf>, RoiError> {
+    let mut files: Vec<PathBuf> = if Path::new(input).is_dir() {
+        fs::read_dir(input)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?
+            .into_iter()
+            .filter(|path| path.is_file() && InputFormat::from_path(path).is_some())
+            .collect()
+    } else {
+        glob::glob(input)
+            .map_err(|error| RoiError::Parse(format!("Invalid pattern {}: {}", input, error)))?
+            .filter_map(|path| path.ok())
+            .filter(|path| path.is_file())
+            .collect()
+    };
+
+    if files.is_empty() {
+        return Err(RoiError::Parse(format!(
+            "No input files found in {}",
+            input
+        )));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Output directory of each of the `files` of a batch, relative to the output root: the
+/// path of the file from the deepest directory holding all of them, without extension,
+/// or with it for the files that would otherwise share a directory, such as `a.mzML` and
+/// `a.mzXML`. Files that still can't be told apart are an error, so that no run of the
+/// batch overwrites the results of another.
+pub fn output_directories(files: &[PathBuf]) -> Result<Vec<PathBuf>, RoiError> {
+    let mut root: Vec<_> = match files.first().and_then(|file| file.parent()) {
+        Some(parent) => parent.components().collect(),
+        None => Vec::new(),
+    };
+    for file in files.iter() {
+        let parent: Vec<_> = file
+            .parent()
+            .map_or(Vec::new(), |parent| parent.components().collect());
+        let shared = root
+            .iter()
+            .zip(parent.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        root.truncate(shared);
+    }
+    let root: PathBuf = root.iter().collect();
+
+    let relative: Vec<&Path> = files
+        .iter()
+        .map(|file| file.strip_prefix(&root).unwrap_or(file))
+        .collect();
+    let mut stems: HashMap<PathBuf, usize> = HashMap::new();
+    for path in relative.iter() {
+        *stems.entry(path.with_extension("")).or_insert(0) += 1;
+    }
+
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    relative
+        .into_iter()
+        .zip(files.iter())
+        .map(|(path, file)| {
+            let stem = path.with_extension("");
+            let directory = if stems[&stem] > 1 {
+                path.to_path_buf()
+            } else {
+                stem
+            };
+            if !seen.insert(directory.clone()) {
+                return Err(RoiError::InvalidArgument(format!(
+                    "The results of {} would overwrite those of another file of the batch in {}",
+                    file.display(),
+                    directory.display()
+                )));
+            }
+            Ok(directory)
+        })
+        .collect()
+}
+
+/// Load the `files` concurrently on `n_threads` threads (all available cores if 0) and
+/// pass each run to `process`, in the format given or detected from the extension.
+///
+/// A file that can't be read, has no MS1 scans or fails in `process`, by returning an
+/// error or by panicking, is recorded in the report and the batch goes on. The outputs
+/// of `process` are in the order of `files`, `None` for the failed ones.
+pub fn process_batch<T, F>(
+    files: &[PathBuf],
+    format: Option<InputFormat>,
+    n_threads: usize,
+    process: F,
+) -> (Vec<Option<T>>, BatchReport)
+where
+    T: Send,
+    F: Fn(&Path, MsRun) -> Result<T, RoiError> + Sync,
+{
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(n_threads)
+        .build()
+        .expect("Couldn't create the thread pool");
+
+    let outcomes: Vec<(Option<T>, BatchEntry)> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| {
+                let mut entry = BatchEntry {
+                    path: path.clone(),
+                    format: format.or_else(|| InputFormat::from_path(path)),
+                    ms1_scans: None,
+                    error: None,
+                };
+                let outcome = match entry.format {
+                    Some(format) => panic::catch_unwind(AssertUnwindSafe(|| {
+                        let run = read_run(path, format)?;
+                        let ms1_scans = run.scans.iter().filter(|scan| scan.ms_level == 1).count();
+                        entry.ms1_scans = Some(ms1_scans);
+                        if ms1_scans == 0 {
+                            return Err(RoiError::Parse(format!(
+                                "No MS1 scans in {}",
+                                path.display()
+                            )));
+                        }
+                        process(path, run)
+                    }))
+                    .unwrap_or_else(|payload| Err(RoiError::Parse(panic_message(payload)))),
+                    None => Err(RoiError::UnsupportedFormat(format!(
+                        "Can't detect the format of {}",
+                        path.display()
+                    ))),
+                };
+
+                match outcome {
+                    Ok(output) => (Some(output), entry),
+                    Err(error) => {
+                        entry.error = Some(error.to_string());
+                        (None, entry)
+                    }
+                }
+            })
+            .collect()
+    });
+
+    let (outputs, entries): (Vec<Option<T>>, Vec<BatchEntry>) = outcomes.into_iter().unzip();
+    let loaded = entries.iter().filter(|entry| entry.loaded()).count();
+    let report = BatchReport {
+        loaded,
+        failed: entries.len() - loaded,
+        files: entries,
+    };
+    (outputs, report)
+}
+
+/// Load the `files` concurrently, see `process_batch`.
+pub fn load_batch(
+    files: &[PathBuf],
+    format: Option<InputFormat>,
+    n_threads: usize,
+) -> (Vec<Option<MsRun>>, BatchReport) {
+    process_batch(files, format, n_threads, |_, run| Ok(run))
+}
+
+/// Write a batch report as JSON.
+pub fn write_batch_report(report: &BatchReport, path: &Path) -> Result<(), RoiError> {
+    let text = serde_json::to_string_pretty(report)
+        .map_err(|error| RoiError::Write(format!("Can't serialise the report: {}", error)))?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| {
+            payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+        })
+        .unwrap_or_else(|| "Unknown error".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::options::WriteParams;
+    use crate::test_utils::{synthetic_run, temp_path};
+    use crate::writer::write_mzxml;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn output_directories_are_unique() {
+        let directories = output_directories(&paths(&["data/a.mzML", "data/b.mzXML"])).unwrap();
+        assert_eq!(directories, paths(&["a", "b"]));
+
+        // Same stem in the same directory, and the same name in different directories
+        let directories = output_directories(&paths(&["data/a.mzML", "data/a.mzXML"])).unwrap();
+        assert_eq!(directories, paths(&["a.mzML", "a.mzXML"]));
+        let directories =
+            output_directories(&paths(&["data/d1/a.mzML", "data/d2/a.mzML"])).unwrap();
+        assert_eq!(directories, paths(&["d1/a", "d2/a"]));
+
+        assert!(matches!(
+            output_directories(&paths(&["data/a.mzML", "data/a.mzML"])),
+            Err(RoiError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn failed_files_are_reported_and_the_batch_goes_on() {
+        let directory = temp_path("batch");
+        fs::create_dir_all(&directory).unwrap();
+        let params = WriteParams {
+            zlib: true,
+            double_precision: false,
+        };
+        for (i, name) in ["a.mzXML", "b.mzXML", "c.mzXML"].iter().enumerate() {
+            let run = synthetic_run(&[200.0], 10 + i, i as u64);
+            write_mzxml(&run, &directory.join(name), &params).unwrap();
+        }
+        fs::write(directory.join("broken.mzXML"), "<mzXML>").unwrap();
+        fs::write(directory.join("notes.txt"), "not a run").unwrap();
+
+        let files = collect_files(directory.to_str().unwrap()).unwrap();
+        let names: Vec<_> = files.iter().map(|file| file.file_name().unwrap()).collect();
+        assert_eq!(names, ["a.mzXML", "b.mzXML", "broken.mzXML", "c.mzXML"]);
+
+        let (outputs, report) = process_batch(&files, None, 2, |path, run| {
+            if path.ends_with("c.mzXML") {
+                panic!("Processing failed");
+            }
+            Ok(run.scans.len())
+        });
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(outputs, vec![Some(10), Some(11), None, None]);
+        assert_eq!((report.loaded, report.failed), (2, 2));
+        assert_eq!(report.files[1].ms1_scans, Some(11));
+        assert!(report.files[2].error.is_some());
+        assert_eq!(report.files[3].error.as_deref(), Some("Processing failed"));
+    }
+
+    #[test]
+    fn missing_inputs_are_an_error() {
+        let pattern = temp_path("*.mzML");
+        assert!(matches!(
+            collect_files(pattern.to_str().unwrap()),
+            Err(RoiError::Parse(_))
+        ));
+
+        let (outputs, report) = load_batch(&paths(&["run.raw"]), None, 1);
+        assert!(outputs[0].is_none());
+        assert_eq!(report.failed, 1);
+
+        let path = temp_path("report.json");
+        write_batch_report(&report, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(text.contains("run.raw"));
+    }
+}
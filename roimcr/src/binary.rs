@@ -0,0 +1,133 @@
+//! Base64 encoded, optionally zlib compressed, arrays of floats as stored in mzXML and
+//! mzML files.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    BigEndian,
+    LittleEndian,
+}
+
+/// Decode a base64 string, inflating it if it is zlib compressed.
+pub fn decode(text: &str, zlib: bool) -> Result<Vec<u8>, String> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes =
+        base64::decode(text).map_err(|error| format!("Couldn't decode peaks: {}", error))?;
+    if !zlib {
+        return Ok(bytes);
+    }
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(&bytes[..])
+        .read_to_end(&mut inflated)
+        .map_err(|error| format!("Couldn't decompress peaks: {}", error))?;
+    Ok(inflated)
+}
+
+/// Encode bytes in base64, deflating them first with `zlib`.
+pub fn encode(bytes: &[u8], zlib: bool) -> String {
+    if !zlib {
+        return base64::encode(bytes);
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|_| encoder.finish())
+        .map(base64::encode)
+        .expect("Couldn't compress peaks in memory")
+}
+
+/// Floats of 32 or 64 bits (`double`) from their bytes.
+pub fn to_floats(bytes: &[u8], double: bool, order: ByteOrder) -> Result<Vec<f64>, String> {
+    let width = if double { 8 } else { 4 };
+    if !bytes.len().is_multiple_of(width) {
+        return Err(format!(
+            "Peaks data of {} bytes isn't made of {}-bit floats",
+            bytes.len(),
+            width * 8
+        ));
+    }
+
+    Ok(bytes
+        .chunks_exact(width)
+        .map(|chunk| {
+            if double {
+                let chunk: [u8; 8] = chunk.try_into().unwrap();
+                match order {
+                    ByteOrder::BigEndian => f64::from_be_bytes(chunk),
+                    ByteOrder::LittleEndian => f64::from_le_bytes(chunk),
+                }
+            } else {
+                let chunk: [u8; 4] = chunk.try_into().unwrap();
+                (match order {
+                    ByteOrder::BigEndian => f32::from_be_bytes(chunk),
+                    ByteOrder::LittleEndian => f32::from_le_bytes(chunk),
+                }) as f64
+            }
+        })
+        .collect())
+}
+
+/// Bytes of floats stored with 32 or 64 bits (`double`).
+pub fn from_floats<I: Iterator<Item = f64>>(values: I, double: bool, order: ByteOrder) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in values {
+        match (double, order) {
+            (true, ByteOrder::BigEndian) => bytes.extend_from_slice(&value.to_be_bytes()),
+            (true, ByteOrder::LittleEndian) => bytes.extend_from_slice(&value.to_le_bytes()),
+            (false, ByteOrder::BigEndian) => bytes.extend_from_slice(&(value as f32).to_be_bytes()),
+            (false, ByteOrder::LittleEndian) => {
+                bytes.extend_from_slice(&(value as f32).to_le_bytes())
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [f64; 4] = [100.5, 0.0, -2.25, 1e6];
+
+    #[test]
+    fn floats_are_read_back() {
+        for double in [false, true] {
+            for order in [ByteOrder::BigEndian, ByteOrder::LittleEndian] {
+                let bytes = from_floats(VALUES.iter().copied(), double, order);
+                assert_eq!(bytes.len(), VALUES.len() * if double { 8 } else { 4 });
+                assert_eq!(to_floats(&bytes, double, order).unwrap(), VALUES);
+            }
+        }
+        // Network order, as in mzXML
+        assert_eq!(
+            from_floats([1.0].into_iter(), false, ByteOrder::BigEndian),
+            [0x3f, 0x80, 0, 0]
+        );
+    }
+
+    #[test]
+    fn base64_is_read_back() {
+        let bytes = from_floats(VALUES.iter().copied(), true, ByteOrder::LittleEndian);
+        for zlib in [false, true] {
+            let text = encode(&bytes, zlib);
+            assert_eq!(decode(&text, zlib).unwrap(), bytes);
+        }
+        assert_eq!(encode(b"peaks", false), "cGVha3M=");
+        // Line breaks and indentation of the XML text are ignored
+        assert_eq!(decode("cGVh\n    a3M=", false).unwrap(), b"peaks");
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        assert!(to_floats(&[0; 6], false, ByteOrder::BigEndian).is_err());
+        assert!(to_floats(&[0; 12], true, ByteOrder::LittleEndian).is_err());
+        assert!(decode("not base64!", false).is_err());
+        // Valid base64 that isn't zlib data
+        assert!(decode("cGVha3M=", true).is_err());
+    }
+}
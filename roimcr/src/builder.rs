@@ -0,0 +1,309 @@
+use ndarray::prelude::*;
+
+use crate::enums::SparseLayout;
+use crate::error::RoiError;
+use crate::structs::data::{RoiResult, Roicell, SparseMatrix, SparseRoiResult};
+use crate::structs::options::RoiParams;
+use crate::utils::{argsort, sort_by_indices};
+
+/// Incremental ROI extraction: scans are pushed one at a time, in acquisition order,
+/// and the ROIs are consolidated into `mzroi`/`msroi` only when `finish` is called.
+pub struct RoiBuilder {
+    settings: RoiParams,
+    mzroi: Vec<f64>,
+    roicell: Roicell,
+    times: Vec<f64>,
+    runs: Vec<usize>,
+}
+
+impl RoiBuilder {
+    pub fn new(settings: RoiParams) -> Self {
+        RoiBuilder {
+            settings,
+            mzroi: Vec::new(),
+            roicell: Roicell::new(),
+            times: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Number of scans pushed so far, i.e. the number of rows of the final `msroi`.
+    pub fn n_scans(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Add the centroided peaks of a single scan acquired at time `rt` in run `run`.
+    /// Every call adds a row to `msroi`, even if no peak is above the threshold.
+    pub fn push_scan(
+        &mut self,
+        run: usize,
+        rt: f64,
+        mz: &[f64],
+        intensity: &[f64],
+    ) -> Result<(), RoiError> {
+        if mz.len() != intensity.len() {
+            return Err(RoiError::InvalidArgument(format!(
+                "m/z and intensity arrays must have the same length, got {} and {}",
+                mz.len(),
+                intensity.len()
+            )));
+        }
+
+        let scan = self.times.len();
+        self.times.push(rt);
+        self.runs.push(run);
+
+        for (&mz, &intensity) in mz.iter().zip(intensity.iter()) {
+            if intensity <= self.settings.threshold {
+                continue;
+            }
+
+            let compatible_rois: Vec<usize> = self
+                .mzroi
+                .iter()
+                .enumerate()
+                .filter(|(_, &roi_value)| {
+                    (roi_value - mz).abs() <= self.settings.mz_error.get_error(&mz)
+                })
+                .map(|(index, _)| index)
+                .collect();
+
+            if compatible_rois.is_empty() {
+                self.roicell.new_roi();
+                self.roicell.update_roi(
+                    self.roicell.mzs.len() - 1,
+                    &self.settings.mzroi_updater,
+                    mz,
+                    rt,
+                    intensity,
+                    scan,
+                );
+                self.mzroi
+                    .push(self.roicell.mz_roi[self.roicell.mzs.len() - 1])
+            } else {
+                for &roi in compatible_rois.iter() {
+                    self.roicell.update_roi(
+                        roi,
+                        &self.settings.mzroi_updater,
+                        mz,
+                        rt,
+                        intensity,
+                        scan,
+                    );
+                    self.mzroi[roi] = self.roicell.mz_roi[roi];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the accumulated ROIs whose current m/z value does not satisfy `keep`.
+    pub(crate) fn retain_rois<F: Fn(f64) -> bool>(&mut self, keep: F) {
+        for roi in (0..self.mzroi.len()).rev() {
+            if !keep(self.mzroi[roi]) {
+                self.roicell.remove_roi(roi);
+                self.mzroi.remove(roi);
+            }
+        }
+    }
+
+    /// Move the ROIs accumulated by `other`, built over the same scans, into this builder.
+    pub(crate) fn absorb(&mut self, mut other: RoiBuilder) {
+        assert_eq!(
+            self.n_scans(),
+            other.n_scans(),
+            "Can't join ROIs built over a different number of scans"
+        );
+        self.mzroi.append(&mut other.mzroi);
+        self.roicell.append(&mut other.roicell);
+    }
+
+    /// Sort, merge and filter the accumulated ROIs and build the `msroi` matrix.
+    pub fn finish(self) -> RoiResult {
+        let (mzroi, roicell, times, runs) = self.consolidate();
+
+        let mut msroi: Array2<f64> = Array2::zeros((times.len(), mzroi.len()));
+        for i in 0..mzroi.len() {
+            let nval = roicell.scans[i].len();
+            for j in 0..nval {
+                let irow = roicell.scans[i][j] as usize;
+                let msi = roicell.intensities[i][j];
+                msroi[[irow, i]] += msi;
+            }
+        }
+
+        RoiResult {
+            mzroi,
+            msroi,
+            times: Array1::from_vec(times),
+            runs: Array1::from_vec(runs),
+            roicell,
+        }
+    }
+
+    /// Same as `finish`, building `msroi` directly as a sparse matrix with `layout`.
+    pub fn finish_sparse(self, layout: SparseLayout) -> SparseRoiResult {
+        let (mzroi, roicell, times, runs) = self.consolidate();
+
+        let mut rows = Vec::new();
+        let mut cols = Vec::new();
+        let mut values = Vec::new();
+        for i in 0..mzroi.len() {
+            rows.extend(roicell.scans[i].iter().map(|&scan| scan as usize));
+            cols.extend(std::iter::repeat_n(i, roicell.scans[i].len()));
+            values.extend_from_slice(&roicell.intensities[i]);
+        }
+        let msroi =
+            SparseMatrix::compress((times.len(), mzroi.len()), layout, &rows, &cols, &values);
+
+        SparseRoiResult {
+            mzroi,
+            msroi,
+            times: Array1::from_vec(times),
+            runs: Array1::from_vec(runs),
+            roicell,
+        }
+    }
+
+    /// Sort, merge and filter the accumulated ROIs, returning `mzroi`, the ROIs and the
+    /// time and run of every scan.
+    fn consolidate(self) -> (Array1<f64>, Roicell, Vec<f64>, Vec<usize>) {
+        let RoiBuilder {
+            settings,
+            mut mzroi,
+            mut roicell,
+            times,
+            runs,
+        } = self;
+
+        let sorted_roi_index = argsort(&mzroi);
+
+        sort_by_indices(&mut mzroi, &sorted_roi_index);
+
+        sort_by_indices(&mut roicell.mzs, &sorted_roi_index);
+        sort_by_indices(&mut roicell.times, &sorted_roi_index);
+        sort_by_indices(&mut roicell.intensities, &sorted_roi_index);
+        sort_by_indices(&mut roicell.scans, &sorted_roi_index);
+        sort_by_indices(&mut roicell.mz_roi, &sorted_roi_index);
+
+        // Raggruppa roi con mz simili
+        loop {
+            let mz_error_roi: Vec<f64> = mzroi.windows(2).map(|w| (w[0] - w[1]).abs()).collect();
+
+            let mergable_roi: Vec<usize> = mz_error_roi
+                .iter()
+                .enumerate()
+                .filter(|(_, &mz_diff)| {
+                    mz_diff < settings.mz_error.get_error(&mzroi[mzroi.len() - 2])
+                })
+                .map(|(index, _)| index)
+                .collect();
+
+            if mergable_roi.is_empty() {
+                break;
+            }
+
+            mzroi[mergable_roi[0]] = roicell.mz_roi[mergable_roi[0] + 1];
+            roicell.merge_roi(
+                mergable_roi[0],
+                mergable_roi[0] + 1,
+                &settings.mzroi_updater,
+            );
+            mzroi.remove(mergable_roi[0] + 1);
+        }
+
+        // Filtrare per minimo numero di mz e minima intensità massima in roi
+        let roi_num: Vec<u32> = roicell.mzs.iter().map(|i| i.len() as u32).collect();
+        let max_int: Vec<f64> = roicell
+            .intensities
+            .iter()
+            .map(|i| *i.iter().max_by(|a, b| a.total_cmp(b)).unwrap())
+            .collect();
+
+        let mut removable_roi: Vec<usize> = roi_num
+            .iter()
+            .zip(max_int.iter())
+            .enumerate()
+            .filter(|(_, (&n, &int))| {
+                (n <= settings.min_occ) || (int <= settings.threshold * settings.t_factor)
+            })
+            .map(|(i, (_, _))| i)
+            .collect::<Vec<usize>>();
+
+        removable_roi.reverse();
+
+        for roi in removable_roi {
+            roicell.remove_roi(roi);
+            mzroi.remove(roi);
+        }
+
+        (arr1(&mzroi), roicell, times, runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_roi;
+    use crate::test_utils::synthetic_runs;
+
+    fn build(peaks: &[Array2<f64>], times: &Array1<f64>) -> RoiBuilder {
+        let mut builder = RoiBuilder::new(RoiParams::default());
+        for (scan, &rt) in peaks.iter().zip(times.iter()) {
+            let mz = scan.column(0).to_vec();
+            let intensity = scan.column(1).to_vec();
+            builder.push_scan(0, rt, &mz, &intensity).unwrap();
+        }
+        builder
+    }
+
+    #[test]
+    fn pushed_scans_match_compute_roi() {
+        let (peaks_list, times_list) = synthetic_runs(&[150.05, 250.1, 300.2], 1, 60, 1);
+        let (mzroi, msroi, _) = compute_roi(&peaks_list, &times_list, RoiParams::default());
+
+        let result = build(&peaks_list[0], &times_list[0]).finish();
+        assert_eq!(result.mzroi, mzroi);
+        assert_eq!(result.msroi, msroi);
+        assert_eq!(result.times, times_list[0]);
+        assert!(result.runs.iter().all(|&run| run == 0));
+        assert_eq!(result.mzroi.len(), 3);
+    }
+
+    #[test]
+    fn every_scan_adds_a_row() {
+        let mut builder = RoiBuilder::new(RoiParams::default());
+        builder.push_scan(0, 0.0, &[], &[]).unwrap();
+        builder
+            .push_scan(0, 1.0, &[200.0, 300.0], &[5e4, 10.0])
+            .unwrap();
+        builder.push_scan(1, 2.0, &[200.1], &[6e4]).unwrap();
+        builder.push_scan(1, 3.0, &[], &[]).unwrap();
+        assert_eq!(builder.n_scans(), 4);
+
+        let result = builder.finish();
+        assert_eq!(result.msroi.dim(), (4, 1));
+        assert_eq!(result.msroi.column(0).to_vec(), vec![0.0, 5e4, 6e4, 0.0]);
+        assert_eq!(result.runs.to_vec(), vec![0, 0, 1, 1]);
+        assert_eq!(result.times.to_vec(), vec![0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn mismatched_arrays_are_rejected() {
+        let mut builder = RoiBuilder::new(RoiParams::default());
+        let error = builder.push_scan(0, 0.0, &[200.0, 300.0], &[5e4]);
+        assert!(matches!(error, Err(RoiError::InvalidArgument(_))));
+        assert_eq!(builder.n_scans(), 0);
+    }
+
+    #[test]
+    fn sparse_finish_matches_dense() {
+        let (peaks_list, times_list) = synthetic_runs(&[120.0, 121.0, 400.0], 1, 40, 2);
+        let dense = build(&peaks_list[0], &times_list[0]).finish();
+        for layout in [SparseLayout::Csr, SparseLayout::Csc] {
+            let sparse = build(&peaks_list[0], &times_list[0]).finish_sparse(layout);
+            assert_eq!(sparse.mzroi, dense.mzroi);
+            assert_eq!(sparse.msroi.to_dense(), dense.msroi);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use ndarray::prelude::*;
+
+use std::fs;
+use std::path::Path;
+
+use crate::efa::efa;
+use crate::enums::{ConfigFormat, InitialEstimate, InitialMethod, InputFormat};
+use crate::error::RoiError;
+use crate::mcr::constraints::McrConstraints;
+use crate::mcr::mcr_als;
+use crate::simplisma::simplisma;
+use crate::smoothing::fill_msroi;
+use crate::structs::data::ProcessingResult;
+use crate::structs::options::ProcessingConfig;
+use crate::{compute_roi, compute_roi_parallel, read_run};
+
+/// Parse a processing configuration. Unknown keys are rejected, and the error reports
+/// where they are and the keys allowed there.
+pub fn parse_config(text: &str, format: ConfigFormat) -> Result<ProcessingConfig, RoiError> {
+    let config: ProcessingConfig = match format {
+        ConfigFormat::Toml => toml::from_str(text).map_err(|error| error.to_string()),
+        ConfigFormat::Json => serde_json::from_str(text).map_err(|error| error.to_string()),
+    }
+    .map_err(RoiError::Parse)?;
+
+    if config.input.files.is_empty() {
+        return Err(RoiError::Parse(
+            "No input files in the configuration".to_string(),
+        ));
+    }
+    if config.mcr.as_ref().is_some_and(|mcr| mcr.n_components == 0) {
+        return Err(RoiError::Parse(
+            "The MCR resolution needs at least one component".to_string(),
+        ));
+    }
+    Ok(config)
+}
+
+/// Read a processing configuration from a `.toml` or `.json` file. Relative input paths
+/// are taken from the directory of the configuration file.
+pub fn read_config(path: &Path) -> Result<ProcessingConfig, RoiError> {
+    let format = config_format(path)?;
+    let text = fs::read_to_string(path)
+        .map_err(|error| RoiError::Parse(format!("Can't read {}: {}", path.display(), error)))?;
+    let mut config = parse_config(&text, format).map_err(|error| {
+        RoiError::Parse(format!(
+            "Invalid configuration {}: {}",
+            path.display(),
+            error
+        ))
+    })?;
+
+    if let Some(directory) = path.parent() {
+        for file in config.input.files.iter_mut() {
+            if file.is_relative() {
+                *file = directory.join(&*file);
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Write a processing configuration to a `.toml` or `.json` file.
+pub fn write_config(config: &ProcessingConfig, path: &Path) -> Result<(), RoiError> {
+    let text = match config_format(path)? {
+        ConfigFormat::Toml => toml::to_string_pretty(config).map_err(|error| error.to_string()),
+        ConfigFormat::Json => {
+            serde_json::to_string_pretty(config).map_err(|error| error.to_string())
+        }
+    }
+    .map_err(|error| RoiError::Write(format!("Can't serialise the configuration: {}", error)))?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Run a processing configuration: load the input runs, build their ROIs, fill the
+/// missing scans of the ROI chromatograms and resolve `msroi` by MCR-ALS, the last two
+/// only when the configuration has a `filling` and an `mcr` section. Filling only
+/// replaces the zero cells of `msroi`, see `smoothing::fill_msroi`.
+pub fn run_config(config: &ProcessingConfig) -> Result<ProcessingResult, RoiError> {
+    let mut peaks_list: Vec<Vec<Array2<f64>>> = Vec::new();
+    let mut times_list: Vec<Array1<f64>> = Vec::new();
+
+    for path in config.input.files.iter() {
+        let format = config
+            .input
+            .format
+            .or_else(|| InputFormat::from_path(path))
+            .ok_or_else(|| {
+                RoiError::UnsupportedFormat(format!(
+                    "Can't detect the format of {}, set input.format (mzxml, mzml or netcdf)",
+                    path.display()
+                ))
+            })?;
+        let run = read_run(path, format)?;
+        let peaks = run.get_peaks();
+        if peaks.is_empty() {
+            return Err(RoiError::Parse(format!(
+                "No MS1 scans in {}",
+                path.display()
+            )));
+        }
+        peaks_list.push(peaks);
+        times_list.push(run.get_times());
+    }
+
+    let settings = config.roi.clone();
+    let (mzroi, mut msroi, _) = match config.threads {
+        None | Some(1) => compute_roi(&peaks_list, &times_list, settings),
+        Some(threads) => compute_roi_parallel(&peaks_list, &times_list, settings, threads),
+    };
+    if let Some(method) = &config.filling {
+        msroi = fill_msroi(&msroi, method)?;
+    }
+
+    if config.mcr.is_some() && msroi.ncols() == 0 {
+        return Err(RoiError::Parse(
+            "No ROIs to resolve, lower roi.threshold or roi.min_occ".to_string(),
+        ));
+    }
+    if let Some(mcr) = config
+        .mcr
+        .as_ref()
+        .filter(|mcr| mcr.n_components > msroi.ncols())
+    {
+        return Err(RoiError::Parse(format!(
+            "The MCR resolution asks for {} components but there are only {} ROIs",
+            mcr.n_components,
+            msroi.ncols()
+        )));
+    }
+    let mcr = config
+        .mcr
+        .as_ref()
+        .map(|mcr| {
+            let n_components = mcr.n_components;
+            let initial = match mcr.initial {
+                InitialMethod::Simplisma(noise) => InitialEstimate::Concentrations(
+                    simplisma(&msroi, n_components, noise)?.profiles,
+                ),
+                InitialMethod::Efa => InitialEstimate::Concentrations(efa(&msroi, n_components)?.c),
+            };
+            let constraints = McrConstraints::from_params(&mcr.constraints);
+            mcr_als(&msroi, &initial, &mcr.params, &constraints)
+        })
+        .transpose()?;
+
+    Ok(ProcessingResult {
+        mzroi,
+        msroi,
+        times: Array1::from_iter(times_list.iter().flatten().cloned()),
+        run_sizes: times_list.iter().map(|times| times.len()).collect(),
+        mcr,
+    })
+}
+
+fn config_format(path: &Path) -> Result<ConfigFormat, RoiError> {
+    ConfigFormat::from_path(path).ok_or_else(|| {
+        RoiError::UnsupportedFormat(format!(
+            "Can't detect the format of the configuration {}, use a .toml or .json file",
+            path.display()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::SmoothingMethod;
+    use crate::structs::options::WriteParams;
+    use crate::test_utils::{synthetic_run, temp_path};
+    use crate::writer::write_mzml;
+
+    const TOML: &str = r#"
+threads = 2
+filling = { moving_average = 5 }
+
+[input]
+files = ["a.mzML", "b.mzXML"]
+
+[roi]
+threshold = 500.0
+
+[mcr]
+n_components = 3
+initial = "efa"
+
+[mcr.params]
+max_iter = 20
+"#;
+
+    #[test]
+    fn toml_and_json_are_parsed_with_defaults() {
+        let config = parse_config(TOML, ConfigFormat::Toml).unwrap();
+        assert_eq!(config.threads, Some(2));
+        assert_eq!(config.input.files.len(), 2);
+        assert_eq!(config.input.format, None);
+        assert_eq!(config.roi.threshold, 500.0);
+        assert_eq!(config.roi.min_occ, 1);
+        assert_eq!(config.filling, Some(SmoothingMethod::MovingAverage(5)));
+        let mcr = config.mcr.unwrap();
+        assert_eq!(mcr.n_components, 3);
+        assert_eq!(mcr.initial, InitialMethod::Efa);
+        assert_eq!(mcr.params.max_iter, 20);
+
+        let json = r#"{"input": {"files": ["a.mzML"], "format": "mzml"}}"#;
+        let config = parse_config(json, ConfigFormat::Json).unwrap();
+        assert_eq!(config.input.format, Some(InputFormat::MzMl));
+        assert_eq!(config.roi.threshold, 1000.0);
+        assert!(config.filling.is_none());
+        assert!(config.mcr.is_none());
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        let unknown = TOML.replace("threshold", "treshold");
+        assert!(matches!(
+            parse_config(&unknown, ConfigFormat::Toml),
+            Err(RoiError::Parse(_))
+        ));
+        let empty = r#"{"input": {"files": []}}"#;
+        assert!(parse_config(empty, ConfigFormat::Json).is_err());
+        let no_components = TOML.replace("n_components = 3", "n_components = 0");
+        assert!(parse_config(&no_components, ConfigFormat::Toml).is_err());
+
+        let config = parse_config(TOML, ConfigFormat::Toml).unwrap();
+        assert!(matches!(
+            write_config(&config, &temp_path("config.yaml")),
+            Err(RoiError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn written_configurations_are_read_back() {
+        let config = parse_config(TOML, ConfigFormat::Toml).unwrap();
+        for name in ["config.toml", "config.json"] {
+            let path = temp_path(name);
+            write_config(&config, &path).unwrap();
+            let read = read_config(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            // Relative inputs are resolved from the directory of the configuration
+            let directory = path.parent().unwrap();
+            assert_eq!(read.input.files[0], directory.join("a.mzML"));
+            assert_eq!(read.filling, config.filling);
+            assert_eq!(read.roi.threshold, config.roi.threshold);
+            assert_eq!(read.mcr.unwrap().n_components, 3);
+        }
+    }
+
+    #[test]
+    fn configured_processing_resolves_the_runs() {
+        let first = temp_path("first.mzML");
+        let second = temp_path("second.mzML");
+        let params = WriteParams {
+            zlib: false,
+            double_precision: true,
+        };
+        write_mzml(&synthetic_run(&[150.0, 250.0], 40, 3), &first, &params).unwrap();
+        write_mzml(&synthetic_run(&[150.0, 250.0], 30, 4), &second, &params).unwrap();
+
+        let text = format!(
+            r#"{{"input": {{"files": [{:?}, {:?}]}}, "mcr": {{"n_components": 2}}}}"#,
+            first, second
+        );
+        let config = parse_config(&text, ConfigFormat::Json).unwrap();
+        let result = run_config(&config);
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+
+        let result = result.unwrap();
+        assert_eq!(result.run_sizes, vec![40, 30]);
+        assert_eq!(result.times.len(), 70);
+        assert_eq!(result.msroi.nrows(), 70);
+        assert_eq!(result.mzroi.len(), 2);
+        let mcr = result.mcr.unwrap();
+        assert_eq!(mcr.c.dim(), (70, 2));
+        assert!(mcr.lof < 10.0, "lof {}", mcr.lof);
+
+        let missing = parse_config(&text, ConfigFormat::Json).unwrap();
+        assert!(run_config(&missing).is_err());
+    }
+
+    #[test]
+    fn filling_and_components_are_checked_against_the_rois() {
+        let path = temp_path("filling.mzML");
+        let params = WriteParams {
+            zlib: true,
+            double_precision: false,
+        };
+        write_mzml(&synthetic_run(&[150.0, 250.0], 40, 5), &path, &params).unwrap();
+        let config = |sections: &str| {
+            let text = format!(r#"{{"input": {{"files": [{:?}]}}{}}}"#, path, sections);
+            run_config(&parse_config(&text, ConfigFormat::Json).unwrap())
+        };
+        let raw = config("");
+        let filled = config(r#", "filling": {"moving_average": 5}"#);
+        let too_many = config(r#", "mcr": {"n_components": 3}"#);
+        fs::remove_file(&path).unwrap();
+
+        let (raw, filled) = (raw.unwrap().msroi, filled.unwrap().msroi);
+        assert_eq!(raw.dim(), filled.dim());
+        assert!(raw
+            .iter()
+            .zip(filled.iter())
+            .all(|(&raw, &filled)| raw == 0.0 || raw == filled));
+        assert!(matches!(too_many, Err(RoiError::Parse(_))));
+    }
+}
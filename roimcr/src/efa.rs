@@ -0,0 +1,223 @@
+use ndarray::prelude::*;
+
+use crate::error::RoiError;
+use crate::structs::data::{EfaResult, MovingWindowEfaResult};
+use crate::utils::{symmetric_eigen, truncated_svd};
+
+/// Evolving factor analysis (Maeder, 1987) of the rows (scans) of `d`, e.g. the `msroi`
+/// returned by `compute_roi`, for `n_components` components.
+///
+/// `forward[i, k]` is the `k`-th singular value of the first `i + 1` rows of `d` and
+/// `backward[i, k]` the one of the rows from `i` to the end; `n_components + 1` values are
+/// kept so that the noise level shows in the curves. A component appears (disappears)
+/// where its forward (backward) curve rises above `noise`, the `n_components + 1`-th
+/// singular value of the whole matrix. The initial concentration profile of the `k`-th
+/// component is the minimum of the `k`-th forward curve and of the `n_components - k`-th
+/// backward curve, scaled to unit maximum.
+///
+/// Singular values are computed on the projection of `d` on its leading principal
+/// components, so that each row costs a small eigenvalue problem.
+pub fn efa(d: &Array2<f64>, n_components: usize) -> Result<EfaResult, RoiError> {
+    if n_components == 0 {
+        return Err(RoiError::InvalidArgument(
+            "EFA needs at least one component".to_string(),
+        ));
+    }
+    let n_values = n_components + 1;
+    let (scores, singular) = principal_scores(d, n_values + 10);
+    let n_rows = d.nrows();
+
+    let mut forward: Array2<f64> = Array2::zeros((n_rows, n_values));
+    let mut backward: Array2<f64> = Array2::zeros((n_rows, n_values));
+    let mut gram: Array2<f64> = Array2::zeros((scores.ncols(), scores.ncols()));
+    for i in 0..n_rows {
+        add_outer(&mut gram, scores.row(i));
+        forward.row_mut(i).assign(&singular_values(&gram, n_values));
+    }
+    gram.fill(0.0);
+    for i in (0..n_rows).rev() {
+        add_outer(&mut gram, scores.row(i));
+        backward
+            .row_mut(i)
+            .assign(&singular_values(&gram, n_values));
+    }
+
+    let noise = singular.get(n_components).cloned().unwrap_or(0.0);
+    let appearance: Vec<Option<usize>> = (0..n_components)
+        .map(|k| forward.column(k).iter().position(|&s| s > noise))
+        .collect();
+    let disappearance: Vec<Option<usize>> = (0..n_components)
+        .map(|k| backward.column(k).iter().rposition(|&s| s > noise))
+        .collect();
+
+    let mut c: Array2<f64> = Array2::zeros((n_rows, n_components));
+    for k in 0..n_components {
+        let mut profile = c.column_mut(k);
+        let descending = backward.column(n_components - 1 - k);
+        for i in 0..n_rows {
+            profile[i] = forward[[i, k]].min(descending[i]);
+        }
+        let max = profile.iter().cloned().fold(0.0, f64::max);
+        if max > 0.0 {
+            profile.mapv_inplace(|v| v / max);
+        }
+    }
+
+    Ok(EfaResult {
+        forward,
+        backward,
+        noise,
+        appearance,
+        disappearance,
+        c,
+    })
+}
+
+/// Fixed size moving window EFA (Keller & Massart, 1991): the first `n_values` singular
+/// values of every window of `window` consecutive rows of `d`, row `i` of the result
+/// covering rows `i` to `i + window - 1`.
+///
+/// The local rank of a window is the number of its singular values above `threshold`.
+/// By default the noise level is the median over all windows of their smallest singular
+/// value, scaled to the largest singular value expected from noise alone in a
+/// `window` × `d.ncols()` matrix (Marchenko-Pastur edges).
+pub fn moving_window_efa(
+    d: &Array2<f64>,
+    window: usize,
+    n_values: usize,
+    threshold: Option<f64>,
+) -> Result<MovingWindowEfaResult, RoiError> {
+    if window == 0 || window > d.nrows() {
+        return Err(RoiError::InvalidArgument(format!(
+            "EFA window must hold between one and the number of rows of the data matrix, {}",
+            d.nrows()
+        )));
+    }
+    let n_windows = d.nrows() - window + 1;
+
+    // Windows are small: their singular values come from the window × window Gram matrix
+    let mut all: Array2<f64> = Array2::zeros((n_windows, window));
+    for start in 0..n_windows {
+        let block = d.slice(s![start..start + window, ..]);
+        all.row_mut(start)
+            .assign(&singular_values(&block.dot(&block.t()), window));
+    }
+
+    let threshold = threshold.unwrap_or_else(|| {
+        let mut smallest = all.column(window - 1).to_vec();
+        smallest.sort_by(|a, b| a.total_cmp(b));
+        let (rows, columns) = (window as f64, d.ncols() as f64);
+        let edges = (columns.sqrt() + rows.sqrt()) / (columns.sqrt() - rows.sqrt()).max(1.0);
+        smallest[smallest.len() / 2] * edges
+    });
+    let local_rank = all
+        .axis_iter(Axis(0))
+        .map(|values| values.iter().filter(|&&s| s > threshold).count())
+        .collect();
+
+    Ok(MovingWindowEfaResult {
+        window,
+        singular_values: all.slice(s![.., ..n_values.min(window)]).to_owned(),
+        threshold,
+        local_rank,
+    })
+}
+
+/// Scores of `d` on its leading `k` principal components (without centering), and the
+/// corresponding singular values.
+fn principal_scores(d: &Array2<f64>, k: usize) -> (Array2<f64>, Array1<f64>) {
+    let (_, singular, vt) = truncated_svd(d, k);
+    (d.dot(&vt.t()), singular)
+}
+
+fn add_outer(gram: &mut Array2<f64>, row: ArrayView1<f64>) {
+    for (i, &a) in row.iter().enumerate() {
+        for (j, &b) in row.iter().enumerate() {
+            gram[[i, j]] += a * b;
+        }
+    }
+}
+
+/// First `n` singular values of a matrix from its Gram matrix, zero padded.
+fn singular_values(gram: &Array2<f64>, n: usize) -> Array1<f64> {
+    let (values, _) = symmetric_eigen(gram);
+    Array1::from_shape_fn(n, |k| values.get(k).map_or(0.0, |v| v.max(0.0).sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bilinear, Random};
+
+    /// Three compounds eluting one after the other over 90 scans, with low noise.
+    fn sequential() -> Array2<f64> {
+        let (d, _, _) = bilinear(90, 20, 3, 41);
+        let mut random = Random::new(42);
+        d.mapv(|x| x + 1e-3 * random.uniform())
+    }
+
+    #[test]
+    fn components_appear_in_elution_order() {
+        let d = sequential();
+        let result = efa(&d, 3).unwrap();
+
+        assert_eq!(result.forward.dim(), (90, 4));
+        assert_eq!(result.backward.dim(), (90, 4));
+        let appearance: Vec<usize> = result.appearance.iter().map(|a| a.unwrap()).collect();
+        let disappearance: Vec<usize> = result.disappearance.iter().map(|a| a.unwrap()).collect();
+        assert!(
+            appearance.windows(2).all(|w| w[0] < w[1]),
+            "{:?}",
+            appearance
+        );
+        // The k-th backward value drops once fewer than k + 1 compounds are left
+        assert!(
+            disappearance.windows(2).all(|w| w[0] > w[1]),
+            "{:?}",
+            disappearance
+        );
+
+        // Each profile peaks near its compound apex, at scans 22.5, 45 and 67.5
+        for (k, apex) in [22.5, 45.0, 67.5].iter().enumerate() {
+            let profile = result.c.column(k);
+            let max = (0..90)
+                .max_by(|&a, &b| profile[a].total_cmp(&profile[b]))
+                .unwrap();
+            assert!((max as f64 - apex).abs() < 15.0, "{} at {}", k, max);
+            assert_eq!(profile.iter().cloned().fold(0.0, f64::max), 1.0);
+        }
+    }
+
+    #[test]
+    fn forward_curves_match_full_svd_at_the_end() {
+        let d = sequential();
+        let result = efa(&d, 3).unwrap();
+        let (values, _) = symmetric_eigen(&d.t().dot(&d));
+        for k in 0..4 {
+            let expected = values[k].max(0.0).sqrt();
+            assert!((result.forward[[89, k]] - expected).abs() < 1e-6 * values[0].sqrt());
+            assert!((result.backward[[0, k]] - expected).abs() < 1e-6 * values[0].sqrt());
+        }
+    }
+
+    #[test]
+    fn moving_window_local_rank() {
+        let d = sequential();
+        let result = moving_window_efa(&d, 5, 3, None).unwrap();
+        assert_eq!(result.singular_values.dim(), (86, 3));
+        assert_eq!(result.local_rank.len(), 86);
+        assert!(result.local_rank.iter().all(|&rank| rank <= 3));
+        assert!(result.local_rank[20] >= 1);
+
+        let fixed = moving_window_efa(&d, 5, 3, Some(f64::INFINITY)).unwrap();
+        assert!(fixed.local_rank.iter().all(|&rank| rank == 0));
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let d = sequential();
+        assert!(matches!(efa(&d, 0), Err(RoiError::InvalidArgument(_))));
+        assert!(moving_window_efa(&d, 0, 3, None).is_err());
+        assert!(moving_window_efa(&d, 91, 3, None).is_err());
+    }
+}
@@ -1,6 +1,13 @@
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
 use crate::utils::argsort;
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MzErrorType {
+    #[serde(rename = "da", alias = "dalton")]
     Dalton(f64),
     Ppm(f64),
 }
@@ -14,6 +21,8 @@ impl MzErrorType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MzRoiUpdater {
     Mean,
     Median,
@@ -44,3 +53,275 @@ impl MzRoiUpdater {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmoothingMethod {
+    SavitzkyGolay(usize, usize),
+    MovingAverage(usize),
+    Gaussian(f64),
+}
+
+impl SmoothingMethod {
+    /// Half width, in scans, of the window used by the smoother.
+    pub fn half_window(&self) -> usize {
+        match self {
+            Self::SavitzkyGolay(window, _) | Self::MovingAverage(window) => window / 2,
+            Self::Gaussian(sigma) => (3.0 * sigma).ceil() as usize,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BaselineMethod {
+    AsymmetricLeastSquares(f64, f64),
+    RollingMinimum(usize),
+    Polynomial(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeakDetectionMethod {
+    ContinuousWavelet(f64, f64),
+    LocalMaxima(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialEstimate {
+    Concentrations(Array2<f64>),
+    Spectra(Array2<f64>),
+}
+
+impl InitialEstimate {
+    pub fn n_components(&self) -> usize {
+        match self {
+            Self::Concentrations(c) => c.ncols(),
+            Self::Spectra(st) => st.nrows(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonNegativityMethod {
+    Clipping,
+    Nnls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalisationType {
+    Max,
+    Length,
+    Area,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NmfMethod {
+    #[serde(rename = "mu", alias = "multiplicative_update")]
+    MultiplicativeUpdate,
+    Hals,
+}
+
+/// Ionisation polarity of a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+impl Polarity {
+    /// Polarity from its mzXML (`+`, `-`) or ANDI-MS (`Positive Polarity`) notation.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        if name == "+" || name.starts_with("pos") {
+            Some(Polarity::Positive)
+        } else if name == "-" || name.starts_with("neg") {
+            Some(Polarity::Negative)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    MzXml,
+    MzMl,
+    NetCdf,
+}
+
+impl InputFormat {
+    /// Format from its name, as accepted by `load_data`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "mzxml" | "xml" => Some(InputFormat::MzXml),
+            "mzml" => Some(InputFormat::MzMl),
+            "netcdf" | "cdf" | "nc" => Some(InputFormat::NetCdf),
+            _ => None,
+        }
+    }
+
+    /// Format from the extension of a file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(InputFormat::from_name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    MzXml,
+    MzMl,
+}
+
+impl OutputFormat {
+    /// Format from its name, `mzml` or `mzxml`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "mzxml" => Some(OutputFormat::MzXml),
+            "mzml" => Some(OutputFormat::MzMl),
+            _ => None,
+        }
+    }
+
+    /// Format from the extension of a file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(OutputFormat::from_name)
+    }
+}
+
+/// Profiles a constraint of a processing configuration acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConstraintTarget {
+    C,
+    St,
+    Both,
+}
+
+impl ConstraintTarget {
+    pub fn on_c(&self) -> bool {
+        matches!(self, Self::C | Self::Both)
+    }
+
+    pub fn on_st(&self) -> bool {
+        matches!(self, Self::St | Self::Both)
+    }
+}
+
+/// Method building the initial estimate of a processing configuration: the concentration
+/// profiles of the purest ROIs found by SIMPLISMA with the given noise (in percent), or
+/// those found by EFA.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialMethod {
+    Simplisma(f64),
+    Efa,
+}
+
+impl Default for InitialMethod {
+    fn default() -> Self {
+        InitialMethod::Simplisma(5.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Format from the extension of a configuration file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .as_deref()
+        {
+            Some("toml") => Some(ConfigFormat::Toml),
+            Some("json") => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectFormat {
+    Npz,
+    Hdf5,
+}
+
+impl ProjectFormat {
+    /// Format from the extension of a project file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .as_deref()
+        {
+            Some("npz") => Some(ProjectFormat::Npz),
+            Some("h5") | Some("hdf5") => Some(ProjectFormat::Hdf5),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SparseLayout {
+    Csr,
+    Csc,
+}
+
+impl SparseLayout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "csr" => Some(SparseLayout::Csr),
+            "csc" => Some(SparseLayout::Csc),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    ArrowIpc,
+    Parquet,
+}
+
+impl TableFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "arrow" | "ipc" | "feather" => Some(TableFormat::ArrowIpc),
+            "parquet" | "pq" => Some(TableFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    /// Format from the extension of a table file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(TableFormat::from_name)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TableFormat::ArrowIpc => "arrow",
+            TableFormat::Parquet => "parquet",
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt;
+use std::io;
+
+/// Errors raised while reading MS data files, writing results or checking the arguments
+/// of the processing functions.
+#[derive(Debug)]
+pub enum RoiError {
+    Io(io::Error),
+    Parse(String),
+    Write(String),
+    UnsupportedFormat(String),
+    InvalidArgument(String),
+}
+
+impl fmt::Display for RoiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoiError::Io(error) => write!(f, "{}", error),
+            RoiError::Parse(message) => write!(f, "{}", message),
+            RoiError::Write(message) => write!(f, "{}", message),
+            RoiError::UnsupportedFormat(message) => write!(f, "{}", message),
+            RoiError::InvalidArgument(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RoiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RoiError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RoiError {
+    fn from(error: io::Error) -> Self {
+        RoiError::Io(error)
+    }
+}
@@ -0,0 +1,362 @@
+use csv::WriterBuilder;
+use ndarray::prelude::*;
+
+use std::fs::File;
+use std::path::Path;
+
+use crate::error::RoiError;
+use crate::structs::data::RoiStatistics;
+use crate::structs::options::ExportParams;
+
+/// Write a matrix as delimited text, one row per line and no labels.
+pub fn write_matrix(
+    data: &Array2<f64>,
+    path: &Path,
+    params: &ExportParams,
+) -> Result<(), RoiError> {
+    let rows: Vec<Vec<String>> = data
+        .axis_iter(Axis(0))
+        .map(|row| row.iter().map(|&x| format_value(x, params)).collect())
+        .collect();
+    write_table(rows, path, params)
+}
+
+/// Write `msroi` with the `mzroi` values as column headers and the retention time and run
+/// index of every scan as row labels. Transposed, the ROIs are the rows and the times and
+/// runs the first two rows.
+pub fn write_msroi(
+    msroi: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times: &Array1<f64>,
+    runs: &Array1<usize>,
+    path: &Path,
+    params: &ExportParams,
+) -> Result<(), RoiError> {
+    check_labels(msroi, mzroi, times, runs, path)?;
+
+    let mut header = vec!["time".to_string(), "run".to_string()];
+    header.extend(mzroi.iter().map(|&mz| format_value(mz, params)));
+
+    let mut rows = vec![header];
+    for (i, row) in msroi.axis_iter(Axis(0)).enumerate() {
+        let mut line = vec![format_value(times[i], params), runs[i].to_string()];
+        line.extend(row.iter().map(|&x| format_value(x, params)));
+        rows.push(line);
+    }
+    write_table(rows, path, params)
+}
+
+/// Write one line of metadata per ROI: its column in `msroi`, m/z value, number of scans
+/// and runs in which it was found, the time and run of its apex, and its maximum and total
+/// intensity. Transposed, each ROI is a column.
+pub fn write_roi_metadata(
+    msroi: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times: &Array1<f64>,
+    runs: &Array1<usize>,
+    path: &Path,
+    params: &ExportParams,
+) -> Result<(), RoiError> {
+    check_labels(msroi, mzroi, times, runs, path)?;
+
+    let header = [
+        "roi",
+        "mz",
+        "n_scans",
+        "n_runs",
+        "apex_time",
+        "apex_run",
+        "max_intensity",
+        "total_intensity",
+    ];
+    let mut rows = vec![header
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>()];
+
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    for (roi, statistics) in roi_statistics(msroi, mzroi, times, runs)?
+        .into_iter()
+        .enumerate()
+    {
+        rows.push(vec![
+            roi.to_string(),
+            format_value(statistics.mz, params),
+            statistics.n_scans.to_string(),
+            statistics.n_runs.to_string(),
+            optional(statistics.apex_time.map(|time| format_value(time, params))),
+            optional(statistics.apex_run.map(|run| run.to_string())),
+            optional(
+                statistics
+                    .max_intensity
+                    .map(|intensity| format_value(intensity, params)),
+            ),
+            format_value(statistics.total_intensity, params),
+        ]);
+    }
+    write_table(rows, path, params)
+}
+
+/// Statistics of every ROI (column) of `msroi`: its m/z value, the number of scans and
+/// runs in which it was found, the time and run of its apex, and its maximum and total
+/// intensity. `times` and `runs` label the rows of `msroi`.
+pub fn roi_statistics(
+    msroi: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times: &Array1<f64>,
+    runs: &Array1<usize>,
+) -> Result<Vec<RoiStatistics>, RoiError> {
+    if mzroi.len() != msroi.ncols() || times.len() != msroi.nrows() || runs.len() != msroi.nrows() {
+        return Err(RoiError::InvalidArgument(format!(
+            "msroi has {} scans and {} ROIs but {} times, {} runs and {} m/z values were given",
+            msroi.nrows(),
+            msroi.ncols(),
+            times.len(),
+            runs.len(),
+            mzroi.len()
+        )));
+    }
+    let statistics = msroi
+        .axis_iter(Axis(1))
+        .enumerate()
+        .map(|(roi, chromatogram)| {
+            let present: Vec<usize> = (0..chromatogram.len())
+                .filter(|&i| chromatogram[i] > 0.0)
+                .collect();
+            let mut present_runs: Vec<usize> = present.iter().map(|&i| runs[i]).collect();
+            present_runs.dedup();
+            let apex = present
+                .iter()
+                .copied()
+                .max_by(|&a, &b| chromatogram[a].total_cmp(&chromatogram[b]));
+
+            RoiStatistics {
+                mz: mzroi[roi],
+                n_scans: present.len(),
+                n_runs: present_runs.len(),
+                apex_time: apex.map(|apex| times[apex]),
+                apex_run: apex.map(|apex| runs[apex]),
+                max_intensity: apex.map(|apex| chromatogram[apex]),
+                total_intensity: chromatogram.sum(),
+            }
+        })
+        .collect();
+    Ok(statistics)
+}
+
+fn check_labels(
+    msroi: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times: &Array1<f64>,
+    runs: &Array1<usize>,
+    path: &Path,
+) -> Result<(), RoiError> {
+    if mzroi.len() != msroi.ncols() || times.len() != msroi.nrows() || runs.len() != msroi.nrows() {
+        return Err(RoiError::Write(format!(
+            "Can't write {}: msroi has {} scans and {} ROIs but {} times, {} runs and {} m/z values were given",
+            path.display(),
+            msroi.nrows(),
+            msroi.ncols(),
+            times.len(),
+            runs.len(),
+            mzroi.len()
+        )));
+    }
+    Ok(())
+}
+
+fn format_value(value: f64, params: &ExportParams) -> String {
+    match params.precision {
+        Some(precision) => format!("{:.*}", precision, value),
+        None => value.to_string(),
+    }
+}
+
+fn write_table(rows: Vec<Vec<String>>, path: &Path, params: &ExportParams) -> Result<(), RoiError> {
+    if !params.delimiter.is_ascii() {
+        return Err(RoiError::Write(format!(
+            "Can't write {}: the delimiter must be an ASCII character",
+            path.display()
+        )));
+    }
+    let rows = if params.transpose {
+        transpose(rows)
+    } else {
+        rows
+    };
+
+    let file = File::create(path)?;
+    let mut writer = WriterBuilder::new()
+        .has_headers(false)
+        .delimiter(params.delimiter as u8)
+        .from_writer(file);
+    let error =
+        |error: csv::Error| RoiError::Write(format!("Can't write {}: {}", path.display(), error));
+    for row in rows.iter() {
+        writer.write_record(row).map_err(error)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn transpose(rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    (0..width)
+        .map(|j| {
+            rows.iter()
+                .map(|row| row.get(j).cloned().unwrap_or_default())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    /// Two runs of two and three scans and three ROIs; the last ROI is only in the
+    /// second run and the middle one is never found.
+    fn rois() -> (Array2<f64>, Array1<f64>, Array1<f64>, Array1<usize>) {
+        let msroi = array![
+            [1.0, 0.0, 0.0],
+            [4.0, 0.0, 0.0],
+            [2.0, 0.0, 5.0],
+            [0.0, 0.0, 7.5],
+            [3.0, 0.0, 1.0]
+        ];
+        let mzroi = array![100.5, 200.25, 300.0];
+        let times = array![1.0, 2.0, 1.5, 2.5, 3.5];
+        let runs = array![0, 0, 1, 1, 1];
+        (msroi, mzroi, times, runs)
+    }
+
+    fn read_lines(path: &Path) -> Vec<String> {
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn statistics_of_every_roi() {
+        let (msroi, mzroi, times, runs) = rois();
+        let statistics = roi_statistics(&msroi, &mzroi, &times, &runs).unwrap();
+
+        assert_eq!(statistics.len(), 3);
+        assert_eq!(statistics[0].n_scans, 4);
+        assert_eq!(statistics[0].n_runs, 2);
+        assert_eq!(statistics[0].apex_time, Some(2.0));
+        assert_eq!(statistics[0].apex_run, Some(0));
+        assert_eq!(statistics[0].total_intensity, 10.0);
+        assert_eq!(statistics[1].n_scans, 0);
+        assert_eq!(statistics[1].n_runs, 0);
+        assert_eq!(statistics[1].apex_time, None);
+        assert_eq!(statistics[1].apex_run, None);
+        assert_eq!(statistics[1].max_intensity, None);
+        assert_eq!(statistics[1].total_intensity, 0.0);
+        assert_eq!(statistics[2].n_runs, 1);
+        assert_eq!(statistics[2].apex_time, Some(2.5));
+        assert_eq!(statistics[2].max_intensity, Some(7.5));
+
+        let short = array![1.0, 2.0];
+        assert!(matches!(
+            roi_statistics(&msroi, &mzroi, &short, &runs),
+            Err(RoiError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn tables_follow_the_export_parameters() {
+        let (msroi, mzroi, times, runs) = rois();
+
+        let path = temp_path("matrix.csv");
+        write_matrix(&msroi, &path, &ExportParams::default()).unwrap();
+        assert_eq!(read_lines(&path)[3], "0,0,7.5");
+
+        let params = ExportParams {
+            delimiter: '\t',
+            precision: Some(2),
+            transpose: false,
+        };
+        let path = temp_path("msroi.tsv");
+        write_msroi(&msroi, &mzroi, &times, &runs, &path, &params).unwrap();
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "time\trun\t100.50\t200.25\t300.00");
+        assert_eq!(lines[3], "1.50\t1\t2.00\t0.00\t5.00");
+
+        let params = ExportParams {
+            transpose: true,
+            ..ExportParams::default()
+        };
+        let path = temp_path("msroi.csv");
+        write_msroi(&msroi, &mzroi, &times, &runs, &path, &params).unwrap();
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "time,1,2,1.5,2.5,3.5");
+        assert_eq!(lines[1], "run,0,0,1,1,1");
+        assert_eq!(lines[2], "100.5,1,4,2,0,3");
+    }
+
+    #[test]
+    fn metadata_has_one_row_per_roi() {
+        let (msroi, mzroi, times, runs) = rois();
+        let path = temp_path("rois.csv");
+        write_roi_metadata(
+            &msroi,
+            &mzroi,
+            &times,
+            &runs,
+            &path,
+            &ExportParams::default(),
+        )
+        .unwrap();
+        let lines = read_lines(&path);
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "roi,mz,n_scans,n_runs,apex_time,apex_run,max_intensity,total_intensity"
+        );
+        assert_eq!(lines[1], "0,100.5,4,2,2,0,4,10");
+        assert_eq!(lines[3], "2,300,3,1,2.5,1,7.5,13.5");
+    }
+
+    #[test]
+    fn invalid_tables_are_rejected() {
+        let (msroi, mzroi, times, runs) = rois();
+        let path = temp_path("invalid.csv");
+        let short = array![100.5, 200.25];
+        assert!(matches!(
+            write_roi_metadata(
+                &msroi,
+                &mzroi,
+                &times,
+                &array![0, 1],
+                &path,
+                &ExportParams::default()
+            ),
+            Err(RoiError::Write(_))
+        ));
+        assert!(matches!(
+            write_msroi(
+                &msroi,
+                &short,
+                &times,
+                &runs,
+                &path,
+                &ExportParams::default()
+            ),
+            Err(RoiError::Write(_))
+        ));
+        let params = ExportParams {
+            delimiter: '→',
+            ..ExportParams::default()
+        };
+        assert!(matches!(
+            write_matrix(&msroi, &path, &params),
+            Err(RoiError::Write(_))
+        ));
+        assert!(!path.exists());
+    }
+}
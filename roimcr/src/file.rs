@@ -1,48 +1,216 @@
+use crate::binary::{decode, to_floats, ByteOrder};
+use crate::enums::Polarity;
+use crate::error::RoiError;
 use crate::structs::io::{MsRun, MzXML, Peak, Scan};
 use crate::utils::subset;
 use netcdf::*;
-use quick_xml::de::{from_str, DeError};
+use quick_xml::de::from_str;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
 use std::{fs, path::Path};
 
-pub fn load_mzxml(path: &Path) -> Result<MsRun, DeError> {
-    let input = fs::read_to_string(path).expect("Error in reading the file");
-    let mut result: MzXML = from_str(&input)?;
-    result.ms_run.name = path
-        .file_stem()
-        .expect("No file name")
-        .to_str()
-        .expect("Can't convert filname to string")
-        .to_string();
+pub fn load_mzxml(path: &Path) -> Result<MsRun, RoiError> {
+    let input = fs::read_to_string(path)
+        .map_err(|error| RoiError::Parse(format!("Can't read {}: {}", path.display(), error)))?;
+    let mut result: MzXML = from_str(&input).map_err(|error| {
+        RoiError::Parse(format!(
+            "Can't parse {} as mzXML: {}",
+            path.display(),
+            error
+        ))
+    })?;
+    result.ms_run.name = run_name(path)?;
 
     Ok(result.ms_run)
 }
 
-pub fn load_netcdf(path: &Path) -> Result<MsRun, DeError> {
+/// Binary array of an mzML spectrum being read.
+#[derive(Default)]
+struct BinaryArray {
+    double: bool,
+    zlib: bool,
+    mz: bool,
+    intensity: bool,
+    text: String,
+}
+
+/// Read the spectra of an mzML file, with their m/z and intensity arrays stored as 32 or
+/// 64-bit floats, uncompressed or zlib compressed. Retention times are converted to
+/// seconds as in the other formats.
+pub fn load_mzml(path: &Path) -> Result<MsRun, RoiError> {
+    let input = fs::read_to_string(path)
+        .map_err(|error| RoiError::Parse(format!("Can't read {}: {}", path.display(), error)))?;
+    let invalid = |message: String| {
+        RoiError::Parse(format!(
+            "Can't parse {} as mzML: {}",
+            path.display(),
+            message
+        ))
+    };
+
+    let mut run = MsRun::new();
+    run.name = run_name(path)?;
+
+    let mut reader = Reader::from_str(&input);
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+
+    let mut spectrum: Option<Scan> = None;
+    let mut mz: Vec<f64> = Vec::new();
+    let mut intensities: Vec<f64> = Vec::new();
+    let mut array: Option<BinaryArray> = None;
+    let mut in_binary = false;
+
+    loop {
+        match reader
+            .read_event(&mut buffer)
+            .map_err(|error| invalid(error.to_string()))?
+        {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name() == b"spectrum" =>
+            {
+                spectrum = Some(Scan {
+                    num: run.scans.len() as u64 + 1,
+                    ms_level: 1,
+                    peaks_count: 0,
+                    retention_time: 0.0,
+                    polarity: None,
+                    peaks: Vec::new(),
+                });
+                mz.clear();
+                intensities.clear();
+            }
+            Event::Start(element)
+                if element.local_name() == b"binaryDataArray" && spectrum.is_some() =>
+            {
+                array = Some(BinaryArray::default());
+            }
+            Event::Start(element) if element.local_name() == b"binary" => {
+                in_binary = array.is_some();
+            }
+            Event::Start(element) | Event::Empty(element) if element.local_name() == b"cvParam" => {
+                let accession = attribute(&element, &reader, b"accession")?;
+                if let Some(array) = array.as_mut() {
+                    match accession.as_str() {
+                        "MS:1000523" => array.double = true,
+                        "MS:1000521" => array.double = false,
+                        "MS:1000574" => array.zlib = true,
+                        "MS:1000576" => array.zlib = false,
+                        "MS:1000514" => array.mz = true,
+                        "MS:1000515" => array.intensity = true,
+                        "MS:1002312" | "MS:1002313" | "MS:1002314" | "MS:1002746"
+                        | "MS:1002747" | "MS:1002748" => {
+                            return Err(invalid(
+                                "MS-Numpress compressed arrays aren't supported".to_string(),
+                            ));
+                        }
+                        _ => {}
+                    }
+                } else if let Some(scan) = spectrum.as_mut() {
+                    let value = || attribute(&element, &reader, b"value");
+                    match accession.as_str() {
+                        "MS:1000511" => {
+                            scan.ms_level = value()?
+                                .parse()
+                                .map_err(|_| invalid("Invalid MS level".to_string()))?;
+                        }
+                        "MS:1000130" => scan.polarity = Some(Polarity::Positive),
+                        "MS:1000129" => scan.polarity = Some(Polarity::Negative),
+                        "MS:1000016" => {
+                            let time: f64 = value()?
+                                .parse()
+                                .map_err(|_| invalid("Invalid scan start time".to_string()))?;
+                            let unit = attribute(&element, &reader, b"unitAccession")?;
+                            scan.retention_time = if unit == "UO:0000031" {
+                                time * 60.0
+                            } else {
+                                time
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::Text(text) if in_binary => {
+                if let Some(array) = array.as_mut() {
+                    array.text.push_str(
+                        &text
+                            .unescape_and_decode(&reader)
+                            .map_err(|error| invalid(error.to_string()))?,
+                    );
+                }
+            }
+            Event::End(element) if element.local_name() == b"binary" => in_binary = false,
+            Event::End(element) if element.local_name() == b"binaryDataArray" => {
+                if let Some(array) = array.take() {
+                    let values = decode(&array.text, array.zlib)
+                        .and_then(|bytes| to_floats(&bytes, array.double, ByteOrder::LittleEndian))
+                        .map_err(invalid)?;
+                    if array.mz {
+                        mz = values;
+                    } else if array.intensity {
+                        intensities = values;
+                    }
+                }
+            }
+            Event::End(element) if element.local_name() == b"spectrum" => {
+                if let Some(mut scan) = spectrum.take() {
+                    if mz.len() != intensities.len() {
+                        return Err(invalid(format!(
+                            "Spectrum {} has {} m/z values and {} intensities",
+                            scan.num,
+                            mz.len(),
+                            intensities.len()
+                        )));
+                    }
+                    scan.peaks = mz
+                        .iter()
+                        .zip(intensities.iter())
+                        .map(|(&mz, &intensity)| Peak { mz, intensity })
+                        .collect();
+                    scan.peaks_count = scan.peaks.len() as u64;
+                    run.scans.push(scan);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok(run)
+}
+
+/// Value of an attribute of an XML element, empty if it is missing.
+fn attribute<B: std::io::BufRead>(
+    element: &BytesStart,
+    reader: &Reader<B>,
+    name: &[u8],
+) -> Result<String, RoiError> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|error| RoiError::Parse(error.to_string()))?;
+        if attribute.key == name {
+            return attribute
+                .unescape_and_decode_value(reader)
+                .map_err(|error| RoiError::Parse(error.to_string()));
+        }
+    }
+    Ok(String::new())
+}
+
+pub fn load_netcdf(path: &Path) -> Result<MsRun, RoiError> {
     let mut run: MsRun = MsRun::new();
-    run.name = path
-        .file_stem()
-        .expect("No file name")
-        .to_str()
-        .expect("Can't convert filname to string")
-        .to_string();
-    let file = open(path).expect("Can't read netcdf file");
-    // for var in file.variables(){
-    //     println!("{}", var.name())
-    // }
-
-    let scan_index = file
-        .variable("scan_index")
-        .unwrap_or_else(|| panic!("Can't find scan_index in variables"))
-        .values::<u32>(None, None)
-        .expect("Can't read values")
-        .into_raw_vec();
-
-    let points_per_scan = file
-        .variable("point_count")
-        .unwrap_or_else(|| panic!("Can't find point_count in variables"))
-        .values::<u32>(None, None)
-        .expect("Can't read values")
-        .into_raw_vec();
+    run.name = run_name(path)?;
+    let file = open(path).map_err(|error| {
+        RoiError::Parse(format!(
+            "Can't read {} as netCDF: {}",
+            path.display(),
+            error
+        ))
+    })?;
+
+    let scan_index = read_variable::<u32>(&file, "scan_index", path)?;
+    let points_per_scan = read_variable::<u32>(&file, "point_count", path)?;
 
     let scans = scan_index
         .iter()
@@ -50,26 +218,29 @@ pub fn load_netcdf(path: &Path) -> Result<MsRun, DeError> {
         .map(|(&a, &b)| ((a as usize)..((a + b) as usize)).collect())
         .collect::<Vec<Vec<usize>>>();
 
-    let scan_time = file
-        .variable("scan_acquisition_time")
-        .unwrap_or_else(|| panic!("Can't find scan_acquisition_time in variables"))
-        .values::<f64>(None, None)
-        .expect("Can't read values")
-        .into_raw_vec();
-
-    let intensity_values = file
-        .variable("intensity_values")
-        .unwrap_or_else(|| panic!("Can't find intensity_values in variables"))
-        .values::<f64>(None, None)
-        .expect("Can't read values")
-        .into_raw_vec();
-
-    let mass_values = file
-        .variable("mass_values")
-        .unwrap_or_else(|| panic!("Can't find mass_values in variables"))
-        .values::<f64>(None, None)
-        .expect("Can't read values")
-        .into_raw_vec();
+    let scan_time = read_variable::<f64>(&file, "scan_acquisition_time", path)?;
+    let intensity_values = read_variable::<f64>(&file, "intensity_values", path)?;
+    let mass_values = read_variable::<f64>(&file, "mass_values", path)?;
+    // ANDI-MS stores a single polarity for the whole run
+    let polarity = match file
+        .attribute("test_ionization_polarity")
+        .and_then(|attribute| attribute.value().ok())
+    {
+        Some(AttrValue::Str(name)) => Polarity::from_name(&name),
+        _ => None,
+    };
+
+    if scan_time.len() < scans.len()
+        || scans
+            .iter()
+            .flatten()
+            .any(|&i| i >= mass_values.len() || i >= intensity_values.len())
+    {
+        return Err(RoiError::Parse(format!(
+            "Inconsistent scan indices in {}",
+            path.display()
+        )));
+    }
 
     for (i, indices) in scans.iter().enumerate() {
         let mz = subset(&mass_values, indices);
@@ -89,8 +260,37 @@ pub fn load_netcdf(path: &Path) -> Result<MsRun, DeError> {
             ms_level: 1,
             peaks_count: indices.len() as u64,
             retention_time: scan_time[i],
+            polarity,
             peaks,
         });
     }
     Ok(run)
 }
+
+fn run_name(path: &Path) -> Result<String, RoiError> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| stem.to_string())
+        .ok_or_else(|| RoiError::Parse(format!("Invalid file name: {}", path.display())))
+}
+
+fn read_variable<T: Numeric>(file: &File, name: &str, path: &Path) -> Result<Vec<T>, RoiError> {
+    file.variable(name)
+        .ok_or_else(|| {
+            RoiError::Parse(format!(
+                "Can't find {} in the variables of {}",
+                name,
+                path.display()
+            ))
+        })?
+        .values::<T>(None, None)
+        .map(|values| values.into_raw_vec())
+        .map_err(|error| {
+            RoiError::Parse(format!(
+                "Can't read {} from {}: {}",
+                name,
+                path.display(),
+                error
+            ))
+        })
+}
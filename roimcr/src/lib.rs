@@ -1,181 +1,340 @@
+pub mod baseline;
+pub mod batch;
+mod binary;
+pub mod builder;
+pub mod config;
+pub mod efa;
 pub mod enums;
+pub mod error;
+pub mod export;
 mod file;
+pub mod mat;
+pub mod mcr;
+pub mod nmf;
+pub mod parafac2;
+pub mod peaks;
+pub mod project;
+pub mod rank;
+pub mod report;
+pub mod simplisma;
+pub mod smoothing;
+pub mod sparse;
 pub mod structs;
+#[cfg(feature = "arrow")]
+pub mod tables;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod writer;
 
 use ndarray::prelude::*;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use std::path::Path;
 
-use crate::file::{load_mzxml, load_netcdf};
-use crate::structs::data::Roicell;
-use crate::structs::options::RoiParams;
-use crate::utils::{argsort, sort_by_indices, subset};
+use crate::builder::RoiBuilder;
+use crate::enums::{InputFormat, OutputFormat, SparseLayout};
+use crate::error::RoiError;
+use crate::file::{load_mzml, load_mzxml, load_netcdf};
+use crate::structs::data::{Roicell, SparseMatrix};
+use crate::structs::io::MsRun;
+use crate::structs::options::{RoiParams, WriteParams};
+use crate::writer::{write_mzml, write_mzxml};
 
 pub fn load_data(path: &str, format: &str) -> (Vec<Array2<f64>>, Array1<f64>) {
-    let parsed = match format.to_lowercase().as_str() {
-        "mzxml" | "xml" => load_mzxml(Path::new(path)).expect("Error in parsing the file"),
-        "netcdf" | "cdf" => load_netcdf(Path::new(path)).expect("Error in parsing the file"),
-        _ => panic!("File format not supported! Supported formats: 'mzxml', 'netcdf'"),
-    };
+    let format = InputFormat::from_name(format).unwrap_or_else(|| {
+        panic!("File format not supported! Supported formats: 'mzxml', 'mzml', 'netcdf'")
+    });
+    let parsed = read_run(Path::new(path), format).unwrap_or_else(|error| panic!("{}", error));
     let peaks = parsed.get_peaks();
     let times = parsed.get_times();
     (peaks, times)
 }
 
+/// Read a run from a file in the given format.
+pub fn read_run(path: &Path, format: InputFormat) -> Result<MsRun, RoiError> {
+    match format {
+        InputFormat::MzXml => load_mzxml(path),
+        InputFormat::MzMl => load_mzml(path),
+        InputFormat::NetCdf => load_netcdf(path),
+    }
+}
+
+/// Write a run to a file in the given format.
+pub fn write_run(
+    run: &MsRun,
+    path: &Path,
+    format: OutputFormat,
+    params: &WriteParams,
+) -> Result<(), RoiError> {
+    match format {
+        OutputFormat::MzXml => write_mzxml(run, path, params),
+        OutputFormat::MzMl => write_mzml(run, path, params),
+    }
+}
+
+/// Width, in multiples of the m/z tolerance, of the region shared by adjacent partitions
+/// in `compute_roi_parallel`.
+const PARTITION_OVERLAP: f64 = 10.0;
+
 pub fn compute_roi(
-    peaks_list: &Vec<Vec<Array2<f64>>>,
-    times_list: &Vec<Array1<f64>>,
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
     settings: RoiParams,
 ) -> (Array1<f64>, Array2<f64>, Roicell) {
-    let mut merged_peaks: Vec<Array2<f64>> = Vec::new();
-    let mut times: Vec<f64> = Vec::new();
+    let result = build_roi(peaks_list, times_list, settings).finish();
 
-    if peaks_list.len() > 1 {
-        for (scan_num, scan) in peaks_list.iter().enumerate() {
-            for (peak_num, peak) in scan.iter().enumerate() {
-                merged_peaks.push(peak.to_owned());
-                times.push((peak_num + (scan_num * scan.len())) as f64);
-            }
-        }
+    (result.mzroi, result.msroi, result.roicell)
+}
+
+/// Same as `compute_roi`, but the m/z axis is split in overlapping partitions whose ROIs
+/// are built concurrently on `n_threads` threads (all available cores if 0).
+/// Each partition keeps the ROIs whose m/z falls in its own range, the union is then
+/// merged and filtered exactly as in the serial path. Falls back to the serial path if
+/// the thread pool can't be created.
+///
+/// Partitions share `PARTITION_OVERLAP` (10) times the m/z tolerance on each side of
+/// their bounds, so the result equals that of `compute_roi` as long as no ROI drifts, or
+/// chains through neighbouring peaks, further than that past a bound. Otherwise a ROI
+/// crossing a bound may be split in two or its mean m/z may differ slightly.
+pub fn compute_roi_parallel(
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+    settings: RoiParams,
+    n_threads: usize,
+) -> (Array1<f64>, Array2<f64>, Roicell) {
+    let result = build_roi_parallel(peaks_list, times_list, settings, n_threads).finish();
+
+    (result.mzroi, result.msroi, result.roicell)
+}
+
+/// Same as `compute_roi`, or `compute_roi_parallel` when `n_threads` isn't 1, with `msroi`
+/// built directly as a sparse matrix with `layout`. With many augmented runs and a tight
+/// m/z tolerance most of `msroi` is zeros and the dense matrix may not fit in memory.
+pub fn compute_roi_sparse(
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+    settings: RoiParams,
+    layout: SparseLayout,
+    n_threads: usize,
+) -> (Array1<f64>, SparseMatrix, Roicell) {
+    let builder = if n_threads == 1 {
+        build_roi(peaks_list, times_list, settings)
     } else {
-        merged_peaks = peaks_list[0].to_owned();
-        times = times_list[0].to_vec();
-    }
+        build_roi_parallel(peaks_list, times_list, settings, n_threads)
+    };
+    let result = builder.finish_sparse(layout);
 
-    let merged_times = Array1::from_vec(times);
+    (result.mzroi, result.msroi, result.roicell)
+}
 
-    let mut mzroi: Vec<f64> = Vec::new();
-    let mut roicell: Roicell = Roicell::new();
+/// ROIs of all the scans, not consolidated yet.
+fn build_roi(
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+    settings: RoiParams,
+) -> RoiBuilder {
+    let mut builder = RoiBuilder::new(settings);
 
-    for (scan, peaks) in merged_peaks.iter().enumerate() {
-        if peaks.is_empty() {
-            continue;
-        }
-        let indices = peaks
-            .axis_iter(Axis(0))
-            .enumerate()
-            .filter(|(_, r)| r[1] > settings.threshold)
-            .map(|(index, _)| index)
-            .collect::<Vec<_>>();
+    for (run, time, peaks) in augmented_scans(peaks_list, times_list) {
+        push_peaks(&mut builder, run, time, peaks, |_| true);
+    }
+    builder
+}
 
-        if indices.is_empty() {
-            continue;
-        }
+/// Same as `build_roi`, over partitions of the m/z axis, see `compute_roi_parallel`.
+fn build_roi_parallel(
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+    settings: RoiParams,
+    n_threads: usize,
+) -> RoiBuilder {
+    // Without a thread pool the partitions can't be built concurrently
+    let pool = match ThreadPoolBuilder::new().num_threads(n_threads).build() {
+        Ok(pool) => pool,
+        Err(_) => return build_roi(peaks_list, times_list, settings),
+    };
 
-        let mz = subset(&peaks.index_axis(Axis(1), 0).to_vec(), &indices);
-        let intensities = subset(&peaks.index_axis(Axis(1), 1).to_vec(), &indices);
+    let scans = augmented_scans(peaks_list, times_list);
 
-        if scan == 1 {
-            mzroi.push(mz[0]);
-        }
+    let mut mz_values: Vec<f64> = scans
+        .iter()
+        .filter(|(_, _, peaks)| !peaks.is_empty())
+        .flat_map(|(_, _, peaks)| {
+            peaks
+                .axis_iter(Axis(0))
+                .filter(|peak| peak[1] > settings.threshold)
+                .map(|peak| peak[0])
+        })
+        .collect();
+    mz_values.sort_unstable_by(|a, b| a.total_cmp(b));
 
-        for i in 0..mz.len() {
-            let compatible_rois: Vec<usize> = mzroi
-                .iter()
-                .enumerate()
-                .filter(|(_, &roi_value)| {
-                    (roi_value - mz[i]).abs() <= settings.mz_error.get_error(&mz[i])
-                })
-                .map(|(index, _)| index)
-                .collect();
-
-            if compatible_rois.is_empty() {
-                roicell.new_roi();
-                roicell.update_roi(
-                    roicell.mzs.len() - 1,
-                    &settings.mzroi_updater,
-                    mz[i],
-                    merged_times[scan],
-                    intensities[i],
-                    scan,
-                );
-                mzroi.push(roicell.mz_roi[roicell.mzs.len() - 1])
-            } else {
-                for &roi in compatible_rois.iter() {
-                    roicell.update_roi(
-                        roi,
-                        &settings.mzroi_updater,
-                        mz[i],
-                        merged_times[scan],
-                        intensities[i],
-                        scan,
-                    );
-                    mzroi[roi] = roicell.mz_roi[roi];
-                }
-            }
-        }
+    let n_partitions = pool.current_num_threads().min(mz_values.len());
+    if n_partitions < 2 {
+        return build_roi(peaks_list, times_list, settings);
     }
 
-    let sorted_roi_index = argsort(&mzroi);
+    let mut bounds: Vec<f64> = vec![f64::NEG_INFINITY];
+    bounds.extend((1..n_partitions).map(|i| mz_values[i * mz_values.len() / n_partitions]));
+    bounds.push(f64::INFINITY);
+    bounds.dedup();
 
-    sort_by_indices(&mut mzroi, &sorted_roi_index);
+    let overlap = |bound: f64| {
+        if bound.is_finite() {
+            PARTITION_OVERLAP * settings.mz_error.get_error(&bound)
+        } else {
+            0.0
+        }
+    };
 
-    sort_by_indices(&mut roicell.mzs, &sorted_roi_index);
-    sort_by_indices(&mut roicell.times, &sorted_roi_index);
-    sort_by_indices(&mut roicell.intensities, &sorted_roi_index);
-    sort_by_indices(&mut roicell.scans, &sorted_roi_index);
-    sort_by_indices(&mut roicell.mz_roi, &sorted_roi_index);
+    let mut partitions: Vec<RoiBuilder> = pool.install(|| {
+        bounds
+            .par_windows(2)
+            .map(|range| {
+                let (low, high) = (range[0], range[1]);
+                let (ext_low, ext_high) = (low - overlap(low), high + overlap(high));
 
-    // Raggruppa roi con mz simili
-    loop {
-        let mz_error_roi: Vec<f64> = mzroi.windows(2).map(|w| (w[0] - w[1]).abs()).collect();
+                let mut builder = RoiBuilder::new(settings.clone());
+                for &(run, time, peaks) in scans.iter() {
+                    push_peaks(&mut builder, run, time, peaks, |mz| {
+                        mz >= ext_low && mz < ext_high
+                    });
+                }
+                builder.retain_rois(|mz| mz >= low && mz < high);
+                builder
+            })
+            .collect()
+    });
 
-        let mergable_roi: Vec<usize> = mz_error_roi
-            .iter()
-            .enumerate()
-            .filter(|(_, &mz_diff)| mz_diff < settings.mz_error.get_error(&mzroi[mzroi.len() - 2]))
-            .map(|(index, _)| index)
-            .collect();
+    let mut builder = partitions.remove(0);
+    for partition in partitions {
+        builder.absorb(partition);
+    }
+    builder
+}
 
-        if mergable_roi.is_empty() {
-            break;
-        }
+/// Scans of every run in the order they are stacked in `msroi`, with their run index and
+/// the time used for the ROIs. When more than one run is given the times are replaced by
+/// the scan position in the augmented matrix.
+fn augmented_scans<'a>(
+    peaks_list: &'a [Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+) -> Vec<(usize, f64, &'a Array2<f64>)> {
+    let mut scans = Vec::new();
 
-        mzroi[mergable_roi[0]] = roicell.mz_roi[mergable_roi[0] + 1];
-        roicell.merge_roi(
-            mergable_roi[0],
-            mergable_roi[0] + 1,
-            &settings.mzroi_updater,
-        );
-        mzroi.remove(mergable_roi[0] + 1);
+    if peaks_list.len() > 1 {
+        for (scan_num, scan) in peaks_list.iter().enumerate() {
+            for (peak_num, peak) in scan.iter().enumerate() {
+                scans.push((scan_num, (peak_num + (scan_num * scan.len())) as f64, peak));
+            }
+        }
+    } else {
+        for (peak, &time) in peaks_list[0].iter().zip(times_list[0].iter()) {
+            scans.push((0, time, peak));
+        }
     }
 
-    // Filtrare per minimo numero di mz e minima intensità massima in roi
-    let roi_num: Vec<u32> = roicell.mzs.iter().map(|i| i.len() as u32).collect();
-    let max_int: Vec<f64> = roicell
-        .intensities
-        .iter()
-        .map(|i| *i.iter().max_by(|a, b| a.total_cmp(b)).unwrap())
-        .collect();
+    scans
+}
 
-    let mut removable_roi: Vec<usize> = roi_num
-        .iter()
-        .zip(max_int.iter())
-        .enumerate()
-        .filter(|(_, (&n, &int))| {
-            (n <= settings.min_occ) || (int <= settings.threshold * settings.t_factor)
-        })
-        .map(|(i, (_, _))| i)
-        .collect::<Vec<usize>>();
+fn push_peaks<F: Fn(f64) -> bool>(
+    builder: &mut RoiBuilder,
+    run: usize,
+    time: f64,
+    peaks: &Array2<f64>,
+    keep: F,
+) {
+    let (mz, intensities): (Vec<f64>, Vec<f64>) = if peaks.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        peaks
+            .axis_iter(Axis(0))
+            .filter(|peak| keep(peak[0]))
+            .map(|peak| (peak[0], peak[1]))
+            .unzip()
+    };
+    builder
+        .push_scan(run, time, &mz, &intensities)
+        .expect("The m/z and intensity columns of a peaks matrix have the same length");
+}
 
-    removable_roi.reverse();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::MzErrorType;
+    use crate::test_utils::{synthetic_runs, Random};
 
-    for roi in removable_roi {
-        roicell.remove_roi(roi);
-        mzroi.remove(roi);
+    fn assert_same_rois(
+        parallel: &(Array1<f64>, Array2<f64>, Roicell),
+        serial: &(Array1<f64>, Array2<f64>, Roicell),
+    ) {
+        assert_eq!(parallel.0, serial.0);
+        assert_eq!(parallel.1, serial.1);
+        assert_eq!(parallel.2.mzs, serial.2.mzs);
+        assert_eq!(parallel.2.times, serial.2.times);
+        assert_eq!(parallel.2.intensities, serial.2.intensities);
+        assert_eq!(parallel.2.scans, serial.2.scans);
+        assert_eq!(parallel.2.mz_roi, serial.2.mz_roi);
     }
 
-    let mzroi: Array1<f64> = arr1(&mzroi);
-    let mut msroi: Array2<f64> = Array2::zeros((merged_peaks.len(), mzroi.len()));
+    #[test]
+    fn parallel_matches_serial_on_augmented_runs() {
+        let mzs = [101.0, 150.05, 150.6, 151.2, 250.1, 250.4, 300.2, 450.0];
+        let (peaks_list, times_list) = synthetic_runs(&mzs, 3, 80, 3);
+        let serial = compute_roi(&peaks_list, &times_list, RoiParams::default());
+        assert!(serial.0.len() >= 4);
 
-    for i in 0..mzroi.len() {
-        let nval = roicell.scans[i].len();
-        for j in 0..nval {
-            let irow = roicell.scans[i][j] as usize;
-            let msi = roicell.intensities[i][j];
-            msroi[[irow, i]] += msi;
+        for n_threads in [2, 3, 4, 7, 0] {
+            let parallel =
+                compute_roi_parallel(&peaks_list, &times_list, RoiParams::default(), n_threads);
+            assert_same_rois(&parallel, &serial);
         }
     }
 
-    (mzroi, msroi, roicell)
+    #[test]
+    fn parallel_matches_serial_across_partition_bounds() {
+        // One compound holds most of the peaks, with an m/z spread wider than the gap
+        // between its peaks, so that the bounds at the quantiles of the peak masses fall
+        // inside its ROI and the partitions around them both see part of its peaks
+        let mut random = Random::new(4);
+        let times = Array1::from_shape_fn(200, |i| i as f64);
+        let peaks: Vec<Array2<f64>> = (0..200)
+            .map(|i| {
+                let mut scan = vec![200.0 + 0.3 * (random.uniform() - 0.5), 5e4];
+                if i % 3 == 0 {
+                    scan.extend([320.0 + 0.01 * random.uniform(), 2e4]);
+                }
+                Array2::from_shape_vec((scan.len() / 2, 2), scan).unwrap()
+            })
+            .collect();
+        let peaks_list = vec![peaks];
+        let times_list = vec![times];
+        let settings = RoiParams {
+            mz_error: MzErrorType::Dalton(0.2),
+            ..RoiParams::default()
+        };
+
+        let serial = compute_roi(&peaks_list, &times_list, settings.clone());
+        assert_eq!(serial.0.len(), 2);
+        for n_threads in [2, 3, 5, 8] {
+            let parallel =
+                compute_roi_parallel(&peaks_list, &times_list, settings.clone(), n_threads);
+            assert_same_rois(&parallel, &serial);
+        }
+    }
+
+    #[test]
+    fn parallel_sparse_matches_serial() {
+        let (peaks_list, times_list) = synthetic_runs(&[130.0, 131.0, 260.0, 390.0], 2, 50, 5);
+        let (mzroi, msroi, _) = compute_roi(&peaks_list, &times_list, RoiParams::default());
+        let (sparse_mzroi, sparse_msroi, _) = compute_roi_sparse(
+            &peaks_list,
+            &times_list,
+            RoiParams::default(),
+            SparseLayout::Csc,
+            4,
+        );
+        assert_eq!(sparse_mzroi, mzroi);
+        assert_eq!(sparse_msroi.to_dense(), msroi);
+    }
 }
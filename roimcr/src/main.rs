@@ -1,202 +1,684 @@
-mod enums;
-mod file;
-mod structs;
-mod utils;
-
+use clap::{Args, Parser, Subcommand};
 use ndarray::prelude::*;
-use std::path::Path;
-use utils::ndarray_to_csv;
 
-use crate::enums::{MzErrorType, MzRoiUpdater};
-use crate::file::{load_mzxml, load_netcdf};
-use crate::structs::data::Roicell;
-use crate::structs::options::RoiParams;
-use crate::utils::{argsort, sort_by_indices, subset};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 
-fn main() {
-    let files = [
-        Path::new("examples/test_p10.xml"),
-        Path::new("examples/test_cnt.xml"),
-    ];
+use roimcr::batch::{collect_files, output_directories, process_batch, write_batch_report};
+use roimcr::config::{read_config, run_config};
+use roimcr::enums::{InputFormat, MzErrorType, MzRoiUpdater, OutputFormat};
+use roimcr::error::RoiError;
+use roimcr::export::{write_matrix, write_msroi, write_roi_metadata};
+use roimcr::mat::{write_mat, write_roi_mat};
+use roimcr::project::write_project;
+use roimcr::structs::data::{Project, RoiMatrices, RunSummary};
+use roimcr::structs::options::{ExportParams, RoiParams, WriteParams};
+use roimcr::{compute_roi, compute_roi_parallel, read_run, write_run};
 
-    let mut peaks_list: Vec<Vec<Array2<f64>>> = Vec::new();
-    let mut times_list: Vec<Array1<f64>> = Vec::new();
-    let mut names_list: Vec<String> = Vec::new();
+#[cfg(feature = "arrow")]
+use roimcr::enums::TableFormat;
+#[cfg(feature = "arrow")]
+use roimcr::export::roi_statistics;
+#[cfg(feature = "arrow")]
+use roimcr::structs::data::Roicell;
+#[cfg(feature = "arrow")]
+use roimcr::tables::{peak_table, roi_summary_table, roi_table, write_table};
 
-    for file_path in files {
-        let parsed = load_mzxml(file_path).expect("Error in parsing the file");
-        // let parsed = load_netcdf(Path::new("examples/test.cdf")).expect("Error in parsing the file");
+#[derive(Parser)]
+#[command(
+    name = "roimcr",
+    version,
+    about = "ROI extraction and MCR of LC-MS data"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-        let peaks = parsed.get_peaks();
-        let times = parsed.get_times();
-        // let tic = parsed.get_tic();
+#[derive(Subcommand)]
+enum Command {
+    /// Extract the regions of interest of one or more runs
+    Roi(RoiArgs),
+    /// Extract the ROIs of every run of a directory or glob pattern separately, going on
+    /// past the files that fail; exits with an error only if all of them fail
+    Batch(BatchArgs),
+    /// Print a summary of one or more runs
+    Info(InfoArgs),
+    /// Convert a run to mzML or mzXML
+    Convert(ConvertArgs),
+    /// Run a whole processing described by a TOML or JSON configuration file
+    Run(RunArgs),
+}
 
-        peaks_list.push(peaks);
-        times_list.push(times);
-        names_list.push(parsed.name);
+#[derive(Args)]
+struct RoiArgs {
+    /// Input files; several runs are augmented row-wise in msroi
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Format of the input files, detected from their extension by default
+    #[arg(long, value_parser = ["mzxml", "mzml", "netcdf"])]
+    input_format: Option<String>,
+
+    /// Output directory, created if needed
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    output_options: OutputOptions,
+
+    #[command(flatten)]
+    options: RoiOptions,
+
+    /// Number of threads, all available cores if 0
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+
+    /// Also save the results to a project file (.npz, or .h5 with the hdf5 feature)
+    #[arg(long)]
+    project: Option<PathBuf>,
+
+    /// Also write the peaks, ROI peaks and ROI summaries as long format tables
+    #[cfg(feature = "arrow")]
+    #[arg(long, value_parser = ["arrow", "parquet"])]
+    tables: Option<String>,
+}
+
+/// Layout of the output tables
+#[derive(Args)]
+#[group(skip)]
+struct OutputOptions {
+    /// Format of the output files, mat writes the ROIs and MCR results to compressed
+    /// MATLAB files with the variable names of the MATLAB ROIMCR scripts
+    #[arg(short, long, default_value = "csv", value_parser = ["csv", "tsv", "mat"])]
+    format: String,
+
+    /// Number of decimals of the values written, all significant digits by default
+    #[arg(long)]
+    precision: Option<usize>,
+
+    /// Write the tables transposed, one ROI per row
+    #[arg(long)]
+    transpose: bool,
+}
+
+impl OutputOptions {
+    fn params(&self) -> ExportParams {
+        ExportParams {
+            delimiter: if self.format == "tsv" { '\t' } else { ',' },
+            precision: self.precision,
+            transpose: self.transpose,
+        }
     }
 
-    let settings = RoiParams {
-        threshold: 1000.0,
-        t_factor: 1.0,
-        mzroi_updater: MzRoiUpdater::Mean,
-        mz_error: MzErrorType::Dalton(0.05),
-        min_occ: 50,
-    };
+    fn path(&self, directory: &Path, name: &str) -> PathBuf {
+        directory.join(format!("{}.{}", name, self.format))
+    }
+}
 
-    let mut aug_peaks: Vec<Array2<f64>> = Vec::new();
-    let mut times: Vec<f64> = Vec::new();
-    if files.len() > 1 {
-        for (scan_num, scan) in peaks_list.iter().enumerate() {
-            for (peak_num, peak) in scan.iter().enumerate() {
-                aug_peaks.push(peak.to_owned());
-                times.push((peak_num + (scan_num * scan.len())) as f64);
-            }
+/// Parameters of the ROIs
+#[derive(Args)]
+#[group(skip)]
+struct RoiOptions {
+    /// Minimum intensity of the peaks added to the ROIs
+    #[arg(long, default_value_t = 1000.0)]
+    threshold: f64,
+
+    /// Factor of the threshold that the most intense peak of a ROI must exceed
+    #[arg(long, default_value_t = 1.0)]
+    t_factor: f64,
+
+    /// m/z tolerance of the ROIs
+    #[arg(long, default_value_t = 0.5)]
+    tol: f64,
+
+    /// Units of the m/z tolerance
+    #[arg(long, default_value = "da", value_parser = ["da", "ppm"])]
+    tol_units: String,
+
+    /// Update of the m/z value of a ROI when a peak is added
+    #[arg(long, default_value = "mean", value_parser = ["mean", "max", "median", "weighted"])]
+    update_method: String,
+
+    /// Minimum number of peaks of a ROI
+    #[arg(long, default_value_t = 1)]
+    min_occ: u32,
+}
+
+impl RoiOptions {
+    fn params(&self) -> RoiParams {
+        RoiParams {
+            threshold: self.threshold,
+            t_factor: self.t_factor,
+            mz_error: match self.tol_units.as_str() {
+                "ppm" => MzErrorType::Ppm(self.tol),
+                _ => MzErrorType::Dalton(self.tol),
+            },
+            mzroi_updater: match self.update_method.as_str() {
+                "max" => MzRoiUpdater::Max,
+                "median" => MzRoiUpdater::Median,
+                "weighted" => MzRoiUpdater::Weighted,
+                _ => MzRoiUpdater::Mean,
+            },
+            min_occ: self.min_occ,
         }
-    } else {
-        aug_peaks = peaks_list[0].to_owned();
-        times = times_list[0].to_vec();
     }
-    let aug_times = Array1::from_vec(times);
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// Directory, whose mzXML, mzML and netCDF files are processed, or glob pattern of the files
+    input: String,
+
+    /// Format of the input files, detected from their extension by default
+    #[arg(long, value_parser = ["mzxml", "mzml", "netcdf"])]
+    input_format: Option<String>,
+
+    /// Output directory, with one subdirectory of results per run named after its file and
+    /// the subdirectories it is in
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    output_options: OutputOptions,
 
-    let (mzroi, roicell) = compute_roi(&aug_peaks, &aug_times, settings);
+    /// JSON report of the batch, batch_report.json in the output directory by default
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    #[command(flatten)]
+    options: RoiOptions,
+
+    /// Number of files processed at the same time, all available cores if 0
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
 }
 
-fn compute_roi(
-    peaks_list: &[Array2<f64>],
-    times: &Array1<f64>,
-    settings: RoiParams,
-) -> (Vec<f64>, Roicell) {
-    let mut mzroi: Vec<f64> = Vec::new();
-    let mut roicell: Roicell = Roicell::new();
+#[derive(Args)]
+struct ConvertArgs {
+    /// Input file
+    input: PathBuf,
+
+    /// Output file
+    output: PathBuf,
 
-    for (scan, peaks) in peaks_list.iter().enumerate() {
+    /// Format of the input file, detected from its extension by default
+    #[arg(long, value_parser = ["mzxml", "mzml", "netcdf"])]
+    input_format: Option<String>,
+
+    /// Format of the output file, detected from its extension by default
+    #[arg(long, value_parser = ["mzxml", "mzml"])]
+    output_format: Option<String>,
+
+    /// Compress the peaks with zlib
+    #[arg(long)]
+    zlib: bool,
+
+    /// Store the peaks as 64-bit instead of 32-bit floats
+    #[arg(long)]
+    double_precision: bool,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    /// Input files
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Format of the input files, detected from their extension by default
+    #[arg(long, value_parser = ["mzxml", "mzml", "netcdf"])]
+    input_format: Option<String>,
+
+    /// Print the summaries as a JSON array
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Configuration file (.toml or .json)
+    config: PathBuf,
+
+    /// Output directory, created if needed
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    output_options: OutputOptions,
+
+    /// Also save the results to a project file (.npz, or .h5 with the hdf5 feature)
+    #[arg(long)]
+    project: Option<PathBuf>,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let outcome = match cli.command {
+        Command::Roi(args) => roi(args),
+        Command::Batch(args) => batch(args),
+        Command::Convert(args) => convert(args),
+        Command::Info(args) => info(args),
+        Command::Run(args) => run(args),
+    };
+    if let Err(error) = outcome {
+        eprintln!("roimcr: {}", error);
+        process::exit(1);
+    }
+}
+
+fn roi(args: RoiArgs) -> Result<(), RoiError> {
+    let settings = args.options.params();
+
+    let mut peaks_list: Vec<Vec<Array2<f64>>> = Vec::new();
+    let mut times_list: Vec<Array1<f64>> = Vec::new();
+    for path in args.files.iter() {
+        let format = input_format(path, args.input_format.as_deref())?;
+        let run = read_run(path, format)?;
+        let peaks = run.get_peaks();
         if peaks.is_empty() {
-            continue;
+            return Err(RoiError::Parse(format!(
+                "No MS1 scans in {}",
+                path.display()
+            )));
         }
-        let indices = peaks
-            .axis_iter(Axis(0))
-            .enumerate()
-            .filter(|(_, r)| r[1] > settings.threshold)
-            .map(|(index, _)| index)
-            .collect::<Vec<_>>();
+        peaks_list.push(peaks);
+        times_list.push(run.get_times());
+    }
 
-        if indices.is_empty() {
-            continue;
-        }
+    #[cfg_attr(not(feature = "arrow"), allow(unused_variables))]
+    let (mzroi, msroi, roicell) = if args.threads == 1 {
+        compute_roi(&peaks_list, &times_list, settings)
+    } else {
+        compute_roi_parallel(&peaks_list, &times_list, settings, args.threads)
+    };
 
-        let mz = subset(&peaks.index_axis(Axis(1), 0).to_vec(), &indices);
-        let intensities = subset(&peaks.index_axis(Axis(1), 1).to_vec(), &indices);
+    // Retention times and runs of the scans of all runs, in the order of the rows of msroi
+    let times = Array1::from_iter(times_list.iter().flatten().cloned());
+    let run_sizes: Vec<usize> = times_list.iter().map(|times| times.len()).collect();
 
-        if scan == 1 {
-            mzroi.push(mz[0]);
-        }
+    write_rois(
+        &args.output,
+        &msroi,
+        &mzroi,
+        &times,
+        &run_sizes,
+        &args.output_options,
+    )?;
 
-        for i in 0..mz.len() {
-            let compatible_rois: Vec<usize> = mzroi
-                .iter()
-                .enumerate()
-                .filter(|(_, &roi_value)| {
-                    (roi_value - mz[i]).abs() <= settings.mz_error.get_error(&mz[i])
-                })
-                .map(|(index, _)| index)
-                .collect();
-
-            if compatible_rois.is_empty() {
-                roicell.new_roi();
-                roicell.update_roi(
-                    roicell.mzs.len() - 1,
-                    &settings.mzroi_updater,
-                    mz[i],
-                    times[scan],
-                    intensities[i],
-                    scan,
-                );
-                mzroi.push(roicell.mz_roi[roicell.mzs.len() - 1])
-            } else {
-                for &roi in compatible_rois.iter() {
-                    roicell.update_roi(
-                        roi,
-                        &settings.mzroi_updater,
-                        mz[i],
-                        times[scan],
-                        intensities[i],
-                        scan,
-                    );
-                    mzroi[roi] = roicell.mz_roi[roi];
-                }
-            }
+    println!(
+        "{} ROIs in {} scans written to {}",
+        msroi.ncols(),
+        msroi.nrows(),
+        args.output.display()
+    );
+
+    #[cfg(feature = "arrow")]
+    if let Some(name) = &args.tables {
+        write_tables(
+            &args.output,
+            &peaks_list,
+            &times_list,
+            &roicell,
+            &msroi,
+            &mzroi,
+            &times,
+            &run_sizes,
+            TableFormat::from_name(name).unwrap(),
+        )?;
+    }
+
+    if let Some(path) = &args.project {
+        let rois = RoiMatrices {
+            mzroi,
+            msroi,
+            times,
+            run_sizes,
+        };
+        let project = Project::new(&args.files, &args.options.params(), rois, None)?;
+        write_project(path, &project, true)?;
+        println!("Project saved to {}", path.display());
+    }
+    Ok(())
+}
+
+fn batch(args: BatchArgs) -> Result<(), RoiError> {
+    let files = collect_files(&args.input)?;
+    let format = match args.input_format.as_deref() {
+        Some(name) => InputFormat::from_name(name),
+        None => None,
+    };
+    let directories: HashMap<&Path, PathBuf> = files
+        .iter()
+        .map(|file| file.as_path())
+        .zip(output_directories(&files)?)
+        .collect();
+    let settings = args.options.params();
+    fs::create_dir_all(&args.output)?;
+
+    let (outputs, report) = process_batch(&files, format, args.threads, |path, run| {
+        let times = run.get_times();
+        let (mzroi, msroi, _) = compute_roi(
+            &[run.get_peaks()],
+            std::slice::from_ref(&times),
+            settings.clone(),
+        );
+
+        write_rois(
+            &args.output.join(&directories[path]),
+            &msroi,
+            &mzroi,
+            &times,
+            &[times.len()],
+            &args.output_options,
+        )?;
+        Ok(msroi.ncols())
+    });
+
+    for (entry, n_rois) in report.files.iter().zip(outputs.iter()) {
+        match (&entry.error, n_rois) {
+            (None, Some(n_rois)) => println!(
+                "ok      {}: {} MS1 scans, {} ROIs",
+                entry.path.display(),
+                entry.ms1_scans.unwrap_or(0),
+                n_rois
+            ),
+            (error, _) => println!(
+                "FAILED  {}: {}",
+                entry.path.display(),
+                error.as_deref().unwrap_or("Unknown error")
+            ),
         }
     }
+    println!("{} files loaded, {} failed", report.loaded, report.failed);
 
-    let sorted_roi_index = argsort(&mzroi);
+    let report_path = args
+        .report
+        .clone()
+        .unwrap_or_else(|| args.output.join("batch_report.json"));
+    write_batch_report(&report, &report_path)?;
+    println!("Report written to {}", report_path.display());
 
-    sort_by_indices(&mut mzroi, &sorted_roi_index);
+    if report.loaded == 0 {
+        return Err(RoiError::Parse(
+            "All the files of the batch failed".to_string(),
+        ));
+    }
+    Ok(())
+}
 
-    sort_by_indices(&mut roicell.mzs, &sorted_roi_index);
-    sort_by_indices(&mut roicell.times, &sorted_roi_index);
-    sort_by_indices(&mut roicell.intensities, &sorted_roi_index);
-    sort_by_indices(&mut roicell.scans, &sorted_roi_index);
-    sort_by_indices(&mut roicell.mz_roi, &sorted_roi_index);
+fn convert(args: ConvertArgs) -> Result<(), RoiError> {
+    let format = input_format(&args.input, args.input_format.as_deref())?;
+    let output_format = match args.output_format.as_deref() {
+        Some(name) => OutputFormat::from_name(name),
+        None => OutputFormat::from_path(&args.output),
+    }
+    .ok_or_else(|| {
+        RoiError::UnsupportedFormat(format!(
+            "Can't detect the format of {}, use --output-format (mzml or mzxml)",
+            args.output.display()
+        ))
+    })?;
 
-    // Raggruppa roi con mz simili
-    loop {
-        let mz_error_roi: Vec<f64> = mzroi.windows(2).map(|w| (w[0] - w[1]).abs()).collect();
+    let run = read_run(&args.input, format)?;
+    let params = WriteParams {
+        zlib: args.zlib,
+        double_precision: args.double_precision,
+    };
+    write_run(&run, &args.output, output_format, &params)?;
 
-        let mergable_roi: Vec<usize> = mz_error_roi
-            .iter()
-            .enumerate()
-            .filter(|(_, &mz_diff)| mz_diff < settings.mz_error.get_error(&mzroi[mzroi.len() - 2]))
-            .map(|(index, _)| index)
-            .collect();
+    println!(
+        "{} scans written to {}",
+        run.scans.len(),
+        args.output.display()
+    );
+    Ok(())
+}
 
-        if mergable_roi.is_empty() {
-            break;
+fn info(args: InfoArgs) -> Result<(), RoiError> {
+    let mut summaries: Vec<RunSummary> = Vec::new();
+    let mut failed = 0;
+    for path in args.files.iter() {
+        match input_format(path, args.input_format.as_deref())
+            .and_then(|format| read_run(path, format))
+        {
+            Ok(run) => summaries.push(run.summary()),
+            Err(error) => {
+                eprintln!("roimcr: {}", error);
+                failed += 1;
+            }
         }
+    }
 
-        mzroi[mergable_roi[0]] = roicell.mz_roi[mergable_roi[0] + 1];
-        roicell.merge_roi(
-            mergable_roi[0],
-            mergable_roi[0] + 1,
-            &settings.mzroi_updater,
-        );
-        mzroi.remove(mergable_roi[0] + 1);
+    if args.json {
+        let text = serde_json::to_string_pretty(&summaries).map_err(|error| {
+            RoiError::Write(format!("Can't serialise the summaries: {}", error))
+        })?;
+        println!("{}", text);
+    } else {
+        for summary in summaries.iter() {
+            print_summary(summary);
+        }
     }
 
-    // Filtrare per minimo numero di mz e minima intensità massima in roi
-    let roi_num: Vec<u32> = roicell.mzs.iter().map(|i| i.len() as u32).collect();
-    let max_int: Vec<f64> = roicell
-        .intensities
+    if failed > 0 {
+        return Err(RoiError::Parse(format!(
+            "{} of {} files couldn't be read",
+            failed,
+            args.files.len()
+        )));
+    }
+    Ok(())
+}
+
+fn print_summary(summary: &RunSummary) {
+    let range = |range: Option<(f64, f64)>| match range {
+        Some((low, high)) => format!("{:.4} - {:.4}", low, high),
+        None => "-".to_string(),
+    };
+    let levels: Vec<String> = summary
+        .scans_per_level
         .iter()
-        .map(|i| *i.iter().max_by(|a, b| a.total_cmp(b)).unwrap())
+        .map(|(level, count)| format!("MS{}: {}", level, count))
         .collect();
-
-    let mut removable_roi: Vec<usize> = roi_num
+    let polarities: Vec<String> = summary
+        .polarities
         .iter()
-        .zip(max_int.iter())
-        .enumerate()
-        .filter(|(_, (&n, &int))| {
-            (n <= settings.min_occ) || (int <= settings.threshold * settings.t_factor)
-        })
-        .map(|(i, (_, _))| i)
-        .collect::<Vec<usize>>();
-
-    removable_roi.reverse();
+        .map(|polarity| format!("{:?}", polarity).to_lowercase())
+        .collect();
 
-    for roi in removable_roi {
-        roicell.remove_roi(roi);
-        mzroi.remove(roi);
+    println!("{}", summary.name);
+    println!(
+        "  scans           {} ({})",
+        summary.n_scans,
+        levels.join(", ")
+    );
+    println!("  RT range        {}", range(summary.rt_range));
+    println!("  m/z range       {}", range(summary.mz_range));
+    println!(
+        "  peaks per scan  {} - {} (mean {:.1})",
+        summary.min_peaks, summary.max_peaks, summary.mean_peaks
+    );
+    match (summary.tic_max, summary.tic_max_rt) {
+        (Some(tic), Some(rt)) => println!("  TIC maximum     {:.4e} at {:.4}", tic, rt),
+        _ => println!("  TIC maximum     -"),
     }
+    println!(
+        "  polarity        {}",
+        if polarities.is_empty() {
+            "unknown".to_string()
+        } else {
+            polarities.join(", ")
+        }
+    );
+}
 
-    let mut msroi: Array2<f64> = Array2::zeros((peaks_list.len(), mzroi.len()));
+fn run(args: RunArgs) -> Result<(), RoiError> {
+    let config = read_config(&args.config)?;
+    let result = run_config(&config)?;
 
-    for i in 0..mzroi.len() {
-        let nval = roicell.scans[i].len();
-        for j in 0..nval {
-            let irow = roicell.scans[i][j] as usize;
-            let msi = roicell.intensities[i][j];
-            msroi[[irow, i]] += msi;
+    let output = &args.output_options;
+    write_rois(
+        &args.output,
+        &result.msroi,
+        &result.mzroi,
+        &result.times,
+        &result.run_sizes,
+        output,
+    )?;
+    if let Some(mcr) = &result.mcr {
+        if output.format == "mat" {
+            // Names of the results of the MCR-ALS GUI
+            write_mat(
+                &args.output.join("mcr.mat"),
+                &[("copt", mcr.c.view()), ("sopt", mcr.st.view())],
+                true,
+            )?;
+        } else {
+            write_matrix(&mcr.c, &output.path(&args.output, "c"), &output.params())?;
+            write_matrix(&mcr.st, &output.path(&args.output, "st"), &output.params())?;
         }
+        println!(
+            "MCR-ALS: {} components, lack of fit {:.3}%, {} iterations{}",
+            mcr.c.ncols(),
+            mcr.lof,
+            mcr.n_iter,
+            if mcr.converged {
+                ""
+            } else {
+                " (not converged)"
+            }
+        );
+    }
+
+    println!(
+        "{} ROIs in {} scans written to {}",
+        result.msroi.ncols(),
+        result.msroi.nrows(),
+        args.output.display()
+    );
+
+    if let Some(path) = &args.project {
+        let rois = RoiMatrices {
+            mzroi: result.mzroi,
+            msroi: result.msroi,
+            times: result.times,
+            run_sizes: result.run_sizes,
+        };
+        let project = Project::new(&config.input.files, &config.roi, rois, result.mcr)?;
+        write_project(path, &project, true)?;
+        println!("Project saved to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Write the peaks, rois and roi_summary tables to `directory`.
+#[cfg(feature = "arrow")]
+#[allow(clippy::too_many_arguments)]
+fn write_tables(
+    directory: &Path,
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+    roicell: &Roicell,
+    msroi: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times: &Array1<f64>,
+    run_sizes: &[usize],
+    format: TableFormat,
+) -> Result<(), RoiError> {
+    let runs = Array1::from_iter(
+        run_sizes
+            .iter()
+            .enumerate()
+            .flat_map(|(run, &size)| std::iter::repeat_n(run, size)),
+    );
+    let statistics = roi_statistics(msroi, mzroi, times, &runs)?;
+    let extension = format.extension();
+
+    write_table(
+        &peak_table(peaks_list, times_list)?,
+        &directory.join(format!("peaks.{}", extension)),
+        format,
+    )?;
+    write_table(
+        &roi_table(roicell, times_list)?,
+        &directory.join(format!("rois.{}", extension)),
+        format,
+    )?;
+    write_table(
+        &roi_summary_table(&statistics)?,
+        &directory.join(format!("roi_summary.{}", extension)),
+        format,
+    )?;
+    println!("Tables written to {}", directory.display());
+    Ok(())
+}
+
+/// Write msroi, labelled with the m/z values of the ROIs and the time and run of every
+/// scan, and the table of ROI metadata to `directory`, or msroi.mat with the mat format.
+fn write_rois(
+    directory: &Path,
+    msroi: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times: &Array1<f64>,
+    run_sizes: &[usize],
+    output: &OutputOptions,
+) -> Result<(), RoiError> {
+    fs::create_dir_all(directory)?;
+    if output.format == "mat" {
+        let rois = RoiMatrices {
+            mzroi: mzroi.to_owned(),
+            msroi: msroi.to_owned(),
+            times: times.to_owned(),
+            run_sizes: run_sizes.to_vec(),
+        };
+        return write_roi_mat(&directory.join("msroi.mat"), &rois, true);
+    }
+
+    let runs = Array1::from_iter(
+        run_sizes
+            .iter()
+            .enumerate()
+            .flat_map(|(run, &size)| std::iter::repeat_n(run, size)),
+    );
+    let params = output.params();
+
+    write_msroi(
+        msroi,
+        mzroi,
+        times,
+        &runs,
+        &output.path(directory, "msroi"),
+        &params,
+    )?;
+    write_roi_metadata(
+        msroi,
+        mzroi,
+        times,
+        &runs,
+        &output.path(directory, "rois"),
+        &params,
+    )
+}
+
+/// Format given on the command line, or detected from the extension of the file.
+fn input_format(path: &Path, name: Option<&str>) -> Result<InputFormat, RoiError> {
+    if !path.is_file() {
+        return Err(RoiError::Parse(format!(
+            "Input file {} doesn't exist",
+            path.display()
+        )));
+    }
+    match name {
+        Some(name) => InputFormat::from_name(name),
+        None => InputFormat::from_path(path),
     }
-    return (mzroi, roicell);
+    .ok_or_else(|| {
+        RoiError::UnsupportedFormat(format!(
+            "Can't detect the format of {}, use --input-format (mzxml, mzml or netcdf)",
+            path.display()
+        ))
+    })
 }
@@ -0,0 +1,493 @@
+//! MATLAB Level 5 MAT-files, as read and written by `load` and `save` in MATLAB 5 to 7.
+//!
+//! Only real numeric matrices are handled: they are written as double arrays, and the
+//! numeric arrays of any class read back are converted to `f64`. Variables of other
+//! kinds (cells, structures, strings, sparse or complex arrays) are skipped when reading.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use ndarray::prelude::*;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::error::RoiError;
+use crate::structs::data::RoiMatrices;
+
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_INT16: u32 = 3;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_SINGLE: u32 = 7;
+const MI_DOUBLE: u32 = 9;
+const MI_INT64: u32 = 12;
+const MI_UINT64: u32 = 13;
+const MI_MATRIX: u32 = 14;
+const MI_COMPRESSED: u32 = 15;
+
+const MX_DOUBLE_CLASS: u32 = 6;
+/// Classes of the numeric arrays, from `mxDOUBLE_CLASS` to `mxUINT64_CLASS`.
+const NUMERIC_CLASSES: std::ops::RangeInclusive<u32> = 6..=15;
+const COMPLEX_FLAG: u32 = 0x0800;
+
+/// Write real matrices to a MAT-file under the given names, compressed with zlib as in
+/// MATLAB 7 files when `compress` is set.
+pub fn write_mat(
+    path: &Path,
+    variables: &[(&str, ArrayView2<f64>)],
+    compress: bool,
+) -> Result<(), RoiError> {
+    let mut bytes = header();
+
+    for (name, data) in variables.iter() {
+        if name.is_empty() || name.len() > 63 || !name.is_ascii() {
+            return Err(RoiError::Write(format!(
+                "Can't write {}: invalid MATLAB variable name '{}'",
+                path.display(),
+                name
+            )));
+        }
+        let invalid = |message: String| {
+            RoiError::Write(format!("Can't write {}: {}", path.display(), message))
+        };
+        let matrix = matrix_element(name, data).map_err(invalid)?;
+        if compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&matrix)?;
+            let compressed = encoder.finish()?;
+            push_tag(&mut bytes, MI_COMPRESSED, compressed.len()).map_err(invalid)?;
+            bytes.extend_from_slice(&compressed);
+        } else {
+            bytes.extend_from_slice(&matrix);
+        }
+    }
+
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Read the real numeric 2-D variables of a MAT-file, by name.
+pub fn read_mat(path: &Path) -> Result<BTreeMap<String, Array2<f64>>, RoiError> {
+    let invalid = |message: &str| {
+        RoiError::Parse(format!(
+            "Can't read {} as a MAT-file: {}",
+            path.display(),
+            message
+        ))
+    };
+
+    let bytes = fs::read(path)?;
+    if bytes.len() < 128 {
+        return Err(invalid("the file is too short"));
+    }
+    let little_endian = match &bytes[126..128] {
+        b"IM" => true,
+        b"MI" => false,
+        _ => return Err(invalid("not a Level 5 MAT-file")),
+    };
+
+    let mut variables = BTreeMap::new();
+    let mut reader = ElementReader {
+        bytes: &bytes[128..],
+        position: 0,
+        little_endian,
+    };
+    while let Some((kind, data)) = reader.next().map_err(|message| invalid(&message))? {
+        let variable = match kind {
+            MI_MATRIX => parse_matrix(data, little_endian),
+            MI_COMPRESSED => {
+                let mut inflated = Vec::new();
+                ZlibDecoder::new(data)
+                    .read_to_end(&mut inflated)
+                    .map_err(|error| invalid(&error.to_string()))?;
+                let mut inner = ElementReader {
+                    bytes: &inflated,
+                    position: 0,
+                    little_endian,
+                };
+                match inner.next().map_err(|message| invalid(&message))? {
+                    Some((MI_MATRIX, data)) => parse_matrix(data, little_endian),
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+        .map_err(|message| invalid(&message))?;
+
+        if let Some((name, data)) = variable {
+            variables.insert(name, data);
+        }
+    }
+    Ok(variables)
+}
+
+/// Write the ROIs in a MAT-file with the names of the MATLAB ROIMCR scripts: `MSroi`
+/// (scans × ROIs), `mzroi` (row vector), `time` (column vector) and `nrows`, the number
+/// of rows of each run in `MSroi` (column vector), as asked for augmented matrices by
+/// the MCR-ALS GUI.
+pub fn write_roi_mat(path: &Path, rois: &RoiMatrices, compress: bool) -> Result<(), RoiError> {
+    let msroi = &rois.msroi;
+    if rois.mzroi.len() != msroi.ncols() || rois.times.len() != msroi.nrows() {
+        return Err(RoiError::Write(format!(
+            "Can't write {}: msroi has {} scans and {} ROIs but {} times and {} m/z values were given",
+            path.display(),
+            msroi.nrows(),
+            msroi.ncols(),
+            rois.times.len(),
+            rois.mzroi.len()
+        )));
+    }
+    if rois.run_sizes.iter().sum::<usize>() != msroi.nrows() {
+        return Err(RoiError::Write(format!(
+            "Can't write {}: the run sizes don't add up to the {} rows of msroi",
+            path.display(),
+            msroi.nrows()
+        )));
+    }
+    let mzroi = rois.mzroi.view().insert_axis(Axis(0));
+    let time = rois.times.view().insert_axis(Axis(1));
+    let nrows = Array1::from_iter(rois.run_sizes.iter().map(|&size| size as f64));
+
+    write_mat(
+        path,
+        &[
+            ("MSroi", rois.msroi.view()),
+            ("mzroi", mzroi),
+            ("time", time),
+            ("nrows", nrows.view().insert_axis(Axis(1))),
+        ],
+        compress,
+    )
+}
+
+/// Read the ROIs of a MAT-file written by `write_roi_mat` or saved from MATLAB. Only
+/// `MSroi` is required: `mzroi` defaults to the column indices, `time` to the row indices
+/// and `nrows` to a single run.
+pub fn read_roi_mat(path: &Path) -> Result<RoiMatrices, RoiError> {
+    let mut variables = read_mat(path)?;
+    let msroi = variables.remove("MSroi").ok_or_else(|| {
+        RoiError::Parse(format!(
+            "No MSroi matrix in {}, it holds: {}",
+            path.display(),
+            variables.keys().cloned().collect::<Vec<_>>().join(", ")
+        ))
+    })?;
+
+    let vector = |data: Option<Array2<f64>>, name: &str, len: usize| match data {
+        Some(data) if data.len() == len => Ok(Some(Array1::from_iter(data.t().iter().cloned()))),
+        Some(data) => Err(RoiError::Parse(format!(
+            "{} in {} has {} values instead of {}",
+            name,
+            path.display(),
+            data.len(),
+            len
+        ))),
+        None => Ok(None),
+    };
+    let mzroi = vector(variables.remove("mzroi"), "mzroi", msroi.ncols())?
+        .unwrap_or_else(|| Array1::from_iter((0..msroi.ncols()).map(|i| i as f64)));
+    let times = vector(variables.remove("time"), "time", msroi.nrows())?
+        .unwrap_or_else(|| Array1::from_iter((0..msroi.nrows()).map(|i| i as f64)));
+    let run_sizes: Vec<usize> = match variables.remove("nrows") {
+        Some(nrows) => nrows.iter().map(|&size| size as usize).collect(),
+        None => vec![msroi.nrows()],
+    };
+    if run_sizes.iter().sum::<usize>() != msroi.nrows() {
+        return Err(RoiError::Parse(format!(
+            "nrows in {} doesn't add up to the {} rows of MSroi",
+            path.display(),
+            msroi.nrows()
+        )));
+    }
+
+    Ok(RoiMatrices {
+        mzroi,
+        msroi,
+        times,
+        run_sizes,
+    })
+}
+
+fn header() -> Vec<u8> {
+    let text = format!(
+        "MATLAB 5.0 MAT-file, Platform: {}, Created by: roimcr {}",
+        std::env::consts::OS,
+        env!("CARGO_PKG_VERSION")
+    );
+    let mut header = vec![b' '; 116];
+    header[..text.len().min(116)].copy_from_slice(&text.as_bytes()[..text.len().min(116)]);
+    // No subsystem data, version 0x0100 and the endian indicator
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&0x0100u16.to_le_bytes());
+    header.extend_from_slice(b"IM");
+    header
+}
+
+/// Level 5 files store the size of every data element on 32 bits.
+fn push_tag(bytes: &mut Vec<u8>, kind: u32, len: usize) -> Result<(), String> {
+    let len = u32::try_from(len).map_err(|_| {
+        format!(
+            "a data element of {} bytes exceeds the 2^32 bytes of Level 5 MAT-files",
+            len
+        )
+    })?;
+    bytes.extend_from_slice(&kind.to_le_bytes());
+    bytes.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+/// Data element with its tag, padded to 8 bytes.
+fn push_element(bytes: &mut Vec<u8>, kind: u32, data: &[u8]) -> Result<(), String> {
+    push_tag(bytes, kind, data.len())?;
+    bytes.extend_from_slice(data);
+    bytes.resize(bytes.len() + (8 - data.len() % 8) % 8, 0);
+    Ok(())
+}
+
+fn matrix_element(name: &str, data: &ArrayView2<f64>) -> Result<Vec<u8>, String> {
+    let dims = [data.nrows(), data.ncols()].map(i32::try_from);
+    let [Ok(nrows), Ok(ncols)] = dims else {
+        return Err(format!(
+            "{} has {} rows and {} columns, Level 5 MAT-files are limited to 2^31 - 1",
+            name,
+            data.nrows(),
+            data.ncols()
+        ));
+    };
+    // Flags, dimensions and name elements, then the values, checked before they are copied
+    let size = data
+        .len()
+        .checked_mul(8)
+        .and_then(|values| values.checked_add(48 + name.len().div_ceil(8) * 8))
+        .filter(|&size| size <= u32::MAX as usize)
+        .ok_or_else(|| {
+            format!(
+                "{} has {} values, more than fit in the 2^32 bytes of a Level 5 MAT-file element",
+                name,
+                data.len()
+            )
+        })?;
+
+    let mut content = Vec::with_capacity(size);
+    let flags: Vec<u8> = [MX_DOUBLE_CLASS, 0]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    push_element(&mut content, MI_UINT32, &flags)?;
+    let dims: Vec<u8> = [nrows, ncols]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    push_element(&mut content, MI_INT32, &dims)?;
+    push_element(&mut content, MI_INT8, name.as_bytes())?;
+    // Column major order
+    let values: Vec<u8> = data.t().iter().flat_map(|x| x.to_le_bytes()).collect();
+    push_element(&mut content, MI_DOUBLE, &values)?;
+
+    let mut element = Vec::new();
+    push_tag(&mut element, MI_MATRIX, content.len())?;
+    element.extend_from_slice(&content);
+    Ok(element)
+}
+
+/// Sequence of data elements, in the normal or in the small (up to 4 bytes) format.
+struct ElementReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    little_endian: bool,
+}
+
+impl<'a> ElementReader<'a> {
+    fn u32_at(&self, position: usize) -> u32 {
+        let word: [u8; 4] = self.bytes[position..position + 4].try_into().unwrap();
+        if self.little_endian {
+            u32::from_le_bytes(word)
+        } else {
+            u32::from_be_bytes(word)
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(u32, &'a [u8])>, String> {
+        if self.position + 8 > self.bytes.len() {
+            return Ok(None);
+        }
+        let first = self.u32_at(self.position);
+        if first >> 16 != 0 {
+            // Small data element: type and size in the first word, data in the second
+            let (kind, len) = (first & 0xffff, (first >> 16) as usize);
+            if len > 4 {
+                return Err("invalid small data element".to_string());
+            }
+            let data = &self.bytes[self.position + 4..self.position + 4 + len];
+            self.position += 8;
+            return Ok(Some((kind, data)));
+        }
+
+        let len = self.u32_at(self.position + 4) as usize;
+        let start = self.position + 8;
+        if start + len > self.bytes.len() {
+            return Err("truncated data element".to_string());
+        }
+        let data = &self.bytes[start..start + len];
+        // Compressed elements aren't padded
+        self.position = if first == MI_COMPRESSED {
+            start + len
+        } else {
+            start + len + (8 - len % 8) % 8
+        };
+        Ok(Some((first, data)))
+    }
+}
+
+/// Name and values of a real numeric 2-D matrix, `None` for the other variables.
+fn parse_matrix(data: &[u8], little_endian: bool) -> Result<Option<(String, Array2<f64>)>, String> {
+    let mut reader = ElementReader {
+        bytes: data,
+        position: 0,
+        little_endian,
+    };
+    let mut next = || reader.next()?.ok_or_else(|| "truncated matrix".to_string());
+
+    let (_, flags) = next()?;
+    let flags = to_f64(MI_UINT32, flags, little_endian)?;
+    let flags = *flags.first().ok_or("invalid array flags")? as u32;
+    let (kind, dims) = next()?;
+    let dims = to_f64(kind, dims, little_endian)?;
+    let (_, name) = next()?;
+    let name = String::from_utf8_lossy(name).to_string();
+
+    if !NUMERIC_CLASSES.contains(&(flags & 0xff)) || flags & COMPLEX_FLAG != 0 || dims.len() != 2 {
+        return Ok(None);
+    }
+    let (rows, cols) = (dims[0] as usize, dims[1] as usize);
+    let (kind, values) = next()?;
+    let values = to_f64(kind, values, little_endian)?;
+
+    Array2::from_shape_vec((rows, cols).f(), values)
+        .map(|matrix| Some((name.clone(), matrix.as_standard_layout().to_owned())))
+        .map_err(|_| format!("{} doesn't have {} x {} values", name, rows, cols))
+}
+
+/// Values of a numeric data element, whatever the type they are stored with.
+fn to_f64(kind: u32, data: &[u8], little_endian: bool) -> Result<Vec<f64>, String> {
+    macro_rules! convert {
+        ($t:ty) => {
+            data.chunks_exact(std::mem::size_of::<$t>())
+                .map(|chunk| {
+                    let chunk = chunk.try_into().unwrap();
+                    (if little_endian {
+                        <$t>::from_le_bytes(chunk)
+                    } else {
+                        <$t>::from_be_bytes(chunk)
+                    }) as f64
+                })
+                .collect()
+        };
+    }
+
+    Ok(match kind {
+        MI_INT8 => convert!(i8),
+        MI_UINT8 => convert!(u8),
+        MI_INT16 => convert!(i16),
+        MI_UINT16 => convert!(u16),
+        MI_INT32 => convert!(i32),
+        MI_UINT32 => convert!(u32),
+        MI_SINGLE => convert!(f32),
+        MI_DOUBLE => convert!(f64),
+        MI_INT64 => convert!(i64),
+        MI_UINT64 => convert!(u64),
+        _ => return Err(format!("unsupported data type {}", kind)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{temp_path, Random};
+
+    fn rois() -> RoiMatrices {
+        let mut random = Random::new(47);
+        RoiMatrices {
+            mzroi: Array1::from_shape_fn(6, |j| 100.0 + 25.5 * j as f64),
+            msroi: Array2::from_shape_fn((9, 6), |_| (1e5 * random.uniform()).floor()),
+            times: Array1::from_shape_fn(9, |i| 0.25 * i as f64),
+            run_sizes: vec![4, 5],
+        }
+    }
+
+    #[test]
+    fn written_rois_are_read_back() {
+        let original = rois();
+        for compress in [false, true] {
+            let path = temp_path("rois.mat");
+            write_roi_mat(&path, &original, compress).unwrap();
+            let read = read_roi_mat(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(read.msroi, original.msroi);
+            assert_eq!(read.mzroi, original.mzroi);
+            assert_eq!(read.times, original.times);
+            assert_eq!(read.run_sizes, original.run_sizes);
+        }
+    }
+
+    #[test]
+    fn variables_keep_their_shape() {
+        let path = temp_path("variables.mat");
+        let column = array![[1.0], [2.0], [3.0]];
+        let matrix = array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+        write_mat(
+            &path,
+            &[("column", column.view()), ("matrix", matrix.view())],
+            true,
+        )
+        .unwrap();
+        let variables = read_mat(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(variables["column"], column);
+        assert_eq!(variables["matrix"], matrix);
+        assert!(write_mat(&path, &[("", column.view())], false).is_err());
+    }
+
+    #[test]
+    fn inconsistent_rois_are_not_written() {
+        let path = temp_path("inconsistent.mat");
+        let mut wrong_runs = rois();
+        wrong_runs.run_sizes = vec![4, 4];
+        assert!(matches!(
+            write_roi_mat(&path, &wrong_runs, false),
+            Err(RoiError::Write(_))
+        ));
+        let mut wrong_times = rois();
+        wrong_times.times = Array1::zeros(8);
+        assert!(write_roi_mat(&path, &wrong_times, true).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn matrices_beyond_the_format_limits_are_not_written() {
+        // Broadcast views, so that the values aren't allocated
+        let zero = array![[0.0]];
+        let path = temp_path("large.mat");
+        for shape in [(1 << 31, 1), (1 << 29, 1)] {
+            let large = zero.broadcast(shape).unwrap();
+            for compress in [false, true] {
+                assert!(matches!(
+                    write_mat(&path, &[("MSroi", large)], compress),
+                    Err(RoiError::Write(_))
+                ));
+            }
+        }
+        assert!(!path.exists());
+
+        let mut bytes = Vec::new();
+        assert!(push_tag(&mut bytes, MI_DOUBLE, 1 << 32).is_err());
+        assert!(bytes.is_empty());
+    }
+}
@@ -0,0 +1,604 @@
+pub mod augmented;
+pub mod bands;
+pub mod constraints;
+pub mod weighted;
+
+use ndarray::prelude::*;
+
+use self::constraints::McrConstraints;
+use crate::enums::InitialEstimate;
+use crate::error::RoiError;
+use crate::structs::data::{McrFiguresOfMerit, McrResult, SparseMatrix};
+use crate::structs::options::McrParams;
+use crate::utils::{least_squares, least_squares_right, regularised_inverse, symmetric_eigen};
+
+/// Multivariate curve resolution by alternating least squares of `d` (e.g. the `msroi`
+/// returned by `compute_roi`) into concentration profiles `c` and spectra `st`, so that
+/// `d = c st + residuals`. After each least squares step the corresponding `constraints`
+/// are applied in order.
+/// Iterations stop when the relative change of the lack of fit between two iterations
+/// falls below `params.tol` or after `params.max_iter` iterations; the solution with the
+/// lowest lack of fit is returned.
+pub fn mcr_als(
+    d: &Array2<f64>,
+    initial: &InitialEstimate,
+    params: &McrParams,
+    constraints: &McrConstraints,
+) -> Result<McrResult, RoiError> {
+    check_params(params)?;
+    check_data(d.mapv(|x| x * x).sum())?;
+    let (mut c, mut st) = initial_profiles(initial, d.dim())?;
+    constraints.validate(c.dim(), st.dim())?;
+    let start_from_spectra = matches!(initial, InitialEstimate::Spectra(_));
+
+    let mut iterations = Iterations::new(params.tol);
+
+    for iteration in 0..params.max_iter {
+        if start_from_spectra || iteration > 0 {
+            c = least_squares_right(d, &st);
+            for constraint in constraints.c.iter() {
+                constraint.apply(&mut c, d.view(), st.t());
+            }
+        }
+
+        let mut s = least_squares(&c, d).reversed_axes();
+        for constraint in constraints.st.iter() {
+            constraint.apply(&mut s, d.t(), c.view());
+        }
+        st = s.reversed_axes();
+
+        let residuals = d - &c.dot(&st);
+        let lof = lack_of_fit(d, &residuals);
+
+        if iterations.update(lof, || {
+            let r2 = explained_variance(d, &residuals);
+            (c.clone(), st.clone(), residuals, r2)
+        }) {
+            break;
+        }
+    }
+
+    Ok(iterations.finish())
+}
+
+/// Bookkeeping shared by the iterative resolutions: the lack of fit of every iteration,
+/// the iterate with the lowest one and the convergence test of `mcr_als`.
+pub(crate) struct Iterations {
+    tol: f64,
+    best: Option<McrResult>,
+    lof_history: Vec<f64>,
+    converged: bool,
+}
+
+impl Iterations {
+    pub(crate) fn new(tol: f64) -> Self {
+        Iterations {
+            tol,
+            best: None,
+            lof_history: Vec::new(),
+            converged: false,
+        }
+    }
+
+    /// Record an iteration with lack of fit `lof`. If it is the best so far, `iterate`
+    /// gives its concentrations, spectra, residuals and explained variance. Returns
+    /// whether the relative change of the lack of fit fell below the tolerance.
+    pub(crate) fn update<F>(&mut self, lof: f64, iterate: F) -> bool
+    where
+        F: FnOnce() -> (Array2<f64>, Array2<f64>, Array2<f64>, f64),
+    {
+        let previous = self.lof_history.last().cloned();
+        self.lof_history.push(lof);
+
+        if self.best.as_ref().is_none_or(|best| lof < best.lof) {
+            let (c, st, residuals, r2) = iterate();
+            self.best = Some(McrResult {
+                c,
+                st,
+                residuals,
+                lof,
+                r2,
+                lof_history: Vec::new(),
+                n_iter: 0,
+                converged: false,
+            });
+        }
+
+        if let Some(previous) = previous {
+            if previous > 0.0 && ((previous - lof) / previous).abs() < self.tol {
+                self.converged = true;
+            }
+        }
+        self.converged
+    }
+
+    /// The best iterate, with the history of the lack of fit.
+    pub(crate) fn finish(self) -> McrResult {
+        let mut result = self.best.expect("max_iter is checked to be at least one");
+        result.n_iter = self.lof_history.len();
+        result.lof_history = self.lof_history;
+        result.converged = self.converged;
+        result
+    }
+}
+
+/// Reject a number of iterations of zero, for which there would be no solution, and a
+/// negative or non-finite tolerance.
+pub(crate) fn check_params(params: &McrParams) -> Result<(), RoiError> {
+    if params.max_iter == 0 {
+        return Err(RoiError::InvalidArgument(
+            "MCR needs at least one iteration".to_string(),
+        ));
+    }
+    if !(params.tol >= 0.0 && params.tol.is_finite()) {
+        return Err(RoiError::InvalidArgument(format!(
+            "MCR tolerance must be finite and non-negative, got {}",
+            params.tol
+        )));
+    }
+    Ok(())
+}
+
+/// Reject data whose sum of squares `total` is zero or not finite, for which the lack of
+/// fit and the explained variance are undefined.
+pub(crate) fn check_data(total: f64) -> Result<(), RoiError> {
+    if !(total > 0.0 && total.is_finite()) {
+        return Err(RoiError::InvalidArgument(format!(
+            "The data matrix must be finite and not all zero, its sum of squares is {}",
+            total
+        )));
+    }
+    Ok(())
+}
+
+/// Starting concentrations and spectra for a data matrix of shape `dim` from `initial`,
+/// with the profiles that aren't given set to zero.
+pub(crate) fn initial_profiles(
+    initial: &InitialEstimate,
+    dim: (usize, usize),
+) -> Result<(Array2<f64>, Array2<f64>), RoiError> {
+    let (n_rows, n_cols) = dim;
+    match initial {
+        InitialEstimate::Concentrations(c) => {
+            if c.nrows() != n_rows || c.ncols() == 0 {
+                return Err(RoiError::InvalidArgument(format!(
+                    "Initial concentrations must have {} rows and at least one column, got {:?}",
+                    n_rows,
+                    c.dim()
+                )));
+            }
+            Ok((c.to_owned(), Array2::zeros((c.ncols(), n_cols))))
+        }
+        InitialEstimate::Spectra(st) => {
+            if st.ncols() != n_cols || st.nrows() == 0 {
+                return Err(RoiError::InvalidArgument(format!(
+                    "Initial spectra must have {} columns and at least one row, got {:?}",
+                    n_cols,
+                    st.dim()
+                )));
+            }
+            Ok((Array2::zeros((n_rows, st.nrows())), st.to_owned()))
+        }
+    }
+}
+
+/// MCR-ALS of a sparse `d`, see `mcr_als`. The least squares steps only need the
+/// products of `d` with the profiles, so `d` is never made dense. The lack of fit comes
+/// from `||d - c st||² = ||d||² - 2 <d, c st> + ||c st||²` and the `residuals` of the
+/// result are left empty, as they would be as large as a dense `d`.
+///
+/// The constraints get, in place of `d`, a small matrix with the same products with the
+/// profiles they are applied to, which is all NNLS needs.
+pub fn mcr_als_sparse(
+    d: &SparseMatrix,
+    initial: &InitialEstimate,
+    params: &McrParams,
+    constraints: &McrConstraints,
+) -> Result<McrResult, RoiError> {
+    check_params(params)?;
+    let total = d.sum_squares();
+    check_data(total)?;
+    let (mut c, mut st) = initial_profiles(initial, d.shape)?;
+    constraints.validate(c.dim(), st.dim())?;
+    let start_from_spectra = matches!(initial, InitialEstimate::Spectra(_));
+
+    let mut iterations = Iterations::new(params.tol);
+
+    for iteration in 0..params.max_iter {
+        if start_from_spectra || iteration > 0 {
+            let s = st.t().to_owned();
+            let projection = d.dot(&s);
+            c = projection.dot(&regularised_inverse(&s.t().dot(&s)));
+            let (data, other) = equivalent_data(&projection, &s);
+            for constraint in constraints.c.iter() {
+                constraint.apply(&mut c, data.view(), other.view());
+            }
+        }
+
+        let projection = d.t_dot(&c);
+        let mut s = projection.dot(&regularised_inverse(&c.t().dot(&c)));
+        let (data, other) = equivalent_data(&projection, &c);
+        for constraint in constraints.st.iter() {
+            constraint.apply(&mut s, data.view(), other.view());
+        }
+
+        // <d, c st> = sum((d' c) * st') and ||c st||² = sum((c'c) * (st st'))
+        let cross = (&projection * &s).sum();
+        let fitted = (c.t().dot(&c) * s.t().dot(&s)).sum();
+        let residual = (total - 2.0 * cross + fitted).max(0.0);
+        st = s.reversed_axes();
+
+        let lof = 100.0 * (residual / total).sqrt();
+        if iterations.update(lof, || {
+            let r2 = 100.0 * (1.0 - residual / total);
+            (c.clone(), st.clone(), Array2::zeros((0, 0)), r2)
+        }) {
+            break;
+        }
+    }
+
+    Ok(iterations.finish())
+}
+
+/// Small `data` and `other` with `data · other = projection` and `other' other` the
+/// same as for the actual `other`, from the eigendecomposition `other' other = V L V'`:
+/// `other = L^1/2 V'` and `data = projection V L^-1/2`. The projection has no component
+/// along the null space of `other' other`, so the product is exact.
+fn equivalent_data(projection: &Array2<f64>, other: &Array2<f64>) -> (Array2<f64>, Array2<f64>) {
+    let (values, vectors) = symmetric_eigen(&other.t().dot(other));
+    let largest = values.iter().cloned().fold(0.0, f64::max);
+    let roots = values.mapv(|value| {
+        if value > 1e-12 * largest {
+            value.sqrt()
+        } else {
+            0.0
+        }
+    });
+    let inverse_roots = roots.mapv(|root| if root > 0.0 { 1.0 / root } else { 0.0 });
+
+    let equivalent_other = (&vectors * &roots).reversed_axes();
+    let data = projection.dot(&(&vectors * &inverse_roots));
+    (data, equivalent_other)
+}
+
+/// Lack of fit, in percent, of the `residuals` of the reconstruction of `d`.
+pub fn lack_of_fit(d: &Array2<f64>, residuals: &Array2<f64>) -> f64 {
+    100.0 * (residuals.mapv(|e| e * e).sum() / d.mapv(|x| x * x).sum()).sqrt()
+}
+
+/// Percentage of the sum of squares of `d` explained by the reconstruction.
+pub fn explained_variance(d: &Array2<f64>, residuals: &Array2<f64>) -> f64 {
+    100.0 * (1.0 - residuals.mapv(|e| e * e).sum() / d.mapv(|x| x * x).sum())
+}
+
+/// Figures of merit of the MCR model `result` of `d`: the overall lack of fit and
+/// explained variance (%), the variance of `d` explained by each component alone
+/// (`r2_components`, %) and the share of each component in the sum of squares of the
+/// individual component contributions `c[:, k] st[k, :]` (`contributions`, %).
+pub fn figures_of_merit(
+    d: &Array2<f64>,
+    result: &McrResult,
+) -> Result<McrFiguresOfMerit, RoiError> {
+    check_model(d.dim(), result)?;
+    let total = d.mapv(|x| x * x).sum();
+    check_data(total)?;
+    let residuals = d - &result.c.dot(&result.st);
+
+    // ||c_k s_k||² = ||c_k||² ||s_k||² and <d, c_k s_k> = c_k' d s_k
+    let c_norms = result.c.mapv(|x| x * x).sum_axis(Axis(0));
+    let s_norms = result.st.mapv(|x| x * x).sum_axis(Axis(1));
+    let component_norms = &c_norms * &s_norms;
+    let cross = (result.c.t().dot(d) * &result.st).sum_axis(Axis(1));
+
+    let r2_components = Array1::from_shape_fn(component_norms.len(), |k| {
+        100.0 * (2.0 * cross[k] - component_norms[k]) / total
+    });
+    let sum = component_norms.sum();
+    let contributions = component_norms.mapv(|n| if sum > 0.0 { 100.0 * n / sum } else { 0.0 });
+
+    Ok(McrFiguresOfMerit {
+        lof: lack_of_fit(d, &residuals),
+        r2: explained_variance(d, &residuals),
+        r2_components,
+        contributions,
+    })
+}
+
+/// Reject a model whose profiles don't reconstruct a matrix of shape `dim`.
+fn check_model(dim: (usize, usize), result: &McrResult) -> Result<(), RoiError> {
+    if result.c.nrows() != dim.0
+        || result.st.ncols() != dim.1
+        || result.c.ncols() != result.st.nrows()
+    {
+        return Err(RoiError::InvalidArgument(format!(
+            "An MCR model with concentrations {:?} and spectra {:?} doesn't fit a {:?} data matrix",
+            result.c.dim(),
+            result.st.dim(),
+            dim
+        )));
+    }
+    Ok(())
+}
+
+/// Same as `figures_of_merit` for a sparse `d`, with the overall lack of fit and explained
+/// variance of `result`, see `mcr_als_sparse`.
+pub fn figures_of_merit_sparse(
+    d: &SparseMatrix,
+    result: &McrResult,
+) -> Result<McrFiguresOfMerit, RoiError> {
+    check_model(d.shape, result)?;
+    let total = d.sum_squares();
+    check_data(total)?;
+    let c_norms = result.c.mapv(|x| x * x).sum_axis(Axis(0));
+    let s_norms = result.st.mapv(|x| x * x).sum_axis(Axis(1));
+    let component_norms = &c_norms * &s_norms;
+    let cross = (d.t_dot(&result.c) * result.st.t()).sum_axis(Axis(0));
+
+    let r2_components = Array1::from_shape_fn(component_norms.len(), |k| {
+        100.0 * (2.0 * cross[k] - component_norms[k]) / total
+    });
+    let sum = component_norms.sum();
+    let contributions = component_norms.mapv(|n| if sum > 0.0 { 100.0 * n / sum } else { 0.0 });
+
+    Ok(McrFiguresOfMerit {
+        lof: result.lof,
+        r2: result.r2,
+        r2_components,
+        contributions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{NonNegativityMethod, SparseLayout};
+    use crate::mcr::constraints::NonNegativity;
+    use crate::test_utils::{bilinear, Random};
+
+    fn non_negative() -> McrConstraints {
+        let mut constraints = McrConstraints::new();
+        for profiles in [&mut constraints.c, &mut constraints.st] {
+            profiles.push(Box::new(NonNegativity {
+                method: NonNegativityMethod::Clipping,
+                components: None,
+            }));
+        }
+        constraints
+    }
+
+    /// The true spectra with up to 30% multiplicative noise.
+    fn perturbed(st: &Array2<f64>, seed: u64) -> Array2<f64> {
+        let mut random = Random::new(seed);
+        st.mapv(|x| x * (0.7 + 0.6 * random.uniform()) + 0.01)
+    }
+
+    #[test]
+    fn bilinear_data_is_resolved() {
+        let (d, _, st) = bilinear(60, 30, 3, 1);
+        let params = McrParams {
+            max_iter: 200,
+            tol: 1e-8,
+        };
+        let initial = InitialEstimate::Spectra(perturbed(&st, 2));
+        let result = mcr_als(&d, &initial, &params, &non_negative()).unwrap();
+
+        assert_eq!(result.c.dim(), (60, 3));
+        assert_eq!(result.st.dim(), (3, 30));
+        assert!(result.lof < 1.0, "lof {}", result.lof);
+        assert!(result.r2 > 99.9);
+        assert!(result.c.iter().chain(result.st.iter()).all(|&x| x >= 0.0));
+        let reconstruction = result.c.dot(&result.st);
+        assert!(((&d - &reconstruction) - &result.residuals)
+            .iter()
+            .all(|x| x.abs() < 1e-9));
+    }
+
+    #[test]
+    fn best_iterate_is_returned() {
+        let (d, c, _) = bilinear(40, 20, 2, 3);
+        let params = McrParams {
+            max_iter: 5,
+            tol: 0.0,
+        };
+        let result = mcr_als(
+            &d,
+            &InitialEstimate::Concentrations(c.mapv(|x| x + 0.2)),
+            &params,
+            &non_negative(),
+        )
+        .unwrap();
+
+        assert_eq!(result.n_iter, 5);
+        assert_eq!(result.lof_history.len(), 5);
+        assert!(!result.converged);
+        let lowest = result.lof_history.iter().cloned().fold(f64::MAX, f64::min);
+        assert_eq!(result.lof, lowest);
+    }
+
+    #[test]
+    fn iterations_stop_once_converged() {
+        let (d, _, st) = bilinear(40, 20, 2, 4);
+        let params = McrParams {
+            max_iter: 500,
+            tol: 1e-2,
+        };
+        let initial = InitialEstimate::Spectra(perturbed(&st, 5));
+        let result = mcr_als(&d, &initial, &params, &non_negative()).unwrap();
+        assert!(result.converged);
+        assert!(result.n_iter < 500);
+    }
+
+    #[test]
+    fn figures_of_merit_of_a_resolution() {
+        let (d, c, st) = bilinear(50, 20, 3, 9);
+        let result = McrResult {
+            residuals: Array2::zeros(d.dim()),
+            lof: 0.0,
+            r2: 100.0,
+            lof_history: Vec::new(),
+            n_iter: 0,
+            converged: true,
+            c,
+            st,
+        };
+        let merit = figures_of_merit(&d, &result).unwrap();
+        assert!(merit.lof < 1e-6);
+        assert!((merit.r2 - 100.0).abs() < 1e-9);
+        assert!((merit.contributions.sum() - 100.0).abs() < 1e-9);
+        assert!(merit.r2_components.iter().all(|&r2| r2 > 0.0 && r2 < 100.0));
+
+        // A model with a single component explains what that component explains alone
+        let single = McrResult {
+            c: result.c.slice(s![.., ..1]).to_owned(),
+            st: result.st.slice(s![..1, ..]).to_owned(),
+            ..result.clone()
+        };
+        let alone = figures_of_merit(&d, &single).unwrap();
+        assert!((alone.r2 - merit.r2_components[0]).abs() < 1e-9);
+        assert_eq!(alone.contributions.to_vec(), vec![100.0]);
+
+        let transposed = d.t().to_owned();
+        assert!(matches!(
+            figures_of_merit(&transposed, &result),
+            Err(RoiError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_initial_estimates_are_rejected() {
+        let (d, c, st) = bilinear(40, 20, 2, 6);
+        let params = McrParams::default();
+        let constraints = McrConstraints::new();
+        for initial in [
+            InitialEstimate::Concentrations(c.slice(s![..30, ..]).to_owned()),
+            InitialEstimate::Spectra(st.slice(s![.., ..10]).to_owned()),
+            InitialEstimate::Spectra(Array2::zeros((0, 20))),
+        ] {
+            assert!(matches!(
+                mcr_als(&d, &initial, &params, &constraints),
+                Err(RoiError::InvalidArgument(_))
+            ));
+        }
+
+        let no_iterations = McrParams {
+            max_iter: 0,
+            tol: 1e-3,
+        };
+        let initial = InitialEstimate::Spectra(st);
+        assert!(mcr_als(&d, &initial, &no_iterations, &constraints).is_err());
+        for tol in [-1e-3, f64::NAN, f64::INFINITY] {
+            let params = McrParams { max_iter: 10, tol };
+            assert!(matches!(
+                mcr_als(&d, &initial, &params, &constraints),
+                Err(RoiError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn data_without_signal_is_rejected() {
+        let (d, c, st) = bilinear(30, 12, 2, 10);
+        let zeros = Array2::zeros(d.dim());
+        let initial = InitialEstimate::Spectra(st.clone());
+        let params = McrParams::default();
+        let constraints = McrConstraints::new();
+        assert!(matches!(
+            mcr_als(&zeros, &initial, &params, &constraints),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        let mut nan = d.clone();
+        nan[[3, 4]] = f64::NAN;
+        assert!(mcr_als(&nan, &initial, &params, &constraints).is_err());
+
+        let sparse = SparseMatrix::from_dense(&zeros, SparseLayout::Csr);
+        assert!(mcr_als_sparse(&sparse, &initial, &params, &constraints).is_err());
+
+        let model = McrResult {
+            residuals: Array2::zeros(d.dim()),
+            lof: 0.0,
+            r2: 100.0,
+            lof_history: Vec::new(),
+            n_iter: 0,
+            converged: true,
+            c,
+            st,
+        };
+        assert!(figures_of_merit(&zeros, &model).is_err());
+        assert!(figures_of_merit_sparse(&sparse, &model).is_err());
+    }
+
+    #[test]
+    fn sparse_data_gives_the_dense_resolution() {
+        // Bilinear data with its small values zeroed, as in an msroi
+        let (d, _, st) = bilinear(50, 24, 3, 7);
+        let d = d.mapv(|x| if x < 0.05 { 0.0 } else { x });
+        let params = McrParams {
+            max_iter: 30,
+            tol: 0.0,
+        };
+        let initial = InitialEstimate::Spectra(perturbed(&st, 8));
+
+        for method in [NonNegativityMethod::Clipping, NonNegativityMethod::Nnls] {
+            let mut constraints = McrConstraints::new();
+            for profiles in [&mut constraints.c, &mut constraints.st] {
+                profiles.push(Box::new(NonNegativity {
+                    method,
+                    components: None,
+                }));
+            }
+            let dense = mcr_als(&d, &initial, &params, &constraints).unwrap();
+            let sparse = SparseMatrix::from_dense(&d, SparseLayout::Csr);
+            assert_eq!(sparse.to_dense(), d);
+            let result = mcr_als_sparse(&sparse, &initial, &params, &constraints).unwrap();
+
+            let close = |a: &Array2<f64>, b: &Array2<f64>| {
+                a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-6)
+            };
+            assert_eq!(result.lof_history.len(), dense.lof_history.len());
+            assert!((result.lof - dense.lof).abs() < 1e-6, "{:?}", method);
+            assert!((result.r2 - dense.r2).abs() < 1e-6);
+            assert!(close(&result.c, &dense.c), "{:?}", method);
+            assert!(close(&result.st, &dense.st), "{:?}", method);
+            assert_eq!(result.residuals.len(), 0);
+
+            let merit = figures_of_merit(&d, &dense).unwrap();
+            let sparse_merit = figures_of_merit_sparse(&sparse, &result).unwrap();
+            for (a, b) in merit
+                .r2_components
+                .iter()
+                .chain(merit.contributions.iter())
+                .zip(
+                    sparse_merit
+                        .r2_components
+                        .iter()
+                        .chain(sparse_merit.contributions.iter()),
+                )
+            {
+                assert!((a - b).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_sparse_arguments_are_rejected() {
+        let (d, c, st) = bilinear(30, 12, 2, 9);
+        let sparse = SparseMatrix::from_dense(&d, SparseLayout::Csc);
+        let params = McrParams::default();
+        let constraints = McrConstraints::new();
+        let short = InitialEstimate::Concentrations(c.slice(s![..20, ..]).to_owned());
+        assert!(matches!(
+            mcr_als_sparse(&sparse, &short, &params, &constraints),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        let no_iterations = McrParams {
+            max_iter: 0,
+            tol: 1e-3,
+        };
+        let initial = InitialEstimate::Spectra(st);
+        assert!(mcr_als_sparse(&sparse, &initial, &no_iterations, &constraints).is_err());
+
+        let result = mcr_als_sparse(&sparse, &initial, &params, &constraints).unwrap();
+        let transposed = SparseMatrix::from_dense(&d.t().to_owned(), SparseLayout::Csc);
+        assert!(figures_of_merit_sparse(&transposed, &result).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use ndarray::prelude::*;
+
+use super::constraints::{check_components, Constraint, McrConstraints};
+use super::mcr_als;
+use crate::enums::InitialEstimate;
+use crate::error::RoiError;
+use crate::structs::data::AugmentedMcrResult;
+use crate::structs::options::{AugmentedParams, McrParams};
+
+/// MCR-ALS of a row-wise augmented matrix, such as the `msroi` built by `compute_roi`
+/// from several runs, whose consecutive row blocks of `run_sizes` rows belong to
+/// different runs.
+///
+/// The concentration constraints in `constraints.c` are applied separately to each run
+/// block, followed by the component presence mask and the trilinearity constraint in
+/// `options`. The spectra constraints act on the shared S^T as in `mcr_als`. The result
+/// holds the area of every concentration profile in every run.
+pub fn mcr_als_augmented(
+    d: &Array2<f64>,
+    run_sizes: &[usize],
+    initial: &InitialEstimate,
+    params: &McrParams,
+    constraints: McrConstraints,
+    options: &AugmentedParams,
+) -> Result<AugmentedMcrResult, RoiError> {
+    if run_sizes.iter().sum::<usize>() != d.nrows() {
+        return Err(RoiError::InvalidArgument(format!(
+            "Run sizes must add up to the number of rows of the data matrix, {}",
+            d.nrows()
+        )));
+    }
+
+    let mut block_constraints = McrConstraints::new();
+    for constraint in constraints.c {
+        block_constraints.c.push(Box::new(PerRun {
+            run_sizes: run_sizes.to_vec(),
+            constraint,
+        }));
+    }
+    if let Some(presence) = &options.presence {
+        block_constraints.c.push(Box::new(ComponentPresence {
+            run_sizes: run_sizes.to_vec(),
+            presence: presence.clone(),
+        }));
+    }
+    if options.trilinearity {
+        block_constraints.c.push(Box::new(Trilinearity {
+            run_sizes: run_sizes.to_vec(),
+            components: None,
+        }));
+    }
+    block_constraints.st = constraints.st;
+
+    let mcr = mcr_als(d, initial, params, &block_constraints)?;
+    let areas = run_areas(&mcr.c, run_sizes);
+
+    Ok(AugmentedMcrResult {
+        mcr,
+        run_sizes: run_sizes.to_vec(),
+        areas,
+    })
+}
+
+/// Area of each concentration profile in each run block, as a components × runs table.
+pub fn run_areas(c: &Array2<f64>, run_sizes: &[usize]) -> Array2<f64> {
+    let mut areas: Array2<f64> = Array2::zeros((c.ncols(), run_sizes.len()));
+
+    for (run, (start, end)) in run_bounds(run_sizes).into_iter().enumerate() {
+        areas
+            .column_mut(run)
+            .assign(&c.slice(s![start..end, ..]).sum_axis(Axis(0)));
+    }
+    areas
+}
+
+/// Number of rows of each run from the run index of every row, as in `RoiResult::runs`.
+pub fn run_sizes(runs: &Array1<usize>) -> Vec<usize> {
+    let mut sizes: Vec<usize> = Vec::new();
+    for &run in runs.iter() {
+        if sizes.len() <= run {
+            sizes.resize(run + 1, 0);
+        }
+        sizes[run] += 1;
+    }
+    sizes
+}
+
+/// Reject `run_sizes` that don't add up to the `n_rows` of the constrained profiles.
+fn check_run_sizes(run_sizes: &[usize], n_rows: usize) -> Result<(), RoiError> {
+    if run_sizes.iter().sum::<usize>() != n_rows {
+        return Err(RoiError::InvalidArgument(format!(
+            "Run sizes must add up to the number of rows of the profiles, {}",
+            n_rows
+        )));
+    }
+    Ok(())
+}
+
+fn run_bounds(run_sizes: &[usize]) -> Vec<(usize, usize)> {
+    run_sizes
+        .iter()
+        .scan(0, |start, &size| {
+            *start += size;
+            Some((*start - size, *start))
+        })
+        .collect()
+}
+
+/// Apply `constraint` separately to the rows of each run block.
+pub struct PerRun {
+    pub run_sizes: Vec<usize>,
+    pub constraint: Box<dyn Constraint>,
+}
+
+impl Constraint for PerRun {
+    fn apply(&self, profiles: &mut Array2<f64>, data: ArrayView2<f64>, other: ArrayView2<f64>) {
+        for (start, end) in run_bounds(&self.run_sizes) {
+            let mut block = profiles.slice(s![start..end, ..]).to_owned();
+            self.constraint
+                .apply(&mut block, data.slice(s![start..end, ..]), other);
+            profiles.slice_mut(s![start..end, ..]).assign(&block);
+        }
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        check_run_sizes(&self.run_sizes, dim.0)?;
+        for &size in self.run_sizes.iter() {
+            self.constraint.validate((size, dim.1))?;
+        }
+        Ok(())
+    }
+}
+
+/// Set to zero the concentration profiles of the components absent from a run, given as
+/// a runs × components mask.
+pub struct ComponentPresence {
+    pub run_sizes: Vec<usize>,
+    pub presence: Array2<bool>,
+}
+
+impl Constraint for ComponentPresence {
+    fn apply(&self, profiles: &mut Array2<f64>, _data: ArrayView2<f64>, _other: ArrayView2<f64>) {
+        for (run, (start, end)) in run_bounds(&self.run_sizes).into_iter().enumerate() {
+            for (k, &present) in self.presence.row(run).iter().enumerate() {
+                if !present {
+                    profiles.slice_mut(s![start..end, k]).fill(0.0);
+                }
+            }
+        }
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        check_run_sizes(&self.run_sizes, dim.0)?;
+        if self.presence.dim() != (self.run_sizes.len(), dim.1) {
+            return Err(RoiError::InvalidArgument(format!(
+                "Presence mask must have one row per run and one column per component, {:?}, got {:?}",
+                (self.run_sizes.len(), dim.1),
+                self.presence.dim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Trilinearity: the concentration profile of a component has the same shape in every
+/// run and only its scale changes. Each profile is replaced by the best rank one
+/// approximation of its run blocks; all runs must have the same number of scans.
+pub struct Trilinearity {
+    pub run_sizes: Vec<usize>,
+    pub components: Option<Vec<usize>>,
+}
+
+impl Constraint for Trilinearity {
+    fn apply(&self, profiles: &mut Array2<f64>, _data: ArrayView2<f64>, _other: ArrayView2<f64>) {
+        let length = self.run_sizes[0];
+        let n_runs = self.run_sizes.len();
+        let components: Vec<usize> = match &self.components {
+            Some(components) => components.clone(),
+            None => (0..profiles.ncols()).collect(),
+        };
+
+        for k in components {
+            let blocks =
+                Array2::from_shape_fn((length, n_runs), |(i, run)| profiles[[run * length + i, k]]);
+            let (shape, scales) = rank_one(&blocks);
+            for run in 0..n_runs {
+                profiles
+                    .slice_mut(s![run * length..(run + 1) * length, k])
+                    .assign(&(&shape * scales[run]));
+            }
+        }
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        match self.run_sizes.first() {
+            Some(&length) if self.run_sizes.iter().all(|&size| size == length) => {}
+            _ => {
+                return Err(RoiError::InvalidArgument(
+                    "Trilinearity needs runs with the same number of scans".to_string(),
+                ))
+            }
+        }
+        check_run_sizes(&self.run_sizes, dim.0)?;
+        check_components(&self.components, dim.1)
+    }
+}
+
+/// Best rank one approximation `u v'` of `m` by power iteration, with `u` of unit length
+/// and positive sum.
+fn rank_one(m: &Array2<f64>) -> (Array1<f64>, Array1<f64>) {
+    let mut v: Array1<f64> = Array1::ones(m.ncols());
+    let mut u: Array1<f64> = Array1::zeros(m.nrows());
+
+    for _ in 0..100 {
+        u = m.dot(&v);
+        let norm = u.dot(&u).sqrt();
+        if norm == 0.0 {
+            return (u, Array1::zeros(m.ncols()));
+        }
+        u /= norm;
+        let next = m.t().dot(&u);
+        let change = (&next - &v).mapv(f64::abs).sum();
+        v = next;
+        if change <= 1e-12 * v.mapv(f64::abs).sum() {
+            break;
+        }
+    }
+
+    if u.sum() < 0.0 {
+        u.mapv_inplace(|x| -x);
+        v.mapv_inplace(|x| -x);
+    }
+    (u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::NonNegativityMethod;
+    use crate::mcr::constraints::NonNegativity;
+    use crate::test_utils::bilinear;
+
+    /// Two runs of 40 scans with the same two compounds, the second at half the
+    /// concentration of the first, and the true spectra.
+    fn two_runs() -> (Array2<f64>, Array2<f64>) {
+        let (d, _, st) = bilinear(40, 16, 2, 21);
+        let augmented = ndarray::concatenate(Axis(0), &[d.view(), (&d * 0.5).view()]).unwrap();
+        (augmented, st)
+    }
+
+    fn non_negative() -> McrConstraints {
+        let mut constraints = McrConstraints::new();
+        constraints.c.push(Box::new(NonNegativity {
+            method: NonNegativityMethod::Clipping,
+            components: None,
+        }));
+        constraints.st.push(Box::new(NonNegativity {
+            method: NonNegativityMethod::Clipping,
+            components: None,
+        }));
+        constraints
+    }
+
+    fn params() -> McrParams {
+        McrParams {
+            max_iter: 100,
+            tol: 1e-8,
+        }
+    }
+
+    #[test]
+    fn trilinear_runs_keep_the_ratio_of_their_areas() {
+        let (d, st) = two_runs();
+        let options = AugmentedParams {
+            presence: None,
+            trilinearity: true,
+        };
+        let result = mcr_als_augmented(
+            &d,
+            &[40, 40],
+            &InitialEstimate::Spectra(st.mapv(|x| x + 0.05)),
+            &params(),
+            non_negative(),
+            &options,
+        )
+        .unwrap();
+
+        assert!(result.mcr.lof < 1.0, "lof {}", result.mcr.lof);
+        assert_eq!(result.run_sizes, vec![40, 40]);
+        assert_eq!(result.areas.dim(), (2, 2));
+        for k in 0..2 {
+            assert!((result.areas[[k, 1]] / result.areas[[k, 0]] - 0.5).abs() < 1e-6);
+            // Same shape in both runs
+            let first = result.mcr.c.slice(s![..40, k]);
+            let second = result.mcr.c.slice(s![40.., k]);
+            assert!((&first * 0.5 - second).iter().all(|x| x.abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn absent_components_are_zero_in_their_run() {
+        let (d, st) = two_runs();
+        let options = AugmentedParams {
+            presence: Some(arr2(&[[true, true], [true, false]])),
+            trilinearity: false,
+        };
+        let result = mcr_als_augmented(
+            &d,
+            &[40, 40],
+            &InitialEstimate::Spectra(st),
+            &params(),
+            non_negative(),
+            &options,
+        )
+        .unwrap();
+        assert!(result.mcr.c.slice(s![40.., 1]).iter().all(|&x| x == 0.0));
+        assert_eq!(result.areas[[1, 1]], 0.0);
+    }
+
+    #[test]
+    fn run_sizes_and_areas() {
+        assert_eq!(run_sizes(&arr1(&[0, 0, 0, 1, 1, 2])), vec![3, 2, 1]);
+        let c = arr2(&[[1.0, 0.0], [2.0, 1.0], [3.0, 4.0]]);
+        assert_eq!(run_areas(&c, &[2, 1]), arr2(&[[3.0, 3.0], [1.0, 4.0]]));
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let (d, st) = two_runs();
+        let initial = InitialEstimate::Spectra(st);
+        let cases = [
+            (vec![40, 30], AugmentedParams::default()),
+            (
+                vec![40, 40],
+                AugmentedParams {
+                    presence: Some(Array2::from_elem((3, 2), true)),
+                    trilinearity: false,
+                },
+            ),
+            (
+                vec![30, 50],
+                AugmentedParams {
+                    presence: None,
+                    trilinearity: true,
+                },
+            ),
+        ];
+        for (sizes, options) in cases.iter() {
+            assert!(matches!(
+                mcr_als_augmented(&d, sizes, &initial, &params(), non_negative(), options),
+                Err(RoiError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn inconsistent_constraints_are_rejected() {
+        let dim = (80, 2);
+        let trilinearity = |run_sizes: Vec<usize>, components: Option<Vec<usize>>| {
+            Trilinearity {
+                run_sizes,
+                components,
+            }
+            .validate(dim)
+        };
+        assert!(trilinearity(vec![40, 40], Some(vec![1])).is_ok());
+        assert!(trilinearity(vec![40, 40], Some(vec![5])).is_err());
+        assert!(trilinearity(vec![30, 30], None).is_err());
+        assert!(trilinearity(vec![], None).is_err());
+
+        let presence = |run_sizes: Vec<usize>| {
+            ComponentPresence {
+                run_sizes,
+                presence: Array2::from_elem((2, 2), true),
+            }
+            .validate(dim)
+        };
+        assert!(presence(vec![40, 40]).is_ok());
+        assert!(matches!(
+            presence(vec![40, 50]),
+            Err(RoiError::InvalidArgument(_))
+        ));
+
+        let per_run = PerRun {
+            run_sizes: vec![40, 30],
+            constraint: Box::new(NonNegativity {
+                method: NonNegativityMethod::Clipping,
+                components: None,
+            }),
+        };
+        assert!(per_run.validate(dim).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use ndarray::prelude::*;
+
+use super::constraints::McrConstraints;
+use crate::error::RoiError;
+use crate::structs::data::{McrBandsResult, McrResult};
+use crate::utils::{inverse, orthonormalise};
+
+/// Penalty weights of the successive constrained optimisations.
+const PENALTIES: [f64; 5] = [1.0, 1e1, 1e2, 1e3, 1e4];
+
+/// MCR-BANDS (Tauler, 2001): boundaries of the feasible solutions of the MCR model
+/// `result` under `constraints`, usually the ones used to compute it.
+///
+/// Every solution with the same fit is a rotation `c T`, `T^-1 st` of the one found. For
+/// each component the signal contribution function `||c_k st_k|| / ||c st||` is
+/// maximised and minimised over `T`. A rotation is feasible when applying the
+/// constraints leaves its profiles unchanged, which is imposed by a penalty of increasing
+/// weight on the changes, measured relative to the signal `||c st||`. The profiles of
+/// component `k` at the maximum and at the minimum are the `k`-th columns of `c_max` and
+/// `c_min` and rows of `st_max` and `st_min`.
+///
+/// The constraints are applied to the profiles fitted to the reconstruction `c st`,
+/// which they see in the reduced coordinates of the model: `data` and `other` have the
+/// usual number of rows but one column per component. Constraints that refit the
+/// profiles by least squares, such as NNLS, only depend on these.
+///
+/// A penalty step whose rotation can't be inverted is dropped and the rotation of the
+/// previous step is kept.
+pub fn mcr_bands(
+    result: &McrResult,
+    constraints: &McrConstraints,
+) -> Result<McrBandsResult, RoiError> {
+    let n = result.c.ncols();
+    if result.st.nrows() != n {
+        return Err(RoiError::InvalidArgument(format!(
+            "The MCR model has {} concentration profiles but {} spectra",
+            n,
+            result.st.nrows()
+        )));
+    }
+    constraints.validate(result.c.dim(), result.st.dim())?;
+    let problem = Rotation::new(result, constraints);
+
+    let mut bands = McrBandsResult {
+        scf_max: Array1::zeros(n),
+        scf_min: Array1::zeros(n),
+        c_max: Array2::zeros(result.c.dim()),
+        c_min: Array2::zeros(result.c.dim()),
+        st_max: Array2::zeros(result.st.dim()),
+        st_min: Array2::zeros(result.st.dim()),
+    };
+
+    for k in 0..n {
+        for maximise in [true, false] {
+            let sign = if maximise { -1.0 } else { 1.0 };
+            let mut t: Vec<f64> = Array2::<f64>::eye(n).into_raw_vec();
+            let (mut c, mut st) = (result.c.clone(), result.st.clone());
+            for &penalty in PENALTIES.iter() {
+                let next = minimise(
+                    |t| {
+                        problem
+                            .evaluate(t, k)
+                            .map_or(f64::INFINITY, |(scf, violation)| {
+                                sign * scf + penalty * violation
+                            })
+                    },
+                    t.clone(),
+                );
+                match problem.rotate(&next) {
+                    Some(rotated) => {
+                        (c, st) = rotated;
+                        t = next;
+                    }
+                    None => break,
+                }
+            }
+
+            let scf = problem.scf(&c, &st, k);
+            if maximise {
+                bands.scf_max[k] = scf;
+                bands.c_max.column_mut(k).assign(&c.column(k));
+                bands.st_max.row_mut(k).assign(&st.row(k));
+            } else {
+                bands.scf_min[k] = scf;
+                bands.c_min.column_mut(k).assign(&c.column(k));
+                bands.st_min.row_mut(k).assign(&st.row(k));
+            }
+        }
+    }
+    Ok(bands)
+}
+
+struct Rotation<'a> {
+    c: &'a Array2<f64>,
+    st: &'a Array2<f64>,
+    constraints: &'a McrConstraints,
+    // Reconstruction `c st` in the bases of the columns of `c` and of the rows of `st`
+    reduced_c: Array2<f64>,
+    reduced_st: Array2<f64>,
+    basis_c: Array2<f64>,
+    basis_st: Array2<f64>,
+    norm: f64,
+}
+
+impl<'a> Rotation<'a> {
+    fn new(result: &'a McrResult, constraints: &'a McrConstraints) -> Self {
+        let (c, st) = (&result.c, &result.st);
+        let basis_c = orthonormalise(c);
+        let basis_st = orthonormalise(&st.t().to_owned());
+        let norm = (c.t().dot(c) * st.dot(&st.t())).sum().sqrt();
+
+        Rotation {
+            c,
+            st,
+            constraints,
+            reduced_c: c.dot(&st.dot(&basis_st)),
+            reduced_st: st.t().dot(&c.t().dot(&basis_c)),
+            basis_c,
+            basis_st,
+            norm,
+        }
+    }
+
+    fn rotate(&self, t: &[f64]) -> Option<(Array2<f64>, Array2<f64>)> {
+        let n = self.c.ncols();
+        let t = Array2::from_shape_fn((n, n), |(i, j)| t[i * n + j]);
+        let inverse = inverse(&t)?;
+        Some((self.c.dot(&t), inverse.dot(self.st)))
+    }
+
+    fn scf(&self, c: &Array2<f64>, st: &Array2<f64>, k: usize) -> f64 {
+        let c_norm = c.column(k).dot(&c.column(k));
+        let s_norm = st.row(k).dot(&st.row(k));
+        (c_norm * s_norm).sqrt() / self.norm
+    }
+
+    /// Signal contribution function of component `k` and relative constraint violation.
+    fn evaluate(&self, t: &[f64], k: usize) -> Option<(f64, f64)> {
+        let (c, st) = self.rotate(t)?;
+        if !c.iter().chain(st.iter()).all(|x| x.is_finite()) {
+            return None;
+        }
+
+        let mut constrained = c.clone();
+        let other = self.basis_st.t().dot(&st.t());
+        for constraint in self.constraints.c.iter() {
+            constraint.apply(&mut constrained, self.reduced_c.view(), other.view());
+        }
+        let c_change = (&constrained - &c).mapv(|x| x * x).sum_axis(Axis(0));
+
+        let s = st.t().to_owned();
+        let mut constrained = s.clone();
+        let other = self.basis_c.t().dot(&c);
+        for constraint in self.constraints.st.iter() {
+            constraint.apply(&mut constrained, self.reduced_st.view(), other.view());
+        }
+        let s_change = (&constrained - &s).mapv(|x| x * x).sum_axis(Axis(0));
+
+        // Changes of each component weighted by the norm of its other profile, so that
+        // the violation is measured on the signal and doesn't depend on their scaling
+        let c_norms = c.mapv(|x| x * x).sum_axis(Axis(0));
+        let s_norms = s.mapv(|x| x * x).sum_axis(Axis(0));
+        let violation =
+            ((&c_change * &s_norms).sum() + (&c_norms * &s_change).sum()) / (self.norm * self.norm);
+
+        Some((self.scf(&c, &st, k), violation))
+    }
+}
+
+/// Minimise `f` from `x` by BFGS with central difference gradients and a backtracking
+/// line search.
+fn minimise(f: impl Fn(&[f64]) -> f64, mut x: Vec<f64>) -> Vec<f64> {
+    let n = x.len();
+    let gradient = |x: &[f64]| -> Array1<f64> {
+        let mut point = x.to_vec();
+        Array1::from_shape_fn(n, |i| {
+            let h = 1e-6 * x[i].abs().max(1.0);
+            point[i] = x[i] + h;
+            let forward = f(&point);
+            point[i] = x[i] - h;
+            let backward = f(&point);
+            point[i] = x[i];
+            (forward - backward) / (2.0 * h)
+        })
+    };
+
+    let mut value = f(&x);
+    let mut g = gradient(&x);
+    let mut h: Array2<f64> = Array2::eye(n);
+
+    for _ in 0..200 {
+        if !value.is_finite() || g.dot(&g).sqrt() < 1e-10 {
+            break;
+        }
+        let mut direction = -h.dot(&g);
+        if direction.dot(&g) >= 0.0 {
+            h = Array2::eye(n);
+            direction = -g.clone();
+        }
+
+        let slope = direction.dot(&g);
+        let mut step = 1.0;
+        let mut next: Vec<f64> = x.clone();
+        let mut next_value = f64::INFINITY;
+        while step > 1e-12 {
+            next = x
+                .iter()
+                .zip(direction.iter())
+                .map(|(x, d)| x + step * d)
+                .collect();
+            next_value = f(&next);
+            if next_value <= value + 1e-4 * step * slope {
+                break;
+            }
+            step *= 0.5;
+        }
+        if next_value.is_nan() || next_value >= value {
+            break;
+        }
+
+        let next_g = gradient(&next);
+        let s = Array1::from_shape_fn(n, |i| next[i] - x[i]);
+        let y = &next_g - &g;
+        let sy = s.dot(&y);
+        if sy > 1e-12 {
+            // H+ = (I - rho s y') H (I - rho y s') + rho s s'
+            let rho = 1.0 / sy;
+            let hy = h.dot(&y);
+            let yhy = y.dot(&hy);
+            for i in 0..n {
+                for j in 0..n {
+                    h[[i, j]] +=
+                        rho * ((1.0 + rho * yhy) * s[i] * s[j] - hy[i] * s[j] - s[i] * hy[j]);
+                }
+            }
+        }
+
+        let improvement = value - next_value;
+        x = next;
+        value = next_value;
+        g = next_g;
+        if improvement <= 1e-12 * value.abs().max(1e-12) {
+            break;
+        }
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{InitialEstimate, NonNegativityMethod};
+    use crate::mcr::constraints::{Equality, NonNegativity};
+    use crate::mcr::mcr_als;
+    use crate::structs::options::McrParams;
+    use crate::test_utils::bilinear;
+
+    fn non_negative() -> McrConstraints {
+        let mut constraints = McrConstraints::new();
+        for profiles in [&mut constraints.c, &mut constraints.st] {
+            profiles.push(Box::new(NonNegativity {
+                method: NonNegativityMethod::Clipping,
+                components: None,
+            }));
+        }
+        constraints
+    }
+
+    fn resolved(n_rows: usize) -> McrResult {
+        let (d, _, st) = bilinear(n_rows, 24, 2, 81);
+        let params = McrParams {
+            max_iter: 200,
+            tol: 1e-10,
+        };
+        mcr_als(&d, &InitialEstimate::Spectra(st), &params, &non_negative()).unwrap()
+    }
+
+    #[test]
+    fn bands_enclose_the_solution() {
+        let result = resolved(60);
+        let bands = mcr_bands(&result, &non_negative()).unwrap();
+
+        let norm = result.c.dot(&result.st).mapv(|x| x * x).sum().sqrt();
+        for k in 0..2 {
+            let c_norm = result.c.column(k).dot(&result.c.column(k));
+            let s_norm = result.st.row(k).dot(&result.st.row(k));
+            let scf = (c_norm * s_norm).sqrt() / norm;
+            assert!(bands.scf_min[k] <= scf + 1e-6, "{:?}", bands.scf_min);
+            assert!(bands.scf_max[k] >= scf - 1e-6, "{:?}", bands.scf_max);
+            // Both components have selective scans and m/z values, so the solution is
+            // close to unique
+            assert!(bands.scf_max[k] - bands.scf_min[k] < 0.05);
+        }
+        assert_eq!(bands.c_max.dim(), result.c.dim());
+        assert_eq!(bands.st_min.dim(), result.st.dim());
+    }
+
+    #[test]
+    fn invalid_models_and_constraints_are_rejected() {
+        let result = resolved(30);
+        let mut constraints = McrConstraints::new();
+        constraints.c.push(Box::new(Equality {
+            mask: Array2::zeros((29, 2)),
+            upper_bound: true,
+        }));
+        assert!(matches!(
+            mcr_bands(&result, &constraints),
+            Err(RoiError::InvalidArgument(_))
+        ));
+
+        let mut truncated = resolved(30);
+        truncated.st = truncated.st.slice(s![..1, ..]).to_owned();
+        assert!(mcr_bands(&truncated, &non_negative()).is_err());
+    }
+}
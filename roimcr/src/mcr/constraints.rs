@@ -0,0 +1,573 @@
+use ndarray::prelude::*;
+
+use crate::enums::{NonNegativityMethod, NormalisationType};
+use crate::error::RoiError;
+use crate::structs::options::ConstraintParams;
+use crate::utils::solve_linear_system;
+
+/// A constraint applied to the profiles estimated at each MCR-ALS step.
+///
+/// `profiles` holds one component per column: the concentration profiles `c` when
+/// constraining C, the spectra `st` transposed when constraining S^T. `data` and `other`
+/// describe the least squares problem the profiles were fitted to, `data ≈ profiles ·
+/// other'`, for constraints such as NNLS that need to refit them. Only the products
+/// `data · other` and `other' · other` are guaranteed to be those of the fitted matrices:
+/// `data` and `other` may be smaller equivalents with other shapes, as in
+/// `mcr_als_sparse` and `mcr_bands`, so constraints must not rely on their shapes.
+///
+/// `validate` is called once, before the first iteration, with the shape of the profiles
+/// the constraint will be applied to, and rejects the constraints that can't act on them.
+pub trait Constraint: Send + Sync {
+    fn apply(&self, profiles: &mut Array2<f64>, data: ArrayView2<f64>, other: ArrayView2<f64>);
+
+    fn validate(&self, _dim: (usize, usize)) -> Result<(), RoiError> {
+        Ok(())
+    }
+}
+
+/// Constraints applied to the concentration profiles and to the spectra, in order.
+#[derive(Default)]
+pub struct McrConstraints {
+    pub c: Vec<Box<dyn Constraint>>,
+    pub st: Vec<Box<dyn Constraint>>,
+}
+
+impl McrConstraints {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Constraints described by `params`: non-negativity, unimodality and closure of the
+    /// concentration profiles, non-negativity and normalisation of the spectra.
+    pub fn from_params(params: &ConstraintParams) -> Self {
+        let mut constraints = McrConstraints::new();
+        let method = if params.nnls {
+            NonNegativityMethod::Nnls
+        } else {
+            NonNegativityMethod::Clipping
+        };
+        let target = params.non_negativity;
+
+        if target.is_some_and(|target| target.on_c()) {
+            constraints.c.push(Box::new(NonNegativity {
+                method,
+                components: None,
+            }));
+        }
+        if let Some(tolerance) = params.unimodality {
+            constraints.c.push(Box::new(Unimodality {
+                tolerance,
+                components: None,
+            }));
+        }
+        if let Some(total) = params.closure {
+            constraints.c.push(Box::new(Closure {
+                total,
+                components: None,
+            }));
+        }
+
+        if target.is_some_and(|target| target.on_st()) {
+            constraints.st.push(Box::new(NonNegativity {
+                method,
+                components: None,
+            }));
+        }
+        if let Some(kind) = params.normalisation {
+            constraints.st.push(Box::new(Normalisation { kind }));
+        }
+        constraints
+    }
+
+    /// Check every constraint against concentration profiles of shape `c_dim` and spectra
+    /// of shape `st_dim`.
+    pub fn validate(&self, c_dim: (usize, usize), st_dim: (usize, usize)) -> Result<(), RoiError> {
+        for constraint in self.c.iter() {
+            constraint.validate(c_dim)?;
+        }
+        for constraint in self.st.iter() {
+            constraint.validate((st_dim.1, st_dim.0))?;
+        }
+        Ok(())
+    }
+}
+
+/// Components a constraint acts on, all of them if `None`.
+fn selected(components: &Option<Vec<usize>>, n_components: usize) -> Vec<usize> {
+    match components {
+        Some(components) => components.clone(),
+        None => (0..n_components).collect(),
+    }
+}
+
+/// Reject the `components` of a constraint that aren't columns of its profiles.
+pub(crate) fn check_components(
+    components: &Option<Vec<usize>>,
+    n_components: usize,
+) -> Result<(), RoiError> {
+    match components.iter().flatten().find(|&&k| k >= n_components) {
+        Some(k) => Err(RoiError::InvalidArgument(format!(
+            "Constrained component {} out of range, there are {} components",
+            k, n_components
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Non-negativity, either by setting negative values to zero or by refitting each row of
+/// the profiles by non-negative least squares (Lawson & Hanson active set, in the
+/// fast normal equations form of Bro & de Jong).
+pub struct NonNegativity {
+    pub method: NonNegativityMethod,
+    pub components: Option<Vec<usize>>,
+}
+
+impl Constraint for NonNegativity {
+    fn apply(&self, profiles: &mut Array2<f64>, data: ArrayView2<f64>, other: ArrayView2<f64>) {
+        let components = selected(&self.components, profiles.ncols());
+
+        match self.method {
+            NonNegativityMethod::Clipping => {
+                for &k in components.iter() {
+                    profiles.column_mut(k).mapv_inplace(|v| v.max(0.0));
+                }
+            }
+            NonNegativityMethod::Nnls => {
+                let mut constrained = vec![false; profiles.ncols()];
+                for &k in components.iter() {
+                    constrained[k] = true;
+                }
+                let gram = other.t().dot(&other);
+                let projections = data.dot(&other);
+
+                for (mut row, projection) in profiles
+                    .axis_iter_mut(Axis(0))
+                    .zip(projections.axis_iter(Axis(0)))
+                {
+                    row.assign(&nnls(&gram, projection, &constrained));
+                }
+            }
+        }
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        check_components(&self.components, dim.1)
+    }
+}
+
+/// Solve `min ||Q x - y||` with `x[k] >= 0` for the `constrained` components, given
+/// `gram = Q'Q` and `projection = Q'y`.
+pub fn nnls(gram: &Array2<f64>, projection: ArrayView1<f64>, constrained: &[bool]) -> Array1<f64> {
+    let n = projection.len();
+    let tolerance = 10.0 * f64::EPSILON * gram.mapv(f64::abs).sum() * n as f64;
+
+    let solve_passive = |passive: &[bool]| -> Array1<f64> {
+        let indices: Vec<usize> = (0..n).filter(|&i| passive[i]).collect();
+        let mut solution: Array1<f64> = Array1::zeros(n);
+        if indices.is_empty() {
+            return solution;
+        }
+        let a: Vec<Vec<f64>> = indices
+            .iter()
+            .map(|&i| indices.iter().map(|&j| gram[[i, j]]).collect())
+            .collect();
+        let b: Vec<f64> = indices.iter().map(|&i| projection[i]).collect();
+        let partial = solve_linear_system(a.clone(), b.clone()).unwrap_or_else(|| {
+            let ridge = 1e-12 * indices.iter().map(|&i| gram[[i, i]]).sum::<f64>();
+            let mut a = a;
+            for (i, row) in a.iter_mut().enumerate() {
+                row[i] += ridge.max(f64::EPSILON);
+            }
+            solve_linear_system(a, b).unwrap_or_else(|| vec![0.0; indices.len()])
+        });
+        for (&i, value) in indices.iter().zip(partial) {
+            solution[i] = value;
+        }
+        solution
+    };
+
+    let mut passive: Vec<bool> = constrained.iter().map(|&c| !c).collect();
+    let mut x = solve_passive(&passive);
+    let mut gradient = &projection - &gram.dot(&x);
+
+    for _ in 0..(3 * n + 1) {
+        let candidate = (0..n)
+            .filter(|&i| constrained[i] && !passive[i] && gradient[i] > tolerance)
+            .max_by(|&a, &b| gradient[a].total_cmp(&gradient[b]));
+        let candidate = match candidate {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        passive[candidate] = true;
+
+        let mut s = solve_passive(&passive);
+        while (0..n).any(|i| constrained[i] && passive[i] && s[i] <= 0.0) {
+            let alpha = (0..n)
+                .filter(|&i| constrained[i] && passive[i] && s[i] <= 0.0)
+                .map(|i| x[i] / (x[i] - s[i]))
+                .fold(f64::INFINITY, f64::min);
+            x = &x + &((&s - &x) * alpha);
+            for i in 0..n {
+                if constrained[i] && passive[i] && x[i] <= tolerance {
+                    passive[i] = false;
+                    x[i] = 0.0;
+                }
+            }
+            s = solve_passive(&passive);
+        }
+        x = s;
+        gradient = &projection - &gram.dot(&x);
+    }
+    x
+}
+
+/// Unimodality: moving away from the maximum of each profile, a value can exceed the
+/// previous one by at most a factor `tolerance` (1.0 for strict unimodality), otherwise it
+/// is set to the previous value.
+pub struct Unimodality {
+    pub tolerance: f64,
+    pub components: Option<Vec<usize>>,
+}
+
+impl Constraint for Unimodality {
+    fn apply(&self, profiles: &mut Array2<f64>, _data: ArrayView2<f64>, _other: ArrayView2<f64>) {
+        for k in selected(&self.components, profiles.ncols()) {
+            let mut profile = profiles.column_mut(k);
+            let n = profile.len();
+            if n == 0 {
+                continue;
+            }
+            let apex = (0..n)
+                .max_by(|&a, &b| profile[a].total_cmp(&profile[b]))
+                .unwrap();
+
+            for i in (apex + 1)..n {
+                if profile[i] > profile[i - 1] * self.tolerance {
+                    profile[i] = profile[i - 1];
+                }
+            }
+            for i in (0..apex).rev() {
+                if profile[i] > profile[i + 1] * self.tolerance {
+                    profile[i] = profile[i + 1];
+                }
+            }
+        }
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        if !(self.tolerance >= 1.0 && self.tolerance.is_finite()) {
+            return Err(RoiError::InvalidArgument(format!(
+                "Unimodality tolerance must be at least 1, got {}",
+                self.tolerance
+            )));
+        }
+        check_components(&self.components, dim.1)
+    }
+}
+
+/// Closure: the selected components of every row are scaled to sum to `total`, as for
+/// concentrations subject to a mass balance.
+pub struct Closure {
+    pub total: f64,
+    pub components: Option<Vec<usize>>,
+}
+
+impl Constraint for Closure {
+    fn apply(&self, profiles: &mut Array2<f64>, _data: ArrayView2<f64>, _other: ArrayView2<f64>) {
+        let components = selected(&self.components, profiles.ncols());
+
+        for mut row in profiles.axis_iter_mut(Axis(0)) {
+            let sum: f64 = components.iter().map(|&k| row[k]).sum();
+            if sum == 0.0 {
+                continue;
+            }
+            for &k in components.iter() {
+                row[k] *= self.total / sum;
+            }
+        }
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        if !self.total.is_finite() {
+            return Err(RoiError::InvalidArgument(format!(
+                "Closure total must be finite, got {}",
+                self.total
+            )));
+        }
+        check_components(&self.components, dim.1)
+    }
+}
+
+/// Normalisation of every profile to unit maximum, unit length or unit area.
+pub struct Normalisation {
+    pub kind: NormalisationType,
+}
+
+impl Constraint for Normalisation {
+    fn apply(&self, profiles: &mut Array2<f64>, _data: ArrayView2<f64>, _other: ArrayView2<f64>) {
+        for mut profile in profiles.axis_iter_mut(Axis(1)) {
+            let norm = match self.kind {
+                NormalisationType::Max => profile.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                NormalisationType::Length => profile.mapv(|v| v * v).sum().sqrt(),
+                NormalisationType::Area => profile.sum(),
+            };
+            if norm != 0.0 && norm.is_finite() {
+                profile.mapv_inplace(|v| v / norm);
+            }
+        }
+    }
+}
+
+/// Equality constraints from a mask with the same shape as the profiles (so transposed,
+/// one column per component, for the spectra): finite cells are imposed, NaN cells are
+/// left free. With `upper_bound` the finite cells are upper
+/// limits instead of exact values. Selectivity and local rank information are expressed
+/// as zeros where a component is known to be absent.
+pub struct Equality {
+    pub mask: Array2<f64>,
+    pub upper_bound: bool,
+}
+
+impl Constraint for Equality {
+    fn apply(&self, profiles: &mut Array2<f64>, _data: ArrayView2<f64>, _other: ArrayView2<f64>) {
+        assert_eq!(
+            self.mask.dim(),
+            profiles.dim(),
+            "Equality mask shape is checked by validate"
+        );
+        profiles.zip_mut_with(&self.mask, |value, &imposed| {
+            if imposed.is_nan() {
+                return;
+            }
+            if !self.upper_bound || *value > imposed {
+                *value = imposed;
+            }
+        });
+    }
+
+    fn validate(&self, dim: (usize, usize)) -> Result<(), RoiError> {
+        if self.mask.dim() != dim {
+            return Err(RoiError::InvalidArgument(format!(
+                "Equality mask must have the same shape as the constrained profiles, {:?}, got {:?}",
+                dim,
+                self.mask.dim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{ConstraintTarget, InitialEstimate};
+    use crate::mcr::mcr_als;
+    use crate::structs::options::McrParams;
+    use crate::test_utils::{bilinear, Random};
+
+    fn apply(constraint: &dyn Constraint, profiles: &mut Array2<f64>) {
+        let empty = Array2::zeros((0, 0));
+        constraint.apply(profiles, empty.view(), empty.view());
+    }
+
+    #[test]
+    fn nnls_matches_exhaustive_search() {
+        // Least squares on every subset of free variables, keeping the feasible solution
+        // with the smallest residual
+        let mut random = Random::new(11);
+        for _ in 0..20 {
+            let q = Array2::from_shape_fn((8, 4), |_| random.uniform() - 0.3);
+            let y = Array1::from_shape_fn(8, |_| random.uniform() - 0.5);
+            let gram = q.t().dot(&q);
+            let projection = q.t().dot(&y);
+            let residual = |x: &Array1<f64>| (&y - &q.dot(x)).mapv(|e| e * e).sum();
+
+            let mut best = f64::INFINITY;
+            for subset in 0..16usize {
+                let indices: Vec<usize> = (0..4).filter(|&i| subset & (1 << i) != 0).collect();
+                let a = indices
+                    .iter()
+                    .map(|&i| indices.iter().map(|&j| gram[[i, j]]).collect())
+                    .collect();
+                let b = indices.iter().map(|&i| projection[i]).collect();
+                let mut x = Array1::zeros(4);
+                if let Some(solution) = solve_linear_system(a, b) {
+                    for (&i, value) in indices.iter().zip(solution) {
+                        x[i] = value;
+                    }
+                }
+                if x.iter().all(|&v| v >= 0.0) {
+                    best = best.min(residual(&x));
+                }
+            }
+
+            let x = nnls(&gram, projection.view(), &[true; 4]);
+            assert!(x.iter().all(|&v| v >= 0.0));
+            assert!((residual(&x) - best).abs() < 1e-9 * (1.0 + best));
+        }
+    }
+
+    #[test]
+    fn nnls_leaves_unconstrained_components_free() {
+        let gram = Array2::eye(2);
+        let projection = arr1(&[-1.0, -2.0]);
+        let x = nnls(&gram, projection.view(), &[false, true]);
+        assert_eq!(x.to_vec(), vec![-1.0, 0.0]);
+    }
+
+    #[test]
+    fn clipping_acts_on_the_selected_components() {
+        let mut profiles = arr2(&[[-1.0, -2.0], [3.0, -4.0]]);
+        let constraint = NonNegativity {
+            method: NonNegativityMethod::Clipping,
+            components: Some(vec![1]),
+        };
+        assert!(constraint.validate(profiles.dim()).is_ok());
+        apply(&constraint, &mut profiles);
+        assert_eq!(profiles, arr2(&[[-1.0, 0.0], [3.0, 0.0]]));
+    }
+
+    #[test]
+    fn out_of_range_components_are_rejected() {
+        let components = Some(vec![1, 5]);
+        let constraints: [Box<dyn Constraint>; 3] = [
+            Box::new(NonNegativity {
+                method: NonNegativityMethod::Clipping,
+                components: components.clone(),
+            }),
+            Box::new(Unimodality {
+                tolerance: 1.0,
+                components: components.clone(),
+            }),
+            Box::new(Closure {
+                total: 1.0,
+                components,
+            }),
+        ];
+        for constraint in constraints.iter() {
+            assert!(matches!(
+                constraint.validate((10, 2)),
+                Err(RoiError::InvalidArgument(_))
+            ));
+            assert!(constraint.validate((10, 6)).is_ok());
+        }
+    }
+
+    #[test]
+    fn unimodality_flattens_secondary_maxima() {
+        let mut profiles = arr2(&[[1.0], [4.0], [2.0], [3.0], [5.0], [1.0], [2.0]]);
+        apply(
+            &Unimodality {
+                tolerance: 1.0,
+                components: None,
+            },
+            &mut profiles,
+        );
+        assert_eq!(
+            profiles.column(0).to_vec(),
+            vec![1.0, 2.0, 2.0, 3.0, 5.0, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn closure_and_normalisation() {
+        let mut profiles = arr2(&[[1.0, 3.0], [0.0, 0.0], [2.0, 2.0]]);
+        apply(
+            &Closure {
+                total: 2.0,
+                components: None,
+            },
+            &mut profiles,
+        );
+        assert_eq!(profiles, arr2(&[[0.5, 1.5], [0.0, 0.0], [1.0, 1.0]]));
+
+        for (kind, norms) in [
+            (NormalisationType::Max, [1.0, 1.0]),
+            (NormalisationType::Area, [1.0, 1.0]),
+        ] {
+            let mut profiles = arr2(&[[1.0, 3.0], [4.0, 1.0]]);
+            apply(&Normalisation { kind }, &mut profiles);
+            let measured = match kind {
+                NormalisationType::Max => profiles.fold_axis(Axis(0), 0.0, |a, &b| b.max(*a)),
+                _ => profiles.sum_axis(Axis(0)),
+            };
+            assert_eq!(measured.to_vec(), norms.to_vec());
+        }
+        let mut profiles = arr2(&[[3.0], [4.0]]);
+        apply(
+            &Normalisation {
+                kind: NormalisationType::Length,
+            },
+            &mut profiles,
+        );
+        assert_eq!(profiles, arr2(&[[0.6], [0.8]]));
+    }
+
+    #[test]
+    fn equality_imposes_values_or_bounds() {
+        let mask = arr2(&[[f64::NAN, 0.0], [2.0, f64::NAN]]);
+        let mut exact = arr2(&[[1.0, 1.0], [1.0, 1.0]]);
+        apply(
+            &Equality {
+                mask: mask.clone(),
+                upper_bound: false,
+            },
+            &mut exact,
+        );
+        assert_eq!(exact, arr2(&[[1.0, 0.0], [2.0, 1.0]]));
+
+        let mut bounded = arr2(&[[1.0, 1.0], [1.0, 1.0]]);
+        apply(
+            &Equality {
+                mask,
+                upper_bound: true,
+            },
+            &mut bounded,
+        );
+        assert_eq!(bounded, arr2(&[[1.0, 0.0], [1.0, 1.0]]));
+    }
+
+    #[test]
+    fn from_params_places_the_constraints() {
+        let constraints = McrConstraints::from_params(&ConstraintParams {
+            non_negativity: Some(ConstraintTarget::St),
+            nnls: true,
+            unimodality: Some(1.0),
+            closure: None,
+            normalisation: Some(NormalisationType::Max),
+        });
+        assert_eq!(constraints.c.len(), 1);
+        assert_eq!(constraints.st.len(), 2);
+    }
+
+    #[test]
+    fn invalid_constraints_are_rejected_before_resolution() {
+        let (d, _, st) = bilinear(30, 12, 2, 8);
+        let initial = InitialEstimate::Spectra(st);
+        let params = McrParams::default();
+
+        let mut wrong_mask = McrConstraints::new();
+        wrong_mask.st.push(Box::new(Equality {
+            mask: Array2::zeros((2, 12)),
+            upper_bound: false,
+        }));
+        let mut right_mask = McrConstraints::new();
+        right_mask.st.push(Box::new(Equality {
+            mask: Array2::from_elem((12, 2), f64::NAN),
+            upper_bound: false,
+        }));
+        let mut tolerance = McrConstraints::new();
+        tolerance.c.push(Box::new(Unimodality {
+            tolerance: 0.5,
+            components: None,
+        }));
+
+        for constraints in [&wrong_mask, &tolerance] {
+            assert!(matches!(
+                mcr_als(&d, &initial, &params, constraints),
+                Err(RoiError::InvalidArgument(_))
+            ));
+        }
+        assert!(mcr_als(&d, &initial, &params, &right_mask).is_ok());
+    }
+}
@@ -0,0 +1,293 @@
+use ndarray::prelude::*;
+
+use super::constraints::{nnls, McrConstraints};
+use super::{check_data, check_params, initial_profiles, Iterations};
+use crate::enums::InitialEstimate;
+use crate::error::RoiError;
+use crate::structs::data::McrResult;
+use crate::structs::options::{McrParams, WeightParams};
+
+/// Weighted MCR-ALS of `d`, minimising `sum(weights * (d - c st)^2)`, with one weight per
+/// cell, e.g. the inverse variances from `uncertainty_weights` or `roi_weights`.
+///
+/// Every row of `c` and every column of `st` is fitted by weighted least squares
+/// (MCR-WALS, Wentzell et al.). The `constraints` are then applied as in `mcr_als`, but
+/// to the data completed by the weighted model, `c st + weights / max(weights) * (d - c st)`:
+/// its ordinary least squares fit is the weighted one, so that constraints refitting the
+/// profiles, such as NNLS, stay consistent with the weights, and cells with zero weight
+/// are imputed. The lack of fit and explained variance of the result are weighted.
+pub fn mcr_als_weighted(
+    d: &Array2<f64>,
+    weights: &Array2<f64>,
+    initial: &InitialEstimate,
+    params: &McrParams,
+    constraints: &McrConstraints,
+) -> Result<McrResult, RoiError> {
+    check_params(params)?;
+    if weights.dim() != d.dim() {
+        return Err(RoiError::InvalidArgument(format!(
+            "Weights must have the same shape as the data matrix, {:?}, got {:?}",
+            d.dim(),
+            weights.dim()
+        )));
+    }
+    if weights
+        .iter()
+        .any(|&w| w.is_nan() || w < 0.0 || w.is_infinite())
+    {
+        return Err(RoiError::InvalidArgument(
+            "Weights must be finite and non-negative".to_string(),
+        ));
+    }
+    let largest = weights.iter().cloned().fold(0.0, f64::max);
+    if largest == 0.0 {
+        return Err(RoiError::InvalidArgument(
+            "At least one weight must be positive".to_string(),
+        ));
+    }
+    check_data((weights * &d.mapv(|x| x * x)).sum())?;
+    let relative = weights / largest;
+    let complete = |c: &Array2<f64>, st: &Array2<f64>| -> Array2<f64> {
+        let model = c.dot(st);
+        &model + &(&relative * &(d - &model))
+    };
+
+    let (mut c, mut st) = initial_profiles(initial, d.dim())?;
+    constraints.validate(c.dim(), st.dim())?;
+    let start_from_spectra = matches!(initial, InitialEstimate::Spectra(_));
+
+    let mut iterations = Iterations::new(params.tol);
+
+    for iteration in 0..params.max_iter {
+        if start_from_spectra || iteration > 0 {
+            c = weighted_least_squares(d.view(), weights.view(), &st.t().to_owned());
+            let completed = complete(&c, &st);
+            for constraint in constraints.c.iter() {
+                constraint.apply(&mut c, completed.view(), st.t());
+            }
+        }
+
+        let mut s = weighted_least_squares(d.t(), weights.t(), &c);
+        let completed = complete(&c, &s.t().to_owned());
+        for constraint in constraints.st.iter() {
+            constraint.apply(&mut s, completed.t(), c.view());
+        }
+        st = s.reversed_axes();
+
+        let residuals = d - &c.dot(&st);
+        let (lof, r2) = weighted_fit(d, weights, &residuals);
+
+        if iterations.update(lof, || (c.clone(), st.clone(), residuals, r2)) {
+            break;
+        }
+    }
+
+    Ok(iterations.finish())
+}
+
+/// Rows `x_i` minimising `sum_j weights_ij (data_ij - x_i other_j')^2`, one least squares
+/// problem per row of `data`.
+fn weighted_least_squares(
+    data: ArrayView2<f64>,
+    weights: ArrayView2<f64>,
+    other: &Array2<f64>,
+) -> Array2<f64> {
+    let free = vec![false; other.ncols()];
+    let mut x: Array2<f64> = Array2::zeros((data.nrows(), other.ncols()));
+
+    for ((mut row, values), row_weights) in x
+        .axis_iter_mut(Axis(0))
+        .zip(data.axis_iter(Axis(0)))
+        .zip(weights.axis_iter(Axis(0)))
+    {
+        let weighted = &other.t() * &row_weights;
+        let gram = weighted.dot(other);
+        let projection = weighted.dot(&values);
+        row.assign(&nnls(&gram, projection.view(), &free));
+    }
+    x
+}
+
+/// Weighted lack of fit and explained variance, in percent.
+fn weighted_fit(d: &Array2<f64>, weights: &Array2<f64>, residuals: &Array2<f64>) -> (f64, f64) {
+    let error = (weights * &residuals.mapv(|e| e * e)).sum();
+    let total = (weights * &d.mapv(|x| x * x)).sum();
+    (
+        100.0 * (error / total).sqrt(),
+        100.0 * (1.0 - error / total),
+    )
+}
+
+/// Weights `1 / uncertainty^2` from the standard deviation of every cell; cells with
+/// zero uncertainty get the largest weight of the other cells, so at least one cell
+/// must have a positive uncertainty.
+pub fn uncertainty_weights(uncertainty: &Array2<f64>) -> Result<Array2<f64>, RoiError> {
+    if uncertainty.iter().any(|u| !u.is_finite() || *u < 0.0) {
+        return Err(RoiError::InvalidArgument(
+            "Uncertainties must be finite and non-negative".to_string(),
+        ));
+    }
+    if !uncertainty.iter().any(|&u| u > 0.0) {
+        return Err(RoiError::InvalidArgument(
+            "At least one uncertainty must be positive".to_string(),
+        ));
+    }
+    let mut weights = uncertainty.mapv(|u| if u > 0.0 { 1.0 / (u * u) } else { 0.0 });
+    let largest = weights.iter().cloned().fold(0.0, f64::max);
+    weights.zip_mut_with(uncertainty, |w, &u| {
+        if u <= 0.0 {
+            *w = largest
+        }
+    });
+    Ok(weights)
+}
+
+/// Weights of the cells of an `msroi` under a heteroscedastic noise model, with
+/// standard deviation `sqrt(noise^2 + (proportional * intensity)^2)`. Empty cells,
+/// where no peak was detected, are not measurements: their weight is `missing` times
+/// the weight of a cell at the noise level, 0.0 to ignore them.
+pub fn roi_weights(msroi: &Array2<f64>, params: &WeightParams) -> Result<Array2<f64>, RoiError> {
+    let terms = [params.noise, params.proportional, params.missing];
+    if terms.iter().any(|x| !x.is_finite() || *x < 0.0) {
+        return Err(RoiError::InvalidArgument(
+            "The noise model terms must be finite and non-negative".to_string(),
+        ));
+    }
+    if params.noise == 0.0 && params.proportional == 0.0 {
+        return Err(RoiError::InvalidArgument(
+            "The noise model needs a positive noise level or proportional term".to_string(),
+        ));
+    }
+    let floor = if params.noise > 0.0 {
+        1.0 / (params.noise * params.noise)
+    } else {
+        1.0
+    };
+
+    Ok(msroi.mapv(|x| {
+        if x == 0.0 {
+            return params.missing * floor;
+        }
+        let variance = params.noise * params.noise + (params.proportional * x).powi(2);
+        1.0 / variance
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcr::mcr_als;
+    use crate::test_utils::bilinear;
+
+    fn params() -> McrParams {
+        McrParams {
+            max_iter: 100,
+            tol: 1e-9,
+        }
+    }
+
+    #[test]
+    fn uniform_weights_match_mcr_als() {
+        let (d, _, st) = bilinear(40, 16, 2, 111);
+        let initial = InitialEstimate::Spectra(st.mapv(|x| x + 0.1));
+        let constraints = McrConstraints::new();
+
+        let weighted = mcr_als_weighted(
+            &d,
+            &Array2::from_elem(d.dim(), 3.0),
+            &initial,
+            &params(),
+            &constraints,
+        )
+        .unwrap();
+        let plain = mcr_als(&d, &initial, &params(), &constraints).unwrap();
+        assert!((weighted.lof - plain.lof).abs() < 1e-6);
+        let difference = &weighted.c.dot(&weighted.st) - &plain.c.dot(&plain.st);
+        assert!(difference.iter().all(|x| x.abs() < 1e-6));
+    }
+
+    #[test]
+    fn cells_without_weight_are_ignored() {
+        let (d, _, st) = bilinear(40, 16, 2, 112);
+        let mut corrupted = d.clone();
+        let mut weights = Array2::ones(d.dim());
+        for i in (0..40).step_by(7) {
+            corrupted[[i, 3]] += 50.0;
+            weights[[i, 3]] = 0.0;
+        }
+
+        let initial = InitialEstimate::Spectra(st);
+        let result = mcr_als_weighted(
+            &corrupted,
+            &weights,
+            &initial,
+            &params(),
+            &McrConstraints::new(),
+        )
+        .unwrap();
+        assert!(result.lof < 1e-3, "lof {}", result.lof);
+        // The corrupted cells are imputed from the model
+        let model = result.c.dot(&result.st);
+        assert!((model[[14, 3]] - d[[14, 3]]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn uncertainty_and_roi_weights() {
+        let uncertainty = arr2(&[[0.5, 2.0], [0.0, 1.0]]);
+        let weights = uncertainty_weights(&uncertainty).unwrap();
+        assert_eq!(weights, arr2(&[[4.0, 0.25], [4.0, 1.0]]));
+
+        let msroi = arr2(&[[0.0, 10.0], [100.0, 0.0]]);
+        let params = WeightParams {
+            noise: 2.0,
+            proportional: 0.1,
+            missing: 0.5,
+        };
+        let weights = roi_weights(&msroi, &params).unwrap();
+        assert_eq!(weights[[0, 0]], 0.125);
+        assert_eq!(weights[[0, 1]], 1.0 / 5.0);
+        assert_eq!(weights[[1, 0]], 1.0 / 104.0);
+    }
+
+    #[test]
+    fn invalid_weights_are_rejected() {
+        for uncertainty in [
+            Array2::zeros((2, 2)),
+            arr2(&[[-1.0, 0.0], [0.0, 0.0]]),
+            arr2(&[[f64::NAN, 1.0]]),
+        ] {
+            assert!(matches!(
+                uncertainty_weights(&uncertainty),
+                Err(RoiError::InvalidArgument(_))
+            ));
+        }
+
+        let msroi = Array2::ones((2, 2));
+        for (noise, proportional, missing) in [(0.0, 0.0, 0.1), (1.0, -0.1, 0.1), (1.0, 0.1, -1.0)]
+        {
+            let params = WeightParams {
+                noise,
+                proportional,
+                missing,
+            };
+            assert!(roi_weights(&msroi, &params).is_err());
+        }
+
+        let (d, _, st) = bilinear(20, 8, 2, 113);
+        let initial = InitialEstimate::Spectra(st);
+        let constraints = McrConstraints::new();
+        for weights in [
+            Array2::ones((20, 7)),
+            Array2::zeros((20, 8)),
+            Array2::from_elem((20, 8), -1.0),
+        ] {
+            assert!(mcr_als_weighted(&d, &weights, &initial, &params(), &constraints).is_err());
+        }
+        let zeros = Array2::zeros(d.dim());
+        let weights = Array2::ones(d.dim());
+        assert!(matches!(
+            mcr_als_weighted(&zeros, &weights, &initial, &params(), &constraints),
+            Err(RoiError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use ndarray::prelude::*;
+
+use crate::enums::{InitialEstimate, NmfMethod};
+use crate::error::RoiError;
+use crate::mcr::{
+    check_data, check_params, explained_variance, initial_profiles, lack_of_fit, Iterations,
+};
+use crate::structs::data::McrResult;
+use crate::structs::options::McrParams;
+use crate::utils::{least_squares, least_squares_right};
+
+/// Non-negative matrix factorisation of `d` (e.g. the `msroi` returned by
+/// `compute_roi`) into `c` and `st`, by the multiplicative updates of Lee & Seung or by
+/// hierarchical alternating least squares (HALS, Cichocki & Phan).
+///
+/// The factor missing from `initial` is its least squares estimate, with negative
+/// values set to zero (or to a small positive value for the multiplicative updates,
+/// which can't move away from zero). Iterations stop as in `mcr_als`, so the results of
+/// both methods can be compared directly.
+pub fn nmf(
+    d: &Array2<f64>,
+    initial: &InitialEstimate,
+    method: NmfMethod,
+    params: &McrParams,
+) -> Result<McrResult, RoiError> {
+    check_params(params)?;
+    check_data(d.mapv(|x| x * x).sum())?;
+    if method == NmfMethod::MultiplicativeUpdate && d.iter().any(|&x| x < 0.0) {
+        return Err(RoiError::InvalidArgument(
+            "Multiplicative updates need non-negative data".to_string(),
+        ));
+    }
+    let floor = match method {
+        NmfMethod::MultiplicativeUpdate => 1e-9 * d.iter().cloned().fold(0.0, f64::max),
+        NmfMethod::Hals => 0.0,
+    };
+
+    let (c, st) = initial_profiles(initial, d.dim())?;
+    let (mut c, mut st) = match initial {
+        InitialEstimate::Concentrations(_) => {
+            let c = c.mapv(|x| x.max(floor));
+            let st = least_squares(&c, d).mapv(|x| x.max(floor));
+            (c, st)
+        }
+        InitialEstimate::Spectra(_) => {
+            let st = st.mapv(|x| x.max(floor));
+            let c = least_squares_right(d, &st).mapv(|x| x.max(floor));
+            (c, st)
+        }
+    };
+
+    let mut iterations = Iterations::new(params.tol);
+
+    for _ in 0..params.max_iter {
+        match method {
+            NmfMethod::MultiplicativeUpdate => {
+                let numerator = d.dot(&st.t());
+                let denominator = c.dot(&st.dot(&st.t()));
+                c.zip_mut_with(
+                    &(numerator / denominator.mapv(|x| x + f64::EPSILON)),
+                    |x, r| *x *= r,
+                );
+
+                let numerator = c.t().dot(d);
+                let denominator = c.t().dot(&c).dot(&st);
+                st.zip_mut_with(
+                    &(numerator / denominator.mapv(|x| x + f64::EPSILON)),
+                    |x, r| *x *= r,
+                );
+            }
+            NmfMethod::Hals => {
+                hals_update(&mut c, &d.dot(&st.t()), &st.dot(&st.t()));
+                let mut s = st.t().to_owned();
+                hals_update(&mut s, &d.t().dot(&c), &c.t().dot(&c));
+                st = s.reversed_axes();
+            }
+        }
+
+        let residuals = d - &c.dot(&st);
+        let lof = lack_of_fit(d, &residuals);
+
+        if iterations.update(lof, || {
+            let r2 = explained_variance(d, &residuals);
+            (c.clone(), st.clone(), residuals, r2)
+        }) {
+            break;
+        }
+    }
+
+    Ok(iterations.finish())
+}
+
+/// One HALS sweep on the columns of `w` for `data ≈ w h'`, given `projection = data h`
+/// and `gram = h'h`.
+fn hals_update(w: &mut Array2<f64>, projection: &Array2<f64>, gram: &Array2<f64>) {
+    for k in 0..w.ncols() {
+        if gram[[k, k]] <= 0.0 {
+            continue;
+        }
+        let fitted = w.dot(&gram.column(k));
+        let update = (&projection.column(k) - &fitted) / gram[[k, k]];
+        let mut column = w.column_mut(k);
+        column += &update;
+        column.mapv_inplace(|x| x.max(0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bilinear, Random};
+
+    fn params() -> McrParams {
+        McrParams {
+            max_iter: 500,
+            tol: 1e-9,
+        }
+    }
+
+    #[test]
+    fn both_methods_factorise_bilinear_data() {
+        let (d, _, st) = bilinear(50, 20, 3, 91);
+        let mut random = Random::new(92);
+        let initial = InitialEstimate::Spectra(st.mapv(|x| x + 0.3 * random.uniform()));
+
+        for method in [NmfMethod::Hals, NmfMethod::MultiplicativeUpdate] {
+            let result = nmf(&d, &initial, method, &params()).unwrap();
+            assert!(result.lof < 5.0, "{:?}: lof {}", method, result.lof);
+            assert!(result.c.iter().chain(result.st.iter()).all(|&x| x >= 0.0));
+            assert_eq!(result.lof_history.len(), result.n_iter);
+        }
+    }
+
+    #[test]
+    fn concentrations_can_start_the_factorisation() {
+        let (d, c, _) = bilinear(50, 20, 2, 93);
+        let initial = InitialEstimate::Concentrations(c.mapv(|x| x + 0.1));
+        let result = nmf(&d, &initial, NmfMethod::Hals, &params()).unwrap();
+        assert!(result.lof < 5.0, "lof {}", result.lof);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let (d, c, st) = bilinear(30, 10, 2, 94);
+        let negative = d.mapv(|x| x - 0.5);
+        assert!(matches!(
+            nmf(
+                &negative,
+                &InitialEstimate::Spectra(st.clone()),
+                NmfMethod::MultiplicativeUpdate,
+                &params()
+            ),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        let short = InitialEstimate::Concentrations(c.slice(s![..20, ..]).to_owned());
+        assert!(nmf(&d, &short, NmfMethod::Hals, &params()).is_err());
+        let no_iterations = McrParams {
+            max_iter: 0,
+            tol: 1e-3,
+        };
+        let initial = InitialEstimate::Spectra(st);
+        assert!(nmf(&d, &initial, NmfMethod::Hals, &no_iterations).is_err());
+        let zeros = Array2::zeros(d.dim());
+        assert!(nmf(&zeros, &initial, NmfMethod::Hals, &params()).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use ndarray::prelude::*;
+
+use crate::enums::InitialEstimate;
+use crate::error::RoiError;
+use crate::mcr::constraints::nnls;
+use crate::mcr::{
+    check_data, check_params, explained_variance, initial_profiles, lack_of_fit, Iterations,
+};
+use crate::structs::data::McrResult;
+use crate::structs::options::McrParams;
+use crate::utils::{least_squares, symmetric_eigen};
+
+/// PARAFAC2 by alternating least squares (Kiers, ten Berge & Bro, 1999) of a row-wise
+/// augmented matrix, such as the `msroi` built by `compute_roi` from several runs,
+/// whose consecutive row blocks of `run_sizes` rows are the runs.
+///
+/// Each run `k` is modelled as `P_k F D_k st`: the spectra `st` are shared, while the
+/// concentration profiles `P_k F D_k` may change shape between runs (e.g. with
+/// retention time shifts) as long as their cross products `F' F` stay the same. Runs
+/// may have different numbers of scans. With `non_negativity` the spectra and the run
+/// scales `D_k` are fitted by non-negative least squares.
+///
+/// The result holds the stacked concentration profiles of all runs in `c`, as for
+/// `mcr_als_augmented`. Without `non_negativity` each component is oriented to have a
+/// positive sum; with it the spectra stay non-negative whatever the sign of the profiles.
+pub fn parafac2(
+    d: &Array2<f64>,
+    run_sizes: &[usize],
+    initial: &InitialEstimate,
+    params: &McrParams,
+    non_negativity: bool,
+) -> Result<McrResult, RoiError> {
+    check_params(params)?;
+    check_data(d.mapv(|x| x * x).sum())?;
+    if run_sizes.iter().sum::<usize>() != d.nrows() {
+        return Err(RoiError::InvalidArgument(format!(
+            "Run sizes must add up to the number of rows of the data matrix, {}",
+            d.nrows()
+        )));
+    }
+    let bounds: Vec<(usize, usize)> = run_sizes
+        .iter()
+        .scan(0, |start, &size| {
+            *start += size;
+            Some((*start - size, *start))
+        })
+        .collect();
+    let slices: Vec<ArrayView2<f64>> = bounds
+        .iter()
+        .map(|&(start, end)| d.slice(s![start..end, ..]))
+        .collect();
+
+    // Spectra as the columns of `a`
+    let (c, st) = initial_profiles(initial, d.dim())?;
+    let mut a = match initial {
+        InitialEstimate::Spectra(_) => st.reversed_axes(),
+        InitialEstimate::Concentrations(_) => least_squares(&c, d).reversed_axes(),
+    };
+    let n = a.ncols();
+    let mut f: Array2<f64> = Array2::eye(n);
+    let mut scales: Array2<f64> = Array2::ones((run_sizes.len(), n));
+    let constrained = vec![non_negativity; n];
+
+    let mut iterations = Iterations::new(params.tol);
+
+    for _ in 0..params.max_iter {
+        // Orthonormal P_k closest to X_k A D_k F', and the projected slices P_k' X_k
+        let (projections, reduced): (Vec<Array2<f64>>, Vec<Array2<f64>>) = slices
+            .iter()
+            .enumerate()
+            .map(|(k, x)| {
+                let target = x.dot(&(&a * &scales.row(k))).dot(&f.t());
+                let p = orthonormal_factor(&target);
+                let y = p.t().dot(x);
+                (p, y)
+            })
+            .unzip();
+
+        // One PARAFAC step on the R x J x K array of the projected slices
+        let gram = a.t().dot(&a) * scales.t().dot(&scales);
+        let mut numerator: Array2<f64> = Array2::zeros((n, n));
+        for (k, y) in reduced.iter().enumerate() {
+            numerator += &y.dot(&(&a * &scales.row(k)));
+        }
+        f = solve_right(&numerator, &gram);
+
+        let gram = f.t().dot(&f) * scales.t().dot(&scales);
+        let mut projection: Array2<f64> = Array2::zeros((d.ncols(), n));
+        for (k, y) in reduced.iter().enumerate() {
+            projection += &y.t().dot(&(&f * &scales.row(k)));
+        }
+        a = fit_rows(&projection, &gram, &constrained);
+
+        let gram = f.t().dot(&f) * a.t().dot(&a);
+        let mut projection: Array2<f64> = Array2::zeros((run_sizes.len(), n));
+        for (k, y) in reduced.iter().enumerate() {
+            projection
+                .row_mut(k)
+                .assign(&(f.t().dot(y) * a.t()).sum_axis(Axis(1)));
+        }
+        scales = fit_rows(&projection, &gram, &constrained);
+
+        let mut c: Array2<f64> = Array2::zeros((d.nrows(), n));
+        for (k, &(start, end)) in bounds.iter().enumerate() {
+            c.slice_mut(s![start..end, ..])
+                .assign(&(projections[k].dot(&f) * scales.row(k)));
+        }
+        let st = a.t().to_owned();
+
+        let residuals = d - &c.dot(&st);
+        let lof = lack_of_fit(d, &residuals);
+
+        if iterations.update(lof, || {
+            let r2 = explained_variance(d, &residuals);
+            (c, st, residuals, r2)
+        }) {
+            break;
+        }
+    }
+
+    let mut result = iterations.finish();
+    // The signs of the profiles of a component and of its spectrum are arbitrary, unless
+    // the spectrum is constrained to be non-negative
+    for k in 0..n {
+        if !non_negativity && result.c.column(k).sum() < 0.0 {
+            result.c.column_mut(k).mapv_inplace(|x| -x);
+            result.st.row_mut(k).mapv_inplace(|x| -x);
+        }
+    }
+    Ok(result)
+}
+
+/// Orthonormal factor `m (m'm)^-1/2` of the polar decomposition of `m`.
+fn orthonormal_factor(m: &Array2<f64>) -> Array2<f64> {
+    let (values, vectors) = symmetric_eigen(&m.t().dot(m));
+    let largest = values.iter().cloned().fold(0.0, f64::max);
+    let inverse_root = Array1::from_shape_fn(values.len(), |i| {
+        if values[i] > 1e-12 * largest {
+            1.0 / values[i].sqrt()
+        } else {
+            0.0
+        }
+    });
+    m.dot(&(&vectors * &inverse_root).dot(&vectors.t()))
+}
+
+/// Solution `x` of `x gram = numerator`, with `gram` symmetric.
+fn solve_right(numerator: &Array2<f64>, gram: &Array2<f64>) -> Array2<f64> {
+    fit_rows(numerator, gram, &vec![false; gram.nrows()])
+}
+
+/// Least squares rows `x` from their `projection = x gram`, non-negative for the
+/// `constrained` components.
+fn fit_rows(projection: &Array2<f64>, gram: &Array2<f64>, constrained: &[bool]) -> Array2<f64> {
+    let mut x: Array2<f64> = Array2::zeros(projection.dim());
+    for (mut row, projection) in x.axis_iter_mut(Axis(0)).zip(projection.axis_iter(Axis(0))) {
+        row.assign(&nnls(gram, projection, constrained));
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bilinear, gaussian};
+
+    /// Two runs of the same three compounds, the second with 50 scans instead of 60 and
+    /// the peaks shifted by 4 scans, and the true spectra.
+    fn shifted_runs() -> (Array2<f64>, Array2<f64>) {
+        let (first, _, st) = bilinear(60, 24, 3, 101);
+        let c = Array2::from_shape_fn((50, 3), |(i, k)| {
+            let apex = (k + 1) as f64 * 15.0 - 4.0;
+            0.7 * gaussian(i as f64, apex, 6.0)
+        });
+        let second = c.dot(&st);
+        let d = ndarray::concatenate(Axis(0), &[first.view(), second.view()]).unwrap();
+        (d, st)
+    }
+
+    #[test]
+    fn shifted_runs_are_resolved() {
+        let (d, st) = shifted_runs();
+        let params = McrParams {
+            max_iter: 300,
+            tol: 1e-9,
+        };
+        let initial = InitialEstimate::Spectra(st.mapv(|x| x + 0.05));
+        let result = parafac2(&d, &[60, 50], &initial, &params, true).unwrap();
+
+        assert_eq!(result.c.dim(), (110, 3));
+        assert_eq!(result.st.dim(), (3, 24));
+        assert!(result.lof < 3.0, "lof {}", result.lof);
+        assert!(result.st.iter().all(|&x| x >= 0.0));
+        for k in 0..3 {
+            assert!(result.c.column(k).sum() > 0.0);
+        }
+    }
+
+    #[test]
+    fn non_negative_spectra_are_not_flipped() {
+        // Negated data can only be fitted with non-negative spectra by negative profiles
+        let (d, st) = shifted_runs();
+        let d = -d;
+        let params = McrParams {
+            max_iter: 300,
+            tol: 1e-9,
+        };
+        let initial = InitialEstimate::Spectra(st.mapv(|x| x + 0.05));
+        let result = parafac2(&d, &[60, 50], &initial, &params, true).unwrap();
+
+        assert!(result.lof < 3.0, "lof {}", result.lof);
+        assert!(result.st.iter().all(|&x| x >= 0.0));
+        for k in 0..3 {
+            assert!(result.c.column(k).sum() < 0.0);
+        }
+
+        let result = parafac2(&d, &[60, 50], &initial, &params, false).unwrap();
+        for k in 0..3 {
+            assert!(result.c.column(k).sum() > 0.0);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let (d, st) = shifted_runs();
+        let initial = InitialEstimate::Spectra(st.clone());
+        let params = McrParams::default();
+        assert!(matches!(
+            parafac2(&d, &[60, 60], &initial, &params, false),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        let narrow = InitialEstimate::Spectra(st.slice(s![.., ..20]).to_owned());
+        assert!(parafac2(&d, &[60, 50], &narrow, &params, false).is_err());
+        let zeros = Array2::zeros(d.dim());
+        assert!(parafac2(&zeros, &[60, 50], &initial, &params, false).is_err());
+    }
+}
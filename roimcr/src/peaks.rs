@@ -0,0 +1,396 @@
+use ndarray::prelude::*;
+
+use crate::enums::PeakDetectionMethod;
+use crate::error::RoiError;
+use crate::structs::data::ChromPeak;
+use crate::structs::options::PeakParams;
+
+/// Number of wavelet scales between the minimum and maximum scale of the CWT detector.
+const CWT_SCALES: usize = 10;
+
+/// Detect the chromatographic peaks of every ROI chromatogram (column) of `msroi`.
+/// `times` holds the retention time of each row and is used for the apex time and to
+/// integrate the peak areas.
+pub fn detect_peaks(
+    msroi: &Array2<f64>,
+    times: &Array1<f64>,
+    params: &PeakParams,
+) -> Result<Vec<ChromPeak>, RoiError> {
+    let mut peaks = Vec::new();
+    for (roi, chromatogram) in msroi.axis_iter(Axis(1)).enumerate() {
+        peaks.extend(detect_chromatogram_peaks(chromatogram, times, roi, params)?);
+    }
+    Ok(peaks)
+}
+
+/// Detect the peaks of a single chromatogram, reporting them as belonging to ROI `roi`.
+pub fn detect_chromatogram_peaks(
+    values: ArrayView1<f64>,
+    times: &Array1<f64>,
+    roi: usize,
+    params: &PeakParams,
+) -> Result<Vec<ChromPeak>, RoiError> {
+    if values.len() != times.len() {
+        return Err(RoiError::InvalidArgument(format!(
+            "The chromatograms have {} scans but {} times were given",
+            values.len(),
+            times.len()
+        )));
+    }
+    let values: Vec<f64> = values.to_vec();
+    let noise = estimate_noise(&values);
+
+    let candidates: Vec<(usize, usize, usize)> = match params.method {
+        PeakDetectionMethod::LocalMaxima(min_prominence) => {
+            prominent_maxima(&values, min_prominence)
+        }
+        PeakDetectionMethod::ContinuousWavelet(min_scale, max_scale) => {
+            if !(min_scale > 0.0 && max_scale >= min_scale && max_scale.is_finite()) {
+                return Err(RoiError::InvalidArgument(
+                    "CWT scales must be positive with the maximum not below the minimum"
+                        .to_string(),
+                ));
+            }
+            wavelet_maxima(&values, min_scale, max_scale)
+        }
+    };
+
+    Ok(candidates
+        .into_iter()
+        .map(|(apex, start, end)| {
+            let area = (start..end)
+                .map(|i| 0.5 * (values[i] + values[i + 1]) * (times[i + 1] - times[i]))
+                .sum();
+            ChromPeak {
+                roi,
+                apex,
+                start,
+                end,
+                apex_time: times[apex],
+                height: values[apex],
+                area,
+                snr: (values[apex] - values[start].min(values[end])) / noise,
+            }
+        })
+        .filter(|peak| peak.snr >= params.min_snr)
+        .collect())
+}
+
+/// Noise level from the median absolute deviation of the differences between consecutive
+/// measured scans. Scans without a peak (zeros) are not measurements and are skipped;
+/// when no pair of measured scans is available the smallest measured intensity is used.
+fn estimate_noise(values: &[f64]) -> f64 {
+    let mut differences: Vec<f64> = values
+        .windows(2)
+        .filter(|w| w[0] != 0.0 && w[1] != 0.0)
+        .map(|w| w[1] - w[0])
+        .collect();
+
+    let noise = if differences.is_empty() {
+        0.0
+    } else {
+        let center = median(&mut differences);
+        let mut deviations: Vec<f64> = differences.iter().map(|d| (d - center).abs()).collect();
+        1.4826 * median(&mut deviations) / f64::sqrt(2.0)
+    };
+
+    if noise > 0.0 {
+        noise
+    } else {
+        values
+            .iter()
+            .cloned()
+            .filter(|&v| v > 0.0)
+            .fold(f64::INFINITY, f64::min)
+            .min(f64::MAX)
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
+
+/// Local maxima whose topographic prominence is at least `min_prominence`, returned as
+/// (apex, start, end). Each boundary is the lowest point between the apex and the
+/// neighbouring retained peak (or the base used for the prominence), moved towards the
+/// apex over the points within 5% of the prominence from it.
+fn prominent_maxima(values: &[f64], min_prominence: f64) -> Vec<(usize, usize, usize)> {
+    let candidates: Vec<(usize, usize, usize, f64)> = local_maxima(values)
+        .into_iter()
+        .filter_map(|apex| {
+            let height = values[apex];
+
+            let mut left = apex;
+            while left > 0 && values[left - 1] <= height {
+                left -= 1;
+            }
+            let mut right = apex;
+            while right + 1 < values.len() && values[right + 1] <= height {
+                right += 1;
+            }
+
+            let base =
+                values[argmin(values, left, apex + 1)].max(values[argmin(values, apex, right + 1)]);
+            let prominence = height - base;
+            if prominence >= min_prominence && prominence > 0.0 {
+                Some((apex, left, right, prominence))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let apexes: Vec<usize> = candidates.iter().map(|c| c.0).collect();
+
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(k, &(apex, left, right, prominence))| {
+            let left = if k > 0 { left.max(apexes[k - 1]) } else { left };
+            let right = if k + 1 < apexes.len() {
+                right.min(apexes[k + 1])
+            } else {
+                right
+            };
+
+            let tolerance = 0.05 * prominence;
+            let mut start = argmin(values, left, apex + 1);
+            let start_base = values[start];
+            while start < apex && values[start + 1] - start_base < tolerance {
+                start += 1;
+            }
+            let mut end = argmin(values, apex, right + 1);
+            let end_base = values[end];
+            while end > apex && values[end - 1] - end_base < tolerance {
+                end -= 1;
+            }
+            (apex, start, end)
+        })
+        .collect()
+}
+
+/// Index of the smallest value in `values[from..to]`.
+fn argmin(values: &[f64], from: usize, to: usize) -> usize {
+    (from..to)
+        .min_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .unwrap()
+}
+
+/// Indices of the local maxima of `values`; flat tops are reported at their middle.
+fn local_maxima(values: &[f64]) -> Vec<usize> {
+    let mut maxima = Vec::new();
+    let mut i = 1;
+
+    while i + 1 < values.len() {
+        if values[i - 1] < values[i] {
+            let mut plateau_end = i;
+            while plateau_end + 1 < values.len() && values[plateau_end + 1] == values[i] {
+                plateau_end += 1;
+            }
+            if plateau_end + 1 < values.len() && values[plateau_end + 1] < values[i] {
+                maxima.push((i + plateau_end) / 2);
+            }
+            i = plateau_end + 1;
+        } else {
+            i += 1;
+        }
+    }
+    maxima
+}
+
+/// Continuous wavelet transform detector (Du et al., 2006): maxima of the Ricker wavelet
+/// coefficients are linked into ridges from the largest to the smallest scale, and ridges
+/// spanning at least half of the scales are reported as peaks. The boundaries are found by
+/// descending the raw signal from the apex, up to three times the best scale.
+fn wavelet_maxima(values: &[f64], min_scale: f64, max_scale: f64) -> Vec<(usize, usize, usize)> {
+    let n = values.len();
+    let scales: Vec<f64> = (0..CWT_SCALES)
+        .map(|k| min_scale * (max_scale / min_scale).powf(k as f64 / (CWT_SCALES - 1) as f64))
+        .collect();
+
+    let coefficients: Vec<Vec<f64>> = scales
+        .iter()
+        .map(|&s| ricker_transform(values, s))
+        .collect();
+
+    // (current position, best scale index, best coefficient, length)
+    let mut ridges: Vec<(usize, usize, f64, usize)> = Vec::new();
+    for (k, row) in coefficients.iter().enumerate().rev() {
+        let maxima: Vec<usize> = local_maxima(row)
+            .into_iter()
+            .filter(|&i| row[i] > 0.0)
+            .collect();
+        let gap = (scales[k] / 2.0).ceil().max(1.0) as usize;
+        let mut used = vec![false; maxima.len()];
+
+        for ridge in ridges.iter_mut() {
+            let closest = maxima
+                .iter()
+                .enumerate()
+                .filter(|(m, &i)| !used[*m] && i.abs_diff(ridge.0) <= gap)
+                .min_by_key(|(_, &i)| i.abs_diff(ridge.0));
+            if let Some((m, &i)) = closest {
+                used[m] = true;
+                ridge.0 = i;
+                ridge.3 += 1;
+                if row[i] > ridge.2 {
+                    ridge.1 = k;
+                    ridge.2 = row[i];
+                }
+            }
+        }
+        for (m, &i) in maxima.iter().enumerate() {
+            if !used[m] {
+                ridges.push((i, k, row[i], 1));
+            }
+        }
+    }
+
+    let mut peaks: Vec<(usize, usize, usize)> = Vec::new();
+    for (position, best_scale, _, length) in ridges {
+        if 2 * length < CWT_SCALES {
+            continue;
+        }
+
+        let reach = (3.0 * scales[best_scale]).ceil() as usize;
+        let window = scales[best_scale].ceil() as usize;
+        let apex = (position.saturating_sub(window)..(position + window + 1).min(n))
+            .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+            .unwrap();
+        if values[apex] <= 0.0 || peaks.iter().any(|p| p.0 == apex) {
+            continue;
+        }
+
+        let mut start = apex;
+        while start > 0 && apex - start < reach && values[start - 1] < values[start] {
+            start -= 1;
+        }
+        let mut end = apex;
+        while end + 1 < n && end - apex < reach && values[end + 1] < values[end] {
+            end += 1;
+        }
+        peaks.push((apex, start, end));
+    }
+
+    peaks.sort_unstable();
+    peaks
+}
+
+/// Convolution of `values` with a Ricker (Mexican hat) wavelet of width `scale`.
+fn ricker_transform(values: &[f64], scale: f64) -> Vec<f64> {
+    let half = (5.0 * scale).ceil() as isize;
+    let norm = 2.0 / (f64::sqrt(3.0 * scale) * std::f64::consts::PI.powf(0.25));
+    let kernel: Vec<f64> = (-half..=half)
+        .map(|x| {
+            let t = x as f64 / scale;
+            norm * (1.0 - t * t) * (-t * t / 2.0).exp()
+        })
+        .collect();
+
+    (0..values.len() as isize)
+        .map(|i| {
+            (-half..=half)
+                .filter(|&x| i + x >= 0 && i + x < values.len() as isize)
+                .map(|x| kernel[(x + half) as usize] * values[(i + x) as usize])
+                .sum()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{gaussian, Random};
+
+    /// Two ROIs over 200 scans 0.1 min apart: two separated peaks at scans 50 and 130 in
+    /// the first, a single peak at scan 100 in the second, both on low noise.
+    fn chromatograms() -> (Array2<f64>, Array1<f64>) {
+        let mut random = Random::new(7);
+        let times = Array1::from_shape_fn(200, |i| 0.1 * i as f64);
+        let msroi = Array2::from_shape_fn((200, 2), |(i, roi)| {
+            let x = i as f64;
+            let signal = match roi {
+                0 => 1e4 * gaussian(x, 50.0, 4.0) + 5e3 * gaussian(x, 130.0, 6.0),
+                _ => 2e4 * gaussian(x, 100.0, 5.0),
+            };
+            signal + 100.0 + 10.0 * random.uniform()
+        });
+        (msroi, times)
+    }
+
+    fn params(method: PeakDetectionMethod) -> PeakParams {
+        PeakParams {
+            method,
+            min_snr: 3.0,
+        }
+    }
+
+    #[test]
+    fn separated_peaks_are_found() {
+        let (msroi, times) = chromatograms();
+        for method in [
+            PeakDetectionMethod::LocalMaxima(1e3),
+            PeakDetectionMethod::ContinuousWavelet(2.0, 16.0),
+        ] {
+            let peaks = detect_peaks(&msroi, &times, &params(method)).unwrap();
+            let found: Vec<(usize, usize)> = peaks.iter().map(|p| (p.roi, p.apex)).collect();
+            assert_eq!(found.len(), 3, "{:?}: {:?}", method, found);
+            for ((roi, apex), (peak_roi, peak_apex)) in
+                [(0, 50usize), (0, 130), (1, 100)].iter().zip(found.iter())
+            {
+                assert_eq!(roi, peak_roi);
+                assert!(apex.abs_diff(*peak_apex) <= 1, "{:?}: {:?}", method, found);
+            }
+        }
+    }
+
+    #[test]
+    fn peak_area_and_bounds() {
+        let (msroi, times) = chromatograms();
+        let peaks = detect_peaks(
+            &msroi,
+            &times,
+            &params(PeakDetectionMethod::LocalMaxima(1e3)),
+        )
+        .unwrap();
+        let peak = &peaks[2];
+
+        assert!(peak.start < peak.apex && peak.apex < peak.end);
+        assert!((peak.apex_time - 10.0).abs() < 0.15);
+        assert!(peak.height > 2e4);
+        assert!(peak.snr > 100.0);
+        // Gaussian of height 2e4 and width 5 scans of 0.1 min on a baseline of ~100
+        let expected = 2e4 * 0.5 * f64::sqrt(2.0 * std::f64::consts::PI);
+        let baseline = 105.0 * (times[peak.end] - times[peak.start]);
+        assert!((peak.area - baseline - expected).abs() / expected < 0.05);
+    }
+
+    #[test]
+    fn low_snr_peaks_are_dropped() {
+        let (msroi, times) = chromatograms();
+        let strict = PeakParams {
+            method: PeakDetectionMethod::LocalMaxima(1e3),
+            min_snr: 1e9,
+        };
+        assert!(detect_peaks(&msroi, &times, &strict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let (msroi, times) = chromatograms();
+        let short = times.slice(s![..150]).to_owned();
+        assert!(matches!(
+            detect_peaks(
+                &msroi,
+                &short,
+                &params(PeakDetectionMethod::LocalMaxima(0.0))
+            ),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        for (min_scale, max_scale) in [(0.0, 4.0), (4.0, 2.0), (1.0, f64::INFINITY)] {
+            let method = PeakDetectionMethod::ContinuousWavelet(min_scale, max_scale);
+            assert!(detect_peaks(&msroi, &times, &params(method)).is_err());
+        }
+    }
+}
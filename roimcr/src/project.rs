@@ -0,0 +1,516 @@
+//! Project files holding every result of an analysis, as NPZ archives that `numpy.load`
+//! reads directly or, with the `hdf5` feature, as HDF5 files.
+//!
+//! Both formats store the same arrays under the same names:
+//! - `roimcr_version`, `roi_params` (JSON): text scalars;
+//! - `files`, `hashes`: the input files and their SHA-256 hashes;
+//! - `mzroi`, `msroi`, `times`, `run_sizes`: the ROIs, see `RoiMatrices`;
+//! - `roi_statistics`: one row per ROI with the columns named in `roi_statistics_columns`,
+//!   NaN where there is no apex;
+//! - `mcr_c`, `mcr_st`, `mcr_lof_history`, `mcr_lof`, `mcr_r2`, `mcr_n_iter` and
+//!   `mcr_converged` when the project holds an MCR-ALS resolution.
+
+#[cfg(feature = "hdf5")]
+mod h5;
+mod npz;
+
+use ndarray::prelude::*;
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::enums::ProjectFormat;
+use crate::error::RoiError;
+use crate::export::roi_statistics;
+use crate::structs::data::{McrResult, Project, RoiMatrices, RoiStatistics};
+use crate::structs::options::RoiParams;
+
+const STATISTICS_COLUMNS: [&str; 7] = [
+    "mz",
+    "n_scans",
+    "n_runs",
+    "apex_time",
+    "apex_run",
+    "max_intensity",
+    "total_intensity",
+];
+
+/// Array stored in a project file.
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Floats(ArrayD<f64>),
+    Integers(ArrayD<i64>),
+    Strings(ArrayD<String>),
+}
+
+impl Project {
+    /// Project of the ROIs computed from `files` with `roi_params`, hashing the files and
+    /// computing the statistics of the ROIs.
+    pub fn new(
+        files: &[PathBuf],
+        roi_params: &RoiParams,
+        rois: RoiMatrices,
+        mcr: Option<McrResult>,
+    ) -> Result<Project, RoiError> {
+        let hashes = files
+            .iter()
+            .map(|path| file_hash(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let runs = Array1::from_iter(
+            rois.run_sizes
+                .iter()
+                .enumerate()
+                .flat_map(|(run, &size)| std::iter::repeat_n(run, size)),
+        );
+        let statistics = roi_statistics(&rois.msroi, &rois.mzroi, &rois.times, &runs)?;
+
+        Ok(Project {
+            files: files.to_vec(),
+            hashes,
+            roi_params: roi_params.clone(),
+            rois,
+            statistics,
+            mcr,
+        })
+    }
+}
+
+/// SHA-256 hash of a file, as lowercase hexadecimal.
+pub fn file_hash(path: &Path) -> Result<String, RoiError> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Write a project file in the format given by its extension, `.npz`, `.h5` or `.hdf5`,
+/// with the arrays compressed when `compress` is set.
+pub fn write_project(path: &Path, project: &Project, compress: bool) -> Result<(), RoiError> {
+    let entries = to_entries(project)?;
+    match project_format(path)? {
+        ProjectFormat::Npz => npz::write_npz(path, &entries, compress),
+        #[cfg(feature = "hdf5")]
+        ProjectFormat::Hdf5 => h5::write_hdf5(path, &entries, compress),
+        #[cfg(not(feature = "hdf5"))]
+        ProjectFormat::Hdf5 => Err(hdf5_disabled()),
+    }
+}
+
+/// Read a project file in the format given by its extension, `.npz`, `.h5` or `.hdf5`.
+pub fn read_project(path: &Path) -> Result<Project, RoiError> {
+    let entries = match project_format(path)? {
+        ProjectFormat::Npz => npz::read_npz(path)?,
+        #[cfg(feature = "hdf5")]
+        ProjectFormat::Hdf5 => h5::read_hdf5(path)?,
+        #[cfg(not(feature = "hdf5"))]
+        ProjectFormat::Hdf5 => return Err(hdf5_disabled()),
+    };
+    from_entries(entries).map_err(|message| {
+        RoiError::Parse(format!(
+            "Can't read {} as a project file: {}",
+            path.display(),
+            message
+        ))
+    })
+}
+
+fn project_format(path: &Path) -> Result<ProjectFormat, RoiError> {
+    ProjectFormat::from_path(path).ok_or_else(|| {
+        RoiError::UnsupportedFormat(format!(
+            "Can't detect the format of {}, project files end in .npz, .h5 or .hdf5",
+            path.display()
+        ))
+    })
+}
+
+#[cfg(not(feature = "hdf5"))]
+fn hdf5_disabled() -> RoiError {
+    RoiError::UnsupportedFormat(
+        "HDF5 project files need roimcr built with the hdf5 feature".to_string(),
+    )
+}
+
+fn to_entries(project: &Project) -> Result<Vec<(&'static str, Entry)>, RoiError> {
+    let text = |value: String| Entry::Strings(arr0(value).into_dyn());
+    let strings = |values: Vec<String>| Entry::Strings(Array1::from(values).into_dyn());
+    let floats = |values: ArrayD<f64>| Entry::Floats(values);
+
+    let roi_params = serde_json::to_string(&project.roi_params).map_err(|error| {
+        RoiError::Write(format!("Can't serialise the ROI parameters: {}", error))
+    })?;
+    let missing = |value: Option<f64>| value.unwrap_or(f64::NAN);
+    let statistics = Array2::from_shape_fn(
+        (project.statistics.len(), STATISTICS_COLUMNS.len()),
+        |(roi, column)| {
+            let statistics = &project.statistics[roi];
+            match column {
+                0 => statistics.mz,
+                1 => statistics.n_scans as f64,
+                2 => statistics.n_runs as f64,
+                3 => missing(statistics.apex_time),
+                4 => missing(statistics.apex_run.map(|run| run as f64)),
+                5 => missing(statistics.max_intensity),
+                _ => statistics.total_intensity,
+            }
+        },
+    );
+
+    let mut entries = vec![
+        (
+            "roimcr_version",
+            text(env!("CARGO_PKG_VERSION").to_string()),
+        ),
+        ("roi_params", text(roi_params)),
+        (
+            "files",
+            strings(
+                project
+                    .files
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+            ),
+        ),
+        ("hashes", strings(project.hashes.clone())),
+        ("mzroi", floats(project.rois.mzroi.clone().into_dyn())),
+        ("msroi", floats(project.rois.msroi.clone().into_dyn())),
+        ("times", floats(project.rois.times.clone().into_dyn())),
+        (
+            "run_sizes",
+            Entry::Integers(
+                Array1::from_iter(project.rois.run_sizes.iter().map(|&size| size as i64))
+                    .into_dyn(),
+            ),
+        ),
+        ("roi_statistics", floats(statistics.into_dyn())),
+        (
+            "roi_statistics_columns",
+            strings(
+                STATISTICS_COLUMNS
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+            ),
+        ),
+    ];
+    if let Some(mcr) = &project.mcr {
+        entries.extend([
+            ("mcr_c", floats(mcr.c.clone().into_dyn())),
+            ("mcr_st", floats(mcr.st.clone().into_dyn())),
+            (
+                "mcr_lof_history",
+                floats(Array1::from(mcr.lof_history.clone()).into_dyn()),
+            ),
+            ("mcr_lof", floats(arr0(mcr.lof).into_dyn())),
+            ("mcr_r2", floats(arr0(mcr.r2).into_dyn())),
+            (
+                "mcr_n_iter",
+                Entry::Integers(arr0(mcr.n_iter as i64).into_dyn()),
+            ),
+            (
+                "mcr_converged",
+                Entry::Integers(arr0(mcr.converged as i64).into_dyn()),
+            ),
+        ]);
+    }
+    Ok(entries)
+}
+
+fn from_entries(mut entries: BTreeMap<String, Entry>) -> Result<Project, String> {
+    let mut take = |name: &str, ndim: usize| match entries.remove(name) {
+        Some(entry) => {
+            let shape = match &entry {
+                Entry::Floats(values) => values.shape().to_vec(),
+                Entry::Integers(values) => values.shape().to_vec(),
+                Entry::Strings(values) => values.shape().to_vec(),
+            };
+            if shape.len() == ndim {
+                Ok(Some(entry))
+            } else {
+                Err(format!(
+                    "{} has {} dimensions instead of {}",
+                    name,
+                    shape.len(),
+                    ndim
+                ))
+            }
+        }
+        None => Ok(None),
+    };
+    let required = |entry: Option<Entry>, name: &str| entry.ok_or(format!("no {} array", name));
+    let floats = |entry: Entry, name: &str| match entry {
+        Entry::Floats(values) => Ok(values),
+        // numpy arrays of whole numbers saved from Python
+        Entry::Integers(values) => Ok(values.mapv(|x| x as f64)),
+        Entry::Strings(_) => Err(format!("{} isn't numeric", name)),
+    };
+    let integers = |entry: Entry, name: &str| match entry {
+        Entry::Integers(values) => Ok(values),
+        _ => Err(format!("{} isn't an integer array", name)),
+    };
+    let strings = |entry: Entry, name: &str| match entry {
+        Entry::Strings(values) => Ok(values),
+        _ => Err(format!("{} isn't a string array", name)),
+    };
+    let matrix = |values: ArrayD<f64>| values.into_dimensionality::<Ix2>().unwrap();
+    let vector = |values: ArrayD<f64>| values.into_dimensionality::<Ix1>().unwrap();
+
+    let roi_params = strings(
+        required(take("roi_params", 0)?, "roi_params")?,
+        "roi_params",
+    )?;
+    let roi_params: RoiParams = serde_json::from_str(roi_params.first().unwrap())
+        .map_err(|error| format!("invalid roi_params: {}", error))?;
+    let files = strings(required(take("files", 1)?, "files")?, "files")?;
+    let hashes = strings(required(take("hashes", 1)?, "hashes")?, "hashes")?;
+
+    let rois = RoiMatrices {
+        mzroi: vector(floats(required(take("mzroi", 1)?, "mzroi")?, "mzroi")?),
+        msroi: matrix(floats(required(take("msroi", 2)?, "msroi")?, "msroi")?),
+        times: vector(floats(required(take("times", 1)?, "times")?, "times")?),
+        run_sizes: integers(required(take("run_sizes", 1)?, "run_sizes")?, "run_sizes")?
+            .iter()
+            .map(|&size| usize::try_from(size).map_err(|_| format!("negative run size {}", size)))
+            .collect::<Result<_, _>>()?,
+    };
+    if rois.mzroi.len() != rois.msroi.ncols()
+        || rois.times.len() != rois.msroi.nrows()
+        || rois.run_sizes.iter().sum::<usize>() != rois.msroi.nrows()
+    {
+        return Err("mzroi, times and run_sizes don't match the shape of msroi".to_string());
+    }
+
+    let statistics = matrix(floats(
+        required(take("roi_statistics", 2)?, "roi_statistics")?,
+        "roi_statistics",
+    )?);
+    if statistics.ncols() != STATISTICS_COLUMNS.len() {
+        return Err(format!(
+            "roi_statistics has {} columns instead of {}",
+            statistics.ncols(),
+            STATISTICS_COLUMNS.len()
+        ));
+    }
+    let present = |value: f64| if value.is_nan() { None } else { Some(value) };
+    let statistics = statistics
+        .axis_iter(Axis(0))
+        .map(|row| RoiStatistics {
+            mz: row[0],
+            n_scans: row[1] as usize,
+            n_runs: row[2] as usize,
+            apex_time: present(row[3]),
+            apex_run: present(row[4]).map(|run| run as usize),
+            max_intensity: present(row[5]),
+            total_intensity: row[6],
+        })
+        .collect();
+
+    let mcr = match take("mcr_c", 2)? {
+        Some(c) => {
+            let c = matrix(floats(c, "mcr_c")?);
+            let st = matrix(floats(required(take("mcr_st", 2)?, "mcr_st")?, "mcr_st")?);
+            if c.nrows() != rois.msroi.nrows()
+                || st.ncols() != rois.msroi.ncols()
+                || c.ncols() != st.nrows()
+            {
+                return Err("mcr_c and mcr_st don't match the shape of msroi".to_string());
+            }
+            let scalar = |values: ArrayD<f64>| values.first().cloned().unwrap_or(f64::NAN);
+            let flag = |values: ArrayD<i64>| values.first().cloned().unwrap_or(0);
+            Some(McrResult {
+                residuals: &rois.msroi - &c.dot(&st),
+                c,
+                st,
+                lof_history: match take("mcr_lof_history", 1)? {
+                    Some(history) => floats(history, "mcr_lof_history")?.into_raw_vec(),
+                    None => Vec::new(),
+                },
+                lof: scalar(floats(
+                    required(take("mcr_lof", 0)?, "mcr_lof")?,
+                    "mcr_lof",
+                )?),
+                r2: scalar(floats(required(take("mcr_r2", 0)?, "mcr_r2")?, "mcr_r2")?),
+                n_iter: flag(integers(
+                    required(take("mcr_n_iter", 0)?, "mcr_n_iter")?,
+                    "mcr_n_iter",
+                )?) as usize,
+                converged: flag(integers(
+                    required(take("mcr_converged", 0)?, "mcr_converged")?,
+                    "mcr_converged",
+                )?) != 0,
+            })
+        }
+        None => None,
+    };
+
+    Ok(Project {
+        files: files.iter().map(PathBuf::from).collect(),
+        hashes: hashes.into_raw_vec(),
+        roi_params,
+        rois,
+        statistics,
+        mcr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bilinear, temp_path};
+
+    use std::fs;
+    use std::process::Command;
+
+    /// A project of two runs of 10 and 14 scans, with an exact two component resolution,
+    /// and the input files it was computed from.
+    fn project() -> (Project, Vec<PathBuf>) {
+        let (msroi, c, st) = bilinear(24, 5, 2, 48);
+        let files = vec![temp_path("first.mzML"), temp_path("second.mzML")];
+        for (i, file) in files.iter().enumerate() {
+            fs::write(file, format!("run {}", i)).unwrap();
+        }
+        let rois = RoiMatrices {
+            mzroi: array![101.0, 150.5, 200.0, 250.25, 300.0],
+            times: Array1::from_shape_fn(24, |i| i as f64 * 0.5),
+            run_sizes: vec![10, 14],
+            msroi: msroi.clone(),
+        };
+        let mcr = McrResult {
+            residuals: Array2::zeros(msroi.dim()),
+            c,
+            st,
+            lof: 0.0,
+            r2: 100.0,
+            lof_history: vec![3.0, 0.5, 0.0],
+            n_iter: 3,
+            converged: true,
+        };
+        let project = Project::new(&files, &RoiParams::default(), rois, Some(mcr)).unwrap();
+        (project, files)
+    }
+
+    fn remove(paths: &[PathBuf]) {
+        for path in paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    fn assert_same(read: &Project, original: &Project) {
+        assert_eq!(read.files, original.files);
+        assert_eq!(read.hashes, original.hashes);
+        assert_eq!(read.rois.msroi, original.rois.msroi);
+        assert_eq!(read.rois.mzroi, original.rois.mzroi);
+        assert_eq!(read.rois.times, original.rois.times);
+        assert_eq!(read.rois.run_sizes, original.rois.run_sizes);
+        assert_eq!(read.statistics, original.statistics);
+        assert_eq!(read.roi_params.threshold, original.roi_params.threshold);
+
+        let (read, original) = (read.mcr.as_ref().unwrap(), original.mcr.as_ref().unwrap());
+        assert_eq!(read.c, original.c);
+        assert_eq!(read.st, original.st);
+        assert_eq!(read.lof_history, original.lof_history);
+        assert_eq!((read.n_iter, read.converged), (3, true));
+        assert!(read.residuals.iter().all(|x| x.abs() < 1e-12));
+    }
+
+    #[test]
+    fn npz_projects_are_read_back() {
+        let (original, files) = project();
+        assert_eq!(original.hashes[0].len(), 64);
+        assert_eq!(original.statistics.len(), 5);
+
+        for compress in [false, true] {
+            let path = temp_path("project.npz");
+            write_project(&path, &original, compress).unwrap();
+            let read = read_project(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_same(&read, &original);
+        }
+        remove(&files);
+    }
+
+    #[test]
+    fn inconsistent_entries_are_rejected() {
+        let (original, files) = project();
+        remove(&files);
+        let entries = |project: &Project| -> BTreeMap<String, Entry> {
+            to_entries(project)
+                .unwrap()
+                .into_iter()
+                .map(|(name, entry)| (name.to_string(), entry))
+                .collect()
+        };
+
+        // mcr_st with one component more than mcr_c
+        let mut wrong = entries(&original);
+        wrong.insert(
+            "mcr_st".to_string(),
+            Entry::Floats(Array2::<f64>::ones((3, 5)).into_dyn()),
+        );
+        assert!(from_entries(wrong).is_err());
+
+        let mut wrong = entries(&original);
+        wrong.insert(
+            "run_sizes".to_string(),
+            Entry::Integers(array![30i64, -6].into_dyn()),
+        );
+        assert!(from_entries(wrong).is_err());
+
+        let mut wrong = entries(&original);
+        wrong.remove("msroi");
+        assert!(from_entries(wrong).is_err());
+
+        assert!(matches!(
+            write_project(&temp_path("project.zip"), &original, false),
+            Err(RoiError::UnsupportedFormat(_))
+        ));
+    }
+
+    /// The NPZ files are meant to be read by `numpy.load`, text arrays included; skipped
+    /// when numpy isn't installed.
+    #[test]
+    fn numpy_reads_npz_projects() {
+        let has_numpy = Command::new("python3")
+            .args(["-c", "import numpy"])
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if !has_numpy {
+            eprintln!("numpy isn't installed, skipping");
+            return;
+        }
+
+        let (original, files) = project();
+        let path = temp_path("numpy.npz");
+        write_project(&path, &original, true).unwrap();
+        let script = r#"
+import sys
+import numpy
+project = numpy.load(sys.argv[1])
+assert project["files"].dtype.kind == "U", project["files"].dtype
+assert project["roi_params"].dtype.kind == "U" and project["roi_params"].shape == ()
+assert project["roi_statistics_columns"][0] == "mz"
+assert project["msroi"].shape == (24, 5)
+assert list(project["run_sizes"]) == [10, 14]
+print(project["files"][1])
+"#;
+        let output = Command::new("python3")
+            .args(["-c", script])
+            .arg(&path)
+            .output()
+            .unwrap();
+        remove(&[path]);
+        remove(&files);
+
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            original.files[1].display().to_string()
+        );
+    }
+}
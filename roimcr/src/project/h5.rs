@@ -0,0 +1,112 @@
+use hdf5::types::VarLenUnicode;
+use ndarray::prelude::*;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::Entry;
+use crate::error::RoiError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EntryKind {
+    Floats,
+    Integers,
+    Strings,
+}
+
+/// Names and kinds of all the datasets of a project file.
+const ENTRIES: [(&str, EntryKind); 17] = [
+    ("roimcr_version", EntryKind::Strings),
+    ("roi_params", EntryKind::Strings),
+    ("files", EntryKind::Strings),
+    ("hashes", EntryKind::Strings),
+    ("mzroi", EntryKind::Floats),
+    ("msroi", EntryKind::Floats),
+    ("times", EntryKind::Floats),
+    ("run_sizes", EntryKind::Integers),
+    ("roi_statistics", EntryKind::Floats),
+    ("roi_statistics_columns", EntryKind::Strings),
+    ("mcr_c", EntryKind::Floats),
+    ("mcr_st", EntryKind::Floats),
+    ("mcr_lof_history", EntryKind::Floats),
+    ("mcr_lof", EntryKind::Floats),
+    ("mcr_r2", EntryKind::Floats),
+    ("mcr_n_iter", EntryKind::Integers),
+    ("mcr_converged", EntryKind::Integers),
+];
+
+/// Write the entries as datasets of the root group, strings as variable length UTF-8,
+/// deflated when `compress` is set.
+pub fn write_hdf5(path: &Path, entries: &[(&str, Entry)], compress: bool) -> Result<(), RoiError> {
+    let error =
+        |error: hdf5::Error| RoiError::Write(format!("Can't write {}: {}", path.display(), error));
+
+    let file = hdf5::File::create(path).map_err(error)?;
+    for (name, entry) in entries.iter() {
+        // Filters need chunked datasets, which can't be scalars or empty
+        let deflate = |shape: &[usize]| compress && !shape.is_empty() && !shape.contains(&0);
+        match entry {
+            Entry::Floats(values) => {
+                let mut builder = file.new_dataset_builder().with_data(values);
+                if deflate(values.shape()) {
+                    builder = builder.deflate(4);
+                }
+                builder.create(*name).map_err(error)?;
+            }
+            Entry::Integers(values) => {
+                let mut builder = file.new_dataset_builder().with_data(values);
+                if deflate(values.shape()) {
+                    builder = builder.deflate(4);
+                }
+                builder.create(*name).map_err(error)?;
+            }
+            Entry::Strings(values) => {
+                let strings = values
+                    .iter()
+                    .map(|text| text.parse::<VarLenUnicode>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| {
+                        RoiError::Write(format!(
+                            "Can't write {} to {}: {}",
+                            name,
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                let strings = ArrayD::from_shape_vec(values.raw_dim(), strings).unwrap();
+                file.new_dataset_builder()
+                    .with_data(&strings)
+                    .create(*name)
+                    .map_err(error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the datasets of a project file present in the root group, by name.
+pub fn read_hdf5(path: &Path) -> Result<BTreeMap<String, Entry>, RoiError> {
+    let error =
+        |error: hdf5::Error| RoiError::Parse(format!("Can't read {}: {}", path.display(), error));
+
+    let file = hdf5::File::open(path).map_err(error)?;
+    let mut entries = BTreeMap::new();
+    for &(name, kind) in ENTRIES.iter() {
+        if !file.link_exists(name) {
+            continue;
+        }
+        let dataset = file.dataset(name).map_err(error)?;
+        let entry = match kind {
+            EntryKind::Floats => Entry::Floats(dataset.read_dyn::<f64>().map_err(error)?),
+            EntryKind::Integers => Entry::Integers(dataset.read_dyn::<i64>().map_err(error)?),
+            EntryKind::Strings => Entry::Strings(
+                dataset
+                    .read_dyn::<VarLenUnicode>()
+                    .map_err(error)?
+                    .map(|text| text.as_str().to_string()),
+            ),
+        };
+        entries.insert(name.to_string(), entry);
+    }
+    Ok(entries)
+}
@@ -0,0 +1,380 @@
+use ndarray::prelude::*;
+use ndarray::{IxDyn, ShapeBuilder};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::Entry;
+use crate::error::RoiError;
+
+/// Write the entries as the `.npy` members of a zip archive, deflated when `compress` is
+/// set, as `numpy.savez_compressed` does.
+pub fn write_npz(path: &Path, entries: &[(&str, Entry)], compress: bool) -> Result<(), RoiError> {
+    let error = |error: zip::result::ZipError| {
+        RoiError::Write(format!("Can't write {}: {}", path.display(), error))
+    };
+    let method = if compress {
+        CompressionMethod::Deflated
+    } else {
+        CompressionMethod::Stored
+    };
+
+    let mut zip = ZipWriter::new(File::create(path)?);
+    for (name, entry) in entries.iter() {
+        let bytes = npy_bytes(entry);
+        let options = FileOptions::default()
+            .compression_method(method)
+            .large_file(bytes.len() >= u32::MAX as usize);
+        zip.start_file(format!("{}.npy", name), options)
+            .map_err(error)?;
+        zip.write_all(&bytes)?;
+    }
+    zip.finish().map_err(error)?;
+    Ok(())
+}
+
+/// Read all the `.npy` members of an NPZ archive, by name.
+pub fn read_npz(path: &Path) -> Result<BTreeMap<String, Entry>, RoiError> {
+    let invalid = |message: String| {
+        RoiError::Parse(format!(
+            "Can't read {} as an NPZ archive: {}",
+            path.display(),
+            message
+        ))
+    };
+
+    let mut archive = ZipArchive::new(File::open(path)?).map_err(|e| invalid(e.to_string()))?;
+    let mut entries = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut member = archive.by_index(i).map_err(|e| invalid(e.to_string()))?;
+        let name = member.name().trim_end_matches(".npy").to_string();
+        let mut bytes = Vec::new();
+        member.read_to_end(&mut bytes)?;
+        let entry =
+            parse_npy(&bytes).map_err(|message| invalid(format!("{}: {}", name, message)))?;
+        entries.insert(name, entry);
+    }
+    Ok(entries)
+}
+
+/// `.npy` file (format version 1.0) of an entry, with the values in C order.
+fn npy_bytes(entry: &Entry) -> Vec<u8> {
+    let (descr, shape, data): (String, &[usize], Vec<u8>) = match entry {
+        Entry::Floats(values) => (
+            "<f8".to_string(),
+            values.shape(),
+            values.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        ),
+        Entry::Integers(values) => (
+            "<i8".to_string(),
+            values.shape(),
+            values.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        ),
+        Entry::Strings(values) => {
+            // Fixed width UTF-32 strings, padded with zeros
+            let width = values
+                .iter()
+                .map(|text| text.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max(1);
+            let data = values
+                .iter()
+                .flat_map(|text| {
+                    let mut chars: Vec<u32> = text.chars().map(|c| c as u32).collect();
+                    chars.resize(width, 0);
+                    chars.into_iter().flat_map(|c| c.to_le_bytes())
+                })
+                .collect();
+            (format!("<U{}", width), values.shape(), data)
+        }
+    };
+
+    let shape = match shape {
+        [] => "()".to_string(),
+        [length] => format!("({},)", length),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|size| size.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // The data starts on a multiple of 64 bytes, after the newline ending the header
+    let length = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - length % 64) % 64));
+    header.push('\n');
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(&data);
+    bytes
+}
+
+/// Entry of a `.npy` file of floats, integers, booleans or strings.
+fn parse_npy(bytes: &[u8]) -> Result<Entry, String> {
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err("not a .npy array".to_string());
+    }
+    let (length, start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        _ if bytes.len() >= 12 => (
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            12,
+        ),
+        _ => return Err("truncated header".to_string()),
+    };
+    let header = bytes
+        .get(start..start + length)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or("invalid header")?;
+    let data = &bytes[start + length..];
+
+    let field = |key: &str| {
+        header
+            .find(&format!("'{}':", key))
+            .map(|position| header[position + key.len() + 3..].trim_start())
+            .ok_or(format!("no {} in the header", key))
+    };
+    let descr = field("descr")?
+        .strip_prefix('\'')
+        .and_then(|descr| descr.split('\'').next())
+        .ok_or("invalid descr")?;
+    let fortran_order = field("fortran_order")?.starts_with("True");
+    let shape = field("shape")?
+        .strip_prefix('(')
+        .and_then(|shape| shape.split(')').next())
+        .ok_or("invalid shape")?
+        .split(',')
+        .map(|size| size.trim())
+        .filter(|size| !size.is_empty())
+        .map(|size| {
+            size.parse::<usize>()
+                .map_err(|_| "invalid shape".to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = shape
+        .iter()
+        .try_fold(1usize, |count, &size| count.checked_mul(size))
+        .ok_or("invalid shape")?;
+    let shape = IxDyn(&shape).set_f(fortran_order);
+
+    let unsupported = || format!("unsupported dtype {}", descr);
+    let (order, kind) = descr.split_at_checked(1).ok_or_else(unsupported)?;
+    let little_endian = order != ">";
+    let (kind, size) = kind.split_at_checked(1).ok_or_else(unsupported)?;
+    let size: usize = size.parse().map_err(|_| unsupported())?;
+    let item_size = if kind == "U" { 4 * size } else { size };
+    let length = count
+        .checked_mul(item_size)
+        .filter(|&length| length <= data.len())
+        .ok_or("truncated data")?;
+    let items = data[..length].chunks_exact(item_size.max(1));
+
+    macro_rules! convert {
+        ($t:ty, $target:ty) => {
+            items
+                .map(|chunk| {
+                    let chunk = chunk.try_into().unwrap();
+                    (if little_endian {
+                        <$t>::from_le_bytes(chunk)
+                    } else {
+                        <$t>::from_be_bytes(chunk)
+                    }) as $target
+                })
+                .collect::<Vec<$target>>()
+        };
+    }
+
+    let entry = match (kind, size) {
+        ("f", 8) => Entry::Floats(from_shape(shape, convert!(f64, f64))?),
+        ("f", 4) => Entry::Floats(from_shape(shape, convert!(f32, f64))?),
+        ("i", 8) => Entry::Integers(from_shape(shape, convert!(i64, i64))?),
+        ("i", 4) => Entry::Integers(from_shape(shape, convert!(i32, i64))?),
+        ("i", 2) => Entry::Integers(from_shape(shape, convert!(i16, i64))?),
+        ("i", 1) => Entry::Integers(from_shape(shape, convert!(i8, i64))?),
+        ("u", 8) => {
+            let values = convert!(u64, u64)
+                .into_iter()
+                .map(|value| {
+                    i64::try_from(value)
+                        .map_err(|_| format!("uint64 value {} beyond the range of int64", value))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Entry::Integers(from_shape(shape, values)?)
+        }
+        ("u", 4) => Entry::Integers(from_shape(shape, convert!(u32, i64))?),
+        ("u", 2) => Entry::Integers(from_shape(shape, convert!(u16, i64))?),
+        ("u", 1) | ("b", 1) => Entry::Integers(from_shape(shape, convert!(u8, i64))?),
+        ("U", _) => {
+            let strings = items
+                .map(|chunk| {
+                    chunk
+                        .chunks_exact(4)
+                        .map(|c| {
+                            let c = c.try_into().unwrap();
+                            if little_endian {
+                                u32::from_le_bytes(c)
+                            } else {
+                                u32::from_be_bytes(c)
+                            }
+                        })
+                        .take_while(|&c| c != 0)
+                        .filter_map(char::from_u32)
+                        .collect::<String>()
+                })
+                .collect();
+            Entry::Strings(from_shape(shape, strings)?)
+        }
+        _ => return Err(unsupported()),
+    };
+    Ok(entry)
+}
+
+fn from_shape<T: Clone>(shape: ndarray::Shape<IxDyn>, values: Vec<T>) -> Result<ArrayD<T>, String> {
+    ArrayD::from_shape_vec(shape, values)
+        .map(|values| values.as_standard_layout().into_owned())
+        .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    /// `.npy` bytes with a version 1.0 header, as written by numpy.
+    fn npy(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn entries_are_read_back() {
+        let entries = vec![
+            (
+                "floats",
+                Entry::Floats(array![[1.5, -2.0, 3.25], [0.0, 1e300, f64::NAN]].into_dyn()),
+            ),
+            ("integers", Entry::Integers(array![3i64, -1, 7].into_dyn())),
+            ("scalar", Entry::Integers(arr0(42i64).into_dyn())),
+            (
+                "strings",
+                Entry::Strings(array!["a.mzML".to_string(), "Δt µm".to_string()].into_dyn()),
+            ),
+            ("text", Entry::Strings(arr0("{}".to_string()).into_dyn())),
+            (
+                "empty",
+                Entry::Floats(Array2::<f64>::zeros((0, 3)).into_dyn()),
+            ),
+        ];
+        for compress in [false, true] {
+            let path = temp_path("entries.npz");
+            write_npz(&path, &entries, compress).unwrap();
+            let read = read_npz(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(read.len(), entries.len());
+            for (name, entry) in entries.iter() {
+                match (&read[*name], entry) {
+                    (Entry::Floats(read), Entry::Floats(entry)) => {
+                        assert_eq!(read.shape(), entry.shape());
+                        assert!(read
+                            .iter()
+                            .zip(entry.iter())
+                            .all(|(a, b)| a == b || (a.is_nan() && b.is_nan())));
+                    }
+                    (read, entry) => assert_eq!(read, entry, "{}", name),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn numpy_dtypes_and_orders_are_read() {
+        // Big endian float32 in Fortran order: the columns are stored one after the other
+        let data: Vec<u8> = [1.0f32, 4.0, 2.0, 5.0, 3.0, 6.0]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect();
+        let bytes = npy(
+            "{'descr': '>f4', 'fortran_order': True, 'shape': (2, 3), }",
+            &data,
+        );
+        let expected = array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]].into_dyn();
+        assert_eq!(parse_npy(&bytes).unwrap(), Entry::Floats(expected));
+
+        let bytes = npy(
+            "{'descr': '|b1', 'fortran_order': False, 'shape': (3,), }",
+            &[1, 0, 1],
+        );
+        assert_eq!(
+            parse_npy(&bytes).unwrap(),
+            Entry::Integers(array![1i64, 0, 1].into_dyn())
+        );
+
+        let data: Vec<u8> = [-2i16, 300].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let bytes = npy(
+            "{'descr': '<i2', 'fortran_order': False, 'shape': (2,), }",
+            &data,
+        );
+        assert_eq!(
+            parse_npy(&bytes).unwrap(),
+            Entry::Integers(array![-2i64, 300].into_dyn())
+        );
+    }
+
+    #[test]
+    fn invalid_arrays_are_rejected() {
+        assert!(parse_npy(b"PK\x03\x04 not an array").is_err());
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (2, 2), }";
+        assert!(parse_npy(&npy(header, &[0; 24])).is_err());
+        let header = "{'descr': '<c16', 'fortran_order': False, 'shape': (1,), }";
+        assert!(parse_npy(&npy(header, &[0; 16])).is_err());
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (4294967296, 4294967296, 4294967296), }";
+        assert!(parse_npy(&npy(header, &[0; 8])).is_err());
+        // Empty and non-ASCII dtypes of a corrupt header
+        for descr in ["", "<", "é8", "<é8"] {
+            let header = format!(
+                "{{'descr': '{}', 'fortran_order': False, 'shape': (1,), }}",
+                descr
+            );
+            assert!(parse_npy(&npy(&header, &[0; 8])).is_err(), "{}", descr);
+        }
+
+        // Unsigned 64-bit integers are read only within the range of i64
+        let header = "{'descr': '<u8', 'fortran_order': False, 'shape': (2,), }";
+        let data: Vec<u8> = [7u64, i64::MAX as u64]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        assert_eq!(
+            parse_npy(&npy(header, &data)).unwrap(),
+            Entry::Integers(array![7, i64::MAX].into_dyn())
+        );
+        let data: Vec<u8> = [7u64, u64::MAX]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        assert!(parse_npy(&npy(header, &data)).is_err());
+
+        let path = temp_path("broken.npz");
+        std::fs::write(&path, b"not a zip archive").unwrap();
+        let read = read_npz(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(read, Err(RoiError::Parse(_))));
+    }
+}
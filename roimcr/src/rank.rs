@@ -0,0 +1,177 @@
+use ndarray::prelude::*;
+
+use crate::error::RoiError;
+use crate::structs::data::RankResult;
+use crate::utils::truncated_svd;
+
+/// Diagnostics for the number of components of `d`, e.g. the `msroi` returned by
+/// `compute_roi`, from its first `max_rank` singular values. As for MCR the data are not
+/// centered. Entry `k` of each curve refers to the model with `k + 1` components.
+///
+/// - `explained_variance`: percentage of the sum of squares of `d` explained by each
+///   component, and `cumulative_variance` its running total;
+/// - `ind`: Malinowski's indicator function, whose minimum gives `ind_rank`;
+/// - `broken_stick`: percentage each component would explain if the variance were split
+///   at random; `broken_stick_rank` counts the leading components that explain more;
+/// - `press`: prediction error sum of squares of the rows left out in `n_splits`
+///   interleaved groups, projected on the loadings fitted on the other rows.
+///   `press_rank` counts the leading components that reduce the PRESS more than twice
+///   as much as a random direction of the remaining space would.
+///
+/// The `suggested` rank is the median of the three.
+pub fn estimate_rank(
+    d: &Array2<f64>,
+    max_rank: usize,
+    n_splits: usize,
+) -> Result<RankResult, RoiError> {
+    let (n_rows, n_columns) = d.dim();
+    let small = n_rows.min(n_columns);
+    if small < 2 {
+        return Err(RoiError::InvalidArgument(format!(
+            "Rank estimation needs a data matrix with at least two rows and columns, got {:?}",
+            d.dim()
+        )));
+    }
+    if n_splits < 2 || n_splits > n_rows {
+        return Err(RoiError::InvalidArgument(format!(
+            "Cross-validation needs between two and {} splits, the number of rows, got {}",
+            n_rows, n_splits
+        )));
+    }
+    let max_rank = max_rank.clamp(1, small - 1);
+
+    let (_, singular_values, _) = truncated_svd(d, max_rank);
+    let max_rank = singular_values.len();
+    let total = d.mapv(|x| x * x).sum();
+    let eigenvalues = singular_values.mapv(|s| s * s);
+
+    let explained_variance = eigenvalues.mapv(|l| 100.0 * l / total);
+    let mut cumulative_variance = explained_variance.clone();
+    cumulative_variance.accumulate_axis_inplace(Axis(0), |&previous, current| *current += previous);
+
+    // Malinowski: RE(k) = sqrt(residual / (r (c - k))), IND(k) = RE(k) / (c - k)^2
+    let (r, c) = (n_rows.max(n_columns) as f64, small as f64);
+    let mut residual = total;
+    let ind = Array1::from_shape_fn(max_rank, |k| {
+        residual -= eigenvalues[k];
+        let free = c - (k + 1) as f64;
+        (residual.max(0.0) / (r * free)).sqrt() / (free * free)
+    });
+    let ind_rank = 1 + argmin(&ind);
+
+    let broken_stick = Array1::from_shape_fn(max_rank, |k| {
+        100.0 / c * ((k + 1)..=small).map(|i| 1.0 / i as f64).sum::<f64>()
+    });
+    let broken_stick_rank = explained_variance
+        .iter()
+        .zip(broken_stick.iter())
+        .take_while(|(explained, expected)| explained > expected)
+        .count();
+
+    let press = cross_validated_press(d, max_rank, n_splits);
+    let mut press_rank = 0;
+    let mut previous = d.mapv(|x| x * x).sum();
+    for (k, &current) in press.iter().enumerate() {
+        let random = 2.0 / (n_columns - k) as f64;
+        if previous <= 0.0 || current / previous > 1.0 - random {
+            break;
+        }
+        press_rank = k + 1;
+        previous = current;
+    }
+
+    let mut ranks = [ind_rank, broken_stick_rank, press_rank];
+    ranks.sort_unstable();
+
+    Ok(RankResult {
+        singular_values,
+        explained_variance,
+        cumulative_variance,
+        ind,
+        broken_stick,
+        press,
+        ind_rank,
+        broken_stick_rank,
+        press_rank,
+        suggested: ranks[1],
+    })
+}
+
+/// Row-wise cross-validated PRESS for 1 to `max_rank` components, leaving out every
+/// `n_splits`-th row in turn.
+fn cross_validated_press(d: &Array2<f64>, max_rank: usize, n_splits: usize) -> Array1<f64> {
+    let mut press: Array1<f64> = Array1::zeros(max_rank);
+
+    for split in 0..n_splits {
+        let (test, train): (Vec<usize>, Vec<usize>) =
+            (0..d.nrows()).partition(|i| i % n_splits == split);
+        let training = d.select(Axis(0), &train);
+        let mut residual = d.select(Axis(0), &test);
+
+        let (_, _, vt) = truncated_svd(&training, max_rank);
+        for (k, loading) in vt.axis_iter(Axis(0)).enumerate() {
+            // Loadings are orthonormal, so they can be removed one at a time
+            let scores = residual.dot(&loading);
+            for (mut row, &score) in residual.axis_iter_mut(Axis(0)).zip(scores.iter()) {
+                row.scaled_add(-score, &loading);
+            }
+            press[k] += residual.mapv(|x| x * x).sum();
+        }
+    }
+    press
+}
+
+fn argmin(values: &Array1<f64>) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bilinear, Random};
+
+    fn noisy(k: usize, seed: u64) -> Array2<f64> {
+        let (d, _, _) = bilinear(80, 40, k, seed);
+        let mut random = Random::new(seed + 1);
+        d.mapv(|x| x + 1e-3 * (random.uniform() - 0.5))
+    }
+
+    #[test]
+    fn rank_of_bilinear_data_is_found() {
+        for k in [2, 3, 4] {
+            let result = estimate_rank(&noisy(k, 50 + k as u64), 10, 7).unwrap();
+            assert_eq!(result.suggested, k, "{:?}", result);
+            assert_eq!(result.singular_values.len(), 10);
+        }
+    }
+
+    #[test]
+    fn curves_are_consistent() {
+        let result = estimate_rank(&noisy(3, 60), 6, 5).unwrap();
+        let total: f64 = result.explained_variance.sum();
+        assert!((result.cumulative_variance[5] - total).abs() < 1e-9);
+        assert!(result.cumulative_variance[2] > 99.9);
+        assert!(result
+            .singular_values
+            .windows(2)
+            .into_iter()
+            .all(|w| w[0] >= w[1]));
+        assert!(result.press.windows(2).into_iter().all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let d = noisy(2, 70);
+        assert!(matches!(
+            estimate_rank(&d.slice(s![..1, ..]).to_owned(), 5, 2),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        assert!(estimate_rank(&d, 5, 1).is_err());
+        assert!(estimate_rank(&d, 5, 81).is_err());
+    }
+}
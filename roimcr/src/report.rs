@@ -0,0 +1,224 @@
+use csv::WriterBuilder;
+use ndarray::prelude::*;
+
+use std::fs::File;
+use std::path::Path;
+
+use crate::error::RoiError;
+use crate::structs::data::{ComponentRoi, ComponentSummary};
+
+/// Map the components of an MCR model `c st` of an `msroi` (e.g. from `McrResult`) back
+/// to its ROIs.
+///
+/// For every component the `n_top` ROIs with the largest loadings in `st` are listed with
+/// their `mzroi` value, their loading relative to the largest one and their selectivity,
+/// the share of the component in the signal of the ROI (`||c_k|| |st_kj|` over all
+/// components). `times_list` holds the scan times of each run, stacked in `c` as in
+/// `compute_roi`; the apex time of the component is given for every run.
+pub fn component_report(
+    c: &Array2<f64>,
+    st: &Array2<f64>,
+    mzroi: &Array1<f64>,
+    times_list: &[Array1<f64>],
+    n_top: usize,
+) -> Result<Vec<ComponentSummary>, RoiError> {
+    if c.ncols() != st.nrows() {
+        return Err(RoiError::InvalidArgument(format!(
+            "c has {} components but st has {}",
+            c.ncols(),
+            st.nrows()
+        )));
+    }
+    if mzroi.len() != st.ncols() {
+        return Err(RoiError::InvalidArgument(format!(
+            "mzroi must have one value per column of the spectra, {}",
+            st.ncols()
+        )));
+    }
+    if times_list.iter().map(|times| times.len()).sum::<usize>() != c.nrows() {
+        return Err(RoiError::InvalidArgument(format!(
+            "The scan times of the runs must add up to the number of rows of c, {}",
+            c.nrows()
+        )));
+    }
+
+    let c_norms = c.mapv(|x| x * x).sum_axis(Axis(0)).mapv(f64::sqrt);
+    let contributions = st.mapv(f64::abs) * c_norms.view().insert_axis(Axis(1));
+    let totals = contributions.sum_axis(Axis(0));
+
+    let report = (0..st.nrows())
+        .map(|k| {
+            let spectrum = st.row(k);
+            let largest = spectrum.iter().cloned().fold(0.0, f64::max);
+            let mut order: Vec<usize> = (0..spectrum.len()).collect();
+            order.sort_by(|&a, &b| spectrum[b].total_cmp(&spectrum[a]));
+
+            let rois = order
+                .into_iter()
+                .take(n_top)
+                .map(|j| ComponentRoi {
+                    roi: j,
+                    mz: mzroi[j],
+                    loading: if largest > 0.0 {
+                        spectrum[j] / largest
+                    } else {
+                        0.0
+                    },
+                    selectivity: if totals[j] > 0.0 {
+                        contributions[[k, j]] / totals[j]
+                    } else {
+                        0.0
+                    },
+                })
+                .collect();
+
+            let mut start = 0;
+            let apex_times = times_list
+                .iter()
+                .map(|times| {
+                    let profile = c.slice(s![start..start + times.len(), k]);
+                    start += times.len();
+                    profile
+                        .iter()
+                        .enumerate()
+                        .max_by(|a, b| a.1.total_cmp(b.1))
+                        .map_or(f64::NAN, |(i, _)| times[i])
+                })
+                .collect();
+
+            ComponentSummary {
+                component: k,
+                apex_times,
+                rois,
+            }
+        })
+        .collect();
+    Ok(report)
+}
+
+/// Write a component report as a table with one row per component and ROI: component,
+/// rank of the ROI, ROI index, m/z, loading, selectivity and the apex time of the
+/// component in every run. Columns are separated by tabs for `.tsv` files and by commas
+/// otherwise.
+pub fn write_component_report(report: &[ComponentSummary], path: &Path) -> Result<(), RoiError> {
+    let tsv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("tsv"));
+    let delimiter = if tsv { b'\t' } else { b',' };
+    let file = File::create(path)?;
+    let mut writer = WriterBuilder::new().delimiter(delimiter).from_writer(file);
+    let error =
+        |error: csv::Error| RoiError::Write(format!("Can't write {}: {}", path.display(), error));
+
+    let n_runs = report.first().map_or(0, |summary| summary.apex_times.len());
+    let mut header: Vec<String> = ["component", "rank", "roi", "mz", "loading", "selectivity"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    header.extend((0..n_runs).map(|run| format!("apex_time_run{}", run)));
+    writer.write_record(&header).map_err(error)?;
+
+    for summary in report {
+        for (rank, roi) in summary.rois.iter().enumerate() {
+            let mut record = vec![
+                summary.component.to_string(),
+                rank.to_string(),
+                roi.roi.to_string(),
+                roi.mz.to_string(),
+                roi.loading.to_string(),
+                roi.selectivity.to_string(),
+            ];
+            record.extend(summary.apex_times.iter().map(|time| time.to_string()));
+            writer.write_record(&record).map_err(error)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{bilinear, temp_path};
+
+    /// A three component model of two runs of 30 scans each, with 12 ROIs.
+    fn model() -> (Array2<f64>, Array2<f64>, Array1<f64>, Vec<Array1<f64>>) {
+        let (_, c, st) = bilinear(60, 12, 3, 7);
+        let mzroi = Array1::from_shape_fn(12, |j| 100.0 + 10.0 * j as f64);
+        let times_list = vec![
+            Array1::from_shape_fn(30, |i| i as f64),
+            Array1::from_shape_fn(30, |i| 100.0 + i as f64),
+        ];
+        (c, st, mzroi, times_list)
+    }
+
+    #[test]
+    fn rois_are_ranked_by_loading() {
+        let (c, st, mzroi, times_list) = model();
+        let report = component_report(&c, &st, &mzroi, &times_list, 4).unwrap();
+
+        assert_eq!(report.len(), 3);
+        for (k, summary) in report.iter().enumerate() {
+            assert_eq!(summary.component, k);
+            assert_eq!(summary.rois.len(), 4);
+            assert_eq!(summary.rois[0].loading, 1.0);
+            assert!(summary
+                .rois
+                .windows(2)
+                .all(|pair| pair[0].loading >= pair[1].loading));
+            for roi in summary.rois.iter() {
+                assert_eq!(roi.mz, mzroi[roi.roi]);
+                assert!(roi.selectivity > 0.0 && roi.selectivity <= 1.0);
+            }
+            assert_eq!(summary.apex_times.len(), 2);
+        }
+        // The profiles of the first component peak at scan 15, in the first run
+        assert_eq!(report[0].apex_times[0], 15.0);
+        assert_eq!(report[0].apex_times[1], 100.0);
+    }
+
+    #[test]
+    fn report_is_written_with_one_row_per_roi() {
+        let (c, st, mzroi, times_list) = model();
+        let report = component_report(&c, &st, &mzroi, &times_list, 2).unwrap();
+
+        for name in ["report.csv", "report.tsv"] {
+            let path = temp_path(name);
+            write_component_report(&report, &path).unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let delimiter = if name.ends_with(".tsv") { '\t' } else { ',' };
+            let lines: Vec<Vec<&str>> = text
+                .lines()
+                .map(|line| line.split(delimiter).collect())
+                .collect();
+            assert_eq!(lines.len(), 1 + 3 * 2);
+            assert_eq!(lines[0][0], "component");
+            assert_eq!(lines[0][7], "apex_time_run1");
+            assert!(lines.iter().all(|line| line.len() == 8));
+            assert_eq!(lines[3][0], "1");
+            assert_eq!(lines[3][1], "0");
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let (c, st, mzroi, times_list) = model();
+        let short = mzroi.slice(s![..10]).to_owned();
+        assert!(matches!(
+            component_report(&c, &st, &short, &times_list, 2),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        assert!(component_report(&c, &st, &mzroi, &times_list[..1], 2).is_err());
+        let narrow = c.slice(s![.., ..2]).to_owned();
+        assert!(component_report(&narrow, &st, &mzroi, &times_list, 2).is_err());
+
+        let report = component_report(&c, &st, &mzroi, &times_list, 2).unwrap();
+        let missing = temp_path("missing").join("report.csv");
+        assert!(matches!(
+            write_component_report(&report, &missing),
+            Err(RoiError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+use ndarray::prelude::*;
+use ndarray::Zip;
+
+use crate::error::RoiError;
+use crate::structs::data::SimplismaResult;
+
+/// SIMPLISMA (Windig & Guilment, 1991): find the `n_components` purest variables (columns)
+/// of `d`. `noise` is the offset added to the means, in percent of the largest mean.
+///
+/// The weight of a variable is the determinant of the correlation around the origin
+/// matrix of the variable and the purest ones already found; it is computed
+/// incrementally as the squared norm of the scaled variable left after Gram-Schmidt
+/// orthogonalisation against the purest ones, so each component costs one pass on `d`.
+///
+/// On `msroi` the purest ROIs give initial concentration profiles (`profiles` are the
+/// corresponding columns of `d`); on the transposed `msroi` the purest scans give initial
+/// spectra. Fails if fewer than `n_components` columns have a finite purity, e.g. when
+/// the others are all zero and `noise` is zero.
+pub fn simplisma(
+    d: &Array2<f64>,
+    n_components: usize,
+    noise: f64,
+) -> Result<SimplismaResult, RoiError> {
+    let (n_rows, n_vars) = d.dim();
+    if n_components > n_vars {
+        return Err(RoiError::InvalidArgument(format!(
+            "Can't find {} pure variables in {} columns",
+            n_components, n_vars
+        )));
+    }
+    if !noise.is_finite() || noise < 0.0 {
+        return Err(RoiError::InvalidArgument(format!(
+            "SIMPLISMA noise must be a non-negative percentage, got {}",
+            noise
+        )));
+    }
+
+    let mean = match d.mean_axis(Axis(0)) {
+        Some(mean) => mean,
+        None => {
+            return Err(RoiError::InvalidArgument(
+                "SIMPLISMA needs at least one row".to_string(),
+            ))
+        }
+    };
+    let std = d.std_axis(Axis(0), 0.0);
+    let offset = noise / 100.0 * mean.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    let initial_purity = &std / &mean.mapv(|m| m + offset);
+    let length = Zip::from(&std)
+        .and(&mean)
+        .map_collect(|s, m| (s * s + (m + offset) * (m + offset)).sqrt());
+
+    // Scaled variables, divided by sqrt(n) so that their Gram matrix is the correlation
+    // around the origin matrix
+    let scaled = d / &length.mapv(|l| l * (n_rows as f64).sqrt());
+
+    // Squared norm of each scaled variable orthogonal to the purest ones found so far
+    let mut residual_norms = scaled.mapv(|v| v * v).sum_axis(Axis(0));
+    let mut basis: Vec<Array1<f64>> = Vec::new();
+    let mut determinant = 1.0;
+
+    let mut purest: Vec<usize> = Vec::new();
+    let mut purity: Array2<f64> = Array2::zeros((n_components, n_vars));
+
+    for component in 0..n_components {
+        let weights = residual_norms.mapv(|r| determinant * r.max(0.0));
+        let current = &initial_purity * &weights;
+        let best = current
+            .iter()
+            .enumerate()
+            .filter(|(j, p)| !purest.contains(j) && p.is_finite())
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(j, _)| j)
+            .ok_or_else(|| {
+                RoiError::InvalidArgument(format!(
+                    "Only {} columns have a finite purity, increase the noise",
+                    component
+                ))
+            })?;
+        purity.row_mut(component).assign(&current);
+
+        let mut direction = scaled.column(best).to_owned();
+        for q in basis.iter() {
+            let projection = q.dot(&direction);
+            direction.scaled_add(-projection, q);
+        }
+        let norm = direction.dot(&direction).sqrt();
+        determinant *= residual_norms[best].max(0.0);
+        purest.push(best);
+
+        if norm > 0.0 {
+            direction /= norm;
+            let projections = scaled.t().dot(&direction);
+            residual_norms -= &projections.mapv(|p| p * p);
+            basis.push(direction);
+        }
+    }
+
+    let profiles = d.select(Axis(1), &purest);
+
+    Ok(SimplismaResult {
+        purest,
+        purity,
+        profiles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::bilinear;
+
+    #[test]
+    fn selective_variables_are_the_purest() {
+        // Columns 0 and 1 only hold the first and second compound, the others are mixtures
+        let c = bilinear(50, 1, 2, 31).1;
+        let mixing = arr2(&[[1.0, 0.0, 0.6, 0.5, 0.3], [0.0, 1.0, 0.4, 0.5, 0.7]]);
+        let d = c.dot(&mixing);
+
+        let result = simplisma(&d, 2, 5.0).unwrap();
+        let mut purest = result.purest.clone();
+        purest.sort_unstable();
+        assert_eq!(purest, vec![0, 1]);
+        assert_eq!(result.purity.dim(), (2, 5));
+        assert_eq!(result.profiles.column(0), d.column(result.purest[0]));
+    }
+
+    #[test]
+    fn matches_the_determinant_definition() {
+        // Second purity: p_j · det of the correlation around the origin matrix of
+        // variables (first, j)
+        let (d, _, _) = bilinear(30, 8, 3, 32);
+        let result = simplisma(&d, 2, 1.0).unwrap();
+
+        let n = d.nrows() as f64;
+        let mean = d.mean_axis(Axis(0)).unwrap();
+        let std = d.std_axis(Axis(0), 0.0);
+        let offset = 0.01 * mean.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let scaled = Array2::from_shape_fn(d.dim(), |(i, j)| {
+            d[[i, j]] / ((std[j].powi(2) + (mean[j] + offset).powi(2)).sqrt() * n.sqrt())
+        });
+        let first = result.purest[0];
+        for j in 0..d.ncols() {
+            let a = scaled.column(first).dot(&scaled.column(first));
+            let b = scaled.column(first).dot(&scaled.column(j));
+            let e = scaled.column(j).dot(&scaled.column(j));
+            let expected = std[j] / (mean[j] + offset) * (a * e - b * b);
+            assert!((result.purity[[1, j]] - expected).abs() < 1e-9 * (1.0 + expected.abs()));
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let (d, _, _) = bilinear(20, 4, 2, 33);
+        assert!(matches!(
+            simplisma(&d, 5, 5.0),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        assert!(simplisma(&d, 2, -1.0).is_err());
+        assert!(simplisma(&Array2::zeros((0, 4)), 1, 5.0).is_err());
+        assert!(simplisma(&Array2::zeros((10, 4)), 1, 0.0).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use ndarray::prelude::*;
+
+use crate::enums::{SmoothingMethod, SparseLayout};
+use crate::error::RoiError;
+use crate::structs::data::SparseMatrix;
+use crate::utils::solve_linear_system;
+
+/// Smooth every ROI chromatogram (column) of `msroi`, see `smooth_chromatogram`.
+pub fn smooth_msroi(
+    msroi: &Array2<f64>,
+    method: &SmoothingMethod,
+) -> Result<Array2<f64>, RoiError> {
+    check_method(method)?;
+    let mut smoothed: Array2<f64> = Array2::zeros(msroi.raw_dim());
+
+    for (i, chromatogram) in msroi.axis_iter(Axis(1)).enumerate() {
+        smoothed
+            .column_mut(i)
+            .assign(&smooth_values(chromatogram, method));
+    }
+    Ok(smoothed)
+}
+
+/// Same as `smooth_msroi` for a sparse `msroi`, one chromatogram at a time. The result
+/// has the layout of `msroi`.
+pub fn smooth_msroi_sparse(
+    msroi: &SparseMatrix,
+    method: &SmoothingMethod,
+) -> Result<SparseMatrix, RoiError> {
+    check_method(method)?;
+    let columns = msroi.to_layout(SparseLayout::Csc);
+    let mut rows = Vec::new();
+    let mut cols = Vec::new();
+    let mut values = Vec::new();
+
+    let mut chromatogram: Array1<f64> = Array1::zeros(msroi.nrows());
+    for j in 0..msroi.ncols() {
+        let (start, end) = (columns.indptr[j], columns.indptr[j + 1]);
+        chromatogram.fill(0.0);
+        for k in start..end {
+            chromatogram[columns.indices[k]] = columns.data[k];
+        }
+        for (i, &value) in smooth_values(chromatogram.view(), method)
+            .iter()
+            .enumerate()
+        {
+            if value != 0.0 {
+                rows.push(i);
+                cols.push(j);
+                values.push(value);
+            }
+        }
+    }
+    Ok(SparseMatrix::compress(
+        msroi.shape,
+        msroi.layout,
+        &rows,
+        &cols,
+        &values,
+    ))
+}
+
+/// Fill the missing scans (zero intensities) of every ROI chromatogram of `msroi` with
+/// the values `smooth_msroi` gives them, leaving the measured intensities unchanged.
+pub fn fill_msroi(msroi: &Array2<f64>, method: &SmoothingMethod) -> Result<Array2<f64>, RoiError> {
+    let mut filled = smooth_msroi(msroi, method)?;
+    filled.zip_mut_with(msroi, |filled, &measured| {
+        if measured != 0.0 {
+            *filled = measured;
+        }
+    });
+    Ok(filled)
+}
+
+/// Smooth a single ROI chromatogram.
+/// Zero intensities are scans in which the ROI had no peak: they are left out of the
+/// local fits, which are always evaluated on the original scan grid so that peaks are
+/// not shifted. Missing scans with measured neighbours on both sides within the window
+/// are filled by the smoother, the others stay at zero.
+pub fn smooth_chromatogram(
+    values: ArrayView1<f64>,
+    method: &SmoothingMethod,
+) -> Result<Array1<f64>, RoiError> {
+    check_method(method)?;
+    Ok(smooth_values(values, method))
+}
+
+fn check_method(method: &SmoothingMethod) -> Result<(), RoiError> {
+    let valid = match *method {
+        SmoothingMethod::SavitzkyGolay(window, order) => window % 2 == 1 && order < window,
+        SmoothingMethod::MovingAverage(window) => window > 0,
+        SmoothingMethod::Gaussian(sigma) => sigma.is_finite() && sigma > 0.0,
+    };
+    if valid {
+        return Ok(());
+    }
+    Err(RoiError::InvalidArgument(
+        match method {
+            SmoothingMethod::SavitzkyGolay(..) => {
+                "Savitzky-Golay window must be odd and larger than the polynomial order"
+            }
+            SmoothingMethod::MovingAverage(_) => "Moving average window must be at least one scan",
+            SmoothingMethod::Gaussian(_) => "Gaussian sigma must be positive",
+        }
+        .to_string(),
+    ))
+}
+
+/// `smooth_chromatogram` with a valid `method`.
+fn smooth_values(values: ArrayView1<f64>, method: &SmoothingMethod) -> Array1<f64> {
+    let n = values.len();
+    let half = method.half_window();
+    let observed: Vec<bool> = values.iter().map(|&v| v != 0.0).collect();
+    let mut smoothed: Array1<f64> = Array1::zeros(n);
+
+    for i in 0..n {
+        let start = i.saturating_sub(half);
+        let end = (i + half + 1).min(n);
+
+        let enclosed = observed[start..i].contains(&true) && observed[i + 1..end].contains(&true);
+        if !(observed[i] || enclosed) {
+            continue;
+        }
+
+        let points: Vec<(f64, f64)> = (start..end)
+            .filter(|&j| observed[j])
+            .map(|j| (j as f64 - i as f64, values[j]))
+            .collect();
+
+        smoothed[i] = match method {
+            SmoothingMethod::SavitzkyGolay(_, order) => local_polynomial(&points, *order),
+            SmoothingMethod::MovingAverage(_) => {
+                points.iter().map(|(_, y)| y).sum::<f64>() / points.len() as f64
+            }
+            SmoothingMethod::Gaussian(sigma) => {
+                let weights: Vec<f64> = points
+                    .iter()
+                    .map(|(x, _)| (-x * x / (2.0 * sigma * sigma)).exp())
+                    .collect();
+                points
+                    .iter()
+                    .zip(weights.iter())
+                    .map(|((_, y), w)| y * w)
+                    .sum::<f64>()
+                    / weights.iter().sum::<f64>()
+            }
+        };
+    }
+    smoothed
+}
+
+/// Value in zero of the least squares polynomial through `points`, given as
+/// (offset from the smoothed scan, intensity). The degree is lowered when the window
+/// holds too few measured scans.
+fn local_polynomial(points: &[(f64, f64)], order: usize) -> f64 {
+    let degree = order.min(points.len() - 1);
+    let mut ata = vec![vec![0.0; degree + 1]; degree + 1];
+    let mut atb = vec![0.0; degree + 1];
+
+    for &(x, y) in points {
+        let powers: Vec<f64> = (0..=2 * degree).map(|p| x.powi(p as i32)).collect();
+        for p in 0..=degree {
+            for q in 0..=degree {
+                ata[p][q] += powers[p + q];
+            }
+            atb[p] += powers[p] * y;
+        }
+    }
+
+    match solve_linear_system(ata, atb) {
+        Some(coefficients) => coefficients[0],
+        None => points.iter().map(|(_, y)| y).sum::<f64>() / points.len() as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::gaussian;
+
+    const METHODS: [SmoothingMethod; 3] = [
+        SmoothingMethod::SavitzkyGolay(7, 2),
+        SmoothingMethod::MovingAverage(5),
+        SmoothingMethod::Gaussian(1.5),
+    ];
+
+    #[test]
+    fn constant_chromatograms_are_unchanged() {
+        let values = Array1::from_elem(30, 4.0);
+        for method in METHODS.iter() {
+            let smoothed = smooth_chromatogram(values.view(), method).unwrap();
+            assert!(smoothed.iter().all(|&v| (v - 4.0).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn savitzky_golay_keeps_polynomials_of_its_order() {
+        let values = Array1::from_shape_fn(25, |i| 2.0 + 0.5 * i as f64 + 0.1 * (i * i) as f64);
+        let smoothed =
+            smooth_chromatogram(values.view(), &SmoothingMethod::SavitzkyGolay(9, 2)).unwrap();
+        for (a, b) in smoothed.iter().zip(values.iter()) {
+            assert!((a - b).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn only_enclosed_gaps_are_filled() {
+        let mut values = Array1::zeros(20);
+        for i in 5..15 {
+            values[i] = 10.0;
+        }
+        values[9] = 0.0;
+        for method in METHODS.iter() {
+            let smoothed = smooth_chromatogram(values.view(), method).unwrap();
+            assert!((smoothed[9] - 10.0).abs() < 1e-9);
+            assert_eq!(smoothed[0], 0.0);
+            assert_eq!(smoothed[19], 0.0);
+            assert!(smoothed.iter().all(|&v| v >= 0.0));
+        }
+    }
+
+    #[test]
+    fn msroi_is_smoothed_column_by_column() {
+        let msroi = Array2::from_shape_fn((40, 3), |(i, j)| {
+            100.0 * gaussian(i as f64, 10.0 * (j + 1) as f64, 3.0)
+        });
+        let smoothed = smooth_msroi(&msroi, &METHODS[0]).unwrap();
+        for j in 0..3 {
+            let column = smooth_chromatogram(msroi.column(j), &METHODS[0]).unwrap();
+            assert_eq!(smoothed.column(j), column);
+        }
+    }
+
+    #[test]
+    fn filling_keeps_the_measured_intensities() {
+        let mut msroi = Array2::from_shape_fn((30, 2), |(i, j)| {
+            100.0 * gaussian(i as f64, 10.0 * (j + 1) as f64, 3.0) + 1.0
+        });
+        msroi[[9, 0]] = 0.0;
+        msroi[[0, 1]] = 0.0;
+        for method in METHODS.iter() {
+            let filled = fill_msroi(&msroi, method).unwrap();
+            let smoothed = smooth_msroi(&msroi, method).unwrap();
+            // The enclosed gap is filled, the leading one isn't
+            assert_eq!(filled[[9, 0]], smoothed[[9, 0]]);
+            assert!(filled[[9, 0]] > 0.0);
+            assert_eq!(filled[[0, 1]], 0.0);
+            for (index, &measured) in msroi.indexed_iter() {
+                if index != (9, 0) {
+                    assert_eq!(filled[index], measured);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_msroi_matches_dense() {
+        // ROIs with gaps, as built by compute_roi, so that the sparse matrix has holes
+        let msroi = Array2::from_shape_fn((40, 4), |(i, j)| {
+            let value = 100.0 * gaussian(i as f64, 8.0 * (j + 1) as f64, 3.0);
+            if value < 1.0 || (i + j) % 7 == 0 {
+                0.0
+            } else {
+                value
+            }
+        });
+        for layout in [SparseLayout::Csr, SparseLayout::Csc] {
+            let sparse = SparseMatrix::from_dense(&msroi, layout);
+            for method in METHODS.iter() {
+                let dense = smooth_msroi(&msroi, method).unwrap();
+                let smoothed = smooth_msroi_sparse(&sparse, method).unwrap();
+                assert_eq!(smoothed.layout, layout);
+                assert!(smoothed
+                    .to_dense()
+                    .iter()
+                    .zip(dense.iter())
+                    .all(|(a, b)| (a - b).abs() < 1e-9));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let values = Array1::from_elem(10, 1.0);
+        for method in [
+            SmoothingMethod::SavitzkyGolay(6, 2),
+            SmoothingMethod::SavitzkyGolay(5, 5),
+            SmoothingMethod::MovingAverage(0),
+            SmoothingMethod::Gaussian(0.0),
+            SmoothingMethod::Gaussian(f64::NAN),
+        ] {
+            assert!(matches!(
+                smooth_chromatogram(values.view(), &method),
+                Err(RoiError::InvalidArgument(_))
+            ));
+            assert!(smooth_msroi(&Array2::zeros((5, 2)), &method).is_err());
+            let sparse = SparseMatrix::from_dense(&Array2::zeros((5, 2)), SparseLayout::Csr);
+            assert!(smooth_msroi_sparse(&sparse, &method).is_err());
+        }
+    }
+}
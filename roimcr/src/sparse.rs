@@ -0,0 +1,303 @@
+use ndarray::prelude::*;
+
+use crate::enums::SparseLayout;
+use crate::error::RoiError;
+use crate::structs::data::SparseMatrix;
+
+impl SparseMatrix {
+    /// Sparse matrix from its compressed arrays, e.g. those of a `scipy.sparse` matrix.
+    /// Indices may be unsorted and repeated: duplicates are summed.
+    pub fn new(
+        shape: (usize, usize),
+        layout: SparseLayout,
+        indptr: Vec<usize>,
+        indices: Vec<usize>,
+        data: Vec<f64>,
+    ) -> Result<Self, RoiError> {
+        let (major, minor) = match layout {
+            SparseLayout::Csr => shape,
+            SparseLayout::Csc => (shape.1, shape.0),
+        };
+        if indptr.len() != major + 1
+            || indptr[0] != 0
+            || indptr.windows(2).any(|w| w[0] > w[1])
+            || indptr[major] != indices.len()
+            || indices.len() != data.len()
+        {
+            return Err(RoiError::InvalidArgument(
+                "Inconsistent indptr, indices and data arrays of the sparse matrix".to_string(),
+            ));
+        }
+        if indices.iter().any(|&index| index >= minor) {
+            return Err(RoiError::InvalidArgument(format!(
+                "Sparse matrix indices out of range, they must be below {}",
+                minor
+            )));
+        }
+
+        let matrix = SparseMatrix {
+            shape,
+            layout,
+            indptr,
+            indices,
+            data,
+        };
+        let (rows, cols, values) = matrix.triplets();
+        Ok(SparseMatrix::compress(shape, layout, &rows, &cols, &values))
+    }
+
+    /// Sparse matrix holding `values[k]` at (`rows[k]`, `cols[k]`), summing duplicates.
+    pub fn from_triplets(
+        shape: (usize, usize),
+        layout: SparseLayout,
+        rows: &[usize],
+        cols: &[usize],
+        values: &[f64],
+    ) -> Result<Self, RoiError> {
+        if rows.len() != values.len() || cols.len() != values.len() {
+            return Err(RoiError::InvalidArgument(format!(
+                "{} rows, {} columns and {} values given for a sparse matrix",
+                rows.len(),
+                cols.len(),
+                values.len()
+            )));
+        }
+        if rows.iter().any(|&row| row >= shape.0) || cols.iter().any(|&col| col >= shape.1) {
+            return Err(RoiError::InvalidArgument(format!(
+                "Sparse matrix indices out of range of its shape {:?}",
+                shape
+            )));
+        }
+        Ok(SparseMatrix::compress(shape, layout, rows, cols, values))
+    }
+
+    /// Same as `from_triplets`, for triplets of the same length within `shape`.
+    pub(crate) fn compress(
+        shape: (usize, usize),
+        layout: SparseLayout,
+        rows: &[usize],
+        cols: &[usize],
+        values: &[f64],
+    ) -> Self {
+        let (majors, minors, n_major) = match layout {
+            SparseLayout::Csr => (rows, cols, shape.0),
+            SparseLayout::Csc => (cols, rows, shape.1),
+        };
+
+        // Counting sort on the major index
+        let mut indptr = vec![0; n_major + 1];
+        for &major in majors.iter() {
+            indptr[major + 1] += 1;
+        }
+        for i in 0..n_major {
+            indptr[i + 1] += indptr[i];
+        }
+        let mut next = indptr.clone();
+        let mut entries = vec![(0, 0.0); values.len()];
+        for k in 0..values.len() {
+            entries[next[majors[k]]] = (minors[k], values[k]);
+            next[majors[k]] += 1;
+        }
+
+        // Sort every line and sum the duplicates
+        let mut compressed_indptr = vec![0; n_major + 1];
+        let mut indices = Vec::with_capacity(entries.len());
+        let mut data = Vec::with_capacity(entries.len());
+        for i in 0..n_major {
+            let line = &mut entries[indptr[i]..indptr[i + 1]];
+            line.sort_unstable_by_key(|&(minor, _)| minor);
+            let start = indices.len();
+            for &(minor, value) in line.iter() {
+                if indices.len() > start && indices[indices.len() - 1] == minor {
+                    *data.last_mut().unwrap() += value;
+                } else {
+                    indices.push(minor);
+                    data.push(value);
+                }
+            }
+            compressed_indptr[i + 1] = indices.len();
+        }
+
+        SparseMatrix {
+            shape,
+            layout,
+            indptr: compressed_indptr,
+            indices,
+            data,
+        }
+    }
+
+    /// Sparse copy of the non-zero values of `dense`.
+    pub fn from_dense(dense: &Array2<f64>, layout: SparseLayout) -> Self {
+        let ((rows, cols), values): ((Vec<usize>, Vec<usize>), Vec<f64>) = dense
+            .indexed_iter()
+            .filter(|(_, &value)| value != 0.0)
+            .map(|((row, col), &value)| ((row, col), value))
+            .unzip();
+        SparseMatrix::compress(dense.dim(), layout, &rows, &cols, &values)
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.shape.0
+    }
+
+    pub fn ncols(&self) -> usize {
+        self.shape.1
+    }
+
+    /// Number of stored values.
+    pub fn nnz(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Rows, columns and values of the stored entries.
+    pub fn triplets(&self) -> (Vec<usize>, Vec<usize>, Vec<f64>) {
+        let mut majors = Vec::with_capacity(self.nnz());
+        for i in 0..self.indptr.len() - 1 {
+            majors.extend(std::iter::repeat_n(i, self.indptr[i + 1] - self.indptr[i]));
+        }
+        let minors = self.indices.clone();
+        match self.layout {
+            SparseLayout::Csr => (majors, minors, self.data.clone()),
+            SparseLayout::Csc => (minors, majors, self.data.clone()),
+        }
+    }
+
+    /// Same matrix with the other layout, or a copy if it already has `layout`.
+    pub fn to_layout(&self, layout: SparseLayout) -> SparseMatrix {
+        if layout == self.layout {
+            return self.clone();
+        }
+        let (rows, cols, values) = self.triplets();
+        SparseMatrix::compress(self.shape, layout, &rows, &cols, &values)
+    }
+
+    pub fn to_dense(&self) -> Array2<f64> {
+        let mut dense = Array2::zeros(self.shape);
+        self.for_each(|row, col, value| dense[[row, col]] += value);
+        dense
+    }
+
+    /// Product `self · b` with a dense matrix.
+    pub fn dot(&self, b: &Array2<f64>) -> Array2<f64> {
+        if b.nrows() != self.ncols() {
+            panic!("Shapes of the sparse and dense matrices don't match");
+        }
+        let mut product = Array2::zeros((self.nrows(), b.ncols()));
+        self.for_each(|row, col, value| {
+            product.row_mut(row).scaled_add(value, &b.row(col));
+        });
+        product
+    }
+
+    /// Product `self' · b` with a dense matrix.
+    pub fn t_dot(&self, b: &Array2<f64>) -> Array2<f64> {
+        if b.nrows() != self.nrows() {
+            panic!("Shapes of the sparse and dense matrices don't match");
+        }
+        let mut product = Array2::zeros((self.ncols(), b.ncols()));
+        self.for_each(|row, col, value| {
+            product.row_mut(col).scaled_add(value, &b.row(row));
+        });
+        product
+    }
+
+    /// Sum of the squares of the values.
+    pub fn sum_squares(&self) -> f64 {
+        self.data.iter().map(|x| x * x).sum()
+    }
+
+    fn for_each<F: FnMut(usize, usize, f64)>(&self, mut f: F) {
+        for i in 0..self.indptr.len() - 1 {
+            for k in self.indptr[i]..self.indptr[i + 1] {
+                match self.layout {
+                    SparseLayout::Csr => f(i, self.indices[k], self.data[k]),
+                    SparseLayout::Csc => f(self.indices[k], i, self.data[k]),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::Random;
+
+    fn dense() -> Array2<f64> {
+        array![
+            [1.0, 0.0, 2.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0],
+            [3.0, 4.0, 0.0, 5.0]
+        ]
+    }
+
+    #[test]
+    fn compressed_arrays_are_sorted_and_summed() {
+        // Row 2 has its columns unsorted and column 3 twice
+        let matrix = SparseMatrix::new(
+            (3, 4),
+            SparseLayout::Csr,
+            vec![0, 2, 2, 6],
+            vec![2, 0, 3, 1, 0, 3],
+            vec![2.0, 1.0, 2.0, 4.0, 3.0, 3.0],
+        )
+        .unwrap();
+        assert_eq!(matrix.indices, vec![0, 2, 0, 1, 3]);
+        assert_eq!(matrix.nnz(), 5);
+        assert_eq!(matrix.to_dense(), dense());
+
+        let transposed = SparseMatrix::new(
+            (3, 4),
+            SparseLayout::Csc,
+            matrix.to_layout(SparseLayout::Csc).indptr,
+            matrix.to_layout(SparseLayout::Csc).indices,
+            matrix.to_layout(SparseLayout::Csc).data,
+        )
+        .unwrap();
+        assert_eq!(transposed.to_dense(), dense());
+    }
+
+    #[test]
+    fn products_match_dense() {
+        let mut random = Random::new(49);
+        let b = Array2::from_shape_fn((4, 2), |_| random.uniform());
+        let c = Array2::from_shape_fn((3, 2), |_| random.uniform());
+        for layout in [SparseLayout::Csr, SparseLayout::Csc] {
+            let matrix = SparseMatrix::from_dense(&dense(), layout);
+            let close = |a: Array2<f64>, b: Array2<f64>| {
+                a.dim() == b.dim() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-12)
+            };
+            assert!(close(matrix.dot(&b), dense().dot(&b)));
+            assert!(close(matrix.t_dot(&c), dense().t().dot(&c)));
+            assert_eq!(matrix.sum_squares(), 55.0);
+        }
+    }
+
+    #[test]
+    fn inconsistent_arrays_are_rejected() {
+        let new = |indptr: Vec<usize>, indices: Vec<usize>, data: Vec<f64>| {
+            SparseMatrix::new((2, 3), SparseLayout::Csr, indptr, indices, data)
+        };
+        assert!(new(vec![0, 1, 2], vec![0, 2], vec![1.0, 2.0]).is_ok());
+        // indptr of the wrong length, not starting at zero, decreasing or too short
+        assert!(new(vec![0, 2], vec![0, 2], vec![1.0, 2.0]).is_err());
+        assert!(new(vec![1, 1, 2], vec![0, 2], vec![1.0, 2.0]).is_err());
+        assert!(new(vec![0, 2, 1], vec![0, 2], vec![1.0, 2.0]).is_err());
+        assert!(new(vec![0, 1, 1], vec![0, 2], vec![1.0, 2.0]).is_err());
+        // Fewer values than indices, and a column out of range
+        assert!(new(vec![0, 1, 2], vec![0, 2], vec![1.0]).is_err());
+        assert!(matches!(
+            new(vec![0, 1, 2], vec![0, 3], vec![1.0, 2.0]),
+            Err(RoiError::InvalidArgument(_))
+        ));
+
+        let triplets = |rows: &[usize], cols: &[usize]| {
+            SparseMatrix::from_triplets((2, 3), SparseLayout::Csc, rows, cols, &[1.0, 2.0])
+        };
+        assert!(triplets(&[0, 1], &[2, 2]).is_ok());
+        assert!(triplets(&[0, 2], &[2, 2]).is_err());
+        assert!(triplets(&[0, 1], &[3, 2]).is_err());
+        assert!(triplets(&[0], &[2]).is_err());
+    }
+}
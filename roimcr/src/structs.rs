@@ -1,9 +1,11 @@
 pub mod options {
     use crate::enums::*;
-    // pub struct Settings {
-    //     roi_params: RoiParams,
-    // }
+    use ndarray::Array2;
+    use serde::{Deserialize, Serialize};
+    use std::path::PathBuf;
 
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields, default)]
     pub struct RoiParams {
         pub threshold: f64,
         pub t_factor: f64,
@@ -11,11 +13,145 @@ pub mod options {
         pub mzroi_updater: MzRoiUpdater,
         pub min_occ: u32,
     }
+
+    impl Default for RoiParams {
+        fn default() -> Self {
+            RoiParams {
+                threshold: 1000.0,
+                t_factor: 1.0,
+                mz_error: MzErrorType::Dalton(0.5),
+                mzroi_updater: MzRoiUpdater::Mean,
+                min_occ: 1,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields, default)]
+    pub struct McrParams {
+        pub max_iter: usize,
+        pub tol: f64,
+    }
+
+    impl Default for McrParams {
+        fn default() -> Self {
+            McrParams {
+                max_iter: 50,
+                tol: 1e-3,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields, default)]
+    pub struct AugmentedParams {
+        pub presence: Option<Array2<bool>>,
+        pub trilinearity: bool,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct WeightParams {
+        pub noise: f64,
+        pub proportional: f64,
+        pub missing: f64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct PeakParams {
+        pub method: PeakDetectionMethod,
+        pub min_snr: f64,
+    }
+
+    /// Layout of the delimited text files written by the `export` functions: field
+    /// delimiter, number of decimals (shortest exact representation if `None`) and
+    /// transposition of the tables.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields, default)]
+    pub struct ExportParams {
+        pub delimiter: char,
+        pub precision: Option<usize>,
+        pub transpose: bool,
+    }
+
+    impl Default for ExportParams {
+        fn default() -> Self {
+            ExportParams {
+                delimiter: ',',
+                precision: None,
+                transpose: false,
+            }
+        }
+    }
+
+    /// Encoding of the peaks written by `write_run`: zlib compression and 64-bit instead
+    /// of 32-bit floats.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields, default)]
+    pub struct WriteParams {
+        pub zlib: bool,
+        pub double_precision: bool,
+    }
+
+    /// Serialisable description of the usual MCR constraints, see
+    /// `McrConstraints::from_params`.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields, default)]
+    pub struct ConstraintParams {
+        pub non_negativity: Option<ConstraintTarget>,
+        pub nnls: bool,
+        pub unimodality: Option<f64>,
+        pub closure: Option<f64>,
+        pub normalisation: Option<NormalisationType>,
+    }
+
+    /// A whole processing: the input runs, their ROIs, the optional filling of the missing
+    /// scans of the ROI chromatograms and the optional MCR-ALS resolution of `msroi`.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ProcessingConfig {
+        /// Threads used to build the ROIs (all available cores if 0), serial if `None`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub threads: Option<usize>,
+        pub input: InputConfig,
+        #[serde(default)]
+        pub roi: RoiParams,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub filling: Option<SmoothingMethod>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub mcr: Option<McrConfig>,
+    }
+
+    /// Input runs of a processing, augmented row-wise in the order given. The format of
+    /// each file is detected from its extension unless `format` is set.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct InputConfig {
+        pub files: Vec<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub format: Option<InputFormat>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct McrConfig {
+        pub n_components: usize,
+        #[serde(default)]
+        pub initial: InitialMethod,
+        #[serde(default)]
+        pub params: McrParams,
+        #[serde(default)]
+        pub constraints: ConstraintParams,
+    }
 }
 
 pub mod io {
+    use crate::enums::Polarity;
+    use crate::structs::data::RunSummary;
     use ndarray::prelude::*;
     use serde::Deserialize;
+    use std::collections::BTreeMap;
 
     #[derive(Debug, Deserialize, PartialEq)]
     pub struct Peak {
@@ -32,6 +168,8 @@ pub mod io {
         pub peaks_count: u64,
         #[serde(rename = "retentionTime", with = "read_rt")]
         pub retention_time: f64,
+        #[serde(default, deserialize_with = "read_polarity::deserialize")]
+        pub polarity: Option<Polarity>,
         #[serde(with = "read_peaks")]
         pub peaks: Vec<Peak>,
     }
@@ -85,6 +223,64 @@ pub mod io {
             Array::from_vec(result)
         }
 
+        /// Overview of the run: scans per MS level, ranges of retention times and m/z,
+        /// peaks per scan and polarities over all the scans, and the largest total ion
+        /// current of the MS1 scans with its retention time.
+        pub fn summary(&self) -> RunSummary {
+            let mut scans_per_level: BTreeMap<u8, usize> = BTreeMap::new();
+            for scan in &self.scans {
+                *scans_per_level.entry(scan.ms_level).or_insert(0) += 1;
+            }
+
+            let range = |values: &mut dyn Iterator<Item = f64>| {
+                values.fold(None, |range: Option<(f64, f64)>, value| match range {
+                    Some((low, high)) => Some((low.min(value), high.max(value))),
+                    None => Some((value, value)),
+                })
+            };
+            let rt_range = range(&mut self.scans.iter().map(|scan| scan.retention_time));
+            let mz_range = range(
+                &mut self
+                    .scans
+                    .iter()
+                    .flat_map(|scan| scan.peaks.iter().map(|peak| peak.mz)),
+            );
+
+            let peaks: Vec<usize> = self.scans.iter().map(|scan| scan.peaks.len()).collect();
+            let tic_max = self
+                .scans
+                .iter()
+                .filter(|scan| scan.ms_level == 1)
+                .map(|scan| {
+                    let tic: f64 = scan.peaks.iter().map(|peak| peak.intensity).sum();
+                    (tic, scan.retention_time)
+                })
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut polarities: Vec<Polarity> =
+                self.scans.iter().filter_map(|scan| scan.polarity).collect();
+            polarities.sort();
+            polarities.dedup();
+
+            RunSummary {
+                name: self.name.clone(),
+                n_scans: self.scans.len(),
+                scans_per_level,
+                rt_range,
+                mz_range,
+                min_peaks: peaks.iter().cloned().min().unwrap_or(0),
+                mean_peaks: if peaks.is_empty() {
+                    0.0
+                } else {
+                    peaks.iter().sum::<usize>() as f64 / peaks.len() as f64
+                },
+                max_peaks: peaks.iter().cloned().max().unwrap_or(0),
+                tic_max: tic_max.map(|(tic, _)| tic),
+                tic_max_rt: tic_max.map(|(_, rt)| rt),
+                polarities,
+            }
+        }
+
         pub fn get_tic(&self) -> Array1<f64> {
             let mut result: Vec<f64> = Vec::new();
 
@@ -115,59 +311,306 @@ pub mod io {
         where
             D: Deserializer<'de>,
         {
-            let input = String::deserialize(deserializer).expect("Can't deserialize RT");
+            let input = String::deserialize(deserializer)?;
             let s = input.trim_start_matches('P');
             let s = s.trim_start_matches('T');
             let s = s.trim_end_matches('S');
 
-            let result: f64 = s.parse().expect("Couldn't convert RT {s} to f32");
-            Ok(result)
+            s.parse().map_err(|_| {
+                serde::de::Error::custom(format!("Couldn't convert RT {} to f64", input))
+            })
+        }
+    }
+
+    mod read_polarity {
+        use crate::enums::Polarity;
+        use serde::{self, Deserialize, Deserializer};
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Polarity>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let input = Option::<String>::deserialize(deserializer)?;
+            Ok(input.as_deref().and_then(Polarity::from_name))
         }
     }
 
     mod read_peaks {
+        use crate::binary::{decode, to_floats, ByteOrder};
         use crate::structs::io::Peak;
         use serde::{self, Deserialize, Deserializer};
 
+        /// The `peaks` element, whose pairs of m/z and intensity are stored with 32 or 64
+        /// bits in network byte order, optionally zlib compressed.
+        #[derive(Deserialize)]
+        struct RawPeaks {
+            #[serde(default)]
+            precision: Option<String>,
+            #[serde(rename = "compressionType", default)]
+            compression_type: Option<String>,
+            #[serde(rename = "$value", default)]
+            data: String,
+        }
+
         pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Peak>, D::Error>
         where
             D: Deserializer<'de>,
         {
-            let s = String::deserialize(deserializer)?;
-            let mut buffer = [0u8; 4];
-            let mut peaks = Vec::<Peak>::new();
-
-            let decoded = base64::decode(s).expect("couldn't decode");
-
-            for peak in 0..(decoded.len() / 8) {
-                let mut new_peak = Peak {
-                    mz: 0.0,
-                    intensity: 0.0,
-                };
-                for i in 0..2 {
-                    let start: usize = (peak * 8) + i * 4;
-                    let finish: usize = (peak * 8) + 4 + (i * 4);
-                    buffer.clone_from_slice(&decoded[start..finish]);
-                    match i {
-                        0 => {
-                            new_peak.mz = f32::from_be_bytes(buffer) as f64;
-                        }
-                        1 => {
-                            new_peak.intensity = f32::from_be_bytes(buffer) as f64;
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-                peaks.push(new_peak);
+            let raw = RawPeaks::deserialize(deserializer)?;
+            let double = raw.precision.as_deref() == Some("64");
+            let zlib = raw.compression_type.as_deref() == Some("zlib");
+
+            let values = decode(&raw.data, zlib)
+                .and_then(|bytes| to_floats(&bytes, double, ByteOrder::BigEndian))
+                .map_err(serde::de::Error::custom)?;
+            if values.len() % 2 != 0 {
+                return Err(serde::de::Error::custom(format!(
+                    "The peaks of a scan hold an odd number of values, {}",
+                    values.len()
+                )));
             }
 
-            Ok(peaks)
+            Ok(values
+                .chunks_exact(2)
+                .map(|pair| Peak {
+                    mz: pair[0],
+                    intensity: pair[1],
+                })
+                .collect())
         }
     }
 }
 
 pub mod data {
-    use crate::enums::MzRoiUpdater;
+    use crate::enums::{InputFormat, MzRoiUpdater, Polarity, SparseLayout};
+    use crate::structs::options::RoiParams;
+    use ndarray::prelude::*;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    #[derive(Debug)]
+    pub struct RoiResult {
+        pub mzroi: Array1<f64>,
+        pub msroi: Array2<f64>,
+        pub times: Array1<f64>,
+        pub runs: Array1<usize>,
+        pub roicell: Roicell,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct McrResult {
+        pub c: Array2<f64>,
+        pub st: Array2<f64>,
+        pub residuals: Array2<f64>,
+        pub lof: f64,
+        pub r2: f64,
+        pub lof_history: Vec<f64>,
+        pub n_iter: usize,
+        pub converged: bool,
+    }
+
+    /// Same as `RoiResult`, with `msroi` as a sparse matrix.
+    #[derive(Debug)]
+    pub struct SparseRoiResult {
+        pub mzroi: Array1<f64>,
+        pub msroi: SparseMatrix,
+        pub times: Array1<f64>,
+        pub runs: Array1<usize>,
+        pub roicell: Roicell,
+    }
+
+    /// Compressed sparse matrix, laid out as in `scipy.sparse`: the values of major line
+    /// `i` (a row for `Csr`, a column for `Csc`) are `data[indptr[i]..indptr[i + 1]]`, at
+    /// the minor positions given by `indices`. Matrices built by the `sparse` module have
+    /// sorted indices and no duplicates.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct SparseMatrix {
+        pub shape: (usize, usize),
+        pub layout: SparseLayout,
+        pub indptr: Vec<usize>,
+        pub indices: Vec<usize>,
+        pub data: Vec<f64>,
+    }
+
+    /// The ROIs of one or more runs with the retention time of every row of `msroi` and
+    /// the number of rows of each run, as stored in MAT and NPZ files.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct RoiMatrices {
+        pub mzroi: Array1<f64>,
+        pub msroi: Array2<f64>,
+        pub times: Array1<f64>,
+        pub run_sizes: Vec<usize>,
+    }
+
+    /// Summary of one ROI of `msroi`, see `export::roi_statistics`. The apex is `None`
+    /// when the ROI has no peaks.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct RoiStatistics {
+        pub mz: f64,
+        pub n_scans: usize,
+        pub n_runs: usize,
+        pub apex_time: Option<f64>,
+        pub apex_run: Option<usize>,
+        pub max_intensity: Option<f64>,
+        pub total_intensity: f64,
+    }
+
+    /// Everything about one analysis, as stored in project files: the input files and
+    /// their SHA-256 hashes, the parameters of the ROIs, the ROIs, their statistics and
+    /// optionally the MCR-ALS resolution of `msroi`.
+    #[derive(Debug, Clone)]
+    pub struct Project {
+        pub files: Vec<PathBuf>,
+        pub hashes: Vec<String>,
+        pub roi_params: RoiParams,
+        pub rois: RoiMatrices,
+        pub statistics: Vec<RoiStatistics>,
+        pub mcr: Option<McrResult>,
+    }
+
+    /// Outcome of `config::run_config`: the ROIs of the input runs, the retention times of
+    /// the rows of `msroi`, the number of scans of every run and the MCR-ALS resolution
+    /// when the configuration asks for one.
+    #[derive(Debug, Clone)]
+    pub struct ProcessingResult {
+        pub mzroi: Array1<f64>,
+        pub msroi: Array2<f64>,
+        pub times: Array1<f64>,
+        pub run_sizes: Vec<usize>,
+        pub mcr: Option<McrResult>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct AugmentedMcrResult {
+        pub mcr: McrResult,
+        pub run_sizes: Vec<usize>,
+        pub areas: Array2<f64>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct McrFiguresOfMerit {
+        pub lof: f64,
+        pub r2: f64,
+        pub r2_components: Array1<f64>,
+        pub contributions: Array1<f64>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct McrBandsResult {
+        pub scf_max: Array1<f64>,
+        pub scf_min: Array1<f64>,
+        pub c_max: Array2<f64>,
+        pub c_min: Array2<f64>,
+        pub st_max: Array2<f64>,
+        pub st_min: Array2<f64>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct SimplismaResult {
+        pub purest: Vec<usize>,
+        pub purity: Array2<f64>,
+        pub profiles: Array2<f64>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct EfaResult {
+        pub forward: Array2<f64>,
+        pub backward: Array2<f64>,
+        pub noise: f64,
+        pub appearance: Vec<Option<usize>>,
+        pub disappearance: Vec<Option<usize>>,
+        pub c: Array2<f64>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct MovingWindowEfaResult {
+        pub window: usize,
+        pub singular_values: Array2<f64>,
+        pub threshold: f64,
+        pub local_rank: Array1<usize>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct RankResult {
+        pub singular_values: Array1<f64>,
+        pub explained_variance: Array1<f64>,
+        pub cumulative_variance: Array1<f64>,
+        pub ind: Array1<f64>,
+        pub broken_stick: Array1<f64>,
+        pub press: Array1<f64>,
+        pub ind_rank: usize,
+        pub broken_stick_rank: usize,
+        pub press_rank: usize,
+        pub suggested: usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ComponentRoi {
+        pub roi: usize,
+        pub mz: f64,
+        pub loading: f64,
+        pub selectivity: f64,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ComponentSummary {
+        pub component: usize,
+        pub apex_times: Vec<f64>,
+        pub rois: Vec<ComponentRoi>,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ChromPeak {
+        pub roi: usize,
+        pub apex: usize,
+        pub start: usize,
+        pub end: usize,
+        pub apex_time: f64,
+        pub height: f64,
+        pub area: f64,
+        pub snr: f64,
+    }
+
+    /// Outcome of one file of a batch: its format and number of MS1 scans once loaded, or
+    /// why it failed.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct BatchEntry {
+        pub path: PathBuf,
+        pub format: Option<InputFormat>,
+        pub ms1_scans: Option<usize>,
+        pub error: Option<String>,
+    }
+
+    impl BatchEntry {
+        pub fn loaded(&self) -> bool {
+            self.error.is_none()
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct BatchReport {
+        pub loaded: usize,
+        pub failed: usize,
+        pub files: Vec<BatchEntry>,
+    }
+
+    /// Overview of a run, see `MsRun::summary`. Ranges are `(min, max)` and are `None`
+    /// for a run without scans or peaks.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct RunSummary {
+        pub name: String,
+        pub n_scans: usize,
+        pub scans_per_level: BTreeMap<u8, usize>,
+        pub rt_range: Option<(f64, f64)>,
+        pub mz_range: Option<(f64, f64)>,
+        pub min_peaks: usize,
+        pub mean_peaks: f64,
+        pub max_peaks: usize,
+        pub tic_max: Option<f64>,
+        pub tic_max_rt: Option<f64>,
+        pub polarities: Vec<Polarity>,
+    }
 
     #[derive(Default, Debug)]
     pub struct Roicell {
@@ -244,6 +687,14 @@ pub mod data {
             self.remove_roi(roi_keep);
         }
 
+        pub fn append(&mut self, other: &mut Roicell) {
+            self.mzs.append(&mut other.mzs);
+            self.times.append(&mut other.times);
+            self.intensities.append(&mut other.intensities);
+            self.scans.append(&mut other.scans);
+            self.mz_roi.append(&mut other.mz_roi);
+        }
+
         pub fn remove_roi(&mut self, roi: usize) {
             self.mzs.remove(roi);
             self.times.remove(roi);
@@ -253,3 +704,77 @@ pub mod data {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::io::{MsRun, Peak, Scan};
+    use crate::enums::Polarity;
+
+    fn scan(num: u64, ms_level: u8, time: f64, polarity: Polarity, peaks: &[(f64, f64)]) -> Scan {
+        Scan {
+            num,
+            ms_level,
+            peaks_count: peaks.len() as u64,
+            retention_time: time,
+            polarity: Some(polarity),
+            peaks: peaks
+                .iter()
+                .map(|&(mz, intensity)| Peak { mz, intensity })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn summary_covers_all_scans() {
+        let run = MsRun {
+            name: "run".to_string(),
+            scans: vec![
+                scan(
+                    1,
+                    1,
+                    10.0,
+                    Polarity::Positive,
+                    &[(100.0, 5.0), (200.0, 5.0)],
+                ),
+                scan(2, 2, 11.0, Polarity::Positive, &[(50.0, 1000.0)]),
+                scan(
+                    3,
+                    1,
+                    12.0,
+                    Polarity::Negative,
+                    &[(150.0, 20.0), (300.0, 1.0), (400.0, 1.0)],
+                ),
+                scan(4, 1, 13.0, Polarity::Positive, &[]),
+            ],
+        };
+        let summary = run.summary();
+
+        assert_eq!(summary.name, "run");
+        assert_eq!(summary.n_scans, 4);
+        assert_eq!(summary.scans_per_level.get(&1), Some(&3));
+        assert_eq!(summary.scans_per_level.get(&2), Some(&1));
+        assert_eq!(summary.rt_range, Some((10.0, 13.0)));
+        assert_eq!(summary.mz_range, Some((50.0, 400.0)));
+        assert_eq!((summary.min_peaks, summary.max_peaks), (0, 3));
+        assert_eq!(summary.mean_peaks, 1.5);
+        // The MS2 scan has the largest TIC but isn't counted
+        assert_eq!(summary.tic_max, Some(22.0));
+        assert_eq!(summary.tic_max_rt, Some(12.0));
+        assert_eq!(
+            summary.polarities,
+            vec![Polarity::Positive, Polarity::Negative]
+        );
+    }
+
+    #[test]
+    fn summary_of_an_empty_run() {
+        let summary = MsRun::new().summary();
+        assert_eq!(summary.n_scans, 0);
+        assert!(summary.scans_per_level.is_empty());
+        assert_eq!(summary.rt_range, None);
+        assert_eq!(summary.mz_range, None);
+        assert_eq!(summary.mean_peaks, 0.0);
+        assert_eq!(summary.tic_max, None);
+        assert!(summary.polarities.is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+//! Long format tables of the peaks and ROIs as Arrow record batches, written to Arrow IPC
+//! or Parquet files, for dataframe libraries and databases such as polars and DuckDB.
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{Field, Schema};
+use ndarray::prelude::*;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::enums::TableFormat;
+use crate::error::RoiError;
+use crate::structs::data::{RoiStatistics, Roicell};
+
+/// One row per centroided peak of every scan: `run`, `scan` (position in the run), `rt`,
+/// `mz` and `intensity`. The inputs are those of `compute_roi`.
+pub fn peak_table(
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+) -> Result<RecordBatch, RoiError> {
+    check_runs(peaks_list, times_list)?;
+
+    let mut runs = Vec::new();
+    let mut scans = Vec::new();
+    let mut rts = Vec::new();
+    let mut mzs = Vec::new();
+    let mut intensities = Vec::new();
+
+    for (run, (peaks, times)) in peaks_list.iter().zip(times_list.iter()).enumerate() {
+        for (scan, (peaks, &rt)) in peaks.iter().zip(times.iter()).enumerate() {
+            if peaks.is_empty() {
+                continue;
+            }
+            for peak in peaks.axis_iter(Axis(0)) {
+                runs.push(run as u64);
+                scans.push(scan as u64);
+                rts.push(rt);
+                mzs.push(peak[0]);
+                intensities.push(peak[1]);
+            }
+        }
+    }
+
+    batch(
+        vec![
+            ("run", Arc::new(UInt64Array::from(runs))),
+            ("scan", Arc::new(UInt64Array::from(scans))),
+            ("rt", Arc::new(Float64Array::from(rts))),
+            ("mz", Arc::new(Float64Array::from(mzs))),
+            ("intensity", Arc::new(Float64Array::from(intensities))),
+        ],
+        &[],
+    )
+}
+
+/// Check that `times_list` has a time for every scan of `peaks_list`, and that the peaks
+/// have an m/z and an intensity column, as expected by `compute_roi` and `peak_table`.
+pub fn check_runs(
+    peaks_list: &[Vec<Array2<f64>>],
+    times_list: &[Array1<f64>],
+) -> Result<(), RoiError> {
+    if peaks_list.len() != times_list.len() {
+        return Err(RoiError::InvalidArgument(format!(
+            "{} runs of peaks but {} of scan times were given",
+            peaks_list.len(),
+            times_list.len()
+        )));
+    }
+    for (run, (peaks, times)) in peaks_list.iter().zip(times_list.iter()).enumerate() {
+        if peaks.len() != times.len() {
+            return Err(RoiError::InvalidArgument(format!(
+                "Run {} has {} scans but {} scan times",
+                run,
+                peaks.len(),
+                times.len()
+            )));
+        }
+        if peaks
+            .iter()
+            .any(|peaks| !peaks.is_empty() && peaks.ncols() < 2)
+        {
+            return Err(RoiError::InvalidArgument(format!(
+                "The peaks of run {} must have an m/z and an intensity column",
+                run
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// One row per peak assigned to a ROI: `roi_id` (column of `msroi`), `run`, `scan`
+/// (position in the run), `rt`, and the `mz` and `intensity` of the peak. `roicell` is
+/// the one returned by `compute_roi` for the scans whose times are `times_list`.
+pub fn roi_table(roicell: &Roicell, times_list: &[Array1<f64>]) -> Result<RecordBatch, RoiError> {
+    // Run, scan and time of every row of msroi
+    let rows: Vec<(u64, u64, f64)> = times_list
+        .iter()
+        .enumerate()
+        .flat_map(|(run, times)| {
+            times
+                .iter()
+                .enumerate()
+                .map(move |(scan, &rt)| (run as u64, scan as u64, rt))
+        })
+        .collect();
+    if roicell
+        .scans
+        .iter()
+        .flatten()
+        .any(|&scan| scan < 0.0 || scan as usize >= rows.len())
+    {
+        return Err(RoiError::InvalidArgument(format!(
+            "The ROIs have peaks in scans beyond the {} scan times given",
+            rows.len()
+        )));
+    }
+
+    let mut roi_ids = Vec::new();
+    let mut runs = Vec::new();
+    let mut scans = Vec::new();
+    let mut rts = Vec::new();
+    let mut mzs = Vec::new();
+    let mut intensities = Vec::new();
+
+    for roi in 0..roicell.scans.len() {
+        let mut order: Vec<usize> = (0..roicell.scans[roi].len()).collect();
+        order.sort_by(|&a, &b| roicell.scans[roi][a].total_cmp(&roicell.scans[roi][b]));
+        for i in order {
+            let (run, scan, rt) = rows[roicell.scans[roi][i] as usize];
+            roi_ids.push(roi as u64);
+            runs.push(run);
+            scans.push(scan);
+            rts.push(rt);
+            mzs.push(roicell.mzs[roi][i]);
+            intensities.push(roicell.intensities[roi][i]);
+        }
+    }
+
+    batch(
+        vec![
+            ("roi_id", Arc::new(UInt64Array::from(roi_ids))),
+            ("run", Arc::new(UInt64Array::from(runs))),
+            ("scan", Arc::new(UInt64Array::from(scans))),
+            ("rt", Arc::new(Float64Array::from(rts))),
+            ("mz", Arc::new(Float64Array::from(mzs))),
+            ("intensity", Arc::new(Float64Array::from(intensities))),
+        ],
+        &[],
+    )
+}
+
+/// One row per ROI with its `roi_id` and the statistics of `export::roi_statistics`;
+/// the apex columns are null for the ROIs without peaks.
+pub fn roi_summary_table(statistics: &[RoiStatistics]) -> Result<RecordBatch, RoiError> {
+    let column = |f: fn(&RoiStatistics) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(statistics.iter().map(f)))
+    };
+    let count = |f: fn(&RoiStatistics) -> usize| -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(
+            statistics.iter().map(|s| f(s) as u64),
+        ))
+    };
+
+    batch(
+        vec![
+            (
+                "roi_id",
+                Arc::new(UInt64Array::from_iter_values(0..statistics.len() as u64)),
+            ),
+            ("mz", column(|s| s.mz)),
+            ("n_scans", count(|s| s.n_scans)),
+            ("n_runs", count(|s| s.n_runs)),
+            (
+                "apex_time",
+                Arc::new(Float64Array::from_iter(
+                    statistics.iter().map(|s| s.apex_time),
+                )),
+            ),
+            (
+                "apex_run",
+                Arc::new(UInt64Array::from_iter(
+                    statistics.iter().map(|s| s.apex_run.map(|run| run as u64)),
+                )),
+            ),
+            (
+                "max_intensity",
+                Arc::new(Float64Array::from_iter(
+                    statistics.iter().map(|s| s.max_intensity),
+                )),
+            ),
+            ("total_intensity", column(|s| s.total_intensity)),
+        ],
+        &["apex_time", "apex_run", "max_intensity"],
+    )
+}
+
+/// Write a table to an Arrow IPC file or to a Parquet file compressed with zstd.
+pub fn write_table(table: &RecordBatch, path: &Path, format: TableFormat) -> Result<(), RoiError> {
+    let error = |error: &dyn std::fmt::Display| {
+        RoiError::Write(format!("Can't write {}: {}", path.display(), error))
+    };
+    let file = File::create(path)?;
+
+    match format {
+        TableFormat::ArrowIpc => {
+            let mut writer = FileWriter::try_new(file, &table.schema()).map_err(|e| error(&e))?;
+            writer.write(table).map_err(|e| error(&e))?;
+            writer.finish().map_err(|e| error(&e))
+        }
+        TableFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .build();
+            let mut writer = ArrowWriter::try_new(file, table.schema(), Some(properties))
+                .map_err(|e| error(&e))?;
+            writer.write(table).map_err(|e| error(&e))?;
+            writer.close().map(|_| ()).map_err(|e| error(&e))
+        }
+    }
+}
+
+/// Record batch of the named columns, nullable when their name is in `nullable`.
+fn batch(columns: Vec<(&str, ArrayRef)>, nullable: &[&str]) -> Result<RecordBatch, RoiError> {
+    let schema = Schema::new(
+        columns
+            .iter()
+            .map(|(name, column)| {
+                Field::new(*name, column.data_type().clone(), nullable.contains(name))
+            })
+            .collect::<Vec<_>>(),
+    );
+    RecordBatch::try_new(
+        Arc::new(schema),
+        columns.into_iter().map(|(_, column)| column).collect(),
+    )
+    .map_err(|error| RoiError::InvalidArgument(format!("Can't build the table: {}", error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_roi;
+    use crate::export::roi_statistics;
+    use crate::structs::options::RoiParams;
+    use crate::test_utils::{synthetic_runs, temp_path};
+
+    use arrow_array::Array;
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn peak_table_has_one_row_per_peak() {
+        let (peaks_list, times_list) = synthetic_runs(&[150.0, 300.0], 2, 20, 50);
+        let table = peak_table(&peaks_list, &times_list).unwrap();
+
+        let n_peaks: usize = peaks_list.iter().flatten().map(|peaks| peaks.nrows()).sum();
+        assert_eq!(table.num_rows(), n_peaks);
+        assert_eq!(table.num_columns(), 5);
+        let rts = table
+            .column_by_name("rt")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(rts.value(0), times_list[0][0]);
+        assert_eq!(rts.value(n_peaks - 1), times_list[1][19]);
+    }
+
+    #[test]
+    fn roi_tables_follow_the_rois() {
+        let (peaks_list, times_list) = synthetic_runs(&[150.0, 300.0], 2, 20, 51);
+        let (mzroi, msroi, roicell) = compute_roi(&peaks_list, &times_list, RoiParams::default());
+        let table = roi_table(&roicell, &times_list).unwrap();
+
+        let n_peaks: usize = roicell.scans.iter().map(|scans| scans.len()).sum();
+        assert_eq!(table.num_rows(), n_peaks);
+        let roi_ids = table
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert!(roi_ids
+            .values()
+            .iter()
+            .all(|&roi| (roi as usize) < mzroi.len()));
+
+        let times = Array1::from_iter(times_list.iter().flatten().cloned());
+        let runs = Array1::from_iter((0..40).map(|i| i / 20));
+        let statistics = roi_statistics(&msroi, &mzroi, &times, &runs).unwrap();
+        let summary = roi_summary_table(&statistics).unwrap();
+        assert_eq!(summary.num_rows(), mzroi.len());
+        assert_eq!(summary.column_by_name("apex_time").unwrap().null_count(), 0);
+    }
+
+    #[test]
+    fn mismatched_times_are_rejected() {
+        let (peaks_list, times_list) = synthetic_runs(&[150.0], 2, 10, 52);
+        assert!(matches!(
+            peak_table(&peaks_list, &times_list[..1]),
+            Err(RoiError::InvalidArgument(_))
+        ));
+        let short = vec![
+            times_list[0].clone(),
+            times_list[1].slice(s![..9]).to_owned(),
+        ];
+        assert!(peak_table(&peaks_list, &short).is_err());
+        let mut one_column = peaks_list.clone();
+        one_column[0][0] = Array2::zeros((3, 1));
+        assert!(check_runs(&one_column, &times_list).is_err());
+
+        // The ROIs of both runs, with the scan times of the first run only
+        let (_, _, roicell) = compute_roi(&peaks_list, &times_list, RoiParams::default());
+        assert!(matches!(
+            roi_table(&roicell, &times_list[..1]),
+            Err(RoiError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn tables_are_read_back() {
+        let (peaks_list, times_list) = synthetic_runs(&[150.0], 1, 10, 53);
+        let table = peak_table(&peaks_list, &times_list).unwrap();
+
+        let path = temp_path("peaks.arrow");
+        write_table(&table, &path, TableFormat::ArrowIpc).unwrap();
+        let batches: Vec<RecordBatch> = FileReader::try_new(File::open(&path).unwrap(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches, vec![table.clone()]);
+
+        let path = temp_path("peaks.parquet");
+        write_table(&table, &path, TableFormat::Parquet).unwrap();
+        let batches: Vec<RecordBatch> =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+                .unwrap()
+                .build()
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+            table.num_rows()
+        );
+        assert_eq!(batches[0].column(3), table.column(3));
+    }
+}
@@ -0,0 +1,137 @@
+//! Synthetic data shared by the unit tests.
+
+use ndarray::prelude::*;
+
+use crate::enums::Polarity;
+use crate::structs::io::{MsRun, Peak, Scan};
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Deterministic pseudo-random numbers (xorshift64*), so that the tests don't need a
+/// random number generator crate.
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    /// Uniform value in [0, 1).
+    pub fn uniform(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Gaussian peak of unit height centred on `centre`.
+pub fn gaussian(x: f64, centre: f64, width: f64) -> f64 {
+    (-0.5 * ((x - centre) / width).powi(2)).exp()
+}
+
+/// Centroided scans of `n_runs` runs of `n_scans` scans, in the nested form taken by
+/// `compute_roi`. Every m/z value of `mzs` elutes as a Gaussian peak, at a different
+/// time for each compound, with a small m/z jitter; low intensity noise peaks below the
+/// default threshold are scattered over every scan.
+pub fn synthetic_runs(
+    mzs: &[f64],
+    n_runs: usize,
+    n_scans: usize,
+    seed: u64,
+) -> (Vec<Vec<Array2<f64>>>, Vec<Array1<f64>>) {
+    let mut random = Random::new(seed);
+    let mut peaks_list = Vec::new();
+    let mut times_list = Vec::new();
+
+    for run in 0..n_runs {
+        let times = Array1::from_shape_fn(n_scans, |i| 0.5 * i as f64 + 0.1 * run as f64);
+        let mut scans = Vec::new();
+        for i in 0..n_scans {
+            let mut peaks: Vec<[f64; 2]> = Vec::new();
+            for (k, &mz) in mzs.iter().enumerate() {
+                let apex = (k + 1) as f64 * n_scans as f64 / (mzs.len() + 1) as f64;
+                let intensity = 1e5 * gaussian(i as f64, apex, n_scans as f64 / 20.0 + 1.0);
+                if intensity > 100.0 {
+                    peaks.push([mz + 0.004 * (random.uniform() - 0.5), intensity]);
+                }
+            }
+            for _ in 0..3 {
+                peaks.push([100.0 + 400.0 * random.uniform(), 500.0 * random.uniform()]);
+            }
+            peaks.sort_by(|a, b| a[0].total_cmp(&b[0]));
+            scans.push(Array2::from_shape_fn((peaks.len(), 2), |(p, j)| {
+                peaks[p][j]
+            }));
+        }
+        peaks_list.push(scans);
+        times_list.push(times);
+    }
+    (peaks_list, times_list)
+}
+
+/// The first run of `synthetic_runs` as an `MsRun` of positive MS1 scans.
+pub fn synthetic_run(mzs: &[f64], n_scans: usize, seed: u64) -> MsRun {
+    let (peaks_list, times_list) = synthetic_runs(mzs, 1, n_scans, seed);
+    let scans = peaks_list[0]
+        .iter()
+        .zip(times_list[0].iter())
+        .enumerate()
+        .map(|(i, (peaks, &time))| Scan {
+            num: i as u64 + 1,
+            ms_level: 1,
+            peaks_count: peaks.nrows() as u64,
+            retention_time: time,
+            polarity: Some(Polarity::Positive),
+            peaks: peaks
+                .axis_iter(Axis(0))
+                .map(|peak| Peak {
+                    mz: peak[0],
+                    intensity: peak[1],
+                })
+                .collect(),
+        })
+        .collect();
+    MsRun {
+        name: "synthetic".to_string(),
+        scans,
+    }
+}
+
+/// Bilinear data `c st` with `k` non-negative components: Gaussian elution profiles
+/// in `c` (`n_rows` × `k`) and spectra with a few lines each in `st` (`k` × `n_cols`).
+pub fn bilinear(
+    n_rows: usize,
+    n_cols: usize,
+    k: usize,
+    seed: u64,
+) -> (Array2<f64>, Array2<f64>, Array2<f64>) {
+    let mut random = Random::new(seed);
+    let c = Array2::from_shape_fn((n_rows, k), |(i, j)| {
+        let apex = (j + 1) as f64 * n_rows as f64 / (k + 1) as f64;
+        gaussian(i as f64, apex, n_rows as f64 / 10.0)
+    });
+    let mut st = Array2::zeros((k, n_cols));
+    for j in 0..k {
+        for col in 0..n_cols {
+            if col % k == j || random.uniform() < 0.2 {
+                st[[j, col]] = 0.1 + random.uniform();
+            }
+        }
+    }
+    let d = c.dot(&st);
+    (d, c, st)
+}
+
+/// Path of a scratch file in the temporary directory, unique to the call.
+pub fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!(
+        "roimcr-test-{}-{}-{}",
+        std::process::id(),
+        id,
+        name
+    ))
+}
@@ -1,8 +1,4 @@
-use csv::WriterBuilder;
-use ndarray::Array2;
-use ndarray_csv::Array2Writer;
-
-use std::fs::File;
+use ndarray::{Array1, Array2, Axis};
 
 pub fn subset(array: &[f64], indices: &[usize]) -> Vec<f64> {
     indices.iter().map(|&i| array[i]).collect::<Vec<_>>()
@@ -32,10 +28,184 @@ pub fn sort_by_indices<T>(data: &mut [T], indices: &[usize]) {
     }
 }
 
-pub fn ndarray_to_csv(data: &Array2<f64>) {
-    let file = File::create("examples/test.csv").expect("Couldn't create csv file!");
-    let mut writer = WriterBuilder::new().has_headers(false).from_writer(file);
-    writer
-        .serialize_array2(data)
-        .expect("Couldn't serialize array to csv file!");
+/// Solve the square system `a x = b` by Gaussian elimination with partial pivoting.
+/// Returns `None` if the matrix is singular.
+pub fn solve_linear_system(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    let scale = a
+        .iter()
+        .flat_map(|row| row.iter())
+        .fold(0.0_f64, |acc, v| acc.max(v.abs()));
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= f64::EPSILON * scale {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in (col + 1)..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let partial: f64 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - partial) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Inverse of a square matrix, `None` if it is singular.
+pub fn inverse(a: &Array2<f64>) -> Option<Array2<f64>> {
+    let n = a.nrows();
+    let rows: Vec<Vec<f64>> = a.axis_iter(Axis(0)).map(|row| row.to_vec()).collect();
+    let mut result = Array2::zeros((n, n));
+
+    for col in 0..n {
+        let mut unit = vec![0.0; n];
+        unit[col] = 1.0;
+        let solution = solve_linear_system(rows.clone(), unit)?;
+        for (row, value) in solution.into_iter().enumerate() {
+            result[[row, col]] = value;
+        }
+    }
+    Some(result)
+}
+
+/// Least squares solution of `a x = b` from the normal equations. When `a'a` is singular,
+/// e.g. because a column of `a` is all zeros, a small ridge term is added so that the
+/// corresponding rows of `x` go to zero instead of failing.
+pub fn least_squares(a: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    regularised_inverse(&a.t().dot(a)).dot(&a.t().dot(b))
+}
+
+/// Least squares solution of `x a = b`, see `least_squares`.
+pub fn least_squares_right(b: &Array2<f64>, a: &Array2<f64>) -> Array2<f64> {
+    b.dot(&a.t()).dot(&regularised_inverse(&a.dot(&a.t())))
+}
+
+/// Inverse of the cross product `ata`, with a small ridge term if it is singular.
+pub fn regularised_inverse(ata: &Array2<f64>) -> Array2<f64> {
+    inverse(ata).unwrap_or_else(|| {
+        let ridge = 1e-12 * ata.diag().sum().max(f64::EPSILON);
+        let regularised = ata + &(Array2::<f64>::eye(ata.nrows()) * ridge);
+        inverse(&regularised).expect("Couldn't solve the least squares problem")
+    })
+}
+
+/// Eigenvalues, in decreasing order, and eigenvectors (columns) of a symmetric matrix by
+/// cyclic Jacobi rotations. Meant for the small matrices of projected problems.
+pub fn symmetric_eigen(a: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = a.nrows();
+    let mut a = a.to_owned();
+    let mut vectors: Array2<f64> = Array2::eye(n);
+
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+            .map(|(i, j)| a[[i, j]] * a[[i, j]])
+            .sum();
+        let scale: f64 = a.diag().mapv(|v| v * v).sum();
+        if off_diagonal <= 1e-30 * scale.max(f64::MIN_POSITIVE) {
+            break;
+        }
+
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[[p, q]] == 0.0 {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * akp - s * akq;
+                    a[[k, q]] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * apk - s * aqk;
+                    a[[q, k]] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (vectors[[k, p]], vectors[[k, q]]);
+                    vectors[[k, p]] = c * vkp - s * vkq;
+                    vectors[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let values = a.diag().to_vec();
+    let mut order: Vec<usize> = argsort(&values);
+    order.reverse();
+    (
+        order.iter().map(|&i| values[i]).collect(),
+        vectors.select(Axis(1), &order),
+    )
+}
+
+/// Orthonormalise the columns of `a` by modified Gram-Schmidt, dropping the columns that
+/// are linearly dependent on the previous ones.
+pub fn orthonormalise(a: &Array2<f64>) -> Array2<f64> {
+    let mut columns: Vec<Array1<f64>> = Vec::new();
+
+    for column in a.axis_iter(Axis(1)) {
+        let original = column.dot(&column).sqrt();
+        let mut column = column.to_owned();
+        for q in columns.iter() {
+            let projection = q.dot(&column);
+            column.scaled_add(-projection, q);
+        }
+        let norm = column.dot(&column).sqrt();
+        if norm > 1e-10 * original && norm > 0.0 {
+            columns.push(column / norm);
+        }
+    }
+
+    let mut result = Array2::zeros((a.nrows(), columns.len()));
+    for (i, column) in columns.iter().enumerate() {
+        result.column_mut(i).assign(column);
+    }
+    result
+}
+
+/// Leading `k` singular triplets of `d` (`u`, singular values, `v'`) by subspace iteration
+/// on `d'd` followed by a Rayleigh-Ritz step, which never forms `d'd` or `dd'`.
+pub fn truncated_svd(d: &Array2<f64>, k: usize) -> (Array2<f64>, Array1<f64>, Array2<f64>) {
+    let k = k.min(d.nrows()).min(d.ncols());
+    let block = (k + 10).min(d.ncols());
+
+    // Deterministic, well spread starting block
+    let mut q = orthonormalise(&Array2::from_shape_fn((d.ncols(), block), |(i, j)| {
+        (((i + 1) * (j + 1)) as f64 * 0.618_033_988_75).fract() - 0.5
+    }));
+
+    for _ in 0..30 {
+        q = orthonormalise(&d.t().dot(&d.dot(&q)));
+    }
+
+    let projected = d.dot(&q);
+    let (values, vectors) = symmetric_eigen(&projected.t().dot(&projected));
+    let k = k.min(values.len());
+
+    let singular: Array1<f64> = values.slice(ndarray::s![..k]).mapv(|v| v.max(0.0).sqrt());
+    let v = q.dot(&vectors.slice(ndarray::s![.., ..k]));
+    let mut u = d.dot(&v);
+    for (mut column, &s) in u.axis_iter_mut(Axis(1)).zip(singular.iter()) {
+        if s > 0.0 {
+            column /= s;
+        }
+    }
+    (u, singular, v.reversed_axes())
 }
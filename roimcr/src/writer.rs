@@ -0,0 +1,345 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::binary::{encode, from_floats, ByteOrder};
+use crate::enums::Polarity;
+use crate::error::RoiError;
+use crate::structs::io::MsRun;
+use crate::structs::options::WriteParams;
+
+/// Write a run as mzXML 3.2, one `scan` per spectrum with its peaks as interleaved m/z and
+/// intensity pairs in network byte order. Precursors of MSn scans aren't stored in
+/// `MsRun` and aren't written.
+pub fn write_mzxml(run: &MsRun, path: &Path, params: &WriteParams) -> Result<(), RoiError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let precision = if params.double_precision { 64 } else { 32 };
+    let (start, end) = time_range(run);
+
+    writeln!(out, r#"<?xml version="1.0" encoding="ISO-8859-1"?>"#)?;
+    writeln!(
+        out,
+        r#"<mzXML xmlns="http://sashimi.sourceforge.net/schema_revision/mzXML_3.2" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://sashimi.sourceforge.net/schema_revision/mzXML_3.2 http://sashimi.sourceforge.net/schema_revision/mzXML_3.2/mzXML_idx_3.2.xsd">"#
+    )?;
+    writeln!(
+        out,
+        r#"  <msRun scanCount="{}" startTime="PT{}S" endTime="PT{}S">"#,
+        run.scans.len(),
+        start,
+        end
+    )?;
+    writeln!(
+        out,
+        r#"    <dataProcessing centroided="1"><software type="conversion" name="roimcr" version="{}"/></dataProcessing>"#,
+        env!("CARGO_PKG_VERSION")
+    )?;
+
+    for scan in run.scans.iter() {
+        let values = scan.peaks.iter().flat_map(|peak| [peak.mz, peak.intensity]);
+        let bytes = from_floats(values, params.double_precision, ByteOrder::BigEndian);
+        let data = encode(&bytes, params.zlib);
+        let compression = if params.zlib {
+            format!(r#"compressionType="zlib" compressedLen="{}""#, data.len())
+        } else {
+            r#"compressionType="none" compressedLen="0""#.to_string()
+        };
+        let polarity = match scan.polarity {
+            Some(Polarity::Positive) => r#" polarity="+""#,
+            Some(Polarity::Negative) => r#" polarity="-""#,
+            None => "",
+        };
+
+        writeln!(
+            out,
+            r#"    <scan num="{}" msLevel="{}" peaksCount="{}"{} retentionTime="PT{}S" centroided="1">"#,
+            scan.num,
+            scan.ms_level,
+            scan.peaks.len(),
+            polarity,
+            scan.retention_time
+        )?;
+        writeln!(
+            out,
+            r#"      <peaks precision="{}" byteOrder="network" contentType="m/z-int" {}>{}</peaks>"#,
+            precision, compression, data
+        )?;
+        writeln!(out, "    </scan>")?;
+    }
+
+    writeln!(out, "  </msRun>")?;
+    writeln!(out, "</mzXML>")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write a run as mzML 1.1, one `spectrum` per scan with separate m/z and intensity arrays
+/// in little endian byte order and retention times in seconds. Precursors of MSn scans
+/// aren't stored in `MsRun` and aren't written.
+pub fn write_mzml(run: &MsRun, path: &Path, params: &WriteParams) -> Result<(), RoiError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let name = escape(&run.name);
+    let (precision_accession, precision_name) = if params.double_precision {
+        ("MS:1000523", "64-bit float")
+    } else {
+        ("MS:1000521", "32-bit float")
+    };
+    let (compression_accession, compression_name) = if params.zlib {
+        ("MS:1000574", "zlib compression")
+    } else {
+        ("MS:1000576", "no compression")
+    };
+
+    write!(
+        out,
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<mzML xmlns="http://psi.hupo.org/ms/mzml" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://psi.hupo.org/ms/mzml http://psidev.info/files/ms/mzML/xsd/mzML1.1.0.xsd" id="{name}" version="1.1.0">
+  <cvList count="2">
+    <cv id="MS" fullName="Proteomics Standards Initiative Mass Spectrometry Ontology" URI="https://raw.githubusercontent.com/HUPO-PSI/psi-ms-CV/master/psi-ms.obo"/>
+    <cv id="UO" fullName="Unit Ontology" URI="https://raw.githubusercontent.com/bio-ontology-research-group/unit-ontology/master/unit.obo"/>
+  </cvList>
+  <fileDescription>
+    <fileContent>
+      <cvParam cvRef="MS" accession="MS:1000579" name="MS1 spectrum" value=""/>
+      <cvParam cvRef="MS" accession="MS:1000127" name="centroid spectrum" value=""/>
+    </fileContent>
+  </fileDescription>
+  <softwareList count="1">
+    <software id="roimcr" version="{version}">
+      <cvParam cvRef="MS" accession="MS:1000799" name="custom unreleased software tool" value="roimcr"/>
+    </software>
+  </softwareList>
+  <instrumentConfigurationList count="1">
+    <instrumentConfiguration id="IC1"/>
+  </instrumentConfigurationList>
+  <dataProcessingList count="1">
+    <dataProcessing id="roimcr_conversion">
+      <processingMethod order="0" softwareRef="roimcr">
+        <cvParam cvRef="MS" accession="MS:1000544" name="Conversion to mzML" value=""/>
+      </processingMethod>
+    </dataProcessing>
+  </dataProcessingList>
+  <run id="{name}" defaultInstrumentConfigurationRef="IC1">
+    <spectrumList count="{count}" defaultDataProcessingRef="roimcr_conversion">
+"#,
+        name = name,
+        version = env!("CARGO_PKG_VERSION"),
+        count = run.scans.len()
+    )?;
+
+    for (index, scan) in run.scans.iter().enumerate() {
+        writeln!(
+            out,
+            r#"      <spectrum index="{}" id="scan={}" defaultArrayLength="{}">"#,
+            index,
+            scan.num,
+            scan.peaks.len()
+        )?;
+        writeln!(
+            out,
+            r#"        <cvParam cvRef="MS" accession="MS:1000511" name="ms level" value="{}"/>"#,
+            scan.ms_level
+        )?;
+        if scan.ms_level == 1 {
+            writeln!(
+                out,
+                r#"        <cvParam cvRef="MS" accession="MS:1000579" name="MS1 spectrum" value=""/>"#
+            )?;
+        } else {
+            writeln!(
+                out,
+                r#"        <cvParam cvRef="MS" accession="MS:1000580" name="MSn spectrum" value=""/>"#
+            )?;
+        }
+        match scan.polarity {
+            Some(Polarity::Positive) => writeln!(
+                out,
+                r#"        <cvParam cvRef="MS" accession="MS:1000130" name="positive scan" value=""/>"#
+            )?,
+            Some(Polarity::Negative) => writeln!(
+                out,
+                r#"        <cvParam cvRef="MS" accession="MS:1000129" name="negative scan" value=""/>"#
+            )?,
+            None => {}
+        }
+        writeln!(
+            out,
+            r#"        <cvParam cvRef="MS" accession="MS:1000127" name="centroid spectrum" value=""/>"#
+        )?;
+        writeln!(out, r#"        <scanList count="1">"#)?;
+        writeln!(
+            out,
+            r#"          <cvParam cvRef="MS" accession="MS:1000795" name="no combination" value=""/>"#
+        )?;
+        writeln!(out, "          <scan>")?;
+        writeln!(
+            out,
+            r#"            <cvParam cvRef="MS" accession="MS:1000016" name="scan start time" value="{}" unitCvRef="UO" unitAccession="UO:0000010" unitName="second"/>"#,
+            scan.retention_time
+        )?;
+        writeln!(out, "          </scan>")?;
+        writeln!(out, "        </scanList>")?;
+        writeln!(out, r#"        <binaryDataArrayList count="2">"#)?;
+
+        let arrays = [
+            (
+                scan.peaks.iter().map(|peak| peak.mz).collect::<Vec<f64>>(),
+                r#"accession="MS:1000514" name="m/z array" value="" unitCvRef="MS" unitAccession="MS:1000040" unitName="m/z""#,
+            ),
+            (
+                scan.peaks.iter().map(|peak| peak.intensity).collect(),
+                r#"accession="MS:1000515" name="intensity array" value="" unitCvRef="MS" unitAccession="MS:1000131" unitName="number of detector counts""#,
+            ),
+        ];
+        for (values, kind) in arrays.iter() {
+            let bytes = from_floats(
+                values.iter().cloned(),
+                params.double_precision,
+                ByteOrder::LittleEndian,
+            );
+            let data = encode(&bytes, params.zlib);
+            writeln!(
+                out,
+                r#"          <binaryDataArray encodedLength="{}">"#,
+                data.len()
+            )?;
+            writeln!(
+                out,
+                r#"            <cvParam cvRef="MS" accession="{}" name="{}" value=""/>"#,
+                precision_accession, precision_name
+            )?;
+            writeln!(
+                out,
+                r#"            <cvParam cvRef="MS" accession="{}" name="{}" value=""/>"#,
+                compression_accession, compression_name
+            )?;
+            writeln!(out, r#"            <cvParam cvRef="MS" {}/>"#, kind)?;
+            writeln!(out, "            <binary>{}</binary>", data)?;
+            writeln!(out, "          </binaryDataArray>")?;
+        }
+
+        writeln!(out, "        </binaryDataArrayList>")?;
+        writeln!(out, "      </spectrum>")?;
+    }
+
+    writeln!(out, "    </spectrumList>")?;
+    writeln!(out, "  </run>")?;
+    writeln!(out, "</mzML>")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn time_range(run: &MsRun) -> (f64, f64) {
+    let times = run.scans.iter().map(|scan| scan.retention_time);
+    match times.clone().reduce(f64::min) {
+        Some(start) => (start, times.reduce(f64::max).unwrap_or(start)),
+        None => (0.0, 0.0),
+    }
+}
+
+/// Escape the characters that can't appear in XML attribute values.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{InputFormat, OutputFormat};
+    use crate::test_utils::{synthetic_run, temp_path};
+    use crate::{read_run, write_run};
+
+    use std::fs;
+
+    /// A synthetic run with an MS2 scan and negative scans, so that every field written
+    /// differs from its default somewhere.
+    fn run() -> MsRun {
+        let mut run = synthetic_run(&[150.0, 320.5], 12, 5);
+        for scan in run.scans.iter_mut().skip(6) {
+            scan.polarity = Some(Polarity::Negative);
+        }
+        run.scans[3].ms_level = 2;
+        run
+    }
+
+    #[test]
+    fn written_runs_are_read_back() {
+        let original = run();
+        let formats = [
+            (OutputFormat::MzXml, InputFormat::MzXml, "mzXML"),
+            (OutputFormat::MzMl, InputFormat::MzMl, "mzML"),
+        ];
+        for (output, input, extension) in formats {
+            for zlib in [false, true] {
+                for double_precision in [false, true] {
+                    let params = WriteParams {
+                        zlib,
+                        double_precision,
+                    };
+                    let path = temp_path(&format!("run.{}", extension));
+                    write_run(&original, &path, output, &params).unwrap();
+                    let read = read_run(&path, input).unwrap();
+                    fs::remove_file(&path).unwrap();
+
+                    let case = format!("{} zlib {} 64-bit {}", extension, zlib, double_precision);
+                    let tolerance = if double_precision { 0.0 } else { 1e-6 };
+                    assert_eq!(read.scans.len(), original.scans.len(), "{}", case);
+                    for (read, original) in read.scans.iter().zip(original.scans.iter()) {
+                        assert_eq!(read.ms_level, original.ms_level, "{}", case);
+                        assert_eq!(read.polarity, original.polarity, "{}", case);
+                        assert_eq!(read.retention_time, original.retention_time, "{}", case);
+                        assert_eq!(read.peaks.len(), original.peaks.len(), "{}", case);
+                        for (read, original) in read.peaks.iter().zip(original.peaks.iter()) {
+                            let close = |a: f64, b: f64| (a - b).abs() <= tolerance * b.abs();
+                            assert!(close(read.mz, original.mz), "{}", case);
+                            assert!(close(read.intensity, original.intensity), "{}", case);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn odd_number_of_peak_values_is_a_parse_error() {
+        let mut original = run();
+        original.scans.truncate(1);
+        original.scans[0].peaks.truncate(1);
+        let params = WriteParams {
+            zlib: false,
+            double_precision: true,
+        };
+        let path = temp_path("odd.mzXML");
+        write_mzxml(&original, &path, &params).unwrap();
+
+        let peak = &original.scans[0].peaks[0];
+        let pair = encode(
+            &from_floats(
+                [peak.mz, peak.intensity].into_iter(),
+                true,
+                ByteOrder::BigEndian,
+            ),
+            false,
+        );
+        let odd = encode(
+            &from_floats(
+                [peak.mz, peak.intensity, 1.0].into_iter(),
+                true,
+                ByteOrder::BigEndian,
+            ),
+            false,
+        );
+        let text = fs::read_to_string(&path).unwrap().replace(&pair, &odd);
+        fs::write(&path, text).unwrap();
+        let read = read_run(&path, InputFormat::MzXml);
+        fs::remove_file(&path).unwrap();
+
+        match read {
+            Err(RoiError::Parse(message)) => assert!(message.contains("odd"), "{}", message),
+            other => panic!("Expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! End-to-end runs of the `roimcr` binary.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use roimcr::enums::{OutputFormat, Polarity};
+use roimcr::structs::io::{MsRun, Peak, Scan};
+use roimcr::structs::options::WriteParams;
+use roimcr::write_run;
+
+/// Empty directory of the temporary directory unique to this test process.
+fn temp_dir(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("roimcr-cli-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Run of `n_scans` MS1 scans with two compounds at m/z 200 and 300 eluting one after
+/// the other, and a small peak that is below the default threshold.
+fn run(n_scans: usize) -> MsRun {
+    let scans = (0..n_scans)
+        .map(|i| {
+            let elution = |apex: f64| 1e5 * (-((i as f64 - apex) / 3.0).powi(2) / 2.0).exp();
+            let peaks = vec![
+                Peak {
+                    mz: 150.0,
+                    intensity: 10.0,
+                },
+                Peak {
+                    mz: 200.0 + 0.001 * (i % 3) as f64,
+                    intensity: elution(n_scans as f64 / 3.0) + 2000.0,
+                },
+                Peak {
+                    mz: 300.0,
+                    intensity: elution(2.0 * n_scans as f64 / 3.0) + 2000.0,
+                },
+            ];
+            Scan {
+                num: i as u64 + 1,
+                ms_level: 1,
+                peaks_count: peaks.len() as u64,
+                retention_time: 0.5 * i as f64,
+                polarity: Some(Polarity::Positive),
+                peaks,
+            }
+        })
+        .collect();
+    MsRun {
+        name: "cli".to_string(),
+        scans,
+    }
+}
+
+fn write_mzxml(run: &MsRun, path: &Path) {
+    let params = WriteParams {
+        zlib: true,
+        double_precision: false,
+    };
+    write_run(run, path, OutputFormat::MzXml, &params).unwrap();
+}
+
+fn roimcr(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_roimcr"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn roi_writes_the_tables_of_augmented_runs() {
+    let directory = temp_dir("roi");
+    let (first, second) = (directory.join("a.mzXML"), directory.join("b.mzXML"));
+    write_mzxml(&run(30), &first);
+    write_mzxml(&run(20), &second);
+    let output = directory.join("csv");
+
+    let outcome = roimcr(&[
+        "roi",
+        first.to_str().unwrap(),
+        second.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--tol",
+        "50",
+        "--tol-units",
+        "ppm",
+        "--precision",
+        "2",
+    ]);
+    assert!(outcome.status.success(), "{:?}", outcome);
+    let stdout = String::from_utf8(outcome.stdout).unwrap();
+    assert!(stdout.starts_with("2 ROIs in 50 scans"), "{}", stdout);
+
+    // One row per scan of both runs, labelled with its time and run
+    let msroi = lines(&output.join("msroi.csv"));
+    assert_eq!(msroi.len(), 51);
+    assert!(msroi[0].starts_with("time,run,200."), "{}", msroi[0]);
+    assert!(msroi[1].starts_with("0.00,0,"), "{}", msroi[1]);
+    assert!(msroi[31].starts_with("0.00,1,"), "{}", msroi[31]);
+    let rois = lines(&output.join("rois.csv"));
+    assert_eq!(rois.len(), 3);
+    assert!(rois[1].starts_with("0,200.00,50,2,"), "{}", rois[1]);
+
+    // Transposed TSV, and the MATLAB file of the mat format
+    let output = directory.join("tsv");
+    let outcome = roimcr(&[
+        "roi",
+        first.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--format",
+        "tsv",
+        "--transpose",
+    ]);
+    assert!(outcome.status.success(), "{:?}", outcome);
+    let msroi = lines(&output.join("msroi.tsv"));
+    assert_eq!(msroi.len(), 4);
+    assert!(msroi[0].starts_with("time\t0\t0.5\t"), "{}", msroi[0]);
+
+    let output = directory.join("mat");
+    let outcome = roimcr(&[
+        "roi",
+        first.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--format",
+        "mat",
+    ]);
+    assert!(outcome.status.success(), "{:?}", outcome);
+    let mat = fs::read(output.join("msroi.mat")).unwrap();
+    assert!(mat.starts_with(b"MATLAB 5.0 MAT-file"));
+    assert!(!output.join("msroi.csv").exists());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn unreadable_inputs_exit_with_an_error() {
+    let directory = temp_dir("errors");
+    let output = directory.join("output");
+
+    let missing = directory.join("missing.mzXML");
+    let outcome = roimcr(&[
+        "roi",
+        missing.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(!outcome.status.success());
+    let stderr = String::from_utf8(outcome.stderr).unwrap();
+    assert!(stderr.starts_with("roimcr: "), "{}", stderr);
+    assert!(stderr.contains("doesn't exist"), "{}", stderr);
+
+    // An mzXML file whose extension doesn't tell its format
+    let unknown = directory.join("run.txt");
+    write_mzxml(&run(10), &unknown);
+    let outcome = roimcr(&[
+        "roi",
+        unknown.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(!outcome.status.success());
+    let stderr = String::from_utf8(outcome.stderr).unwrap();
+    assert!(stderr.starts_with("roimcr: "), "{}", stderr);
+    assert!(stderr.contains("Can't detect the format"), "{}", stderr);
+    assert!(!output.exists());
+
+    let outcome = roimcr(&[
+        "roi",
+        unknown.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--input-format",
+        "mzxml",
+    ]);
+    assert!(outcome.status.success(), "{:?}", outcome);
+    assert!(output.join("msroi.csv").exists());
+
+    fs::remove_dir_all(&directory).unwrap();
+}